@@ -0,0 +1,27 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::time::Duration;
+
+mod fixture;
+
+// Decoding every stored fn in full, against just the fields searches filter and order by (see
+// `FnRecord`). `bench_fn_decoding` times the decoding alone, leaving out reading the fns
+fn decode(c: &mut Criterion) {
+    let db = fixture::index(200, 50);
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("full", |b| b.iter_custom(|iters| {
+        let bench = reeves::bench_fn_decoding(&db, usize::MAX, iters as usize);
+        Duration::from_secs_f64(bench.full_ms * iters as f64 / 1000.)
+    }));
+    group.bench_function("filter fields", |b| b.iter_custom(|iters| {
+        let bench = reeves::bench_fn_decoding(&db, usize::MAX, iters as usize);
+        Duration::from_secs_f64(bench.filter_ms * iters as f64 / 1000.)
+    }));
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = decode
+}
+criterion_main!(benches);
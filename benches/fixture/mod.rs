@@ -0,0 +1,88 @@
+// An index of generated crates, roughly like a slice of crates.io, so searches can be benchmarked
+// without analyzing real crates. Fns take and return a mix of common types, so some are in many
+// fns (like `&str`) and some in few
+use reeves::Db;
+use reeves_types::*;
+
+const TYPES: &[&str] = &[
+    "&str", "&str", "&str", "String", "u8", "u32", "usize", "usize", "bool", "bool", "char", "f64", "i64",
+    "&[u8]", "Vec<u8>", "&mut Vec<u8>", "Option<String>", "Option<usize>", "Result<(), Error>",
+    "io::Result<usize>", "Result<String, Error>", "&Path", "PathBuf", "HashMap<String, String>",
+    "Duration", "Instant", "&OsStr", "Box<dyn Error + Send + Sync>", "impl Iterator<Item = u8>",
+    "impl FnMut(&str) -> bool", "[u8; 32]", "Cow<str>",
+];
+
+// xorshift, so the index is the same every run
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+pub fn index(num_crates: usize, fns_per_crate: usize) -> Db {
+    let db = Db::in_memory();
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for c in 0..num_crates {
+        let krate = format!("crate{}", c);
+        let fndetails = (0..fns_per_crate)
+            .map(|f| {
+                let num_params = rng.below(4);
+                let params: Vec<&str> = (0..num_params).map(|_| TYPES[rng.below(TYPES.len())]).collect();
+                let ret = TYPES[rng.below(TYPES.len())];
+                fndetail(&krate, &format!("f{}", f), &params, ret)
+            })
+            .collect();
+        reeves::save_analysis(&db, &krate, "1.0.0", &meta(&krate), fndetails);
+    }
+    db
+}
+
+fn fndetail(krate: &str, name: &str, params: &[&str], ret: &str) -> FnDetail {
+    let path = format!("{}::{}", krate, name);
+    FnDetail {
+        krate: krate.to_owned(),
+        version: "1.0.0".to_owned(),
+        kind: ItemKind::Fn,
+        visibility: ItemVisibility::Public,
+        path: path.clone(),
+        aliases: vec![],
+        import_path: path.clone(),
+        params: params.iter().map(|param| param.to_string()).collect(),
+        ret: ret.to_owned(),
+        param_reprs: params.iter().map(|param| TypeRepr::parse(param)).collect(),
+        ret_repr: TypeRepr::parse(ret),
+        written_params: None,
+        written_ret: None,
+        ret_impls: vec![],
+        generics: vec![],
+        is_const: false,
+        is_async: false,
+        is_unsafe: false,
+        features: vec![],
+        profile: AnalysisProfile::default(),
+        doc: Some(format!("Does {} things", name)),
+        location: None,
+        deref_from: None,
+        deprecated: None,
+        unstable: None,
+        s: format!("fn {}({}) -> {}", path, params.join(", "), ret),
+    }
+}
+
+fn meta(krate: &str) -> CrateMeta {
+    CrateMeta {
+        name: krate.to_owned(),
+        version: "1.0.0".to_owned(),
+        license: None,
+        repository: None,
+        docsrs_url: format!("https://docs.rs/{}/1.0.0", krate),
+        indexed_at: 0,
+        categories: vec![],
+        keywords: vec![],
+    }
+}
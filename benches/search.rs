@@ -0,0 +1,30 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use reeves::SearchCache;
+use reeves_types::{Query, SearchOptions};
+
+mod fixture;
+
+// From everywhere (`&str`) to nowhere in particular, so both big and small candidate sets are intersected
+const QUERIES: &[&str] = &["&str -> bool", "&str, usize -> *", "* -> Vec<u8>", "&Path -> io::Result<usize>", "u8, char, f64 -> Instant"];
+
+// Against 1000 crates, without the text search - so candidates are found by scanning keys, which is
+// the same for every search algorithm
+fn search(c: &mut Criterion) {
+    let db = fixture::index(1000, 50);
+    let mut group = c.benchmark_group("search");
+    for query in QUERIES {
+        let parsed = Query::parse(query);
+        group.bench_function(*query, |b| b.iter(|| {
+            // A fresh cache each time, so searches aren't just cache hits
+            reeves::search(&db, &SearchCache::new(), &parsed, &SearchOptions::default()).fndetails.len()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = search
+}
+criterion_main!(benches);
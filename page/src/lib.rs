@@ -54,6 +54,9 @@ fn href<M>(e: yew::events::MouseEvent, msg: M) -> M {
 fn error_div(e: &str) -> Html {
     html!{ <div class="error">{ format!("ERROR: {}", e) }</div> }
 }
+fn warning_div(w: &str) -> Html {
+    html!{ <div class="warning">{ format!("WARNING: {}", w) }</div> }
+}
 
 #[wasm_bindgen(inline_js = r#"
 export function get_base_fetch_path(has_dirty_issues) {
@@ -116,6 +119,7 @@ impl ReevesApi {
 
 pub enum ReevesMsg {
     SearchRequest,
+    MoreResultsRequest,
     SearchResult(proto::SearchResult),
 
     ParamsChange(String),
@@ -127,6 +131,10 @@ pub enum ReevesMsg {
 pub struct ReevesComponent {
     // State from server
     search_results: Vec<FnDetail>,
+    crate_metas: BTreeMap<(String, String), CrateMeta>, // (name, version) => meta
+    next_cursor: Option<String>,
+    search_warnings: Vec<String>,
+    search_suggestions: Vec<TypeSuggestion>,
 
     // User state
     params: String,
@@ -136,6 +144,8 @@ pub struct ReevesComponent {
 
     // Maintained state
     last_error: Option<String>,
+    last_search: Option<proto::SearchRequest>, // to fetch more results with
+    loading_more: bool,
 
     // Internal guts
     api: ReevesApi,
@@ -153,6 +163,10 @@ impl Component for ReevesComponent {
 
         let ret = Self {
             search_results: vec![],
+            crate_metas: BTreeMap::new(),
+            next_cursor: None,
+            search_warnings: vec![],
+            search_suggestions: vec![],
 
             params: String::from("*"),
             parsed_params: None,
@@ -160,6 +174,8 @@ impl Component for ReevesComponent {
             parsed_ret: None,
 
             last_error: None,
+            last_search: None,
+            loading_more: false,
 
             api,
             msg_callback: link.callback(|msg| msg),
@@ -174,9 +190,27 @@ impl Component for ReevesComponent {
             ReevesMsg::SearchRequest => {
                 info!("Doing search for {:?} {:?}", self.params, self.ret);
 
-                let params = self.parsed_params.clone();
-                let ret = self.parsed_ret.clone();
-                let sr = proto::SearchRequest { params, ret };
+                let query = Query {
+                    params: self.parsed_params.as_ref().map(|params| params.iter().map(|param| TypeRepr::parse(param)).collect()),
+                    ret: self.parsed_ret.as_ref().map(|ret| TypeRepr::parse(ret)),
+                    ..Query::default()
+                };
+                let options = SearchOptions::default();
+                let sr = proto::SearchRequest { query, options };
+                self.last_search = Some(sr.clone());
+                self.loading_more = false;
+                self.api.post_search(self.msg_callback.clone(), sr);
+
+                false
+            },
+            ReevesMsg::MoreResultsRequest => {
+                let mut sr = match self.last_search.clone() {
+                    Some(sr) => sr,
+                    None => return false,
+                };
+                info!("Loading more results for {:?} {:?}", sr.query.params, sr.query.ret);
+                sr.options.cursor = self.next_cursor.clone();
+                self.loading_more = true;
                 self.api.post_search(self.msg_callback.clone(), sr);
 
                 false
@@ -184,7 +218,18 @@ impl Component for ReevesComponent {
             ReevesMsg::SearchResult(sr) => {
                 info!("Loaded {} search results", sr.fndetails.len());
 
-                self.search_results = sr.fndetails;
+                if self.loading_more {
+                    self.search_results.extend(sr.fndetails);
+                } else {
+                    self.search_results = sr.fndetails;
+                    self.crate_metas.clear();
+                }
+                for meta in sr.crates {
+                    self.crate_metas.insert((meta.name.clone(), meta.version.clone()), meta);
+                }
+                self.next_cursor = sr.next_cursor;
+                self.search_warnings = sr.warnings;
+                self.search_suggestions = sr.suggestions;
 
                 true
             },
@@ -192,7 +237,7 @@ impl Component for ReevesComponent {
             ReevesMsg::ParamsChange(val) => {
                 self.params = val;
                 self.parsed_params = if self.params.trim() != "*" {
-                    Some(self.params.trim().split(',')
+                    Some(split_top_level(self.params.trim(), ',').into_iter()
                         .map(|s| s.trim().to_owned())
                         .filter(|s| !s.is_empty())
                         .collect())
@@ -243,6 +288,8 @@ impl Component for ReevesComponent {
                 </div>
                 <br />
                 { maybenode(self.last_error.as_ref().map(String::as_str), error_div) }
+                { for self.search_warnings.iter().map(|w| warning_div(w)) }
+                { for self.search_suggestions.iter().map(|s| warning_div(&format!("no fns with {}, did you mean {}?", s.ty, s.suggestions.join(", ")))) }
                 <div id="search-pane"><code>
                     { "fn ???(" }
                     <input
@@ -284,15 +331,47 @@ impl Component for ReevesComponent {
                     for self.search_results.iter().map(|fndetail| {
                         html!{
                             <div>
-                                <a href={ format!("https://crates.io/crates/{}", fndetail.krate) }>
-                                    { &fndetail.krate }
+                                <a href={ format!("https://crates.io/crates/{}/{}", fndetail.krate, fndetail.version) }>
+                                    { format!("{} {}", fndetail.krate, fndetail.version) }
                                 </a>
+                                { maybenode(self.crate_metas.get(&(fndetail.krate.clone(), fndetail.version.clone())), |meta| html!{
+                                    <small class="crate-meta">
+                                        { " (" }<a href={ meta.docsrs_url.clone() }>{ "docs" }</a>
+                                        { maybenode(meta.repository.as_ref(), |repository| html!{
+                                            <>{ ", " }<a href={ repository.clone() }>{ "repo" }</a></>
+                                        }) }
+                                        { maybenode(meta.license.as_ref(), |license| html!{
+                                            <>{ ", " }{ license }</>
+                                        }) }
+                                        { ")" }
+                                    </small>
+                                }) }
                                 { " " }
-                                <code>{ &fndetail.s }</code>
+                                <code>{ render_signature(fndetail, &RenderOptions::default()) }</code>
+                                { maybenode(fndetail.doc.as_ref(), |doc| html!{
+                                    <div class="doc">{ doc }</div>
+                                }) }
+                                { maybenode(fndetail.deref_from.as_ref(), |deref_from| html!{
+                                    <small class="deref">{ format!(" via Deref to {}", deref_from) }</small>
+                                }) }
+                                { maybenode(fndetail.deprecated.as_ref(), |note| html!{
+                                    <small class="deprecated">{ if note.is_empty() { " deprecated".to_owned() } else { format!(" deprecated: {}", note) } }</small>
+                                }) }
+                                { maybenode(fndetail.unstable.as_ref(), |feature| html!{
+                                    <small class="unstable">{ format!(" nightly-only (feature {:?})", feature) }</small>
+                                }) }
+                                { ifnode(!fndetail.features.is_empty(), || html!{
+                                    <small class="features">
+                                        { format!(" requires feature {}", fndetail.features.iter().map(|f| format!("{:?}", f)).collect::<Vec<_>>().join(" or ")) }
+                                    </small>
+                                }) }
                             </div>
                         }
                     })
                 }
+                { ifnode(self.next_cursor.is_some(), || html!{
+                    <button onclick=cb!(|_| ReevesMsg::MoreResultsRequest)>{ "More results" }</button>
+                }) }
             </div>
         </> }
     }
@@ -0,0 +1,358 @@
+// The parts of searching that don't need the DB or the text search - normalizing and tokenizing
+// queries, matching candidates, and intersecting the fns of each part of the query - shared between
+// the server and searches in the browser over a `CompactIndex`. The crate isn't `no_std` (it builds
+// for wasm32 with `std`), but this module keeps to `core` and `alloc` apart from implementing
+// `FnIds` for the server's `HashSet`s, so it'd take little to make it so
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::vec;
+use core::cmp;
+use core::hash::BuildHasher;
+use serde::{Serialize, Deserialize};
+
+use crate::TypeRepr;
+use crate::repr::{self, Tok};
+
+// Parts of a signature that aren't a type - taking no params, and returning nothing (`()`). They're
+// indexed apart from the types, so they're never fuzzy matched or listed as types, and only ever
+// match exactly
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SigMarker {
+    NoParams,
+    NoRet,
+}
+
+impl SigMarker {
+    pub const ALL: &'static [SigMarker] = &[SigMarker::NoParams, SigMarker::NoRet];
+
+    // As stored
+    pub fn key(self) -> &'static str {
+        match self {
+            SigMarker::NoParams => "no-params",
+            SigMarker::NoRet => "no-ret",
+        }
+    }
+
+    // What the marker stands for, in explanations
+    pub fn describe(self) -> &'static str {
+        match self {
+            SigMarker::NoParams => "no params",
+            SigMarker::NoRet => "no ret",
+        }
+    }
+}
+
+// So that each part of a type can be matched individually by the text search - path segments,
+// generic args, tuple fields and fn pointer params each become a word, as do the brackets around
+// them. Separators, lifetimes and ABIs have nothing to match on, so are dropped, e.g.
+// `std::collections::HashMap<K,V>` is `std collections HashMap < K V >`. Types are tokenized the
+// same way when loading the text search and when querying it
+pub fn tokenize_type(s: &str) -> String {
+    let words: Vec<&str> = repr::tokenize(s).into_iter()
+        .filter_map(|tok| match tok {
+            Tok::Ident(word) => Some(word),
+            Tok::Punct("::") | Tok::Punct(",") | Tok::Punct(";") => None,
+            Tok::Punct(punct) => Some(punct),
+            Tok::Lifetime | Tok::Literal => None,
+        })
+        .collect();
+    words.join(" ")
+}
+
+// So that each segment and word of a path can be matched individually
+pub fn tokenize_name(s: &str) -> String {
+    s.replace("::", " ").replace('_', " ")
+}
+
+// The query as given and in the form types are indexed in (e.g. `HashMap<K,V>` is indexed as
+// `HashMap<K, V>`), for finding exact matches
+pub fn exact_forms(query: &str) -> Vec<String> {
+    let mut forms = vec![query.trim().to_owned()];
+    let normalized = TypeRepr::parse(query).to_string();
+    if !forms.contains(&normalized) {
+        forms.push(normalized)
+    }
+    forms
+}
+
+// Whether every word of the query is within the number of edits of some word of the candidate, or
+// starts it (as the text search matches prefixes). Case insensitive
+pub fn within_typos(query: &str, candidate: &str, max_typos: usize) -> bool {
+    fn words(s: &str) -> Vec<Vec<char>> {
+        s.split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_lowercase().chars().collect())
+            .collect()
+    }
+    let candidate_words = words(candidate);
+    words(query).iter().all(|query_word| {
+        candidate_words.iter().any(|word| word.starts_with(query_word) || edit_distance(query_word, word) <= max_typos)
+    })
+}
+
+// Indexed types close to a query type, to suggest when it finds nothing - compared by their letters
+// and digits alone, ignoring case, so `Hashmap` suggests `HashMap` and `Vecu8` suggests `Vec<u8>`.
+// Closest first, then shortest. Longer types may be further off (a third of their letters)
+pub fn closest_types<'a>(query: &str, candidates: impl Iterator<Item = &'a str>, limit: usize) -> Vec<String> {
+    fn squash(s: &str) -> Vec<char> {
+        s.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+    }
+    let query_chars = squash(query);
+    if query_chars.is_empty() {
+        return vec![]
+    }
+    let max_distance = cmp::max(1, query_chars.len() / 3);
+    let mut closest: Vec<(usize, String)> = vec![];
+    for candidate in candidates {
+        if candidate == query.trim() {
+            continue
+        }
+        let candidate_chars = squash(candidate);
+        // Can't be close enough, without working out how far
+        if cmp::max(candidate_chars.len(), query_chars.len()) - cmp::min(candidate_chars.len(), query_chars.len()) > max_distance {
+            continue
+        }
+        let distance = edit_distance(&query_chars, &candidate_chars);
+        if distance <= max_distance {
+            closest.push((distance, candidate.to_owned()))
+        }
+    }
+    closest.sort_by(|(d1, c1), (d2, c2)| d1.cmp(d2).then(c1.len().cmp(&c2.len())).then_with(|| c1.cmp(c2)));
+    closest.dedup_by(|(_, c1), (_, c2)| c1 == c2);
+    closest.into_iter().take(limit).map(|(_, candidate)| candidate).collect()
+}
+
+// Levenshtein distance
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = cmp::min(substitution, cmp::min(prev[j + 1], cur[j]) + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// A set of fn ids, however the caller stores them
+pub trait FnIds {
+    fn has(&self, fn_id: u64) -> bool;
+    fn count(&self) -> usize;
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item = u64> + 'a>;
+}
+
+impl FnIds for BTreeSet<u64> {
+    fn has(&self, fn_id: u64) -> bool { self.contains(&fn_id) }
+    fn count(&self) -> usize { self.len() }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item = u64> + 'a> { Box::new(self.iter().cloned()) }
+}
+
+impl<S: BuildHasher> FnIds for std::collections::HashSet<u64, S> {
+    fn has(&self, fn_id: u64) -> bool { self.contains(&fn_id) }
+    fn count(&self) -> usize { self.len() }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item = u64> + 'a> { Box::new(self.iter().cloned()) }
+}
+
+// Each column is the union of the fns of the candidates considered so far for one part of the query,
+// and only grows between passes - so any fn in every column now but not on the previous pass must be
+// in some column's delta (what it gained this pass). That means only the deltas need checking against
+// the other columns, rather than intersecting everything again. Returns the unseen fns now in every
+// column, in id order
+pub fn matching_fn_ids<S: FnIds>(columns: &[&S], deltas: &[S], seen_fn_ids: &S) -> Vec<u64> {
+    // Check the most restrictive (i.e. smallest) columns first so non-matches are rejected early
+    let mut by_selectivity: Vec<usize> = (0..columns.len()).collect();
+    by_selectivity.sort_by_key(|&c| columns[c].count());
+
+    let mut new_fn_ids = BTreeSet::new();
+    for (c, delta) in deltas.iter().enumerate() {
+        for fn_id in delta.ids() {
+            if seen_fn_ids.has(fn_id) || new_fn_ids.contains(&fn_id) {
+                continue
+            }
+            if by_selectivity.iter().all(|&other| other == c || columns[other].has(fn_id)) {
+                new_fn_ids.insert(fn_id);
+            }
+        }
+    }
+    new_fn_ids.into_iter().collect()
+}
+
+// Results found on the same pass are ordered by this, so the order is stable for a given index
+pub fn result_order<'a>(krate: &'a str, s: &'a str, version: &'a str, fn_id: u64) -> (&'a str, &'a str, &'a str, u64) {
+    (krate, s, version, fn_id)
+}
+
+// Bumped whenever the format changes, so stale snapshots are rejected rather than misread
+pub const COMPACT_INDEX_VERSION: u32 = 2;
+
+// A read-only snapshot of the index for searching without a server, e.g. fetched by a browser. Fns
+// are identified by their position in `fns`, and the keys of each map are sorted. There's no text
+// search, so candidates are keys that match the query closely enough (see `key_candidates`)
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct CompactIndex {
+    pub version: u32,
+    pub crates: Vec<(String, String)>, // (name, version)
+    pub fns: Vec<CompactFn>,
+    pub params: Vec<(String, Vec<u32>)>, // param type => fns
+    pub rets: Vec<(String, Vec<u32>)>, // ret type => fns
+    pub markers: Vec<(SigMarker, Vec<u32>)>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct CompactFn {
+    pub krate: u32, // index into `crates`
+    pub s: String,
+    pub doc: Option<String>,
+}
+
+// Typos allowed in each word of a candidate, without the text search's ranking to fall back on
+const COMPACT_MAX_TYPOS: usize = 1;
+
+impl CompactIndex {
+    pub fn new(crates: Vec<(String, String)>, fns: Vec<CompactFn>, mut params: Vec<(String, Vec<u32>)>, mut rets: Vec<(String, Vec<u32>)>, markers: Vec<(SigMarker, Vec<u32>)>) -> Self {
+        params.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        rets.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        Self { version: COMPACT_INDEX_VERSION, crates, fns, params, rets, markers }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let index: CompactIndex = bincode::deserialize(bytes).map_err(|e| e.to_string())?;
+        if index.version != COMPACT_INDEX_VERSION {
+            return Err(alloc::format!("index is version {}, expected {}", index.version, COMPACT_INDEX_VERSION))
+        }
+        Ok(index)
+    }
+
+    // As for a search on the server (with `parse_query`'s output), minus filters - results from
+    // earlier passes come first, each pass ordered by `result_order`
+    pub fn search(&self, params_search: Option<Vec<String>>, ret_search: Option<String>, candidate_limit: usize, max_results: usize) -> Vec<&CompactFn> {
+        let mut columns: Vec<Vec<&[u32]>> = vec![]; // for each part of the query, its candidates' fns
+        if let Some(ret_search) = ret_search {
+            if TypeRepr::parse(&ret_search).is_unit() {
+                columns.push(self.marker_candidates(SigMarker::NoRet))
+            } else {
+                columns.push(key_candidates(&self.rets, &ret_search, candidate_limit))
+            }
+        }
+        if let Some(params_search) = params_search {
+            if params_search.is_empty() {
+                columns.push(self.marker_candidates(SigMarker::NoParams))
+            }
+            for param in params_search {
+                columns.push(key_candidates(&self.params, &param, candidate_limit))
+            }
+        }
+
+        let max_depth = columns.iter().map(|candidates| candidates.len()).max().unwrap_or(0);
+        let mut column_fn_ids: Vec<BTreeSet<u64>> = vec![BTreeSet::new(); columns.len()];
+        let mut seen_fn_ids = BTreeSet::new();
+        let mut results = vec![];
+        for i in 0..max_depth {
+            let deltas: Vec<BTreeSet<u64>> = columns.iter().zip(column_fn_ids.iter_mut())
+                .map(|(candidates, fn_ids)| match candidates.get(i) {
+                    Some(candidate_fns) => candidate_fns.iter().map(|&f| f as u64).filter(|&f| fn_ids.insert(f)).collect(),
+                    None => BTreeSet::new(),
+                })
+                .collect();
+            let column_refs: Vec<&BTreeSet<u64>> = column_fn_ids.iter().collect();
+            let mut batch = matching_fn_ids(&column_refs, &deltas, &seen_fn_ids);
+            seen_fn_ids.extend(batch.iter().cloned());
+            batch.sort_by(|&f1, &f2| self.result_order(f1).cmp(&self.result_order(f2)));
+            results.extend(batch.into_iter().map(|fn_id| &self.fns[fn_id as usize]));
+            if results.len() >= max_results {
+                results.truncate(max_results);
+                break
+            }
+        }
+        results
+    }
+
+    // Markers only match exactly, so there's one candidate at most
+    fn marker_candidates(&self, marker: SigMarker) -> Vec<&[u32]> {
+        self.markers.iter().filter(|(m, _)| *m == marker).map(|(_, fns)| &fns[..]).collect()
+    }
+
+    fn result_order(&self, fn_id: u64) -> (&str, &str, &str, u64) {
+        let f = &self.fns[fn_id as usize];
+        let (name, version) = &self.crates[f.krate as usize];
+        result_order(name, &f.s, version, fn_id)
+    }
+}
+
+// The fns of the keys matching the query, closest first - exact matches, then keys containing the
+// query, then those within typos of it, each shortest first
+fn key_candidates<'a>(keys: &'a [(String, Vec<u32>)], query: &str, limit: usize) -> Vec<&'a [u32]> {
+    let exact = exact_forms(query);
+    let query = query.trim();
+    let mut matches: Vec<(usize, usize, &'a str, &'a [u32])> = keys.iter()
+        .filter_map(|(key, fns)| {
+            let closeness = if exact.contains(key) {
+                0
+            } else if key.contains(query) {
+                1
+            } else if within_typos(query, key, COMPACT_MAX_TYPOS) {
+                2
+            } else {
+                return None
+            };
+            Some((closeness, key.len(), key.as_str(), fns.as_slice()))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().take(limit).map(|(_, _, _, fns)| fns).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_types() {
+        assert_eq!(tokenize_type("std::collections::HashMap<K,V>"), "std collections HashMap < K V >");
+        assert_eq!(tokenize_type("&'a [u8; 32]"), "& [ u8 32 ]");
+        assert_eq!(tokenize_type("extern \"C\" fn(u8) -> bool"), "extern fn ( u8 ) -> bool");
+        assert_eq!(tokenize_name("serde_json::from_str"), "serde json from str");
+    }
+
+    #[test]
+    fn edit_distances() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("abc")), 3);
+        assert_eq!(edit_distance(&chars("abc"), &chars("abc")), 0);
+    }
+
+    #[test]
+    fn typos() {
+        assert!(within_typos("hashmpa", "HashMap<K, V>", 2));
+        assert!(!within_typos("hashmpa", "HashMap<K, V>", 1));
+        // Prefixes match, whatever the case
+        assert!(within_typos("Hash", "hashmap", 0));
+        assert!(within_typos("vec u8", "Vec<u8>", 0));
+        assert!(!within_typos("vec string", "Vec<u8>", 1));
+    }
+
+    #[test]
+    fn closest() {
+        let candidates = ["HashMap<K, V>", "HashSet<K>", "Hashmap<K, V>", "BTreeMap<K, V>"];
+        assert_eq!(closest_types("Hashmap<K, V>", candidates.iter().cloned(), 5), vec!["HashMap<K, V>"]);
+        assert_eq!(closest_types("Vecu8", ["Vec<u8>", "Vec<u16>"].iter().cloned(), 5), vec!["Vec<u8>"]);
+        // Closest, then shortest
+        let candidates = ["String", "Strings", "Sting", "str"];
+        assert_eq!(closest_types("Strng", candidates.iter().cloned(), 5), vec!["Sting", "String"]);
+        assert_eq!(closest_types("Strng", candidates.iter().cloned(), 1), vec!["Sting"]);
+        assert!(closest_types("<>", candidates.iter().cloned(), 5).is_empty());
+    }
+}
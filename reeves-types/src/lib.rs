@@ -1,24 +1,730 @@
+extern crate alloc;
+
 use serde::{Serialize, Deserialize};
+use std::fmt;
+
+pub mod engine;
+mod query;
+mod render;
+mod repr;
+pub use query::Query;
+pub use render::{RenderOptions, SignatureStyle, generics_str, render_signature, use_line};
+pub use repr::TypeRepr;
 
 #[derive(Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FnDetail {
     pub krate: String,
+    pub version: String,
+    pub kind: ItemKind,
+    pub visibility: ItemVisibility,
+    pub path: String, // shortest public path
+    pub aliases: Vec<String>, // other public paths (re-exports)
+    // What to `use` to call it, within the crate like `path` - e.g. `Regex` for `Regex::new`, or the
+    // trait for a trait method, as the trait must be in scope
+    pub import_path: String,
     pub params: Vec<String>,
     pub ret: String,
+    // Structured versions of the above
+    pub param_reprs: Vec<TypeRepr>,
+    pub ret_repr: TypeRepr,
+    // As written in the source if different, e.g. with type aliases like `io::Result<()>` that are
+    // expanded in `params` and `ret`
+    pub written_params: Option<Vec<String>>,
+    pub written_ret: Option<String>,
+    pub ret_impls: Vec<String>, // traits implemented by the ret type, e.g. `Iterator<Item = u8>`
+    pub generics: Vec<GenericParamDetail>,
+    pub is_const: bool,
+    pub is_async: bool,
+    pub is_unsafe: bool,
+    pub features: Vec<String>, // enabling any of these (non-default) features makes this available
+    // The target and features it was analyzed for, beyond `features` - default for the host target
+    // with default features
+    pub profile: AnalysisProfile,
+    pub doc: Option<String>, // first line of the doc comment
+    pub location: Option<SourceLocation>, // None if defined outside the crate, e.g. a re-export
+    // For methods only reachable through `Deref`, the type they're defined on - e.g. `[T]` for
+    // `Vec::len`, where the receiver is given as `&Vec<T, A>`
+    pub deref_from: Option<String>,
+    // From `#[deprecated]`, with its note (empty if it has none)
+    pub deprecated: Option<String>,
+    // Nightly-only (`#[unstable]`, as in std), with the feature that enables it
+    pub unstable: Option<String>,
     pub s: String,
 }
 
+impl FnDetail {
+    // Whether a param or the ret has a raw pointer anywhere in it, e.g. `*mut c_void` or
+    // `Option<*const u8>` - usually a sign of FFI
+    pub fn has_raw_pointers(&self) -> bool {
+        fn has_ptr(ty: &TypeRepr) -> bool {
+            matches!(ty, TypeRepr::Ptr { .. }) || ty.children().into_iter().any(has_ptr)
+        }
+        self.param_reprs.iter().chain(Some(&self.ret_repr)).any(has_ptr)
+    }
+
+    // Indexed by these rather than as types, see `engine::SigMarker`
+    pub fn markers(&self) -> Vec<engine::SigMarker> {
+        let mut markers = vec![];
+        if self.params.is_empty() { markers.push(engine::SigMarker::NoParams) }
+        if self.ret_repr.is_unit() { markers.push(engine::SigMarker::NoRet) }
+        markers
+    }
+}
+
+// What a crate is analyzed for besides its selected features, for crates whose API differs by target -
+// e.g. `no_std` crates for embedded targets, or crates with `windows`-only APIs
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AnalysisProfile {
+    pub target: Option<String>, // a target triple, e.g. `thumbv7em-none-eabihf`, None for the host's
+    pub no_default_features: bool,
+}
+
+impl AnalysisProfile {
+    pub fn is_default(&self) -> bool {
+        *self == AnalysisProfile::default()
+    }
+}
+
+// e.g. `thumbv7em-none-eabihf, no default features`
+impl fmt::Display for AnalysisProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.target.as_deref().unwrap_or("host"))?;
+        if self.no_default_features {
+            f.write_str(", no default features")?
+        }
+        Ok(())
+    }
+}
+
+// Where an item is defined within its crate, e.g. for jumping to it from an editor
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String, // relative to the crate root (where its Cargo.toml is)
+    // Zero-based, with the character in UTF-16 code units, as in LSP
+    pub line: u32,
+    pub character: u32,
+}
+
+// A step in getting from a value of one type to another, see `search_compositions`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone)]
+pub enum CompositionStep {
+    // Call the fn, passing the value so far as one of its params
+    Call(Box<FnDetail>),
+    // Take the success value out of an `Option` or `Result`, e.g. with `?`
+    Unwrap { from: String, to: String },
+}
+
+// Where a type comes from and where it goes, see `type_info`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone)]
+pub struct TypeInfo {
+    pub name: String, // the last segment of the path, which is what's matched
+    // Crates with constructors or methods of the type, i.e. (almost always) the crate defining it
+    pub defined_in: Vec<String>,
+    // Fns with the type anywhere in their ret (e.g. `Option<Captures>`), and anywhere in their params
+    pub num_producers: usize,
+    pub num_consumers: usize,
+    // The first few of each, those from the defining crates first
+    pub producers: Vec<FnDetail>,
+    pub consumers: Vec<FnDetail>,
+}
+
+// A trait implemented by a type, as found analyzing a crate
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeImpl {
+    pub ty: String,
+    pub trait_: String, // including any assoc types, e.g. `Iterator<Item = u8>`
+    pub krate: String,
+    pub version: String,
+}
+
+// Indexed types like a type of a query that found nothing, e.g. `HashMap<K, V>` for `Hashmap<K, V>`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeSuggestion {
+    pub ty: String, // as in the query
+    pub suggestions: Vec<String>, // closest first
+}
+
+// A conversion from one type to another, through an impl of `From`, `TryFrom`, `Into` or `AsRef`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub from: String,
+    pub to: String,
+    pub trait_: String, // the trait's name, e.g. `From`
+    pub krate: String, // of the impl's type
+    pub version: String,
+}
+
+// A crate version added to or purged from the index, see `journal`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub id: u64, // increasing, in the order changes were made
+    pub op: JournalOp,
+    pub krate: String,
+    pub version: String,
+    pub num_fns: usize, // added or purged
+    pub timestamp: u64, // unix timestamp secs
+    // Of reeves making the change, and the DB schema it wrote
+    pub tool_version: String,
+    pub schema_version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalOp {
+    Add,
+    Purge,
+}
+
+// A step in how an index canonicalizes param and ret types, on top of dropping lifetimes (which every
+// index does), so its searches ignore more of the detail of signatures. See `normalize_type`
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizationPass {
+    // e.g. `&str` as `str` and `Option<&mut T>` as `Option<T>`
+    EraseRefs,
+    // `Option<T>` and `Result<T, E>` as `T`
+    UnwrapWrappers,
+}
+
+impl NormalizationPass {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "erase-refs" => Some(NormalizationPass::EraseRefs),
+            "unwrap-wrappers" => Some(NormalizationPass::UnwrapWrappers),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NormalizationPass::EraseRefs => "erase-refs",
+            NormalizationPass::UnwrapWrappers => "unwrap-wrappers",
+        }
+    }
+}
+
+// The type as an index with these passes stores it, applying each in turn
+pub fn normalize_type(ty: &str, passes: &[NormalizationPass]) -> String {
+    if passes.is_empty() {
+        return ty.to_owned()
+    }
+    let mut repr = TypeRepr::parse(ty);
+    for pass in passes {
+        repr = match pass {
+            NormalizationPass::EraseRefs => repr.erase_refs(),
+            NormalizationPass::UnwrapWrappers => repr.unwrapped().clone(),
+        }
+    }
+    repr.to_string()
+}
+
+// About a crate as a whole, captured from its manifest when it's analyzed
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateMeta {
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    pub docsrs_url: String,
+    pub indexed_at: u64, // unix timestamp secs
+    // As listed on crates.io, e.g. `parsing` and `json`
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+impl CrateMeta {
+    // What searches can be narrowed to, and results counted by, see `category_facet`
+    pub fn facets(&self) -> Vec<String> {
+        self.categories.iter().map(|category| category_facet(category))
+            .chain(self.keywords.iter().map(|keyword| keyword_facet(keyword)))
+            .collect()
+    }
+}
+
+// Facets are categories and keywords, prefixed to tell them apart (a crate may have a keyword that's
+// also a category name)
+pub fn category_facet(category: &str) -> String {
+    format!("category:{}", category)
+}
+
+pub fn keyword_facet(keyword: &str) -> String {
+    format!("keyword:{}", keyword)
+}
+
+// How many results of a search are from crates with a facet
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FacetCount {
+    pub facet: String, // e.g. `category:parsing`
+    pub count: usize,
+}
+
+// Not everything that produces a value is a fn
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemKind {
+    Fn,
+    Constructor, // struct or enum variant
+    Const,
+    Static,
+}
+
+// Only public items are indexed, unless analyzing a local project
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemVisibility {
+    Public,
+    Crate, // e.g. `pub(crate)`, `pub(super)`
+    Private,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenericParamDetail {
+    pub name: String,
+    pub bounds: Vec<String>, // trait names, with the signature for `Fn` traits (e.g. `FnMut(&str) -> bool`)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    // None means don't care
+    pub is_const: Option<bool>,
+    pub is_async: Option<bool>,
+    pub is_unsafe: Option<bool>,
+    // Fns with raw pointers in their params or ret, see `FnDetail::has_raw_pointers`
+    pub raw_pointers: Option<bool>,
+    // Inclusive bounds on the number of params
+    pub min_params: Option<usize>,
+    pub max_params: Option<usize>,
+    pub versions: VersionFilter,
+    // Only search within these crates, each optionally with a semver requirement on its versions
+    // (see `crate_spec_matches`), e.g. the dependencies of a project
+    pub crates: Option<Vec<String>>,
+    pub exclude_crates: Vec<String>,
+    // Glob patterns (`*` and `?`), e.g. "*Raw*"
+    pub exclude_param_types: Vec<String>,
+    pub exclude_ret_types: Vec<String>,
+    // Not a filter as such - match types regardless of references, e.g. `&String` finds `&str`
+    pub ignore_refs: bool,
+    // Also not a filter - how far fuzzy matches of each part of the query may stray from it, as the
+    // most edits allowed to each of its words. None leaves it to the text search. Exact matches
+    // always come first regardless
+    pub max_typos: Option<usize>,
+    // How many fuzzy candidates to consider for each part of the query, and how many results to find
+    // in total. None uses the server defaults, and both are capped by the server
+    pub candidate_limit: Option<usize>,
+    pub max_results: Option<usize>,
+    // Keep considering more candidates while the search finds too few results, e.g. for very generic
+    // types, where the closest candidates may have nothing in common
+    pub adaptive_candidates: bool,
+    // Deprecated items are left out unless asked for, as there'll usually be a replacement
+    pub include_deprecated: bool,
+    // Not a filter either - don't also search for types with configured synonyms swapped in, e.g.
+    // `String` for `str`
+    pub ignore_synonyms: bool,
+    // Nor this - don't also search for fns taking what a param type converts to (see `Conversion`),
+    // e.g. `String` for `&str`
+    pub ignore_conversions: bool,
+    // Not filters either - keep a crate with many matches (e.g. `windows`) from taking up all the
+    // results, by returning at most this many from any one crate, and/or by putting each crate's
+    // first few results before its later ones
+    pub max_per_crate: Option<usize>,
+    pub prefer_distinct_crates: bool,
+    // Only search crates in all of these crates.io categories, and with all of these keywords
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone)]
+pub enum VersionFilter {
+    Any,
+    // Only the newest indexed version of each crate
+    Latest,
+    // A semver requirement, e.g. ">=1.0.100, <2" - parsed when the filters are, so an invalid one is
+    // rejected with the request rather than quietly matching nothing
+    Req(semver::VersionReq),
+}
+
+impl Default for VersionFilter {
+    fn default() -> Self {
+        VersionFilter::Any
+    }
+}
+
+// The parts of an item that `SearchFilters` look at, so they can be checked without the rest of it
+pub struct FilterFields<'a> {
+    pub krate: &'a str,
+    pub version: &'a str,
+    pub params: &'a [String],
+    pub ret: &'a str,
+    pub is_const: bool,
+    pub is_async: bool,
+    pub is_unsafe: bool,
+    pub deprecated: bool,
+}
+
+impl SearchFilters {
+    // Raw pointers aren't among the `FilterFields`, as searches rule fns in or out by them up front
+    pub fn matches(&self, fndetail: &FnDetail) -> bool {
+        let raw_pointers_ok = self.raw_pointers.map_or(true, |f| f == fndetail.has_raw_pointers());
+        raw_pointers_ok && self.matches_fields(&FilterFields {
+            krate: &fndetail.krate,
+            version: &fndetail.version,
+            params: &fndetail.params,
+            ret: &fndetail.ret,
+            is_const: fndetail.is_const,
+            is_async: fndetail.is_async,
+            is_unsafe: fndetail.is_unsafe,
+            deprecated: fndetail.deprecated.is_some(),
+        })
+    }
+
+    pub fn matches_fields(&self, fields: &FilterFields) -> bool {
+        fn check(filter: Option<bool>, val: bool) -> bool {
+            filter.map_or(true, |f| f == val)
+        }
+        let version_ok = match &self.versions {
+            VersionFilter::Req(req) => semver::Version::parse(fields.version).map_or(false, |version| req.matches(&version)),
+            // Latest needs knowledge of the index, so is checked during search (as are facets)
+            VersionFilter::Any |
+            VersionFilter::Latest => true,
+        };
+        check(self.is_const, fields.is_const) &&
+            check(self.is_async, fields.is_async) &&
+            check(self.is_unsafe, fields.is_unsafe) &&
+            self.matches_arity(fields.params.len()) &&
+            version_ok &&
+            (self.include_deprecated || !fields.deprecated) &&
+            self.crates.as_ref().map_or(true, |crates| crates.iter().any(|spec| crate_spec_matches(spec, fields.krate, fields.version))) &&
+            !self.exclude_crates.iter().any(|spec| crate_spec_matches(spec, fields.krate, fields.version)) &&
+            !fields.params.iter().any(|p| self.excludes_param_type(p)) &&
+            !self.excludes_ret_type(fields.ret)
+    }
+
+    pub fn matches_arity(&self, arity: usize) -> bool {
+        self.min_params.map_or(true, |min| arity >= min) && self.max_params.map_or(true, |max| arity <= max)
+    }
+
+    pub fn excludes_param_type(&self, ty: &str) -> bool {
+        self.exclude_param_types.iter().any(|pat| glob_match(pat, ty))
+    }
+
+    pub fn excludes_ret_type(&self, ty: &str) -> bool {
+        self.exclude_ret_types.iter().any(|pat| glob_match(pat, ty))
+    }
+}
+
+// A crate name, optionally followed by `@` and a semver requirement its versions must meet, e.g.
+// `tokio@^1` or `serde@>=1.0.150`
+pub fn split_crate_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('@') {
+        Some((name, req)) => (name.trim(), Some(req.trim())),
+        None => (spec.trim(), None),
+    }
+}
+
+// An invalid requirement matches nothing, which searches warn of when checking the specs up front
+pub fn crate_spec_matches(spec: &str, krate: &str, version: &str) -> bool {
+    let (name, req) = split_crate_spec(spec);
+    name == krate && req.map_or(true, |req| {
+        match (semver::VersionReq::parse(req), semver::Version::parse(version)) {
+            (Ok(req), Ok(version)) => req.matches(&version),
+            _ => false,
+        }
+    })
+}
+
+// Which page of results to return
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub offset: usize,
+    pub limit: Option<usize>, // capped by the server, which also uses its maximum if unset
+    pub cursor: Option<String>, // `next_cursor` from the previous page, takes precedence over `offset`
+    pub explain: bool, // return an `Explanation` of each result, which means the search isn't cached
+    pub group_by: Option<GroupBy>, // also return the results of the page grouped, see `group_results`
+    pub ordered: bool, // params must match in the order given, rather than in any order
+    pub facets: bool, // also count all the results (not just the page) by the facets of their crates
+}
+
+// Why a search result matched and where it ranks. Results are ordered by the pass they were found
+// in, then exact matches before relaxed ones, then by crate and signature
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub pass: usize, // on pass N, the N closest candidates for each part of the query are considered
+    pub relaxed: Option<String>, // the kind of relaxed match used in place of an exact one, if any
+    pub terms: Vec<TermMatch>,
+    pub score: Score,
+}
+
+// What a result's rank comes from, compared in this order (lowest first) before crate and signature
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Score {
+    pub pass: usize,
+    pub relaxed: usize, // 0 for an exact match, otherwise 1 + the relaxation's place in the order they're tried
+    pub crate_rank: usize, // results of the same crate ranked before it, with `prefer_distinct_crates`
+}
+
+// How one part of the query matched
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermMatch {
+    pub kind: String, // e.g. "param", "ret", "unwrapped ret"
+    pub term: String, // as given in the query
+    pub candidate: String, // the indexed type (or name, or doc) it matched
+    pub depth: usize, // of the candidate in the fuzzy candidates for the term, 1 being the closest
+}
+
+// What to group results by, as a long flat list of results is hard to scan
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    // The type an item belongs to (e.g. all `HashMap` methods together), or its module if it's a free fn
+    Type,
+    Module,
+}
+
+// Results sharing a type or module, by their position in the list of results
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultGroup {
+    pub key: String, // path of the type or module
+    pub count: usize,
+    pub indices: Vec<usize>, // in result order
+}
+
+// Groups are ordered by their first result, so the best matches still come first
+pub fn group_results(fndetails: &[FnDetail], by: GroupBy) -> Vec<ResultGroup> {
+    let mut groups: Vec<ResultGroup> = vec![];
+    for (i, fndetail) in fndetails.iter().enumerate() {
+        let key = group_key(&fndetail.path, by);
+        match groups.iter_mut().find(|group| group.key == key) {
+            Some(group) => {
+                group.count += 1;
+                group.indices.push(i)
+            },
+            None => groups.push(ResultGroup { key: key.to_owned(), count: 1, indices: vec![i] }),
+        }
+    }
+    groups
+}
+
+// Items in a type (methods, assoc consts, variants) have its path as their parent. Paths only say
+// which segments are types by convention - they're capitalized, where modules aren't
+fn group_key(path: &str, by: GroupBy) -> &str {
+    fn parent(path: &str) -> Option<&str> {
+        path.rfind("::").map(|i| &path[..i])
+    }
+    let mut key = parent(path).unwrap_or(path);
+    if by == GroupBy::Module {
+        // Out of any types the item is in
+        while let Some(module) = parent(key) {
+            if !key[module.len() + 2..].starts_with(|c: char| c.is_uppercase()) {
+                break
+            }
+            key = module
+        }
+    }
+    key
+}
+
+// Split on a separator, ignoring any inside brackets - e.g. so `HashMap<K, V>, u8` is two types
+pub fn split_top_level(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth: i32 = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            // Don't count the `>` of an `->`
+            '>' if !s[..i].ends_with('-') => depth -= 1,
+            ')' | ']' => depth -= 1,
+            c if c == sep && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            },
+            _ => (),
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+// Parse a query of the form `<params> -> <ret>`, where params are comma separated. A `*` (or
+// omitting `-> <ret>`) means "any", an empty params list means "no params". Params may be wrapped
+// in parens, like a fn signature, which means exactly those params (see `parse_query_arity`)
+pub fn parse_query(query: &str) -> (Option<Vec<String>>, Option<String>) {
+    let (params, ret) = split_query(query);
+    let params = strip_parens(params).unwrap_or(params);
+    let params = match params.trim() {
+        "*" => None,
+        "" => Some(vec![]),
+        params => Some(split_top_level(params, ',').into_iter()
+            .map(|p| p.trim().to_owned())
+            .filter(|p| !p.is_empty())
+            .collect()),
+    };
+    let ret = ret.map(str::trim).and_then(|ret| match ret {
+        "" | "*" => None,
+        ret => Some(ret.to_owned()),
+    });
+    (params, ret)
+}
+
+// If the params of a query are wrapped in parens (e.g. `(&str) -> u32`), fns must have exactly
+// that many params
+pub fn parse_query_arity(query: &str) -> Option<usize> {
+    let (params, _ret) = split_query(query);
+    let params = strip_parens(params)?;
+    Some(split_top_level(params, ',').into_iter().filter(|p| !p.trim().is_empty()).count())
+}
+
+fn split_query(query: &str) -> (&str, Option<&str>) {
+    match find_top_level_arrow(query) {
+        Some(i) => (&query[..i], Some(&query[i+2..])),
+        None => (query, None),
+    }
+}
+
+// The contents of `(...)`, if the parens wrap the whole string
+fn strip_parens(s: &str) -> Option<&str> {
+    let inner = s.trim().strip_prefix('(')?.strip_suffix(')')?;
+    // Rule out e.g. `(A), (B)`, where the first paren closes early
+    let mut depth: i32 = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => (),
+        }
+        if depth < 0 { return None }
+    }
+    Some(inner)
+}
+
+// The last arrow wins, as fn pointer and closure params (`impl Fn(u8) -> bool`) are more common
+// than returning them
+fn find_top_level_arrow(s: &str) -> Option<usize> {
+    let mut depth: i32 = 0;
+    let mut found = None;
+    let bytes = s.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'<' | b'(' | b'[' => depth += 1,
+            b'>' if i > 0 && bytes[i-1] == b'-' => (),
+            b'>' | b')' | b']' => depth -= 1,
+            b'-' if depth == 0 && bytes.get(i+1) == Some(&b'>') => found = Some(i),
+            _ => (),
+        }
+    }
+    found
+}
+
+// Match a string against a pattern where `*` matches any run of chars and `?` matches any one char
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    // Where to resume from if we need to let the last `*` eat another char
+    let mut backtrack: Option<(usize, usize)> = None;
+    while si < s.len() {
+        match pattern.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi, si));
+                pi += 1;
+            },
+            Some(&c) if c == '?' || c == s[si] => {
+                pi += 1;
+                si += 1;
+            },
+            _ => match backtrack {
+                Some((bpi, bsi)) => {
+                    backtrack = Some((bpi, bsi + 1));
+                    pi = bpi + 1;
+                    si = bsi + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[pi..].iter().all(|&c| c == '*')
+}
+
 pub mod proto {
     use super::*;
 
     #[derive(Serialize, Deserialize)]
     #[serde(deny_unknown_fields)]
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct SearchRequest {
-        pub params: Option<Vec<String>>,
-        pub ret: Option<String>,
+        pub query: Query,
+        pub options: SearchOptions,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[derive(Debug, Clone)]
+    pub struct TypeInfoRequest {
+        pub ty: String, // e.g. `regex::Captures`
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[derive(Debug, Clone)]
+    pub struct JournalRequest {
+        pub krate: Option<String>, // None for all crates
+        pub after: Option<u64>, // entry id, e.g. the last one seen
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[derive(Debug, Clone)]
+    pub struct SearchApplicableRequest {
+        pub types: Vec<String>, // types of values in scope
+        pub filters: SearchFilters,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[derive(Debug, Clone)]
+    pub struct SearchCompositionsRequest {
+        pub from: String,
+        pub to: String,
+        pub max_steps: usize, // capped by the server
+        pub filters: SearchFilters,
     }
 
     #[derive(Serialize, Deserialize)]
@@ -26,5 +732,53 @@ pub mod proto {
     #[derive(Debug)]
     pub struct SearchResult {
         pub fndetails: Vec<FnDetail>,
+        pub crates: Vec<CrateMeta>, // for the crates of the fndetails
+        pub explanations: Option<Vec<Option<Explanation>>>, // one per fndetail if requested, None if the index changed since
+        pub groups: Option<Vec<ResultGroup>>, // of the fndetails, if requested
+        pub facets: Option<Vec<FacetCount>>, // of all the results, if requested
+        pub next_cursor: Option<String>,
+        pub warnings: Vec<String>, // e.g. if results are degraded
+        pub suggestions: Vec<TypeSuggestion>, // if there are no results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(strs: &[&str]) -> Option<Vec<String>> {
+        Some(strs.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert_eq!(parse_query("&str, usize -> Option<char>"), (strs(&["&str", "usize"]), Some("Option<char>".to_owned())));
+        assert_eq!(parse_query("HashMap<K, V>"), (strs(&["HashMap<K, V>"]), None));
+        assert_eq!(parse_query("* -> u8"), (None, Some("u8".to_owned())));
+        assert_eq!(parse_query("-> u8"), (strs(&[]), Some("u8".to_owned())));
+        assert_eq!(parse_query("&str -> *"), (strs(&["&str"]), None));
+        assert_eq!(parse_query("(F) -> Box<dyn Fn(u8) -> bool>"), (strs(&["F"]), Some("Box<dyn Fn(u8) -> bool>".to_owned())));
+        assert_eq!(parse_query("impl Fn(u8) -> bool"), (strs(&["impl Fn(u8)"]), Some("bool".to_owned())));
+    }
+
+    #[test]
+    fn parses_query_arity() {
+        assert_eq!(parse_query_arity("(&str, u8) -> u32"), Some(2));
+        assert_eq!(parse_query_arity("() -> u8"), Some(0));
+        assert_eq!(parse_query_arity("&str -> u32"), None);
+        assert_eq!(parse_query_arity("(A), (B)"), None);
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("serde*", "serde_json"));
+        assert!(glob_match("*json", "serde_json"));
+        assert!(glob_match("s?rde", "serde"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("a*b", "ac"));
+        assert!(!glob_match("a", ""));
+        assert!(!glob_match("serde", "serde_json"));
     }
 }
@@ -0,0 +1,80 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{SearchFilters, TypeRepr, parse_query, parse_query_arity, split_query};
+
+// What to search for, for frontends to build directly rather than writing out the query syntax -
+// e.g. from a type picker, or to add a receiver, which the syntax has no way of saying
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    // None means any params, and empty means none at all (see `takes_no_params`)
+    pub params: Option<Vec<TypeRepr>>,
+    // None means any ret, and `()` nothing at all (see `returns_nothing`)
+    pub ret: Option<TypeRepr>,
+    // The type of the first param, which must be in that position (unlike `params`, unless searching
+    // with `SearchOptions::ordered`), e.g. to find methods of a type
+    pub receiver: Option<TypeRepr>,
+    // Match the receiver as a method call on a value of that type would - through references, and
+    // through derefs (e.g. `Vec<u8>` finds `[u8]` methods)
+    pub autoref: bool,
+    pub ret_impl: Option<String>, // a trait the ret type implements, e.g. `Iterator<Item = u8>`
+    pub name: Option<String>, // (part of) a half-remembered fn path, e.g. `read_to_str`
+    pub doc: Option<String>, // searched for in the first line of fn docs, e.g. `parse a header`
+    // Including ranking options, like `max_per_crate`
+    pub filters: SearchFilters,
+}
+
+impl Query {
+    // From the query syntax, e.g. `&str, usize -> Option<char>`. Params in parens (e.g. `(&str) -> u32`)
+    // set the filters to exactly that many params. Method chain style, `Vec<u8> . ? -> String`, searches
+    // for methods callable on the receiver (with any other params)
+    pub fn parse(query: &str) -> Self {
+        if let Some((receiver, ret)) = parse_method_chain(query) {
+            return Query {
+                receiver: Some(TypeRepr::parse(receiver)),
+                autoref: true,
+                ret: ret.map(TypeRepr::parse),
+                ..Query::default()
+            }
+        }
+        let (params, ret) = parse_query(query);
+        let arity = parse_query_arity(query);
+        Query {
+            params: params.map(|params| params.iter().map(|param| TypeRepr::parse(param)).collect()),
+            ret: ret.map(|ret| TypeRepr::parse(&ret)),
+            filters: SearchFilters { min_params: arity, max_params: arity, ..SearchFilters::default() },
+            ..Query::default()
+        }
+    }
+
+    // Fns taking no params at all. With a receiver, no params means no others, which isn't marked
+    pub fn takes_no_params(&self) -> bool {
+        self.receiver.is_none() && self.params.as_ref().map_or(false, Vec::is_empty)
+    }
+
+    pub fn returns_nothing(&self) -> bool {
+        self.ret.as_ref().map_or(false, TypeRepr::is_unit)
+    }
+
+    // The types as searched for
+    pub fn param_strs(&self) -> Option<Vec<String>> {
+        self.params.as_ref().map(|params| params.iter().map(TypeRepr::to_string).collect())
+    }
+
+    pub fn ret_str(&self) -> Option<String> {
+        self.ret.as_ref().map(TypeRepr::to_string)
+    }
+
+    pub fn receiver_str(&self) -> Option<String> {
+        self.receiver.as_ref().map(TypeRepr::to_string)
+    }
+}
+
+// The receiver and ret of `<receiver> . ? -> <ret>` (or with no ret), if the query is in that style
+fn parse_method_chain(query: &str) -> Option<(&str, Option<&str>)> {
+    let (params, ret) = split_query(query);
+    let ret = ret.map(str::trim).filter(|ret| !ret.is_empty() && *ret != "*");
+    let receiver = params.trim().strip_suffix('?')?.trim_end().strip_suffix('.')?.trim();
+    if receiver.is_empty() { None } else { Some((receiver, ret)) }
+}
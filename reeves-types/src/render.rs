@@ -0,0 +1,143 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{FnDetail, GenericParamDetail, ItemKind, TypeRepr};
+
+// How signatures are shown. `FnDetail::s` stays as it is, as results are sorted and deduplicated
+// by it - rendering is only for display
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStyle {
+    // As stored, e.g. `fn Header::path_bytes(&Header) -> Cow<[u8]>`
+    Raw,
+    // Methods and associated fns in their impl, e.g. `impl Header { fn path_bytes(&self) -> Cow<[u8]> }`
+    Impl,
+    // Methods as they'd be called as plain fns, e.g. `fn Header::path_bytes(self: &Header) -> Cow<[u8]>`
+    Ufcs,
+}
+
+impl Default for SignatureStyle {
+    fn default() -> Self {
+        SignatureStyle::Impl
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderOptions {
+    pub style: SignatureStyle,
+    // Paths start with the crate, e.g. `tar::Header` rather than `Header`
+    pub qualified: bool,
+}
+
+pub fn render_signature(fndetail: &FnDetail, opts: &RenderOptions) -> String {
+    let path = if opts.qualified {
+        format!("{}::{}", fndetail.krate.replace('-', "_"), fndetail.path)
+    } else {
+        fndetail.path.clone()
+    };
+    // Other items already read naturally, e.g. `const Foo::MAX: u32`
+    if opts.style == SignatureStyle::Raw || fndetail.kind != ItemKind::Fn {
+        return fndetail.s.replacen(&fndetail.path, &path, 1)
+    }
+
+    let (parent, name) = match path.rfind("::") {
+        Some(i) => (Some(&path[..i]), &path[i + 2..]),
+        None => (None, path.as_str()),
+    };
+    let self_ty = parent.and_then(|parent| self_type(fndetail, parent));
+
+    let mut qualifiers = String::new();
+    if fndetail.is_const { qualifiers.push_str("const ") }
+    if fndetail.is_async { qualifiers.push_str("async ") }
+    if fndetail.is_unsafe { qualifiers.push_str("unsafe ") }
+    // As written where known, e.g. `io::Result<()>` rather than `Result<(), Error>`
+    let mut params = fndetail.written_params.clone().unwrap_or_else(|| fndetail.params.clone());
+    let ret = fndetail.written_ret.as_ref().unwrap_or(&fndetail.ret);
+    let ret_str = if ret == "()" { String::new() } else { format!(" -> {}", ret) };
+
+    let self_ty = match self_ty {
+        Some(self_ty) => self_ty,
+        None => return format!("{}fn {}{}({}){}", qualifiers, path, generics_str(&fndetail.generics), params.join(", "), ret_str),
+    };
+    if self_ty.has_receiver && !params.is_empty() {
+        params[0] = match opts.style {
+            SignatureStyle::Ufcs => format!("self: {}", params[0]),
+            _ => receiver_str(&fndetail.param_reprs[0], &params[0]),
+        }
+    }
+    let params_str = params.join(", ");
+    match opts.style {
+        SignatureStyle::Impl => format!("impl {} {{ {}fn {}{}({}){} }}",
+                                        self_ty.ty, qualifiers, name, generics_str(&fndetail.generics), params_str, ret_str),
+        _ => format!("{}fn {}{}({}){}", qualifiers, path, generics_str(&fndetail.generics), params_str, ret_str),
+    }
+}
+
+// The import needed to call the fn, e.g. `use regex::Regex;` for `Regex::new`
+pub fn use_line(fndetail: &FnDetail) -> String {
+    format!("use {}::{};", fndetail.krate.replace('-', "_"), fndetail.import_path)
+}
+
+// E.g. `<T: Clone, U>`, or nothing if there are no generics. `Self` (given to extension trait
+// methods for its bounds) is left out, as it's implied by the trait
+pub fn generics_str(generics: &[GenericParamDetail]) -> String {
+    let generics_strs: Vec<_> = generics.iter()
+        .filter(|g| g.name != "Self")
+        .map(|g| if g.bounds.is_empty() { g.name.clone() } else { format!("{}: {}", g.name, g.bounds.join(" + ")) })
+        .collect();
+    if generics_strs.is_empty() {
+        return String::new()
+    }
+    format!("<{}>", generics_strs.join(", "))
+}
+
+struct SelfType {
+    ty: String, // e.g. `Vec<T, A>`
+    has_receiver: bool, // whether the first param is `self`
+}
+
+// The type an fn is associated with, if it looks like it is - the parent of its path is a type
+// (capitalized, as with `GroupBy::Module`), or its first param is the parent type, e.g. `&str`
+// for `str::len`
+fn self_type(fndetail: &FnDetail, parent: &str) -> Option<SelfType> {
+    let parent_name = &parent[parent.rfind("::").map_or(0, |i| i + 2)..];
+    let is_self = |ty: &TypeRepr| ty.name() == Some(parent_name) || ty.name() == Some("Self");
+    let receiver = fndetail.param_reprs.first().and_then(|param| {
+        let ty = match param {
+            TypeRepr::Path { args, .. } if SMART_POINTERS.contains(&param.name()?) && args.len() == 1 => &args[0],
+            ty => ty,
+        };
+        // e.g. `&self`, or `self: Pin<&mut Self>`
+        let ty = match ty {
+            TypeRepr::Ref { inner, .. } => &**inner,
+            ty => ty,
+        };
+        if is_self(ty) { Some(ty) } else { None }
+    });
+    // Methods only reachable through `Deref` are shown where they're defined
+    if let Some(deref_from) = fndetail.deref_from.as_ref() {
+        return Some(SelfType { ty: deref_from.clone(), has_receiver: true })
+    }
+    match receiver {
+        Some(TypeRepr::Path { args, .. }) if !args.is_empty() => {
+            // Keep the path as given (e.g. qualified), with the receiver's generics
+            let ty = TypeRepr::Path { segments: parent.split("::").map(str::to_owned).collect(), args: args.clone(), bindings: vec![] };
+            Some(SelfType { ty: ty.to_string(), has_receiver: true })
+        },
+        Some(_) => Some(SelfType { ty: parent.to_owned(), has_receiver: true }),
+        None if parent_name.starts_with(|c: char| c.is_uppercase()) => Some(SelfType { ty: parent.to_owned(), has_receiver: false }),
+        None => None,
+    }
+}
+
+// Receivers that can be written as `self: Box<Self>` etc
+const SMART_POINTERS: &[&str] = &["Box", "Rc", "Arc", "Pin"];
+
+fn receiver_str(repr: &TypeRepr, written: &str) -> String {
+    match repr {
+        TypeRepr::Ref { mutable: false, .. } => "&self".to_owned(),
+        TypeRepr::Ref { mutable: true, .. } => "&mut self".to_owned(),
+        TypeRepr::Path { args, .. } if SMART_POINTERS.contains(&repr.name().unwrap_or("")) && args.len() == 1 => format!("self: {}", written),
+        _ => "self".to_owned(),
+    }
+}
@@ -0,0 +1,756 @@
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+// A type broken down into its structure, so it can be matched on more than just its text
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TypeRepr {
+    // Named types, including generic params and builtins - e.g. `io::Result<T>`, `u8`,
+    // `Iterator<Item = u8>`. `Fn(A) -> R` is stored desugared as `Fn<(A,), Output = R>`
+    Path { segments: Vec<String>, args: Vec<TypeRepr>, bindings: Vec<(String, TypeRepr)> },
+    Ref { mutable: bool, inner: Box<TypeRepr> },
+    Ptr { mutable: bool, inner: Box<TypeRepr> },
+    Tuple(Vec<TypeRepr>), // including `()`
+    Slice(Box<TypeRepr>),
+    Array { inner: Box<TypeRepr>, len: String },
+    DynTrait(Vec<TypeRepr>), // bounds
+    ImplTrait(Vec<TypeRepr>), // bounds
+    FnPtr { params: Vec<TypeRepr>, ret: Box<TypeRepr> },
+    Never,
+    // Anything we couldn't make sense of, e.g. `{unknown}`
+    Unknown(String),
+}
+
+const FN_TRAITS: &[&str] = &["Fn", "FnMut", "FnOnce"];
+// Rarely what a search for a trait object is after, e.g. the `Send + Sync` of `Box<dyn Error + Send + Sync>`
+const AUTO_TRAITS: &[&str] = &["Send", "Sync", "Unpin", "UnwindSafe", "RefUnwindSafe"];
+// Smart pointers a trait object is commonly held in
+const TRAIT_OBJECT_POINTERS: &[&str] = &["Box", "Rc", "Arc"];
+
+impl TypeRepr {
+    // Never fails - anything unparseable becomes `Unknown`
+    pub fn parse(s: &str) -> TypeRepr {
+        let mut parser = Parser { toks: tokenize(s), pos: 0 };
+        match parser.parse_type() {
+            Some(ty) if parser.pos == parser.toks.len() => ty,
+            _ => TypeRepr::Unknown(s.trim().to_owned()),
+        }
+    }
+
+    pub fn unit() -> TypeRepr {
+        TypeRepr::Tuple(vec![])
+    }
+
+    // The last path segment, e.g. `Result` for `io::Result<T>`
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            TypeRepr::Path { segments, .. } => segments.last().map(String::as_str),
+            _ => None,
+        }
+    }
+
+    // Types directly inside this one, e.g. generic args or what a reference points to
+    pub fn children(&self) -> Vec<&TypeRepr> {
+        match self {
+            TypeRepr::Path { args, bindings, .. } => args.iter().chain(bindings.iter().map(|(_, ty)| ty)).collect(),
+            TypeRepr::Ref { inner, .. } |
+            TypeRepr::Ptr { inner, .. } |
+            TypeRepr::Slice(inner) |
+            TypeRepr::Array { inner, .. } => vec![inner],
+            TypeRepr::Tuple(tys) |
+            TypeRepr::DynTrait(tys) |
+            TypeRepr::ImplTrait(tys) => tys.iter().collect(),
+            TypeRepr::FnPtr { params, ret } => params.iter().chain(Some(&**ret)).collect(),
+            TypeRepr::Never |
+            TypeRepr::Unknown(_) => vec![],
+        }
+    }
+
+    // The types a compound type is made of, so they can be searched for individually - the fields
+    // of tuples and the params and ret of closures and fn pointers, recursively. References and
+    // boxes are looked through, e.g. `&dyn Fn(&str) -> bool` has `&str` and `bool`
+    pub fn components(&self) -> Vec<TypeRepr> {
+        let mut components = vec![];
+        self.add_components(&mut components);
+        components
+    }
+
+    fn add_components(&self, components: &mut Vec<TypeRepr>) {
+        fn add(ty: &TypeRepr, components: &mut Vec<TypeRepr>) {
+            if *ty != TypeRepr::unit() && !components.contains(ty) {
+                components.push(ty.clone())
+            }
+            ty.add_components(components)
+        }
+        match self {
+            TypeRepr::Ref { inner, .. } => inner.add_components(components),
+            TypeRepr::Path { args, .. } if self.name() == Some("Box") && args.len() == 1 => args[0].add_components(components),
+            TypeRepr::Path { .. } => {
+                if let Some((params, ret)) = self.fn_trait_sig() {
+                    params.iter().chain(Some(ret)).for_each(|ty| add(ty, components))
+                }
+            },
+            TypeRepr::Tuple(tys) => tys.iter().for_each(|ty| add(ty, components)),
+            TypeRepr::FnPtr { params, ret } => params.iter().chain(Some(&**ret)).for_each(|ty| add(ty, components)),
+            TypeRepr::DynTrait(bounds) |
+            TypeRepr::ImplTrait(bounds) => bounds.iter().for_each(|bound| bound.add_components(components)),
+            TypeRepr::Ptr { .. } |
+            TypeRepr::Slice(_) |
+            TypeRepr::Array { .. } |
+            TypeRepr::Never |
+            TypeRepr::Unknown(_) => (),
+        }
+    }
+
+    // The traits a value of this type is only known by - for `impl Trait` and trait objects, also when
+    // boxed or behind a reference (e.g. `Box<dyn Error + Send>` is known by `Error`). Auto traits are
+    // left out, so there may be none
+    pub fn trait_bounds(&self) -> Option<Vec<&TypeRepr>> {
+        match self {
+            TypeRepr::DynTrait(bounds) |
+            TypeRepr::ImplTrait(bounds) => Some(bounds.iter().filter(|bound| !AUTO_TRAITS.contains(&bound.name().unwrap_or(""))).collect()),
+            TypeRepr::Ref { inner, .. } => inner.trait_bounds(),
+            TypeRepr::Path { args, .. } if TRAIT_OBJECT_POINTERS.contains(&self.name()?) => match args.first()? {
+                inner @ TypeRepr::DynTrait(_) => inner.trait_bounds(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // What a callable type can be called with and returns, as a fn pointer - e.g. `fn(&str) -> bool` for
+    // `impl FnMut(&str) -> bool`, `Box<dyn Fn(&str) -> bool>`, the bound `FnOnce(&str) -> bool` or
+    // `fn(&str) -> bool` itself - so callables can be matched whichever way they're taken
+    pub fn callable_sig(&self) -> Option<TypeRepr> {
+        match self {
+            TypeRepr::FnPtr { .. } => Some(self.clone()),
+            TypeRepr::Path { .. } if FN_TRAITS.contains(&self.name()?) => {
+                let (params, ret) = self.fn_trait_sig()?;
+                Some(TypeRepr::FnPtr { params: params.to_vec(), ret: Box::new(ret.clone()) })
+            },
+            _ => self.trait_bounds()?.into_iter().find_map(TypeRepr::callable_sig),
+        }
+    }
+
+    // The params and ret of `Fn(A) -> R`, `FnMut(A) -> R` or `FnOnce(A) -> R`
+    fn fn_trait_sig(&self) -> Option<(&[TypeRepr], &TypeRepr)> {
+        match self {
+            TypeRepr::Path { args, bindings, .. } if FN_TRAITS.contains(&self.name()?) => {
+                match (args.as_slice(), bindings.as_slice()) {
+                    ([TypeRepr::Tuple(params)], [(output, ret)]) if output == "Output" => Some((params, ret)),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // Every occurrence of one type within this one (including itself) replaced with another, e.g.
+    // `str` with `String` makes `&str` into `&String`
+    pub fn replace(&self, from: &TypeRepr, to: &TypeRepr) -> TypeRepr {
+        if self == from {
+            return to.clone()
+        }
+        let all = |tys: &[TypeRepr]| tys.iter().map(|ty| ty.replace(from, to)).collect();
+        let one = |ty: &TypeRepr| Box::new(ty.replace(from, to));
+        match self {
+            TypeRepr::Path { segments, args, bindings } => TypeRepr::Path {
+                segments: segments.clone(),
+                args: all(args),
+                bindings: bindings.iter().map(|(name, ty)| (name.clone(), ty.replace(from, to))).collect(),
+            },
+            TypeRepr::Ref { mutable, inner } => TypeRepr::Ref { mutable: *mutable, inner: one(inner) },
+            TypeRepr::Ptr { mutable, inner } => TypeRepr::Ptr { mutable: *mutable, inner: one(inner) },
+            TypeRepr::Tuple(tys) => TypeRepr::Tuple(all(tys)),
+            TypeRepr::Slice(inner) => TypeRepr::Slice(one(inner)),
+            TypeRepr::Array { inner, len } => TypeRepr::Array { inner: one(inner), len: len.clone() },
+            TypeRepr::DynTrait(bounds) => TypeRepr::DynTrait(all(bounds)),
+            TypeRepr::ImplTrait(bounds) => TypeRepr::ImplTrait(all(bounds)),
+            TypeRepr::FnPtr { params, ret } => TypeRepr::FnPtr { params: all(params), ret: one(ret) },
+            TypeRepr::Never |
+            TypeRepr::Unknown(_) => self.clone(),
+        }
+    }
+
+    // With any array length or const generic arg (e.g. `32` in `ArrayVec<u8, 32>`, or a typenum like
+    // `U32` in `GenericArray<u8, U32>`) replaced with `_`, so `[u8; _]` stands for any byte array
+    pub fn without_lengths(&self) -> TypeRepr {
+        let all = |tys: &[TypeRepr]| tys.iter().map(TypeRepr::without_lengths).collect();
+        let one = |ty: &TypeRepr| Box::new(ty.without_lengths());
+        match self {
+            _ if self.is_const_arg() => TypeRepr::Unknown("_".to_owned()),
+            TypeRepr::Path { segments, args, bindings } => TypeRepr::Path {
+                segments: segments.clone(),
+                args: all(args),
+                bindings: bindings.iter().map(|(name, ty)| (name.clone(), ty.without_lengths())).collect(),
+            },
+            TypeRepr::Ref { mutable, inner } => TypeRepr::Ref { mutable: *mutable, inner: one(inner) },
+            TypeRepr::Ptr { mutable, inner } => TypeRepr::Ptr { mutable: *mutable, inner: one(inner) },
+            TypeRepr::Tuple(tys) => TypeRepr::Tuple(all(tys)),
+            TypeRepr::Slice(inner) => TypeRepr::Slice(one(inner)),
+            TypeRepr::Array { inner, .. } => TypeRepr::Array { inner: one(inner), len: "_".to_owned() },
+            TypeRepr::DynTrait(bounds) => TypeRepr::DynTrait(all(bounds)),
+            TypeRepr::ImplTrait(bounds) => TypeRepr::ImplTrait(all(bounds)),
+            TypeRepr::FnPtr { params, ret } => TypeRepr::FnPtr { params: all(params), ret: one(ret) },
+            TypeRepr::Never |
+            TypeRepr::Unknown(_) => self.clone(),
+        }
+    }
+
+    // With every reference removed, wherever it is, e.g. `Option<&mut [u8]>` as `Option<[u8]>`. Unlike
+    // `without_refs`, owned types stay as they are
+    pub fn erase_refs(&self) -> TypeRepr {
+        let all = |tys: &[TypeRepr]| tys.iter().map(TypeRepr::erase_refs).collect();
+        let one = |ty: &TypeRepr| Box::new(ty.erase_refs());
+        match self {
+            TypeRepr::Ref { inner, .. } => inner.erase_refs(),
+            TypeRepr::Path { segments, args, bindings } => TypeRepr::Path {
+                segments: segments.clone(),
+                args: all(args),
+                bindings: bindings.iter().map(|(name, ty)| (name.clone(), ty.erase_refs())).collect(),
+            },
+            TypeRepr::Ptr { mutable, inner } => TypeRepr::Ptr { mutable: *mutable, inner: one(inner) },
+            TypeRepr::Tuple(tys) => TypeRepr::Tuple(all(tys)),
+            TypeRepr::Slice(inner) => TypeRepr::Slice(one(inner)),
+            TypeRepr::Array { inner, len } => TypeRepr::Array { inner: one(inner), len: len.clone() },
+            TypeRepr::DynTrait(bounds) => TypeRepr::DynTrait(all(bounds)),
+            TypeRepr::ImplTrait(bounds) => TypeRepr::ImplTrait(all(bounds)),
+            TypeRepr::FnPtr { params, ret } => TypeRepr::FnPtr { params: all(params), ret: one(ret) },
+            TypeRepr::Never |
+            TypeRepr::Unknown(_) => self.clone(),
+        }
+    }
+
+    // The success type inside any `Option` or `Result` around this one, e.g. `PathBuf` for
+    // `Result<Option<PathBuf>, Error>`
+    pub fn unwrapped(&self) -> &TypeRepr {
+        match self {
+            TypeRepr::Path { args, .. } if matches!(self.name(), Some("Option") | Some("Result")) && !args.is_empty() => args[0].unwrapped(),
+            _ => self,
+        }
+    }
+
+    // Const generic args are parsed as types - a number or a typenum (e.g. `U32`) is a single segment
+    // path, and an expression is a `{...}` placeholder
+    fn is_const_arg(&self) -> bool {
+        match self {
+            TypeRepr::Path { segments, args, bindings } if args.is_empty() && bindings.is_empty() => {
+                let name = segments.last().map_or("", String::as_str);
+                let typenum_digits = name.strip_prefix('U').unwrap_or("");
+                let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+                is_number(name) || is_number(typenum_digits)
+            },
+            TypeRepr::Unknown(s) => s.starts_with('{') && s != "{unknown}",
+            _ => false,
+        }
+    }
+
+    // `()`, as returned by fns returning nothing
+    pub fn is_unit(&self) -> bool {
+        matches!(self, TypeRepr::Tuple(fields) if fields.is_empty())
+    }
+
+    // A `_` in a query, which stands for any type
+    pub fn is_hole(&self) -> bool {
+        match self {
+            TypeRepr::Path { segments, args, bindings } => segments.len() == 1 && segments[0] == "_" && args.is_empty() && bindings.is_empty(),
+            TypeRepr::Unknown(s) => s == "_",
+            _ => false,
+        }
+    }
+
+    // Whether there's a `_` anywhere in the type, including as an array length
+    pub fn has_holes(&self) -> bool {
+        match self {
+            TypeRepr::Array { len, .. } if len == "_" => true,
+            _ => self.is_hole() || self.children().into_iter().any(TypeRepr::has_holes),
+        }
+    }
+
+    // Whether this type has the structure of a query type with `_` holes, each matching any type (or
+    // array length) - e.g. `Result<Vec<u8>, io::Error>` matches `Result<_, io::Error>`. Paths only
+    // need to agree on the segments both have, so `Error` and `io::Error` match each other
+    pub fn matches(&self, pattern: &TypeRepr) -> bool {
+        if pattern.is_hole() {
+            return true
+        }
+        let all = |tys: &[TypeRepr], pats: &[TypeRepr]| tys.len() == pats.len() && tys.iter().zip(pats).all(|(ty, pat)| ty.matches(pat));
+        match (self, pattern) {
+            (TypeRepr::Path { segments, args, bindings }, TypeRepr::Path { segments: pat_segments, args: pat_args, bindings: pat_bindings }) => {
+                let n = segments.len().min(pat_segments.len());
+                segments[segments.len() - n..] == pat_segments[pat_segments.len() - n..] &&
+                    all(args, pat_args) &&
+                    bindings.len() == pat_bindings.len() &&
+                    bindings.iter().zip(pat_bindings).all(|((name, ty), (pat_name, pat))| name == pat_name && ty.matches(pat))
+            },
+            (TypeRepr::Ref { mutable, inner }, TypeRepr::Ref { mutable: pat_mutable, inner: pat }) |
+            (TypeRepr::Ptr { mutable, inner }, TypeRepr::Ptr { mutable: pat_mutable, inner: pat }) => mutable == pat_mutable && inner.matches(pat),
+            (TypeRepr::Tuple(tys), TypeRepr::Tuple(pats)) |
+            (TypeRepr::DynTrait(tys), TypeRepr::DynTrait(pats)) |
+            (TypeRepr::ImplTrait(tys), TypeRepr::ImplTrait(pats)) => all(tys, pats),
+            (TypeRepr::Slice(inner), TypeRepr::Slice(pat)) => inner.matches(pat),
+            (TypeRepr::Array { inner, len }, TypeRepr::Array { inner: pat, len: pat_len }) => (pat_len == "_" || len == pat_len) && inner.matches(pat),
+            (TypeRepr::FnPtr { params, ret }, TypeRepr::FnPtr { params: pat_params, ret: pat_ret }) => all(params, pat_params) && ret.matches(pat_ret),
+            _ => self == pattern,
+        }
+    }
+
+    // Ignoring ownership - references are removed and owned types replaced with what they deref
+    // to, so `String`, `&String`, `&mut String` and `&str` are all `str`. Only the type itself and
+    // things behind references are derefed, e.g. `Option<Vec<T>>` stays as it is
+    pub fn without_refs(&self) -> TypeRepr {
+        self.strip_refs(true)
+    }
+
+    fn strip_refs(&self, deref: bool) -> TypeRepr {
+        let all = |tys: &[TypeRepr]| tys.iter().map(|ty| ty.strip_refs(false)).collect();
+        let one = |ty: &TypeRepr| Box::new(ty.strip_refs(false));
+        let ty = match self {
+            TypeRepr::Ref { inner, .. } => return inner.strip_refs(true),
+            TypeRepr::Path { segments, args, bindings } => TypeRepr::Path {
+                segments: segments.clone(),
+                args: all(args),
+                bindings: bindings.iter().map(|(name, ty)| (name.clone(), ty.strip_refs(false))).collect(),
+            },
+            TypeRepr::Ptr { mutable, inner } => TypeRepr::Ptr { mutable: *mutable, inner: one(inner) },
+            TypeRepr::Tuple(tys) => TypeRepr::Tuple(all(tys)),
+            TypeRepr::Slice(inner) => TypeRepr::Slice(one(inner)),
+            TypeRepr::Array { inner, len } => TypeRepr::Array { inner: one(inner), len: len.clone() },
+            TypeRepr::DynTrait(bounds) => TypeRepr::DynTrait(all(bounds)),
+            TypeRepr::ImplTrait(bounds) => TypeRepr::ImplTrait(all(bounds)),
+            TypeRepr::FnPtr { params, ret } => TypeRepr::FnPtr { params: all(params), ret: one(ret) },
+            TypeRepr::Never |
+            TypeRepr::Unknown(_) => self.clone(),
+        };
+        if !deref {
+            return ty
+        }
+        let borrowed = |name: &str| TypeRepr::Path { segments: vec![name.to_owned()], args: vec![], bindings: vec![] };
+        match (ty.name(), &ty) {
+            (Some(owned), TypeRepr::Path { args, .. }) => match (owned, args.as_slice()) {
+                ("String", []) => borrowed("str"),
+                ("PathBuf", []) => borrowed("Path"),
+                ("OsString", []) => borrowed("OsStr"),
+                ("CString", []) => borrowed("CStr"),
+                ("Vec", [inner]) => TypeRepr::Slice(Box::new(inner.clone())),
+                ("Box", [inner]) => inner.clone(),
+                _ => ty,
+            },
+            _ => ty,
+        }
+    }
+}
+
+impl fmt::Display for TypeRepr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join(f: &mut fmt::Formatter<'_>, tys: &[TypeRepr], sep: &str) -> fmt::Result {
+            for (i, ty) in tys.iter().enumerate() {
+                if i > 0 { f.write_str(sep)? }
+                write!(f, "{}", ty)?
+            }
+            Ok(())
+        }
+        fn ret(f: &mut fmt::Formatter<'_>, ty: &TypeRepr) -> fmt::Result {
+            if *ty == TypeRepr::unit() { Ok(()) } else { write!(f, " -> {}", ty) }
+        }
+        match self {
+            TypeRepr::Path { segments, args, bindings } => {
+                f.write_str(&segments.join("::"))?;
+                match (args.as_slice(), bindings.as_slice()) {
+                    ([TypeRepr::Tuple(params)], [(output, out_ty)])
+                            if output == "Output" && FN_TRAITS.contains(&self.name().unwrap()) => {
+                        f.write_str("(")?;
+                        join(f, params, ", ")?;
+                        f.write_str(")")?;
+                        ret(f, out_ty)
+                    },
+                    ([], []) => Ok(()),
+                    _ => {
+                        f.write_str("<")?;
+                        join(f, args, ", ")?;
+                        for (i, (name, ty)) in bindings.iter().enumerate() {
+                            if i > 0 || !args.is_empty() { f.write_str(", ")? }
+                            write!(f, "{} = {}", name, ty)?
+                        }
+                        f.write_str(">")
+                    },
+                }
+            },
+            TypeRepr::Ref { mutable, inner } => write!(f, "&{}{}", if *mutable { "mut " } else { "" }, inner),
+            TypeRepr::Ptr { mutable, inner } => write!(f, "*{} {}", if *mutable { "mut" } else { "const" }, inner),
+            TypeRepr::Tuple(tys) => {
+                f.write_str("(")?;
+                join(f, tys, ", ")?;
+                if tys.len() == 1 { f.write_str(",")? }
+                f.write_str(")")
+            },
+            TypeRepr::Slice(inner) => write!(f, "[{}]", inner),
+            TypeRepr::Array { inner, len } => write!(f, "[{}; {}]", inner, len),
+            TypeRepr::DynTrait(bounds) => { f.write_str("dyn ")?; join(f, bounds, " + ") },
+            TypeRepr::ImplTrait(bounds) => { f.write_str("impl ")?; join(f, bounds, " + ") },
+            TypeRepr::FnPtr { params, ret: ret_ty } => {
+                f.write_str("fn(")?;
+                join(f, params, ", ")?;
+                f.write_str(")")?;
+                ret(f, ret_ty)
+            },
+            TypeRepr::Never => f.write_str("!"),
+            TypeRepr::Unknown(s) => f.write_str(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tok<'a> {
+    Ident(&'a str), // including numbers and placeholders like `{unknown}`
+    Punct(&'a str),
+    Lifetime,
+    Literal, // e.g. the ABI in `extern "C" fn()`
+}
+
+pub(crate) fn tokenize(s: &str) -> Vec<Tok> {
+    let mut toks = vec![];
+    let bytes = s.as_bytes();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let b = bytes[i];
+        if b.is_ascii_whitespace() {
+            i += 1;
+            continue
+        } else if is_ident(b) {
+            while i < bytes.len() && is_ident(bytes[i]) { i += 1 }
+            toks.push(Tok::Ident(&s[start..i]))
+        } else if b == b'{' {
+            while i < bytes.len() && bytes[i] != b'}' { i += 1 }
+            i = (i + 1).min(bytes.len());
+            toks.push(Tok::Ident(&s[start..i]))
+        } else if b == b'\'' {
+            i += 1;
+            while i < bytes.len() && is_ident(bytes[i]) { i += 1 }
+            toks.push(Tok::Lifetime)
+        } else if b == b'"' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' { i += 1 }
+            i = (i + 1).min(bytes.len());
+            toks.push(Tok::Literal)
+        } else if s[i..].starts_with("->") || s[i..].starts_with("::") {
+            i += 2;
+            toks.push(Tok::Punct(&s[start..i]))
+        } else {
+            i += s[i..].chars().next().unwrap().len_utf8();
+            toks.push(Tok::Punct(&s[start..i]))
+        }
+    }
+    toks
+}
+
+struct Parser<'a> {
+    toks: Vec<Tok<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Tok<'a>> {
+        self.toks.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, tok: Tok<'a>) -> bool {
+        if self.peek() == Some(tok) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: Tok<'a>) -> Option<()> {
+        if self.eat(tok) { Some(()) } else { None }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        match self.peek()? {
+            Tok::Ident(ident) => { self.pos += 1; Some(ident) },
+            _ => None,
+        }
+    }
+
+    fn parse_type(&mut self) -> Option<TypeRepr> {
+        let ty = match self.peek()? {
+            Tok::Punct("&") => {
+                self.pos += 1;
+                self.eat(Tok::Lifetime);
+                let mutable = self.eat(Tok::Ident("mut"));
+                TypeRepr::Ref { mutable, inner: Box::new(self.parse_type()?) }
+            },
+            Tok::Punct("*") => {
+                self.pos += 1;
+                let mutable = match self.ident()? {
+                    "mut" => true,
+                    "const" => false,
+                    _ => return None,
+                };
+                TypeRepr::Ptr { mutable, inner: Box::new(self.parse_type()?) }
+            },
+            Tok::Punct("(") => {
+                self.pos += 1;
+                let (mut tys, trailing_comma) = self.parse_list(")")?;
+                if tys.len() == 1 && !trailing_comma { tys.pop().unwrap() } else { TypeRepr::Tuple(tys) }
+            },
+            Tok::Punct("[") => {
+                self.pos += 1;
+                let inner = Box::new(self.parse_type()?);
+                if self.eat(Tok::Punct("]")) {
+                    TypeRepr::Slice(inner)
+                } else {
+                    self.expect(Tok::Punct(";"))?;
+                    let mut len = vec![];
+                    while !self.eat(Tok::Punct("]")) {
+                        match self.peek()? {
+                            Tok::Ident(s) | Tok::Punct(s) => len.push(s),
+                            Tok::Lifetime | Tok::Literal => return None,
+                        }
+                        self.pos += 1;
+                    }
+                    TypeRepr::Array { inner, len: len.join("") }
+                }
+            },
+            Tok::Punct("!") => {
+                self.pos += 1;
+                TypeRepr::Never
+            },
+            Tok::Punct("<") | Tok::Punct("::") => self.parse_path()?,
+            Tok::Ident("dyn") => {
+                self.pos += 1;
+                TypeRepr::DynTrait(self.parse_bounds()?)
+            },
+            Tok::Ident("impl") => {
+                self.pos += 1;
+                TypeRepr::ImplTrait(self.parse_bounds()?)
+            },
+            Tok::Ident("unsafe") | Tok::Ident("extern") | Tok::Ident("fn") => {
+                self.eat(Tok::Ident("unsafe"));
+                if self.eat(Tok::Ident("extern")) { self.eat(Tok::Literal); }
+                self.expect(Tok::Ident("fn"))?;
+                self.expect(Tok::Punct("("))?;
+                let (params, _) = self.parse_list(")")?;
+                let ret = Box::new(self.parse_ret()?);
+                TypeRepr::FnPtr { params, ret }
+            },
+            Tok::Ident("_") => {
+                self.pos += 1;
+                TypeRepr::Unknown("_".to_owned())
+            },
+            Tok::Ident(s) if s.starts_with('{') => {
+                self.pos += 1;
+                TypeRepr::Unknown(s.to_owned())
+            },
+            Tok::Ident(_) => self.parse_path()?,
+            Tok::Punct(_) | Tok::Lifetime | Tok::Literal => return None,
+        };
+        Some(ty)
+    }
+
+    // Types up to the closing bracket, and whether there was a trailing comma
+    fn parse_list(&mut self, close: &'a str) -> Option<(Vec<TypeRepr>, bool)> {
+        let mut tys = vec![];
+        let mut trailing_comma = false;
+        while !self.eat(Tok::Punct(close)) {
+            tys.push(self.parse_type()?);
+            trailing_comma = self.eat(Tok::Punct(","));
+            if !trailing_comma {
+                self.expect(Tok::Punct(close))?;
+                break
+            }
+        }
+        Some((tys, trailing_comma))
+    }
+
+    fn parse_ret(&mut self) -> Option<TypeRepr> {
+        if self.eat(Tok::Punct("->")) { self.parse_type() } else { Some(TypeRepr::unit()) }
+    }
+
+    fn parse_path(&mut self) -> Option<TypeRepr> {
+        let mut segments = vec![];
+        let mut args = vec![];
+        let mut bindings = vec![];
+        // Qualified paths like `<T as Iterator>::Item` - just keep the qualifier as a segment
+        if self.eat(Tok::Punct("<")) {
+            let self_ty = self.parse_type()?;
+            let qualifier = if self.eat(Tok::Ident("as")) {
+                format!("<{} as {}>", self_ty, self.parse_path()?)
+            } else {
+                format!("<{}>", self_ty)
+            };
+            self.expect(Tok::Punct(">"))?;
+            self.expect(Tok::Punct("::"))?;
+            segments.push(qualifier)
+        }
+        self.eat(Tok::Punct("::"));
+        loop {
+            let segment = self.ident()?;
+            segments.push(segment.to_owned());
+            // Args on an earlier segment (e.g. `Vec::<T>::new`) are rare, so are just dropped
+            args.clear();
+            bindings.clear();
+            if self.peek() == Some(Tok::Punct("::")) && self.toks.get(self.pos + 1) == Some(&Tok::Punct("<")) {
+                self.pos += 1
+            }
+            if FN_TRAITS.contains(&segment) && self.eat(Tok::Punct("(")) {
+                let (params, _) = self.parse_list(")")?;
+                args.push(TypeRepr::Tuple(params));
+                bindings.push(("Output".to_owned(), self.parse_ret()?));
+            } else if self.eat(Tok::Punct("<")) {
+                while !self.eat(Tok::Punct(">")) {
+                    match (self.peek()?, self.toks.get(self.pos + 1).cloned()) {
+                        (Tok::Lifetime, _) => self.pos += 1,
+                        (Tok::Ident(name), Some(Tok::Punct("="))) => {
+                            self.pos += 2;
+                            bindings.push((name.to_owned(), self.parse_type()?))
+                        },
+                        _ => args.push(self.parse_type()?),
+                    }
+                    if !self.eat(Tok::Punct(",")) {
+                        self.expect(Tok::Punct(">"))?;
+                        break
+                    }
+                }
+            }
+            if !self.eat(Tok::Punct("::")) {
+                break
+            }
+        }
+        Some(TypeRepr::Path { segments, args, bindings })
+    }
+
+    fn parse_bounds(&mut self) -> Option<Vec<TypeRepr>> {
+        let mut bounds = vec![];
+        loop {
+            match self.peek()? {
+                Tok::Lifetime => self.pos += 1,
+                // e.g. `?Sized`
+                Tok::Punct("?") => {
+                    self.pos += 1;
+                    let bound = self.parse_path()?;
+                    bounds.push(TypeRepr::Unknown(format!("?{}", bound)))
+                },
+                _ => bounds.push(self.parse_path()?),
+            }
+            if !self.eat(Tok::Punct("+")) {
+                break
+            }
+        }
+        Some(bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypeRepr;
+
+    fn path(segments: &[&str], args: Vec<TypeRepr>) -> TypeRepr {
+        TypeRepr::Path { segments: segments.iter().map(|s| s.to_string()).collect(), args, bindings: vec![] }
+    }
+
+    fn ty(name: &str) -> TypeRepr {
+        path(&[name], vec![])
+    }
+
+    #[test]
+    fn generics_and_paths() {
+        assert_eq!(TypeRepr::parse("io::Result<T>"), path(&["io", "Result"], vec![ty("T")]));
+        assert_eq!(TypeRepr::parse("::std::collections::HashMap<String, Vec<u8>>"),
+            path(&["std", "collections", "HashMap"], vec![ty("String"), path(&["Vec"], vec![ty("u8")])]));
+        assert_eq!(TypeRepr::parse("Cow<'a, str>"), path(&["Cow"], vec![ty("str")]));
+        assert_eq!(TypeRepr::parse("Iterator<Item = u8>"),
+            TypeRepr::Path { segments: vec!["Iterator".to_owned()], args: vec![], bindings: vec![("Item".to_owned(), ty("u8"))] });
+    }
+
+    #[test]
+    fn refs_ptrs_and_tuples() {
+        assert_eq!(TypeRepr::parse("&'a mut [u8]"), TypeRepr::Ref { mutable: true, inner: Box::new(TypeRepr::Slice(Box::new(ty("u8")))) });
+        assert_eq!(TypeRepr::parse("*const T"), TypeRepr::Ptr { mutable: false, inner: Box::new(ty("T")) });
+        assert_eq!(TypeRepr::parse("()"), TypeRepr::unit());
+        assert_eq!(TypeRepr::parse("(u8,)"), TypeRepr::Tuple(vec![ty("u8")]));
+        assert_eq!(TypeRepr::parse("(u8)"), ty("u8"));
+        assert_eq!(TypeRepr::parse("!"), TypeRepr::Never);
+    }
+
+    #[test]
+    fn fn_pointers() {
+        assert_eq!(TypeRepr::parse("fn(&str, usize) -> bool"), TypeRepr::FnPtr {
+            params: vec![TypeRepr::Ref { mutable: false, inner: Box::new(ty("str")) }, ty("usize")],
+            ret: Box::new(ty("bool")),
+        });
+        assert_eq!(TypeRepr::parse("unsafe extern \"C\" fn(*mut u8)"), TypeRepr::FnPtr {
+            params: vec![TypeRepr::Ptr { mutable: true, inner: Box::new(ty("u8")) }],
+            ret: Box::new(TypeRepr::unit()),
+        });
+    }
+
+    #[test]
+    fn dyn_and_impl_bounds() {
+        assert_eq!(TypeRepr::parse("Box<dyn Error + Send + Sync + 'static>"),
+            path(&["Box"], vec![TypeRepr::DynTrait(vec![ty("Error"), ty("Send"), ty("Sync")])]));
+        let fn_mut = TypeRepr::Path {
+            segments: vec!["FnMut".to_owned()],
+            args: vec![TypeRepr::Tuple(vec![TypeRepr::Ref { mutable: false, inner: Box::new(ty("str")) }])],
+            bindings: vec![("Output".to_owned(), ty("bool"))],
+        };
+        assert_eq!(TypeRepr::parse("impl FnMut(&str) -> bool + Clone"), TypeRepr::ImplTrait(vec![fn_mut, ty("Clone")]));
+        assert_eq!(TypeRepr::parse("impl AsRef<Path> + ?Sized"),
+            TypeRepr::ImplTrait(vec![path(&["AsRef"], vec![ty("Path")]), TypeRepr::Unknown("?Sized".to_owned())]));
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(TypeRepr::parse("[u8; 32]"), TypeRepr::Array { inner: Box::new(ty("u8")), len: "32".to_owned() });
+        assert_eq!(TypeRepr::parse("[T; N * 2]"), TypeRepr::Array { inner: Box::new(ty("T")), len: "N*2".to_owned() });
+        assert_eq!(TypeRepr::parse("[u8; _]").to_string(), "[u8; _]");
+    }
+
+    #[test]
+    fn qualified_paths() {
+        assert_eq!(TypeRepr::parse("<T as Iterator>::Item"), path(&["<T as Iterator>", "Item"], vec![]));
+        assert_eq!(TypeRepr::parse("<I as IntoIterator>::IntoIter").name(), Some("IntoIter"));
+    }
+
+    #[test]
+    fn unparseable() {
+        assert_eq!(TypeRepr::parse("{unknown}"), TypeRepr::Unknown("{unknown}".to_owned()));
+        assert_eq!(TypeRepr::parse(" Vec< "), TypeRepr::Unknown("Vec<".to_owned()));
+        assert_eq!(TypeRepr::parse("u8 u8"), TypeRepr::Unknown("u8 u8".to_owned()));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let tys = [
+            "io::Result<T>",
+            "HashMap<String, Vec<u8>>",
+            "Iterator<Item = u8>",
+            "Peekable<I, Item = u8>",
+            "&mut [u8]",
+            "*const c_void",
+            "()",
+            "(u8,)",
+            "(u8, &str)",
+            "!",
+            "[u8; 32]",
+            "fn(&str, usize) -> bool",
+            "fn()",
+            "Box<dyn Error + Send + Sync>",
+            "impl FnMut(&str) -> bool + Clone",
+            "impl FnOnce()",
+            "<T as Iterator>::Item",
+            "Option<<T as Deref>::Target>",
+        ];
+        for s in tys.iter() {
+            assert_eq!(TypeRepr::parse(s).to_string(), *s);
+            assert_eq!(TypeRepr::parse(&TypeRepr::parse(s).to_string()), TypeRepr::parse(s));
+        }
+        // Lifetimes and ABIs are dropped
+        assert_eq!(TypeRepr::parse("&'a Cow<'a, str>").to_string(), "&Cow<str>");
+        assert_eq!(TypeRepr::parse("extern \"C\" fn(i32) -> i32").to_string(), "fn(i32) -> i32");
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+use tracing::{info, warn};
+
+use reeves_types::*;
+
+use crate::{CompactionStats, Db, DbStats, EnqueueResult, NoProgress, TextSearchSettings, compact_fn_sets, db_stats, enqueue_analysis, load_text_search, remove_crate, save_analysis, save_analysis_error, update_search_settings};
+
+// sled only lets one process open the DB, so a server can take changes to it from other processes
+// (e.g. `analyze-and-save` while serving) over a unix socket. Each line sent is a JSON request,
+// answered by a line with a JSON response
+#[derive(Serialize, Deserialize)]
+enum AdminRequest {
+    SaveAnalysis { meta: CrateMeta, fndetails: Vec<FnDetail> },
+    SaveAnalysisError { name: String, version: String, err: String },
+    Purge { name: String, version: Option<String> },
+    Enqueue { name: String, version: String },
+    LoadTextSearch { full: bool },
+    UpdateSearchSettings { settings: TextSearchSettings },
+    DbStats,
+    Compact,
+}
+
+#[derive(Serialize, Deserialize)]
+enum AdminResponse {
+    Saved,
+    Purged(Vec<String>), // versions
+    Enqueued(EnqueueResult),
+    Loaded,
+    SettingsUpdated,
+    Stats(DbStats),
+    Compacted(CompactionStats),
+    Error(String),
+}
+
+// Listens on a background thread, handling each connection on its own thread
+pub fn serve_admin(db: Db, socket_path: &Path) -> Result<()> {
+    if socket_path.exists() {
+        // Left behind by a server that didn't exit cleanly, unless it's still running
+        if UnixStream::connect(socket_path).is_ok() {
+            bail!("admin socket {} is already in use by another server", socket_path.display())
+        }
+        fs::remove_file(socket_path)?;
+    }
+    let listener = UnixListener::bind(socket_path)
+        .with_context(|| format!("failed to listen on admin socket {}", socket_path.display()))?;
+    info!("listening for admin requests on {}", socket_path.display());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("failed to accept admin connection: {}", e);
+                    continue
+                },
+            };
+            let db = db.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_admin_connection(&db, stream) {
+                    warn!("admin connection failed: {:?}", e)
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle_admin_connection(db: &Db, stream: UnixStream) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let res = match serde_json::from_str(&line?) {
+            Ok(req) => handle_admin_request(db, req),
+            Err(e) => AdminResponse::Error(format!("malformed request: {}", e)),
+        };
+        serde_json::to_writer(&mut writer, &res)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn handle_admin_request(db: &Db, req: AdminRequest) -> AdminResponse {
+    match req {
+        AdminRequest::SaveAnalysis { meta, fndetails } => {
+            info!("saving {} fns for {} {} from admin socket", fndetails.len(), meta.name, meta.version);
+            save_analysis(db, &meta.name, &meta.version, &meta, fndetails);
+            AdminResponse::Saved
+        },
+        AdminRequest::SaveAnalysisError { name, version, err } => {
+            info!("saving analysis error for {} {} from admin socket", name, version);
+            save_analysis_error(db, &name, &version, &err);
+            AdminResponse::Saved
+        },
+        AdminRequest::Purge { name, version } => {
+            info!("purging {} from admin socket", name);
+            AdminResponse::Purged(remove_crate(db, &name, version.as_deref()))
+        },
+        AdminRequest::Enqueue { name, version } => AdminResponse::Enqueued(enqueue_analysis(db, &name, &version)),
+        AdminRequest::LoadTextSearch { full } => {
+            info!("loading text search from admin socket");
+            load_text_search(db, full, &NoProgress);
+            AdminResponse::Loaded
+        },
+        AdminRequest::UpdateSearchSettings { settings } => {
+            info!("updating text search settings from admin socket");
+            match update_search_settings(db, &settings) {
+                Ok(()) => AdminResponse::SettingsUpdated,
+                Err(e) => AdminResponse::Error(format!("{:?}", e)),
+            }
+        },
+        AdminRequest::DbStats => AdminResponse::Stats(db_stats(db)),
+        AdminRequest::Compact => {
+            info!("compacting fn id sets from admin socket");
+            AdminResponse::Compacted(compact_fn_sets(db))
+        },
+    }
+}
+
+// For making changes to a DB held open by a server listening with `serve_admin`
+pub struct AdminClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl AdminClient {
+    pub fn connect(socket_path: &Path) -> Result<Self> {
+        let writer = UnixStream::connect(socket_path)
+            .with_context(|| format!("failed to connect to admin socket {}, is the server listening on it?", socket_path.display()))?;
+        Ok(Self { reader: BufReader::new(writer.try_clone()?), writer })
+    }
+
+    pub fn save_analysis(&mut self, meta: &CrateMeta, fndetails: Vec<FnDetail>) -> Result<()> {
+        match self.request(&AdminRequest::SaveAnalysis { meta: meta.clone(), fndetails })? {
+            AdminResponse::Saved => Ok(()),
+            _ => Err(anyhow!("unexpected response to saving analysis")),
+        }
+    }
+
+    pub fn save_analysis_error(&mut self, name: &str, version: &str, err: &str) -> Result<()> {
+        match self.request(&AdminRequest::SaveAnalysisError { name: name.to_owned(), version: version.to_owned(), err: err.to_owned() })? {
+            AdminResponse::Saved => Ok(()),
+            _ => Err(anyhow!("unexpected response to saving analysis error")),
+        }
+    }
+
+    pub fn remove_crate(&mut self, name: &str, version: Option<&str>) -> Result<Vec<String>> {
+        match self.request(&AdminRequest::Purge { name: name.to_owned(), version: version.map(|v| v.to_owned()) })? {
+            AdminResponse::Purged(versions) => Ok(versions),
+            _ => Err(anyhow!("unexpected response to purge")),
+        }
+    }
+
+    pub fn enqueue_analysis(&mut self, name: &str, version: &str) -> Result<EnqueueResult> {
+        match self.request(&AdminRequest::Enqueue { name: name.to_owned(), version: version.to_owned() })? {
+            AdminResponse::Enqueued(res) => Ok(res),
+            _ => Err(anyhow!("unexpected response to enqueue")),
+        }
+    }
+
+    pub fn load_text_search(&mut self, full: bool) -> Result<()> {
+        match self.request(&AdminRequest::LoadTextSearch { full })? {
+            AdminResponse::Loaded => Ok(()),
+            _ => Err(anyhow!("unexpected response to loading text search")),
+        }
+    }
+
+    pub fn update_search_settings(&mut self, settings: &TextSearchSettings) -> Result<()> {
+        match self.request(&AdminRequest::UpdateSearchSettings { settings: settings.clone() })? {
+            AdminResponse::SettingsUpdated => Ok(()),
+            _ => Err(anyhow!("unexpected response to updating text search settings")),
+        }
+    }
+
+    pub fn db_stats(&mut self) -> Result<DbStats> {
+        match self.request(&AdminRequest::DbStats)? {
+            AdminResponse::Stats(stats) => Ok(stats),
+            _ => Err(anyhow!("unexpected response to DB stats")),
+        }
+    }
+
+    pub fn compact_fn_sets(&mut self) -> Result<CompactionStats> {
+        match self.request(&AdminRequest::Compact)? {
+            AdminResponse::Compacted(stats) => Ok(stats),
+            _ => Err(anyhow!("unexpected response to compaction")),
+        }
+    }
+
+    fn request(&mut self, req: &AdminRequest) -> Result<AdminResponse> {
+        serde_json::to_writer(&mut self.writer, req)?;
+        self.writer.write_all(b"\n")?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("server closed the admin connection")
+        }
+        match serde_json::from_str(&line)? {
+            AdminResponse::Error(e) => Err(anyhow!("server failed to handle request: {}", e)),
+            res => Ok(res),
+        }
+    }
+}
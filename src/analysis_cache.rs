@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use reeves_types::*;
+
+use crate::{AnalysisOptions, ManifestPackage, new_crate_meta, stable_hash};
+use crate::schema::layout_hash;
+
+// Bumped whenever analysis finds something different for the same crate, e.g. resolving a type it
+// didn't before, so analyses cached before the change are redone
+const ANALYSIS_VERSION: u32 = 1;
+
+// Analyses of published crates, which never change, kept in a dir so saving one again (e.g. into a
+// new DB, or after a purge) skips loading it into rust-analyzer. Entries are keyed by the crate, the
+// version of the analysis, the layout of what's cached and the options that change what's found, so
+// analyzing with a changed analysis or different options misses the cache. Only successful analyses
+// are kept, as failures may not recur
+#[derive(Debug, Clone)]
+pub struct AnalysisCache {
+    dir: PathBuf,
+}
+
+impl AnalysisCache {
+    // The dir is created when the first analysis is cached
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_owned() }
+    }
+
+    fn entry_path(&self, krate_name: &str, krate_version: &str, opts: &AnalysisOptions) -> PathBuf {
+        let opts_key = format!("{:?} {:?} {} {} {} {} {} {:?}", opts.features, opts.profile, opts.build_scripts, opts.proc_macros,
+                               opts.include_private, opts.deref_methods, opts.bins, opts.frontend);
+        let version_key = format!("{} {:016x}", ANALYSIS_VERSION, layout_hash());
+        let key = stable_hash(&[&version_key, &opts_key]);
+        self.dir.join(format!("{}-{}-{:016x}.bin", krate_name, krate_version, key))
+    }
+
+    // The crate meta is as new, i.e. indexed now
+    pub(crate) fn get(&self, krate_name: &str, krate_version: &str, opts: &AnalysisOptions) -> Option<(CrateMeta, Vec<FnDetail>)> {
+        let path = self.entry_path(krate_name, krate_version, opts);
+        let bytes = fs::read(&path).ok()?;
+        match bincode::deserialize::<(CrateMeta, Vec<FnDetail>)>(&bytes) {
+            Ok((meta, fndetails)) => {
+                info!("using cached analysis of {} {} from {}", krate_name, krate_version, path.display());
+                let CrateMeta { license, repository, categories, keywords, .. } = meta;
+                let package = ManifestPackage { license, repository, categories, keywords };
+                Some((new_crate_meta(krate_name, krate_version, package), fndetails))
+            },
+            // e.g. truncated, or changed in a way the layout hash doesn't catch
+            Err(e) => {
+                warn!("ignoring unreadable cached analysis {}: {}", path.display(), e);
+                None
+            },
+        }
+    }
+
+    // Failing to cache an analysis isn't worth failing it for
+    pub(crate) fn put(&self, meta: &CrateMeta, opts: &AnalysisOptions, fndetails: &[FnDetail]) {
+        let path = self.entry_path(&meta.name, &meta.version, opts);
+        // Written to one side first, so a concurrent `get` never reads part of an entry
+        let tmp_path = path.with_extension("tmp");
+        let res = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&tmp_path, bincode::serialize(&(meta, fndetails)).unwrap()))
+            .and_then(|()| fs::rename(&tmp_path, &path));
+        match res {
+            Ok(()) => info!("cached analysis of {} {} in {}", meta.name, meta.version, path.display()),
+            Err(e) => warn!("failed to cache analysis of {} {} in {}: {}", meta.name, meta.version, path.display(), e),
+        }
+    }
+}
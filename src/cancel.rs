@@ -0,0 +1,74 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use reeves_types::FnDetail;
+
+// Shared with whoever might want to stop an analysis, e.g. on shutdown
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    Cancelled,
+    TimedOut(Duration),
+}
+
+// The error an analysis fails with when it's stopped early. Callers can get at it by downcasting,
+// e.g. to use the partial results
+#[derive(Debug)]
+pub struct AnalysisCancelled {
+    pub reason: CancelReason,
+    // Whatever had been found when it stopped - always empty if the workspace hadn't finished loading
+    pub partial: Vec<FnDetail>,
+}
+
+impl fmt::Display for AnalysisCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            CancelReason::Cancelled => write!(f, "analysis cancelled")?,
+            CancelReason::TimedOut(timeout) => write!(f, "analysis timed out after {:?}", timeout)?,
+        }
+        write!(f, " ({} items found before stopping)", self.partial.len())
+    }
+}
+
+impl std::error::Error for AnalysisCancelled {}
+
+// The token and deadline for one analysis, checked between units of work
+#[derive(Clone)]
+pub(crate) struct Abort {
+    token: CancelToken,
+    timeout: Option<(Duration, Instant)>, // (timeout, deadline)
+}
+
+impl Abort {
+    pub(crate) fn start(token: &CancelToken, timeout: Option<Duration>) -> Self {
+        Self { token: token.clone(), timeout: timeout.map(|t| (t, Instant::now() + t)) }
+    }
+
+    pub(crate) fn check(&self) -> Option<CancelReason> {
+        if self.token.is_cancelled() {
+            return Some(CancelReason::Cancelled)
+        }
+        match self.timeout {
+            Some((timeout, deadline)) if Instant::now() >= deadline => Some(CancelReason::TimedOut(timeout)),
+            _ => None,
+        }
+    }
+}
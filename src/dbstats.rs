@@ -0,0 +1,47 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{Db, FN_SET_TREES};
+use crate::fnidset::decode_fn_ids;
+
+// How many of the largest sets of each tree of `FN_SET_TREES` are listed
+const NUM_LARGEST_SETS: usize = 5;
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct DbStats {
+    pub trees: Vec<TreeStats>, // by name
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct TreeStats {
+    pub name: String,
+    pub num_keys: usize,
+    pub key_bytes: usize,
+    pub value_bytes: usize,
+    // For trees of fn id sets, the keys with the most fns (largest first) - e.g. the types that make
+    // searches for them slow
+    pub largest_sets: Vec<(String, u64)>, // (key, num fns)
+}
+
+// Sizes are of the keys and values as stored, not including the store's own overheads
+pub fn db_stats(db: &Db) -> DbStats {
+    let mut names = db.tree_names();
+    names.sort();
+    let trees = names.into_iter().map(|name| {
+        let is_fn_set_tree = FN_SET_TREES.iter().any(|(tree_name, _)| *tree_name == name);
+        let mut stats = TreeStats { name, num_keys: 0, key_bytes: 0, value_bytes: 0, largest_sets: vec![] };
+        for (key, val) in db.open_tree(&stats.name).iter() {
+            stats.num_keys += 1;
+            stats.key_bytes += key.len();
+            stats.value_bytes += val.len();
+            if is_fn_set_tree {
+                stats.largest_sets.push((String::from_utf8_lossy(&key).into_owned(), decode_fn_ids(&val).len()));
+                stats.largest_sets.sort_by(|(_, n1), (_, n2)| n2.cmp(n1));
+                stats.largest_sets.truncate(NUM_LARGEST_SETS);
+            }
+        }
+        stats
+    }).collect();
+    DbStats { trees }
+}
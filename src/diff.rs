@@ -0,0 +1,80 @@
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use reeves_types::*;
+
+use crate::{AnalysisOptions, CRATE_TREE, Db, FN_TREE, ProgressSink, analyze_crate_path, crate_key, has_crate, latest_crate_version};
+use crate::fnrecord::FnRecord;
+
+// How a fresh analysis of a crate differs from what's indexed for it, e.g. to review how an API has
+// evolved, or to check a new rust-analyzer finds the same fns
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct CrateDiff {
+    pub name: String,
+    pub version: String, // as analyzed
+    pub base_version: Option<String>, // compared against, None if no version is indexed
+    pub added: Vec<FnDetail>,
+    pub removed: Vec<FnDetail>,
+    pub changed: Vec<FnChange>,
+}
+
+// A fn at the same path with a different signature
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct FnChange {
+    pub old: FnDetail,
+    pub new: FnDetail,
+}
+
+// Analyzes the crate at the path without saving anything
+pub fn analyze_diff(db: &Db, path: &Path, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> Result<CrateDiff> {
+    let (name, version, res) = analyze_crate_path(path, opts, progress);
+    Ok(diff_crate(db, &name, &version, res?))
+}
+
+// Against the same version if it's indexed, otherwise the latest indexed version
+pub fn diff_crate(db: &Db, name: &str, version: &str, fndetails: Vec<FnDetail>) -> CrateDiff {
+    let base_version = if has_crate(db, name, version) { Some(version.to_owned()) } else { latest_crate_version(db, name) };
+    let base_fndetails = match &base_version {
+        Some(base_version) => indexed_fns(db, name, base_version),
+        None => vec![],
+    };
+
+    // Fns are matched up by kind and path, which may be shared by several (e.g. trait methods implemented
+    // for different types). Identical signatures are paired off first, then any left are changes
+    let mut by_path: BTreeMap<(String, String), (Vec<FnDetail>, Vec<FnDetail>)> = BTreeMap::new(); // (kind, path) => (old, new)
+    for fndetail in base_fndetails {
+        by_path.entry((format!("{:?}", fndetail.kind), fndetail.path.clone())).or_default().0.push(fndetail)
+    }
+    for fndetail in fndetails {
+        by_path.entry((format!("{:?}", fndetail.kind), fndetail.path.clone())).or_default().1.push(fndetail)
+    }
+    let mut diff = CrateDiff { name: name.to_owned(), version: version.to_owned(), base_version, added: vec![], removed: vec![], changed: vec![] };
+    for (_, (mut old, mut new)) in by_path {
+        old.retain(|old_fndetail| match new.iter().position(|new_fndetail| new_fndetail.s == old_fndetail.s) {
+            Some(i) => { new.remove(i); false },
+            None => true,
+        });
+        old.sort_by(|f1, f2| f1.s.cmp(&f2.s));
+        new.sort_by(|f1, f2| f1.s.cmp(&f2.s));
+        let num_changed = old.len().min(new.len());
+        let removed = old.split_off(num_changed);
+        let added = new.split_off(num_changed);
+        diff.changed.extend(old.into_iter().zip(new).map(|(old, new)| FnChange { old, new }));
+        diff.removed.extend(removed);
+        diff.added.extend(added);
+    }
+    diff
+}
+
+fn indexed_fns(db: &Db, name: &str, version: &str) -> Vec<FnDetail> {
+    let fn_tree = db.open_tree(FN_TREE);
+    let fn_ids: Vec<u64> = db.open_tree(CRATE_TREE).get(crate_key(name, version))
+        .map_or_else(Vec::new, |bytes| bincode::deserialize(&bytes).unwrap());
+    fn_ids.into_iter()
+        .map(|fn_id| FnRecord::new(fn_tree.get(bincode::serialize(&fn_id).unwrap()).unwrap()).to_fndetail())
+        .collect()
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context, Result, bail};
+use isahc::prelude::*;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+use reeves_types::*;
+
+use crate::{AnalysisOptions, ProgressSink, analyze_crate_path, read_crate_meta};
+
+// Serves the same tarballs as the crates.io API download endpoint, without the redirect
+const CRATES_IO_DOWNLOAD_URL: &str = "https://static.crates.io/crates";
+const CRATES_IO_API_URL: &str = "https://crates.io/api/v1/crates";
+// crates.io rejects API requests without one
+const USER_AGENT: &str = concat!("reeves/", env!("CARGO_PKG_VERSION"));
+
+// The newest stable version of a crate on crates.io, or the newest of any kind if it has no stable ones
+pub fn crates_io_latest_version(krate_name: &str) -> Result<String> {
+    #[derive(Deserialize)]
+    struct CrateResponse {
+        #[serde(rename = "crate")]
+        krate: CrateInfo,
+    }
+    #[derive(Deserialize)]
+    struct CrateInfo {
+        max_stable_version: Option<String>,
+        max_version: String,
+    }
+
+    let url = format!("{}/{}", CRATES_IO_API_URL, krate_name);
+    let mut res = isahc::Request::get(&url).header("User-Agent", USER_AGENT).body(())?.send()
+        .with_context(|| format!("failed to fetch {}", url))?;
+    if !res.status().is_success() {
+        bail!("failed to fetch {}: {}", url, res.status())
+    }
+    let info = res.json::<CrateResponse>().with_context(|| format!("unexpected response from {}", url))?.krate;
+    Ok(info.max_stable_version.unwrap_or(info.max_version))
+}
+
+// Download a crate from crates.io and analyze it as for `analyze_from_tarball`
+pub fn analyze_from_crates_io(krate_name: &str, krate_version: &str, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> Result<(CrateMeta, Result<Vec<FnDetail>>)> {
+    // Saves downloading it too
+    if let Some((meta, fndetails)) = opts.cache.as_ref().and_then(|cache| cache.get(krate_name, krate_version, opts)) {
+        return Ok((meta, Ok(fndetails)))
+    }
+    let url = format!("{}/{}/{}-{}.crate", CRATES_IO_DOWNLOAD_URL, krate_name, krate_name, krate_version);
+    info!("downloading {}", url);
+    let mut res = isahc::get(&url).with_context(|| format!("failed to download {}", url))?;
+    if !res.status().is_success() {
+        bail!("failed to download {}: {}", url, res.status())
+    }
+    let download_dir = tempfile::tempdir()?;
+    let tarball_path = download_dir.path().join(format!("{}-{}.crate", krate_name, krate_version));
+    let mut tarball = fs::File::create(&tarball_path)?;
+    res.copy_to(&mut tarball).with_context(|| format!("failed to download {}", url))?;
+    drop(tarball);
+
+    let (meta, fndetails) = analyze_from_tarball(&tarball_path, opts, progress)?;
+    if meta.name != krate_name || meta.version != krate_version {
+        bail!("downloaded {} {} but the crate is {} {}", krate_name, krate_version, meta.name, meta.version)
+    }
+    Ok((meta, fndetails))
+}
+
+// Unpack a .crate (i.e. a gzipped tarball, as published to crates.io) into a temporary dir, generate a
+// lockfile and analyze it. Errors preparing the crate are returned in the outer result, analysis errors
+// in the inner one (as for `analyze_crate_path`), so callers can save the latter
+pub fn analyze_from_tarball(path: &Path, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> Result<(CrateMeta, Result<Vec<FnDetail>>)> {
+    let work_dir = tempfile::tempdir()?;
+    let tarball = fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    tar::Archive::new(flate2::read::GzDecoder::new(tarball)).unpack(work_dir.path())
+        .with_context(|| format!("failed to unpack {}", path.display()))?;
+    let crate_path = unpacked_crate_dir(work_dir.path())
+        .with_context(|| format!("unexpected contents in {}", path.display()))?;
+    let cache = opts.cache.as_ref();
+    let package_id = if cache.is_some() { read_package_id(&crate_path) } else { None };
+    if let (Some(cache), Some((krate_name, krate_version))) = (cache, package_id.as_ref()) {
+        if let Some((meta, fndetails)) = cache.get(krate_name, krate_version, opts) {
+            return Ok((meta, Ok(fndetails)))
+        }
+    }
+
+    // Published crates don't include a lockfile for libraries
+    let status = Command::new("cargo")
+        .arg("generate-lockfile")
+        .current_dir(&crate_path)
+        .status()
+        .context("failed to run cargo")?;
+    if !status.success() {
+        bail!("failed to generate lockfile for {}: {}", path.display(), status)
+    }
+
+    let (krate_name, krate_version, fndetails) = analyze_crate_path(&crate_path, opts, progress);
+    let meta = read_crate_meta(&crate_path, &krate_name, &krate_version);
+    if let (Some(cache), Ok(fndetails)) = (cache, fndetails.as_ref()) {
+        cache.put(&meta, opts, fndetails)
+    }
+    Ok((meta, fndetails))
+}
+
+// The name and version from the crate's manifest, for looking it up before analyzing it
+fn read_package_id(crate_path: &Path) -> Option<(String, String)> {
+    #[derive(Deserialize)]
+    struct Manifest {
+        package: ManifestPackage,
+    }
+    #[derive(Deserialize)]
+    struct ManifestPackage {
+        name: String,
+        version: String,
+    }
+
+    let manifest_path = crate_path.join("Cargo.toml");
+    let package = fs::read_to_string(&manifest_path).map_err(anyhow::Error::from)
+        .and_then(|manifest| Ok(toml::from_str::<Manifest>(&manifest)?.package));
+    match package {
+        Ok(package) => Some((package.name, package.version)),
+        Err(e) => {
+            warn!("failed to read crate name and version from {}: {:?}", manifest_path.display(), e);
+            None
+        },
+    }
+}
+
+// A .crate contains a single `<name>-<version>` dir
+fn unpacked_crate_dir(dir: &Path) -> Result<PathBuf> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path())
+    }
+    match entries.as_slice() {
+        [crate_path] if crate_path.is_dir() => Ok(crate_path.to_owned()),
+        _ => bail!("expected a single crate dir, found {} entries", entries.len()),
+    }
+}
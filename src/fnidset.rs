@@ -0,0 +1,269 @@
+use flate2::read::GzDecoder;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::info;
+
+use crate::{Db, FN_SET_TREES, FN_TREE, FnIdSet, TEXT_INDEXED_TREES};
+
+// How sets of fn ids are stored in `FN_SET_TREES`: a format byte, then the set in the roaring bitmap
+// serialization format, so it's read straight into the bitmaps searches intersect
+const FORMAT_ROARING: u8 = 2;
+// Before schema version 10, ids were stored in ascending order as LEB128 varints of the difference
+// from the id before, and gzipped if that was large. Only read when migrating
+const FORMAT_VARINT: u8 = 0;
+const FORMAT_VARINT_GZIP: u8 = 1;
+
+pub(crate) fn encode_fn_ids(fn_ids: &FnIdSet) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(1 + fn_ids.serialized_size());
+    bytes.push(FORMAT_ROARING);
+    fn_ids.serialize_into(&mut bytes).unwrap();
+    bytes
+}
+
+pub(crate) fn decode_fn_ids(bytes: &[u8]) -> FnIdSet {
+    let (&format, rest) = bytes.split_first().unwrap();
+    let mut gunzipped = vec![];
+    let mut varints = match format {
+        FORMAT_ROARING => return FnIdSet::deserialize_from(rest).unwrap(),
+        FORMAT_VARINT => rest,
+        FORMAT_VARINT_GZIP => {
+            GzDecoder::new(rest).read_to_end(&mut gunzipped).unwrap();
+            &gunzipped[..]
+        },
+        _ => panic!("unknown fn id set format {}", format),
+    };
+    let mut fn_ids = FnIdSet::new();
+    let mut prev = 0;
+    while !varints.is_empty() {
+        prev += read_varint(&mut varints);
+        fn_ids.insert(prev);
+    }
+    fn_ids
+}
+
+fn read_varint(bytes: &mut &[u8]) -> u64 {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = bytes.split_first().unwrap();
+        *bytes = rest;
+        n |= u64::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return n
+        }
+        shift += 7
+    }
+}
+
+// Sets this big (encoded) or bigger are kept decoded by `FnSetCache` - smaller ones decode quicker than
+// they'd be worth the memory
+const MIN_CACHED_SET_BYTES: usize = 4 * 1024;
+// Roughly how much memory `FnSetCache` may use (counting encoded sizes, which are close)
+const FN_SET_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+// Decoded fn id sets, e.g. of `&str` or `u32`, which are huge and searched for all the time, so
+// repeated searches don't re-read and re-decode them. The least recently used are dropped when it's
+// full. Shared by all handles to a store, which invalidate sets they write (see `Db`) - writes by
+// another process (only possible with SQLite) aren't seen until the set is dropped
+pub(crate) struct FnSetCache {
+    inner: Mutex<FnSetCacheInner>,
+}
+
+struct FnSetCacheInner {
+    sets: HashMap<(String, Vec<u8>), (Arc<FnIdSet>, usize, u64)>, // (tree, key) => (fn ids, encoded size, last used)
+    bytes: usize,
+    clock: u64, // for last used
+    // Bumped by every invalidation, so a set read before one isn't cached after it
+    generation: u64,
+}
+
+impl FnSetCache {
+    pub(crate) fn new() -> Self {
+        Self { inner: Mutex::new(FnSetCacheInner { sets: HashMap::new(), bytes: 0, clock: 0, generation: 0 }) }
+    }
+
+    pub(crate) fn get_or_load(&self, tree: &str, key: &[u8], load: impl FnOnce() -> Option<Vec<u8>>) -> Option<Arc<FnIdSet>> {
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some((fn_ids, _, last_used)) = inner.sets.get_mut(&(tree.to_owned(), key.to_vec())) {
+                *last_used = clock;
+                return Some(fn_ids.clone())
+            }
+            inner.generation
+        };
+        // Not holding the lock while decoding - at worst, concurrent searches both decode a set
+        let bytes = load()?;
+        let fn_ids = Arc::new(decode_fn_ids(&bytes));
+        if bytes.len() < MIN_CACHED_SET_BYTES || bytes.len() > FN_SET_CACHE_BYTES {
+            return Some(fn_ids)
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation {
+            return Some(fn_ids)
+        }
+        while inner.bytes + bytes.len() > FN_SET_CACHE_BYTES {
+            let lru = inner.sets.iter().min_by_key(|(_, (_, _, last_used))| *last_used).map(|(k, _)| k.clone()).unwrap();
+            let (_, size, _) = inner.sets.remove(&lru).unwrap();
+            inner.bytes -= size;
+        }
+        let clock = inner.clock;
+        inner.bytes += bytes.len();
+        if let Some((_, size, _)) = inner.sets.insert((tree.to_owned(), key.to_vec()), (fn_ids.clone(), bytes.len(), clock)) {
+            inner.bytes -= size;
+        }
+        Some(fn_ids)
+    }
+
+    pub(crate) fn invalidate(&self, tree: &str, key: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        if let Some((_, size, _)) = inner.sets.remove(&(tree.to_owned(), key.to_vec())) {
+            inner.bytes -= size;
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.bytes >= FN_SET_CACHE_BYTES / 2
+    }
+}
+
+// Loads the biggest sets of the trees searches read from into the cache, so the first searches after
+// starting a server are as quick as later ones. Stops at half the cache, leaving the rest for
+// whatever's searched for
+pub fn warm_fn_set_cache(db: &Db) {
+    let mut sizes: Vec<(usize, &str, Vec<u8>)> = vec![]; // (encoded size, tree, key)
+    for &(_, tree_name, _, _) in TEXT_INDEXED_TREES {
+        for (key, val) in db.open_tree(tree_name).iter() {
+            if val.len() >= MIN_CACHED_SET_BYTES {
+                sizes.push((val.len(), tree_name, key))
+            }
+        }
+    }
+    sizes.sort_by(|(size1, _, _), (size2, _, _)| size2.cmp(size1));
+    let mut num_sets = 0;
+    for (_, tree_name, key) in sizes {
+        if db.fn_set_cache().is_full() {
+            break
+        }
+        db.open_tree(tree_name).get_fn_ids(&key);
+        num_sets += 1;
+    }
+    info!("warmed the fn id set cache with {} sets", num_sets);
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct CompactionStats {
+    pub num_sets: usize,
+    pub removed_sets: usize, // with no fns left
+    pub removed_fn_ids: usize, // of fns no longer stored
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+// Rewrites every fn id set, dropping ids of fns that are no longer stored and sets left empty, and
+// re-encoding the rest (e.g. any in an older format). Each set is rewritten in its own
+// transaction, so this can run alongside searches and analyses
+pub fn compact_fn_sets(db: &Db) -> CompactionStats {
+    let mut stats = CompactionStats::default();
+    for (tree_name, _) in FN_SET_TREES.iter() {
+        let tree = db.open_tree(tree_name);
+        let keys: Vec<Vec<u8>> = tree.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            let (before, after, removed_fn_ids) = db.transaction(&[tree_name, FN_TREE], |tx| {
+                let bytes = match tx.get(tree_name, &key)? {
+                    Some(bytes) => bytes,
+                    // Removed since listing the keys
+                    None => return Ok((0, 0, 0)),
+                };
+                let fn_ids = decode_fn_ids(&bytes);
+                let mut kept = FnIdSet::new();
+                for fn_id in fn_ids.iter() {
+                    if tx.get(FN_TREE, &bincode::serialize(&fn_id).unwrap())?.is_some() {
+                        kept.insert(fn_id);
+                    }
+                }
+                let removed_fn_ids = (fn_ids.len() - kept.len()) as usize;
+                if kept.is_empty() {
+                    tx.remove(tree_name, &key)?;
+                    return Ok((bytes.len(), 0, removed_fn_ids))
+                }
+                let compacted = encode_fn_ids(&kept);
+                if compacted != bytes {
+                    tx.insert(tree_name, &key, &compacted)?;
+                }
+                Ok((bytes.len(), compacted.len(), removed_fn_ids))
+            });
+            stats.num_sets += 1;
+            stats.removed_sets += (before > 0 && after == 0) as usize;
+            stats.removed_fn_ids += removed_fn_ids;
+            stats.bytes_before += before;
+            stats.bytes_after += after;
+        }
+        info!("compacted fn id sets in {}", tree_name);
+    }
+    stats
+}
+
+// Compacts every `interval` forever, e.g. on a thread alongside a server
+pub fn run_compaction(db: &Db, interval: Duration) -> ! {
+    loop {
+        thread::sleep(interval);
+        let stats = compact_fn_sets(db);
+        info!("compacted {} fn id sets from {} to {} bytes, removing {} stale fn ids and {} empty sets",
+              stats.num_sets, stats.bytes_before, stats.bytes_after, stats.removed_fn_ids, stats.removed_sets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for fn_ids in [vec![], vec![0], vec![1, 5, 300, u64::from(u32::MAX) + 1, u64::MAX]].iter() {
+            let set: FnIdSet = fn_ids.iter().cloned().collect();
+            let bytes = encode_fn_ids(&set);
+            assert_eq!(bytes[0], FORMAT_ROARING);
+            assert_eq!(decode_fn_ids(&bytes), set);
+        }
+    }
+
+    #[test]
+    fn decodes_varints() {
+        // 1, 5, 300 as differences 1, 4, 295 - the last taking two bytes
+        let varints = [1, 4, 0xa7, 0x02];
+        let expected: FnIdSet = [1, 5, 300].iter().cloned().collect();
+        let mut bytes = vec![FORMAT_VARINT];
+        bytes.extend_from_slice(&varints);
+        assert_eq!(decode_fn_ids(&bytes), expected);
+
+        let mut gz = GzEncoder::new(vec![FORMAT_VARINT_GZIP], Compression::default());
+        gz.write_all(&varints).unwrap();
+        assert_eq!(decode_fn_ids(&gz.finish().unwrap()), expected);
+    }
+
+    #[test]
+    fn cache_invalidates() {
+        let cache = FnSetCache::new();
+        let big: FnIdSet = (0..10_000).map(|i| i * 3).collect();
+        let bytes = encode_fn_ids(&big);
+        assert!(bytes.len() >= MIN_CACHED_SET_BYTES);
+        assert_eq!(*cache.get_or_load("t", b"k", || Some(bytes.clone())).unwrap(), big);
+        // Cached, so not loaded again
+        assert_eq!(*cache.get_or_load("t", b"k", || panic!("loaded a cached set")).unwrap(), big);
+        cache.invalidate("t", b"k");
+        assert!(cache.get_or_load("t", b"k", || None).is_none());
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Serialize, Deserialize};
+use std::convert::TryInto;
+use std::time::Instant;
+
+use reeves_types::*;
+
+use crate::{Db, FN_TREE};
+
+// How fns are stored in `FN_TREE`. Each field of the `FnDetail` is bincode-encoded on its own,
+// after a table of where each one ends (u32 little endian, relative to the start of the first), so
+// a search can check the few fields it filters and orders by for every fn it finds without
+// decoding the rest, like the parsed types
+pub(crate) struct FnRecord {
+    bytes: Vec<u8>,
+}
+
+// Fields by position in the record, which must not change without a `SCHEMA_VERSION` bump. Plain
+// strings are borrowed from the record rather than copied
+macro_rules! fn_record_fields {
+    ($($i:literal => $field:ident: $ty:ty,)*) => {
+        const NUM_FIELDS: usize = [$($i),*].len();
+
+        pub(crate) fn encode_fn(fndetail: &FnDetail) -> Vec<u8> {
+            let fields = [$(bincode::serialize(&fndetail.$field).unwrap()),*];
+            let mut bytes = Vec::with_capacity(NUM_FIELDS * 4 + fields.iter().map(Vec::len).sum::<usize>());
+            let mut end = 0;
+            for field in fields.iter() {
+                end += field.len() as u32;
+                bytes.extend_from_slice(&end.to_le_bytes())
+            }
+            for field in fields.iter() {
+                bytes.extend_from_slice(field)
+            }
+            bytes
+        }
+
+        impl FnRecord {
+            $(pub(crate) fn $field(&self) -> $ty { self.field($i) })*
+
+            // Every field must be listed above for this to compile
+            #[allow(clippy::useless_conversion)]
+            pub(crate) fn to_fndetail(&self) -> FnDetail {
+                FnDetail { $($field: self.$field().into(),)* }
+            }
+        }
+    };
+}
+
+fn_record_fields! {
+    0 => krate: &str,
+    1 => version: &str,
+    2 => kind: ItemKind,
+    3 => visibility: ItemVisibility,
+    4 => path: &str,
+    5 => aliases: Vec<String>,
+    6 => params: Vec<String>,
+    7 => ret: &str,
+    8 => param_reprs: Vec<TypeRepr>,
+    9 => ret_repr: TypeRepr,
+    10 => written_params: Option<Vec<String>>,
+    11 => written_ret: Option<String>,
+    12 => ret_impls: Vec<String>,
+    13 => generics: Vec<GenericParamDetail>,
+    14 => is_const: bool,
+    15 => is_async: bool,
+    16 => is_unsafe: bool,
+    17 => features: Vec<String>,
+    18 => doc: Option<String>,
+    19 => location: Option<SourceLocation>,
+    20 => deref_from: Option<String>,
+    21 => deprecated: Option<String>,
+    22 => unstable: Option<String>,
+    23 => s: &str,
+    24 => import_path: &str,
+    25 => profile: AnalysisProfile,
+}
+
+impl FnRecord {
+    pub(crate) fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    fn field<'a, T: Deserialize<'a>>(&'a self, i: usize) -> T {
+        let end = |i: usize| u32::from_le_bytes(self.bytes[i * 4..i * 4 + 4].try_into().unwrap()) as usize;
+        let start = if i == 0 { 0 } else { end(i - 1) };
+        let fields = &self.bytes[NUM_FIELDS * 4..];
+        bincode::deserialize(&fields[start..end(i)]).unwrap()
+    }
+
+    pub(crate) fn matches(&self, filters: &SearchFilters) -> bool {
+        let params = self.params();
+        filters.matches_fields(&FilterFields {
+            krate: self.krate(),
+            version: self.version(),
+            params: &params,
+            ret: self.ret(),
+            is_const: self.is_const(),
+            is_async: self.is_async(),
+            is_unsafe: self.is_unsafe(),
+            deprecated: self.deprecated().is_some(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct DecodeBench {
+    pub num_fns: usize,
+    pub full_ms: f64, // decoding whole fns
+    pub filter_ms: f64, // decoding what searches filter and order fns by
+}
+
+// Times decoding stored fns the ways searches do, averaged over the iterations
+pub fn bench_fn_decoding(db: &Db, max_fns: usize, iterations: usize) -> DecodeBench {
+    let records: Vec<FnRecord> = db.open_tree(FN_TREE).iter().take(max_fns)
+        .map(|(_key, val)| FnRecord::new(val))
+        .collect();
+    let filters = SearchFilters::default();
+    let time = |f: &dyn Fn(&FnRecord) -> bool| {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            // Use the result, so the decoding isn't optimized away
+            let n = records.iter().filter(|record| f(record)).count();
+            assert!(n <= records.len());
+        }
+        start.elapsed().as_secs_f64() * 1000.0 / iterations as f64
+    };
+    let full_ms = time(&|record| {
+        let fndetail = record.to_fndetail();
+        filters.matches(&fndetail) && !fndetail.s.is_empty()
+    });
+    let filter_ms = time(&|record| record.matches(&filters) && !record.s().is_empty());
+    DecodeBench { num_fns: records.len(), full_ms, filter_ms }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fndetail;
+
+    #[test]
+    fn fields_round_trip() {
+        let mut fndetail = fndetail("a", "parse", &["&str", "u8"], "Result<u32, Error>");
+        fndetail.aliases = vec!["a::prelude::parse".to_owned()];
+        fndetail.is_async = true;
+        fndetail.doc = Some("Parses a number".to_owned());
+        fndetail.location = Some(SourceLocation { file: "src/lib.rs".to_owned(), line: 3, character: 7 });
+        let record = FnRecord::new(encode_fn(&fndetail));
+        assert_eq!(record.krate(), "a");
+        assert_eq!(record.path(), "a::parse");
+        assert_eq!(record.params(), vec!["&str", "u8"]);
+        assert_eq!(record.ret(), "Result<u32, Error>");
+        assert_eq!(record.ret_repr(), TypeRepr::parse("Result<u32, Error>"));
+        assert_eq!(record.aliases(), fndetail.aliases);
+        assert!(record.is_async() && !record.is_const());
+        assert_eq!(record.doc(), fndetail.doc);
+        assert_eq!(record.location(), fndetail.location);
+        assert_eq!(record.s(), fndetail.s);
+        assert_eq!(record.profile(), AnalysisProfile::default());
+        assert_eq!(bincode::serialize(&record.to_fndetail()).unwrap(), bincode::serialize(&fndetail).unwrap());
+    }
+
+    #[test]
+    fn field_offsets() {
+        let fndetail = fndetail("a", "len", &["&str"], "usize");
+        let bytes = encode_fn(&fndetail);
+        let ends: Vec<usize> = bytes[..NUM_FIELDS * 4].chunks(4)
+            .map(|end| u32::from_le_bytes(end.try_into().unwrap()) as usize)
+            .collect();
+        assert!(ends.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(NUM_FIELDS * 4 + ends[NUM_FIELDS - 1], bytes.len());
+        // The first field is the crate name
+        assert_eq!(&bytes[NUM_FIELDS * 4..NUM_FIELDS * 4 + ends[0]], &bincode::serialize("a").unwrap()[..]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+use std::mem;
+use std::thread;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use reeves::Db;
+use reeves_types::*;
+
+mod pb {
+    tonic::include_proto!("reeves");
+}
+
+use pb::reeves_server::{Reeves, ReevesServer};
+
+// How many batches can be waiting to be sent before searching pauses
+const SEARCH_BATCH_BUFFER: usize = 4;
+
+struct ReevesService {
+    db: Db,
+}
+
+fn to_pb_fndetail(fndetail: FnDetail) -> pb::FnDetail {
+    let FnDetail { krate, version, path, params, ret, features, doc, s, .. } = fndetail;
+    pb::FnDetail { krate, version, path, params, ret, features, s, doc: doc.unwrap_or_default() }
+}
+
+fn to_pb_crate_meta(meta: CrateMeta) -> pb::CrateMeta {
+    let CrateMeta { name, version, license, repository, docsrs_url, indexed_at, categories, keywords } = meta;
+    pb::CrateMeta { name, version, license: license.unwrap_or_default(), repository: repository.unwrap_or_default(), docsrs_url, indexed_at, categories, keywords }
+}
+
+#[tonic::async_trait]
+impl Reeves for ReevesService {
+    type SearchStream = ReceiverStream<Result<pb::SearchBatch, Status>>;
+
+    async fn search(&self, request: Request<pb::SearchRequest>) -> Result<Response<Self::SearchStream>, Status> {
+        let req = request.into_inner();
+        let mut query = Query::parse(&req.query);
+        query.filters = SearchFilters {
+            versions: if req.latest_only { VersionFilter::Latest } else { VersionFilter::Any },
+            crates: if req.crates.is_empty() { None } else { Some(req.crates) },
+            exclude_crates: req.exclude_crates,
+            ignore_refs: req.ignore_refs,
+            categories: req.categories,
+            keywords: req.keywords,
+            ..query.filters
+        };
+        query.ret_impl = Some(req.ret_impl).filter(|s| !s.is_empty());
+        query.name = Some(req.name).filter(|s| !s.is_empty());
+        query.doc = Some(req.doc).filter(|s| !s.is_empty());
+        let ordered = req.ordered;
+        info!("streaming results for {:?}", req.query);
+
+        let db = self.db.clone();
+        let (tx, rx) = mpsc::channel(SEARCH_BATCH_BUFFER);
+        // Searching blocks, so send batches from a thread as they're found
+        thread::spawn(move || {
+            let stream = reeves::search_stream(&db, &query, ordered);
+            let mut warnings = stream.warnings().to_vec();
+            let mut sent_crates = HashSet::new();
+            let mut num_results = 0;
+            for batch in stream {
+                num_results += batch.len();
+                let crates = reeves::crate_metas_for(&db, &batch).into_iter()
+                    .filter(|meta| sent_crates.insert((meta.name.clone(), meta.version.clone())))
+                    .map(to_pb_crate_meta)
+                    .collect();
+                let batch = pb::SearchBatch { fndetails: batch.into_iter().map(to_pb_fndetail).collect(), warnings: mem::take(&mut warnings), crates };
+                if tx.blocking_send(Ok(batch)).is_err() {
+                    // Client went away
+                    return
+                }
+            }
+            reeves::record_search(&db, &reeves::SavedQuery::from_query(&query), num_results);
+            // Make sure warnings get to the client even with no results
+            if !warnings.is_empty() {
+                let _ = tx.blocking_send(Ok(pb::SearchBatch { fndetails: vec![], warnings, crates: vec![] }));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type ListCratesStream = tokio_stream::Iter<std::vec::IntoIter<Result<pb::CrateInfo, Status>>>;
+
+    async fn list_crates(&self, _request: Request<pb::ListCratesRequest>) -> Result<Response<Self::ListCratesStream>, Status> {
+        let krates: Vec<_> = reeves::list_crates(&self.db).into_iter()
+            .map(|(name, version, res)| {
+                let result = match res {
+                    Ok(num_fns) => pb::crate_info::Result::NumFns(num_fns as u64),
+                    Err(err) => pb::crate_info::Result::Error(err),
+                };
+                Ok(pb::CrateInfo { name, version, result: Some(result) })
+            })
+            .collect();
+        Ok(Response::new(tokio_stream::iter(krates)))
+    }
+
+    async fn submit_analysis(&self, request: Request<pb::SubmitAnalysisRequest>) -> Result<Response<pb::SubmitAnalysisResponse>, Status> {
+        let pb::SubmitAnalysisRequest { crate_name, crate_version } = request.into_inner();
+        if crate_name.is_empty() || crate_version.is_empty() {
+            return Err(Status::invalid_argument("crate name and version are required"))
+        }
+        let outcome = match reeves::enqueue_analysis(&self.db, &crate_name, &crate_version) {
+            reeves::EnqueueResult::Queued => pb::submit_analysis_response::Outcome::Queued,
+            reeves::EnqueueResult::AlreadyQueued => pb::submit_analysis_response::Outcome::AlreadyQueued,
+            reeves::EnqueueResult::AlreadyAnalyzed => pb::submit_analysis_response::Outcome::AlreadyAnalyzed,
+        };
+        info!("analysis submitted for {} {}: {:?}", crate_name, crate_version, outcome);
+        Ok(Response::new(pb::SubmitAnalysisResponse { outcome: outcome as i32 }))
+    }
+}
+
+pub fn serve(db: Db, addr: String) {
+    let service = ReevesService { db };
+    let addr = addr.parse().unwrap();
+
+    info!("gRPC server starting on {}", addr);
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        tonic::transport::Server::builder()
+            .add_service(ReevesServer::new(service))
+            .serve(addr)
+            .await
+    }).unwrap()
+}
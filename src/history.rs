@@ -0,0 +1,141 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::iter;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reeves_types::Query;
+
+use crate::{Db, stable_hash};
+use crate::typestats::record_type_searches;
+
+// Searches as they were made, so they can be run again, and so whoever runs the index can see what
+// people search for (e.g. to spot searches with no results that a synonym would help)
+const HISTORY_TREE: &str = "history"; // SavedQuery::id().to_be_bytes() => bincode::serialize(HistoryEntry)
+
+// The parts of a search that say what to look for, rather than how to filter or show the results
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedQuery {
+    pub params: Option<Vec<String>>,
+    pub ret: Option<String>,
+    pub ret_impl: Option<String>,
+    pub name: Option<String>,
+    pub doc: Option<String>,
+}
+
+impl SavedQuery {
+    // A receiver is saved as the first of the params, so matches in any position on replay
+    pub fn from_query(query: &Query) -> Self {
+        let params = match (query.receiver_str(), query.param_strs()) {
+            (Some(receiver), params) => Some(iter::once(receiver).chain(params.into_iter().flatten()).collect()),
+            (None, params) => params,
+        };
+        SavedQuery { params, ret: query.ret_str(), ret_impl: query.ret_impl.clone(), name: query.name.clone(), doc: query.doc.clone() }
+    }
+
+    // The query to search for it again, with no filters
+    pub fn to_query(&self) -> Query {
+        Query { ret_impl: self.ret_impl.clone(), name: self.name.clone(), doc: self.doc.clone(), ..Query::parse(&self.query()) }
+    }
+
+    // As it'd be typed, e.g. `&str, usize -> Option<char>`
+    pub fn query(&self) -> String {
+        let params = match &self.params {
+            None => "*".to_owned(),
+            Some(params) if params.is_empty() => "()".to_owned(),
+            Some(params) => params.join(", "),
+        };
+        format!("{} -> {}", params, self.ret.as_deref().unwrap_or("*"))
+    }
+
+    pub fn id(&self) -> u64 {
+        fn opt(s: &Option<String>) -> &str {
+            s.as_deref().unwrap_or("")
+        }
+        stable_hash(&[self.query().as_str(), opt(&self.ret_impl), opt(&self.name), opt(&self.doc)])
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub query: SavedQuery,
+    pub count: u64, // times searched
+    pub first_searched: u64, // unix timestamp secs
+    pub last_searched: u64,
+    pub last_num_results: usize,
+    // Kept as a saved query, e.g. to run again later
+    pub pinned: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryOrder {
+    Recent,
+    Popular, // most searched first
+}
+
+// Only the first page of a search should be recorded, so paging through results isn't counted as
+// searching again
+pub fn record_search(db: &Db, query: &SavedQuery, num_results: usize) {
+    let id = query.id();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    db.transaction(&[HISTORY_TREE], |tx| {
+        let entry = match tx.get(HISTORY_TREE, &id.to_be_bytes())? {
+            Some(bytes) => {
+                let entry: HistoryEntry = bincode::deserialize(&bytes).unwrap();
+                HistoryEntry { count: entry.count + 1, last_searched: now, last_num_results: num_results, ..entry }
+            },
+            None => HistoryEntry {
+                id,
+                query: query.clone(),
+                count: 1,
+                first_searched: now,
+                last_searched: now,
+                last_num_results: num_results,
+                pinned: false,
+            },
+        };
+        tx.insert(HISTORY_TREE, &id.to_be_bytes(), &bincode::serialize(&entry).unwrap())
+    });
+    record_type_searches(db, query, num_results)
+}
+
+pub fn search_history(db: &Db, order: HistoryOrder, pinned_only: bool, limit: usize) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = db.open_tree(HISTORY_TREE).iter()
+        .map(|(_key, val)| bincode::deserialize(&val).unwrap())
+        .filter(|entry: &HistoryEntry| !pinned_only || entry.pinned)
+        .collect();
+    match order {
+        HistoryOrder::Recent => entries.sort_by_key(|entry| Reverse(entry.last_searched)),
+        HistoryOrder::Popular => entries.sort_by_key(|entry| (Reverse(entry.count), Reverse(entry.last_searched))),
+    }
+    entries.truncate(limit);
+    entries
+}
+
+pub fn history_entry(db: &Db, id: u64) -> Option<HistoryEntry> {
+    db.open_tree(HISTORY_TREE).get(id.to_be_bytes()).map(|bytes| bincode::deserialize(&bytes).unwrap())
+}
+
+// Returns whether there was an entry to pin or unpin
+pub fn set_query_pinned(db: &Db, id: u64, pinned: bool) -> bool {
+    db.transaction(&[HISTORY_TREE], |tx| {
+        let mut entry: HistoryEntry = match tx.get(HISTORY_TREE, &id.to_be_bytes())? {
+            Some(bytes) => bincode::deserialize(&bytes).unwrap(),
+            None => return Ok(false),
+        };
+        entry.pinned = pinned;
+        tx.insert(HISTORY_TREE, &id.to_be_bytes(), &bincode::serialize(&entry).unwrap())?;
+        Ok(true)
+    })
+}
+
+// Ids are shown in hex
+pub fn parse_history_id(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+pub fn format_history_id(id: u64) -> String {
+    format!("{:016x}", id)
+}
@@ -0,0 +1,94 @@
+use anyhow::{Result, bail};
+use meilisearch_sdk as meili;
+use serde::{Serialize, Deserialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::{Db, TEXT_INDEXED_TREES, Tree};
+
+// Named indexes, each with its own trees (see `Db`), so curated indexes (e.g. of a company's crates)
+// can be built and searched alongside the default one in the same DB
+const INDEXES_TREE: &str = "indexes"; // name.as_bytes() => bincode::serialize(IndexInfo)
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct IndexInfo {
+    pub name: String,
+    pub created_at: u64, // unix timestamp secs
+}
+
+// Names end up in tree names and text search index names, so are kept to what both allow
+fn check_index_name(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-') {
+        bail!("invalid index name {:?}, only lowercase letters, digits, '_' and '-' are allowed", name)
+    }
+    Ok(())
+}
+
+// The registry is in the default index, whichever index the handle is for
+fn indexes_tree(db: &Db) -> Tree {
+    db.with_index(None).open_tree(INDEXES_TREE)
+}
+
+// Returns a handle to the new (empty) index
+pub fn create_index(db: &Db, name: &str) -> Result<Db> {
+    check_index_name(name)?;
+    let tree = indexes_tree(db);
+    if tree.contains_key(name) {
+        bail!("index {} already exists", name)
+    }
+    let created_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    tree.insert(name, bincode::serialize(&IndexInfo { name: name.to_owned(), created_at }).unwrap());
+    Ok(db.with_index(Some(name)))
+}
+
+// A handle that reads and writes only the named index - searching with it searches just that
+// index. Fails if it hasn't been created
+pub fn open_index(db: &Db, name: &str) -> Result<Db> {
+    if !indexes_tree(db).contains_key(name) {
+        bail!("no index named {}, create it first", name)
+    }
+    Ok(db.with_index(Some(name)))
+}
+
+// In name order, not including the default index
+pub fn list_indexes(db: &Db) -> Vec<IndexInfo> {
+    indexes_tree(db).iter()
+        .map(|(_, bytes)| bincode::deserialize(&bytes).unwrap())
+        .collect()
+}
+
+// The default index and every named one, e.g. to migrate them all
+pub(crate) fn all_indexes(db: &Db) -> Vec<Db> {
+    let mut dbs = vec![db.with_index(None)];
+    dbs.extend(list_indexes(db).into_iter().map(|info| db.with_index(Some(&info.name))));
+    dbs
+}
+
+// Removes everything in the index along with its text search indexes. Handles already open to it
+// see it empty. Failing to remove the text search indexes only warns, as nothing searches them
+// without the index
+pub fn drop_index(db: &Db, name: &str) -> Result<()> {
+    let index_db = open_index(db, name)?;
+    for tree_name in index_db.tree_names() {
+        let tree = index_db.open_tree(&tree_name);
+        let keys: Vec<Vec<u8>> = tree.iter().map(|(key, _)| key).collect();
+        for key in keys {
+            tree.remove(key);
+        }
+    }
+    indexes_tree(db).remove(name);
+    info!("dropped index {}", name);
+
+    let client = meili::client::Client::new("http://localhost:7700", "no_key");
+    let ret: Result<(), meili::errors::Error> = futures::executor::block_on(async {
+        for &(_, _, text_index, _) in TEXT_INDEXED_TREES {
+            client.delete_index_if_exists(&index_db.text_index_name(text_index)).await?;
+        }
+        Ok(())
+    });
+    if let Err(e) = ret {
+        warn!("failed to remove text search indexes of dropped index {}: {:?}", name, e)
+    }
+    Ok(())
+}
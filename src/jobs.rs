@@ -0,0 +1,189 @@
+use anyhow::Result;
+use either::Either;
+use serde::{Serialize, Deserialize};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use reeves_types::*;
+
+use crate::{Db, crate_key, has_crate, save_analysis, save_analysis_error};
+
+// Jobs stay in the queue until they're finished, so if a worker dies partway through a job it
+// gets picked up again (see `requeue_interrupted_jobs`)
+const JOB_QUEUE_TREE: &str = "job-queue"; // job_id.to_be_bytes() => crate_key(name, version)
+const JOB_TREE: &str = "job"; // crate_key(name, version) => bincode::serialize(JobRecord)
+
+// How long an idle worker waits before checking the queue again
+const WORKER_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Analyzed { num_fns: usize },
+    // Analysis ran, but the crate couldn't be analyzed (the error is saved in the DB like any other)
+    AnalysisError(String),
+    // Something went wrong running the analysis, e.g. the crate couldn't be downloaded
+    Failed(String),
+}
+
+impl JobStatus {
+    fn is_active(&self) -> bool {
+        match self {
+            JobStatus::Pending | JobStatus::Running => true,
+            JobStatus::Analyzed { .. } | JobStatus::AnalysisError(_) | JobStatus::Failed(_) => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub job_id: u64,
+    pub name: String,
+    pub version: String,
+    pub status: JobStatus,
+    pub history: Vec<(u64, JobStatus)>, // (unix timestamp secs, status), oldest first
+}
+
+impl JobRecord {
+    fn set_status(&mut self, status: JobStatus) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.history.push((now, status.clone()));
+        self.status = status;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueResult {
+    Queued,
+    AlreadyQueued,
+    AlreadyAnalyzed,
+}
+
+pub fn enqueue_analysis(db: &Db, name: &str, version: &str) -> EnqueueResult {
+    if has_crate(db, name, version) {
+        return EnqueueResult::AlreadyAnalyzed
+    }
+    let key = crate_key(name, version);
+    let job_id = db.generate_id();
+    db.transaction(&[JOB_QUEUE_TREE, JOB_TREE], |tx| {
+        let mut record = match tx.get(JOB_TREE, &key)? {
+            Some(bytes) => {
+                let record: JobRecord = bincode::deserialize(&bytes).unwrap();
+                if record.status.is_active() {
+                    return Ok(EnqueueResult::AlreadyQueued)
+                }
+                record
+            },
+            None => JobRecord { job_id, name: name.to_owned(), version: version.to_owned(), status: JobStatus::Pending, history: vec![] },
+        };
+        record.job_id = job_id;
+        record.set_status(JobStatus::Pending);
+        tx.insert(JOB_QUEUE_TREE, &job_id.to_be_bytes(), &key)?;
+        tx.insert(JOB_TREE, &key, &bincode::serialize(&record).unwrap())?;
+        Ok(EnqueueResult::Queued)
+    })
+}
+
+pub fn job_status(db: &Db, name: &str, version: &str) -> Option<JobRecord> {
+    let job_tree = db.open_tree(JOB_TREE);
+    job_tree.get(crate_key(name, version))
+        .map(|bytes| bincode::deserialize(&bytes).unwrap())
+}
+
+// Jobs still queued, oldest first
+pub fn queued_jobs(db: &Db) -> Vec<JobRecord> {
+    let queue_tree = db.open_tree(JOB_QUEUE_TREE);
+    let job_tree = db.open_tree(JOB_TREE);
+    queue_tree.iter()
+        .map(|(_, key)| {
+            let bytes = job_tree.get(key).unwrap();
+            bincode::deserialize(&bytes).unwrap()
+        })
+        .collect()
+}
+
+// Jobs left running when a previous process died need running again. Call before starting any
+// workers, otherwise their jobs will be run twice
+pub fn requeue_interrupted_jobs(db: &Db) -> usize {
+    let job_tree = db.open_tree(JOB_TREE);
+    let mut num_requeued = 0;
+    for record in queued_jobs(db) {
+        if record.status == JobStatus::Running {
+            let mut record = record;
+            warn!("requeueing interrupted analysis of {} {}", record.name, record.version);
+            record.set_status(JobStatus::Pending);
+            job_tree.insert(crate_key(&record.name, &record.version), bincode::serialize(&record).unwrap());
+            num_requeued += 1
+        }
+    }
+    num_requeued
+}
+
+// Marks the oldest pending job as running and returns it
+fn claim_job(db: &Db) -> Option<JobRecord> {
+    let queue_tree = db.open_tree(JOB_QUEUE_TREE);
+    for (_, key) in queue_tree.iter() {
+        // Another worker may claim it first, in which case try the next
+        let ret = db.transaction(&[JOB_TREE], |tx| {
+            let mut record: JobRecord = bincode::deserialize(&tx.get(JOB_TREE, &key)?.unwrap()).unwrap();
+            if record.status != JobStatus::Pending {
+                return Ok(None)
+            }
+            record.set_status(JobStatus::Running);
+            tx.insert(JOB_TREE, &key, &bincode::serialize(&record).unwrap())?;
+            Ok(Some(record))
+        });
+        if let Some(record) = ret {
+            return Some(record)
+        }
+    }
+    None
+}
+
+fn finish_job(db: &Db, mut record: JobRecord, status: JobStatus) {
+    let key = crate_key(&record.name, &record.version);
+    record.set_status(status);
+    db.transaction(&[JOB_QUEUE_TREE, JOB_TREE], |tx| {
+        tx.insert(JOB_TREE, &key, &bincode::serialize(&record).unwrap())?;
+        tx.remove(JOB_QUEUE_TREE, &record.job_id.to_be_bytes())?;
+        Ok(())
+    })
+}
+
+// Process queued jobs forever, using `analyze` to download and analyze each crate
+pub fn run_worker(db: &Db, analyze: &dyn Fn(&str, &str) -> Result<(CrateMeta, Either<Vec<FnDetail>, String>)>) -> ! {
+    loop {
+        let record = match claim_job(db) {
+            Some(record) => record,
+            None => {
+                thread::sleep(WORKER_POLL_INTERVAL);
+                continue
+            },
+        };
+        let (name, version) = (record.name.clone(), record.version.clone());
+        info!("worker analyzing {} {}", name, version);
+        let status = match analyze(&name, &version) {
+            Ok((meta, Either::Left(fndetails))) => {
+                let num_fns = fndetails.len();
+                save_analysis(db, &name, &version, &meta, fndetails);
+                JobStatus::Analyzed { num_fns }
+            },
+            Ok((_, Either::Right(err))) => {
+                save_analysis_error(db, &name, &version, &err);
+                JobStatus::AnalysisError(err)
+            },
+            Err(e) => {
+                warn!("failed to analyze {} {}: {:?}", name, version, e);
+                JobStatus::Failed(format!("{:?}", e))
+            },
+        };
+        info!("worker finished {} {}: {:?}", name, version, status);
+        finish_job(db, record, status)
+    }
+}
+
@@ -0,0 +1,92 @@
+use anyhow::{Result, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reeves_types::*;
+
+use crate::{AnalysisOptions, Db, Transaction, TxResult, crate_versions, remove_crate, save_analysis};
+use crate::schema::SCHEMA_VERSION;
+
+// Every crate version added to or purged from the index, in the order it happened, so whoever runs the
+// index can see what's in it and when that changed. Entries are written in the same transaction as
+// the change itself, and never removed
+pub(crate) const JOURNAL_TREE: &str = "journal"; // entry_id.to_be_bytes() => bincode::serialize(JournalEntry)
+
+// Records the change in the transaction making it. `id` is from `Db::generate_id`, taken before the
+// transaction so a retry doesn't take another
+pub(crate) fn journal_change(tx: &dyn Transaction, id: u64, op: JournalOp, name: &str, version: &str, num_fns: usize) -> TxResult<()> {
+    let entry = JournalEntry {
+        id,
+        op,
+        krate: name.to_owned(),
+        version: version.to_owned(),
+        num_fns,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        tool_version: env!("CARGO_PKG_VERSION").to_owned(),
+        schema_version: SCHEMA_VERSION,
+    };
+    tx.insert(JOURNAL_TREE, &id.to_be_bytes(), &bincode::serialize(&entry).unwrap())
+}
+
+// Oldest first, optionally only of one crate and after an entry (e.g. the last one seen, to poll for
+// changes)
+pub fn journal(db: &Db, krate_name: Option<&str>, after: Option<u64>) -> Vec<JournalEntry> {
+    db.open_tree(JOURNAL_TREE).iter()
+        .map(|(_key, val)| bincode::deserialize::<JournalEntry>(&val).unwrap())
+        .filter(|entry| krate_name.map_or(true, |name| entry.krate == name))
+        .filter(|entry| after.map_or(true, |after| entry.id > after))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RollbackResult {
+    pub purged: Vec<String>, // versions
+    pub restored: Vec<String>,
+}
+
+// The versions of the crate in the index just after the entry, going by the journal
+fn versions_at(db: &Db, krate_name: &str, entry_id: u64) -> BTreeSet<String> {
+    let mut versions = BTreeSet::new();
+    for entry in journal(db, Some(krate_name), None).into_iter().take_while(|entry| entry.id <= entry_id) {
+        match entry.op {
+            JournalOp::Add => versions.insert(entry.version),
+            JournalOp::Purge => versions.remove(&entry.version),
+        };
+    }
+    versions
+}
+
+// Puts the crate's versions back as they were just after the entry - purging versions added since, and
+// adding back versions purged since from their cached analyses (see `AnalysisCache`) with the options'
+// cache, analyzed with the same options. Fails without changing anything if a version to add back has
+// no cached analysis
+pub fn rollback_crate(db: &Db, opts: &AnalysisOptions, krate_name: &str, to_entry: u64) -> Result<RollbackResult> {
+    let cache = match opts.cache.as_ref() {
+        Some(cache) => cache,
+        None => bail!("rolling back needs the analysis cache the crate's versions were analyzed with"),
+    };
+    let entry_exists = journal(db, Some(krate_name), None).iter().any(|entry| entry.id == to_entry);
+    if !entry_exists {
+        bail!("no journal entry {} for crate {}", to_entry, krate_name)
+    }
+    let target = versions_at(db, krate_name, to_entry);
+    let current: BTreeSet<String> = crate_versions(db, krate_name).into_iter().collect();
+    let mut analyses = vec![];
+    for version in target.difference(&current) {
+        match cache.get(krate_name, version, opts) {
+            Some(analysis) => analyses.push(analysis),
+            None => bail!("no cached analysis of {} {} to roll back to, analyze it again instead", krate_name, version),
+        }
+    }
+    let mut result = RollbackResult { purged: vec![], restored: vec![] };
+    for version in current.difference(&target) {
+        result.purged.extend(remove_crate(db, krate_name, Some(version)))
+    }
+    for (meta, fndetails) in analyses {
+        save_analysis(db, krate_name, &meta.version, &meta, fndetails);
+        result.restored.push(meta.version)
+    }
+    Ok(result)
+}
@@ -1,567 +1,4014 @@
-use ra_base_db::Upcast;
+use ra_base_db::{Change, Upcast};
 use ra_hir::db::{DefDatabase, HirDatabase};
-use ra_hir::{HasVisibility, HirDisplay};
+use ra_hir::{HasAttrs, HasSource, HasVisibility, HirDisplay};
 use ra_hir::Crate;
 use ra_hir::ItemInNs;
 use ra_hir::ModuleDef;
+use ra_hir::ScopeDef;
 use ra_hir::Visibility;
+use ra_ide::AnalysisHost;
 use ra_paths::{AbsPath, AbsPathBuf};
 use ra_profile::StopWatch;
-use ra_project_model::{CargoConfig, ProjectManifest, ProjectWorkspace, TargetKind};
+use ra_project_model::{CargoConfig, CargoWorkspace, PackageData, ProjectManifest, ProjectWorkspace, TargetKind};
+use ra_syntax::AstNode;
+use ra_syntax::ast::{self, GenericParamsOwner, NameOwner, TypeBoundsOwner};
+use ra_vfs::{Vfs, VfsPath};
 use rust_analyzer::cli::load_cargo::{LoadCargoConfig, load_workspace_at};
 
 use anyhow::{Error, Result, anyhow};
-use log::{trace, debug, info};
 use meilisearch_sdk as meili;
+use roaring::RoaringTreemap;
 use serde::{Serialize, Deserialize};
-use sled::Transactional;
-use sled::transaction::TransactionError;
+use std::borrow::Cow;
 use std::cmp;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::str;
-use void::Void;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug_span, info_span, trace, debug, info, warn};
 
 use reeves_types::*;
+use reeves_types::engine::{self, SigMarker, tokenize_name, tokenize_type, within_typos};
 
+mod admin;
+mod analysis_cache;
+mod cancel;
+mod dbstats;
+mod diff;
+mod fetch;
+mod fnidset;
+mod fnrecord;
+mod history;
+mod indexes;
+mod jobs;
+mod journal;
+mod lsp;
+mod metrics;
+mod normalization;
+mod progress;
+mod report;
+mod rustdoc;
+mod schema;
+mod similar;
+mod site;
+mod snapshot;
+mod store;
+mod synonyms;
+mod textsettings;
+mod tracking;
+mod typestats;
+
+pub use admin::{AdminClient, serve_admin};
+pub use analysis_cache::AnalysisCache;
+pub use cancel::{AnalysisCancelled, CancelReason, CancelToken};
+pub use dbstats::{DbStats, TreeStats, db_stats};
+pub use diff::{CrateDiff, FnChange, analyze_diff, diff_crate};
+pub use fetch::{analyze_from_crates_io, analyze_from_tarball, crates_io_latest_version};
+pub use fnidset::{CompactionStats, compact_fn_sets, run_compaction, warm_fn_set_cache};
+pub use fnrecord::{DecodeBench, bench_fn_decoding};
+pub use history::{HistoryEntry, HistoryOrder, SavedQuery, format_history_id, history_entry, parse_history_id, record_search, search_history, set_query_pinned};
+pub use indexes::{IndexInfo, create_index, drop_index, list_indexes, open_index};
+pub use jobs::{EnqueueResult, JobRecord, JobStatus, enqueue_analysis, job_status, queued_jobs, requeue_interrupted_jobs, run_worker};
+pub use journal::{RollbackResult, journal, rollback_crate};
+pub use lsp::serve_lsp;
+pub use metrics::render_metrics;
+pub use normalization::{normalization, set_normalization};
+pub use progress::{NoProgress, Phase, Progress, ProgressSink};
+pub use report::{Report, report};
+pub use rustdoc::analyze_rustdoc_json;
+pub use similar::{SimilarFn, SimilarTarget, similar_to};
+pub use site::{compact_index, generate_site};
+pub use store::{Db, Store, Transaction, Tree, TxError, TxResult, export_snapshot};
+pub use synonyms::{add_synonym, list_synonyms, remove_synonym};
+pub use textsettings::{TextSearchSettings, text_search_settings, update_search_settings};
+pub use tracking::{CheckOutcome, OldVersions, TrackedCrate, run_scheduler, track_crate, tracked_crates, untrack_crate};
+pub use typestats::{TypeSearchStats, set_type_stats_enabled, type_search_stats, type_stats_enabled};
+use cancel::Abort;
+use fnidset::{decode_fn_ids, encode_fn_ids};
+use fnrecord::FnRecord;
+use journal::{JOURNAL_TREE, journal_change};
+use normalization::normalize_keys;
+use progress::ItemsTracker;
+use snapshot::ReadSnapshot;
+
+// Candidates per part of the query, unless the query says otherwise (see `SearchFilters`)
 const FUZZY_SEARCH_LIMIT: usize = 100;
-const MAX_RESULTS: usize = 500;
+const MAX_FUZZY_SEARCH_LIMIT: usize = 1000;
+// Adaptive searches double the candidates (up to the max) while they've found fewer results than this
+const ADAPTIVE_MIN_RESULTS: usize = 20;
+const MAX_RESULTS: usize = 500; // per page
+// Pages are cut from the full list of matching fn ids, which is cached for a while so fetching
+// later pages doesn't repeat the search
+const MAX_PAGINATED_RESULTS: usize = 5000;
+// However many results a query asks for
+const MAX_RESULTS_LIMIT: usize = 20000;
+const SEARCH_CACHE_TTL: Duration = Duration::from_secs(60);
+// Bounds the memory of the cache (8 bytes an id) however many distinct searches there are within the
+// TTL - the least recently used searches are evicted first. Each search counts as at least one id
+const SEARCH_CACHE_MAX_FN_IDS: usize = 2_000_000;
+// Suggestions per query type when a search finds nothing, see `type_suggestions`
+const MAX_TYPE_SUGGESTIONS: usize = 5;
+// Examples of fns producing and consuming a type, see `type_info`
+const TYPE_INFO_EXAMPLES: usize = 10;
+
+const PARAM_TREE: &str = "param"; // param_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const RET_TREE: &str = "ret"; // ret_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const FN_TREE: &str = "fn"; // bincode::serialize(fn_id: u64) => fnrecord::encode_fn(FnDetail)
+const CRATE_TREE: &str = "crate"; // crate_key(name, version) => bincode::serialize(fn_ids: Vec<u64>)
+const RET_IMPLS_TREE: &str = "ret-impls"; // trait_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const NAME_TREE: &str = "name"; // fn_path_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const ARITY_TREE: &str = "arity"; // num_params.to_string().as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const DOC_TREE: &str = "doc"; // doc_summary_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+// Types with references removed, see `TypeRepr::without_refs`
+const PARAM_NO_REFS_TREE: &str = "param-no-refs"; // param_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const RET_NO_REFS_TREE: &str = "ret-no-refs"; // ret_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+// For relaxed ret matching, e.g. `Result<PathBuf, Error>` => `PathBuf`
+const UNWRAPPED_RET_TREE: &str = "unwrapped-ret"; // inner_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+// Likewise with references removed, e.g. `Option<&Path>` => `Path`
+const UNWRAPPED_RET_NO_REFS_TREE: &str = "unwrapped-ret-no-refs"; // inner_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+// For relaxed param matching, e.g. `(usize, usize)` => `usize`
+const PARAM_COMPONENTS_TREE: &str = "param-components"; // component_type_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+// Parts of signatures that aren't types, see `SigMarker`
+const MARKER_TREE: &str = "marker"; // SigMarker::key().as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+// Properties searches can rule fns in or out by up front, see `flag_keys`
+const FLAG_TREE: &str = "flag"; // flag_str.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const CRATE_META_TREE: &str = "crate-meta"; // crate_key(name, version) => bincode::serialize(CrateMeta)
+// Kept apart from the meta, so narrowing a search by facets doesn't decode every crate's meta
+const CRATE_FACET_TREE: &str = "crate-facet"; // crate_key(name, version) => bincode::serialize(CrateMeta::facets())
+// Crates part way through being added, see `add_crate`
+const PENDING_CRATE_TREE: &str = "crate-pending"; // crate_key(name, version) => bincode::serialize(fn_ids: Vec<u64>)
+// When each crate was committed, see `ReadSnapshot`
+const CRATE_GENERATION_TREE: &str = "crate-generation"; // crate_key(name, version) => bincode::serialize(generation: u64)
+const ERROR_TREE: &str = "crate-error"; // crate_key(name, version) => bincode::serialize(err: String)
+// Trait impls found in analysis, see `impl_pairs`. Keys are NUL separated so they can be prefix scanned,
+// and end with the crate so each crate's entries are removed independently
+const IMPLS_OF_TREE: &str = "impls-of"; // trait_name \0 type \0 trait \0 crate_key(name, version) => []
+const TRAITS_OF_TREE: &str = "traits-of"; // type \0 trait \0 crate_key(name, version) => []
+// The conversion graph, from impls of `CONVERSION_TRAITS` (see `conversion_edges`), keyed the same way
+const CONVERSIONS_FROM_TREE: &str = "conversions-from"; // from_type \0 to_type \0 trait_name \0 crate_key(name, version) => []
+const CONVERSIONS_TO_TREE: &str = "conversions-to"; // to_type \0 from_type \0 trait_name \0 crate_key(name, version) => []
+// Named types by the fns with them anywhere in their ret or params, see `type_info`
+const PRODUCES_TREE: &str = "produces"; // type_name.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+const CONSUMES_TREE: &str = "consumes"; // type_name.as_bytes() => fnidset::encode_fn_ids(FnIdSet)
+
+// Trees mapping keys derived from each fn (e.g. its param types) to the set of fn ids with that key
+const FN_SET_TREES: &[(&str, fn(&FnDetail) -> Vec<String>)] = &[
+    (PARAM_TREE, param_keys),
+    (RET_TREE, ret_keys),
+    (RET_IMPLS_TREE, ret_impl_keys),
+    (NAME_TREE, name_keys),
+    (ARITY_TREE, arity_keys),
+    (DOC_TREE, doc_keys),
+    (UNWRAPPED_RET_TREE, unwrapped_ret_keys),
+    (UNWRAPPED_RET_NO_REFS_TREE, unwrapped_ret_no_refs_keys),
+    (PARAM_COMPONENTS_TREE, param_component_keys),
+    (PARAM_NO_REFS_TREE, param_no_refs_keys),
+    (RET_NO_REFS_TREE, ret_no_refs_keys),
+    (FLAG_TREE, flag_keys),
+    (MARKER_TREE, marker_keys),
+    (PRODUCES_TREE, produces_keys),
+    (CONSUMES_TREE, consumes_keys),
+];
 
-const FN_ID_COUNTER: &str = "next_fn_id"; // single u64 serialized value
-const PARAM_TREE: &str = "param"; // param_type_str.as_bytes() => bincode::serialize(HashSet<fn_id: u64>)
-const RET_TREE: &str = "ret"; // ret_type_str.as_bytes() => bincode::serialize(HashSet<fn_id: u64>)
-const FN_TREE: &str = "fn"; // bincode::serialize(fn_id: u64) => bincode::serialize(FnDetail)
-const CRATE_TREE: &str = "crate"; // crate_name_str.as_bytes() => bincode::serialize((version: String, fn_ids: Vec<u64>))
-const ERROR_TREE: &str = "crate-error"; // crate_name_str.as_bytes() => bincode::serialize((version: String, err: String))
+// Traits converting one type to another, whose impls are recorded with the type they convert from or
+// to, e.g. `From<&str>`, so they can be followed (see `conversions_from`)
+const CONVERSION_TRAITS: &[&str] = &["From", "TryFrom", "Into", "AsRef"];
 
-// A sentinel to represent functions with no arguments (must not be a possible type)
-const NIL_PARAMS: &str = "<NOARGS>";
+// Wrappers whose first generic arg is what a caller is usually after
+const SUCCESS_WRAPPERS: &[&str] = &["Option", "Result"];
+
+// Fns of a crate share the high half of their ids (see `stable_fn_id`), so they fall in the same bitmap
+pub type FnIdSet = RoaringTreemap;
 
 // For fuzzy searching
 const PARAM_TYPES_INDEX: &str = "param_types";
 const RET_TYPES_INDEX: &str = "ret_types";
+const RET_IMPLS_INDEX: &str = "ret_impls";
+const NAMES_INDEX: &str = "fn_names";
+const DOCS_INDEX: &str = "docs";
+const UNWRAPPED_RET_TYPES_INDEX: &str = "unwrapped_ret_types";
+const UNWRAPPED_RET_TYPES_NO_REFS_INDEX: &str = "unwrapped_ret_types_no_refs";
+const PARAM_TYPES_NO_REFS_INDEX: &str = "param_types_no_refs";
+const RET_TYPES_NO_REFS_INDEX: &str = "ret_types_no_refs";
+const PARAM_COMPONENT_TYPES_INDEX: &str = "param_component_types";
+
+// Trees with keys that are fuzzy searched: (description, tree, text index of its keys, how keys are
+// tokenized for the index)
+const TEXT_INDEXED_TREES: &[(&str, &str, &str, fn(&str) -> String)] = &[
+    ("param", PARAM_TREE, PARAM_TYPES_INDEX, tokenize_type),
+    ("ret", RET_TREE, RET_TYPES_INDEX, tokenize_type),
+    ("unwrapped ret", UNWRAPPED_RET_TREE, UNWRAPPED_RET_TYPES_INDEX, tokenize_type),
+    ("unwrapped ret (no refs)", UNWRAPPED_RET_NO_REFS_TREE, UNWRAPPED_RET_TYPES_NO_REFS_INDEX, tokenize_type),
+    ("param component", PARAM_COMPONENTS_TREE, PARAM_COMPONENT_TYPES_INDEX, tokenize_type),
+    ("param (no refs)", PARAM_NO_REFS_TREE, PARAM_TYPES_NO_REFS_INDEX, tokenize_type),
+    ("ret (no refs)", RET_NO_REFS_TREE, RET_TYPES_NO_REFS_INDEX, tokenize_type),
+    ("ret impl", RET_IMPLS_TREE, RET_IMPLS_INDEX, tokenize_type),
+    ("name", NAME_TREE, NAMES_INDEX, tokenize_name),
+    // Docs are already words
+    ("doc", DOC_TREE, DOCS_INDEX, str::to_owned),
+];
 
 fn stop_watch() -> StopWatch {
     StopWatch::start()
 }
 
-pub fn open_db(path: &Path) -> sled::Db {
-    let db = sled::open(path).unwrap();
-    if !db.contains_key(FN_ID_COUNTER).unwrap() {
-        db.insert(FN_ID_COUNTER, bincode::serialize(&0u64).unwrap()).unwrap();
+// Crate names can't contain a nul, so this lets us scan all versions of a crate by prefix
+fn crate_key(name: &str, version: &str) -> Vec<u8> {
+    let mut key = crate_key_prefix(name);
+    key.extend_from_slice(version.as_bytes());
+    key
+}
+
+fn crate_key_prefix(name: &str) -> Vec<u8> {
+    let mut key = name.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn parse_crate_key(key: &[u8]) -> (&str, &str) {
+    let key = str::from_utf8(key).unwrap();
+    let mut parts = key.splitn(2, '\0');
+    (parts.next().unwrap(), parts.next().expect("malformed crate key"))
+}
+
+// For servers to report the size of the index in their metrics - it's only counted once, as that
+// means reading the whole crate tree
+pub fn init_index_size_metrics(db: &Db) {
+    let crate_tree = db.open_tree(CRATE_TREE);
+    let mut num_crates = 0;
+    let mut num_fns = 0;
+    for (_key, val) in crate_tree.iter() {
+        let fn_ids: Vec<u64> = bincode::deserialize(&val).unwrap();
+        num_crates += 1;
+        num_fns += fn_ids.len();
     }
+    metrics::set_index_size(num_crates, num_fns)
+}
+
+// Uses SQLite if the path ends in `.sqlite`, otherwise sled. Fails if the DB was written by an
+// incompatible version of reeves (after migrating it, if it's just older)
+pub fn open_db(path: &Path) -> Result<Db> {
+    let db = store::open_store(path)?;
+    let meta = schema::check_schema(&db)?;
+    debug!("opened DB at {}: {:?}", path.display(), meta);
+    Ok(db)
+}
+
+// An empty DB that's never written to disk, e.g. for tests
+pub fn open_in_memory_db() -> Db {
+    let db = Db::in_memory();
+    schema::check_schema(&db).unwrap();
     db
 }
 
-pub fn save_analysis(db: &sled::Db, krate_name: &str, krate_version: &str, fndetails: Vec<FnDetail>) {
-    purge_crate(db, krate_name);
-    add_crate(db, krate_name, krate_version, fndetails);
+// Stable across runs and Rust versions (unlike `DefaultHasher`), so IDs derived from it survive
+// re-indexing. FNV-1a, with a separator after each part so e.g. ["ab", "c"] != ["a", "bc"]
+fn stable_hash(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for &b in part.as_bytes().iter().chain(&[0xff]) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// The preferred fn id for an item, from its identity rather than when it was indexed. The high half is
+// from the crate alone, so a crate's fns are close together in an `FnIdSet`
+fn stable_fn_id(fndetail: &FnDetail) -> u64 {
+    let kind = format!("{:?}", fndetail.kind);
+    let num_params = fndetail.params.len().to_string();
+    let mut parts = vec![&*fndetail.krate, &*fndetail.version, &*kind, &*fndetail.path, &*num_params];
+    parts.extend(fndetail.params.iter().map(String::as_str));
+    parts.push(&fndetail.ret);
+    let crate_hash = stable_hash(&[&fndetail.krate, &fndetail.version]);
+    (crate_hash & 0xffff_ffff_0000_0000) | (stable_hash(&parts) & 0xffff_ffff)
 }
 
-pub fn save_analysis_error(db: &sled::Db, krate_name: &str, krate_version: &str, err: &str) {
-    purge_crate(db, krate_name);
+pub fn save_analysis(db: &Db, krate_name: &str, krate_version: &str, meta: &CrateMeta, fndetails: Vec<FnDetail>) {
+    purge_crate(db, krate_name, krate_version);
+    add_crate(db, krate_name, krate_version, meta, fndetails);
+}
+
+pub fn save_analysis_error(db: &Db, krate_name: &str, krate_version: &str, err: &str) {
+    purge_crate(db, krate_name, krate_version);
     add_crate_error(db, krate_name, krate_version, err);
 }
 
-pub fn has_crate(db: &sled::Db, krate_name: &str, krate_version: &str) -> bool {
-    let crate_tree = db.open_tree(CRATE_TREE).unwrap();
-    let error_tree = db.open_tree(ERROR_TREE).unwrap();
-    // Have a successful analysis of the crate?
-    if let Some(bs) = crate_tree.get(krate_name.as_bytes()).unwrap() {
-        let (version, _fn_ids): (String, Vec<u64>) = bincode::deserialize(&bs).unwrap();
-        return version == krate_version
-    }
-    // Have an errored analysis of the crate?
-    if let Some(bs) = error_tree.get(krate_name.as_bytes()).unwrap() {
-        let version = str::from_utf8(&bs).unwrap();
-        return version == krate_version
-    }
-    false
+pub fn has_crate(db: &Db, krate_name: &str, krate_version: &str) -> bool {
+    let crate_tree = db.open_tree(CRATE_TREE);
+    let error_tree = db.open_tree(ERROR_TREE);
+    let key = crate_key(krate_name, krate_version);
+    // Have a successful or errored analysis of the crate?
+    crate_tree.contains_key(&key) || error_tree.contains_key(&key)
 }
 
-pub fn analyze_crate_path(path: &Path) -> (String, String, Result<Vec<FnDetail>>) {
-    let mut db_load_sw = stop_watch();
-    if !path.is_dir() {
-        panic!("path is not a directory")
-    }
-    info!("loading workspace at path: {}", path.display());
-    let mut cargo_config = CargoConfig::default();
-    cargo_config.no_sysroot = false;
-    let load_cargo_config = LoadCargoConfig {
-        load_out_dirs_from_check: false, // build scripts
-        with_proc_macro: false,
-        prefill_caches: false,
+// Remove a crate (all versions, if none is specified) from the index, returning which versions
+// were removed
+pub fn remove_crate(db: &Db, krate_name: &str, krate_version: Option<&str>) -> Vec<String> {
+    let versions = match krate_version {
+        Some(version) => if has_crate(db, krate_name, version) { vec![version.to_owned()] } else { vec![] },
+        None => {
+            let error_tree = db.open_tree(ERROR_TREE);
+            let mut versions = crate_versions(db, krate_name);
+            for (key, _val) in error_tree.scan_prefix(crate_key_prefix(krate_name)) {
+                versions.push(parse_crate_key(&key).1.to_owned())
+            }
+            versions
+        },
     };
-    let (host, _vfs, _proc_macro) =
-        load_workspace_at(&path, &cargo_config, &load_cargo_config, &|_| {}).unwrap();
-    let rootdb = host.raw_database();
-    info!("{:<20} {}", "Database loaded:", db_load_sw.elapsed());
+    for version in versions.iter() {
+        purge_crate(db, krate_name, version)
+    }
+    versions
+}
 
-    let hirdb: &dyn HirDatabase = rootdb.upcast();
-    let defdb: &dyn DefDatabase = rootdb.upcast();
+// All successfully analyzed versions of a crate, in no particular order
+pub fn crate_versions(db: &Db, krate_name: &str) -> Vec<String> {
+    let crate_tree = db.open_tree(CRATE_TREE);
+    crate_tree.scan_prefix(crate_key_prefix(krate_name))
+        .map(|(key, _val)| parse_crate_key(&key).1.to_owned())
+        .collect()
+}
 
-    use std::convert::TryInto;
-    let abspath: AbsPathBuf = path.canonicalize().unwrap().try_into().unwrap();
-    let (krate_name, krate_import_name, krate_version) = match discover_lib_crate_import_name(&abspath, &cargo_config) {
-        LibCrateResult::Ok(name, import_name, version) => (name, import_name, version),
-        LibCrateResult::Err(name, version, err) => return (name, version, Err(err.context("failed to interpret crate as a lib"))),
+// Crates analyzed before metadata was recorded don't have any
+pub fn crate_meta(db: &Db, krate_name: &str, krate_version: &str) -> Option<CrateMeta> {
+    let crate_meta_tree = db.open_tree(CRATE_META_TREE);
+    crate_meta_tree.get(crate_key(krate_name, krate_version))
+        .map(|bytes| bincode::deserialize(&bytes).unwrap())
+}
+
+// For showing alongside some results, in order of first appearance
+pub fn crate_metas_for(db: &Db, fndetails: &[FnDetail]) -> Vec<CrateMeta> {
+    let mut seen = HashSet::new();
+    fndetails.iter()
+        .filter(|fndetail| seen.insert((&fndetail.krate, &fndetail.version)))
+        .filter_map(|fndetail| crate_meta(db, &fndetail.krate, &fndetail.version))
+        .collect()
+}
+
+// The informational parts of a crate manifest's `[package]`. Published manifests have the same
+// categories and keywords as crates.io shows
+#[derive(Deserialize, Default)]
+struct ManifestPackage {
+    license: Option<String>,
+    repository: Option<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+}
+
+// From the Cargo.toml of the crate at the path. Missing fields are left empty rather than failing,
+// as they're only informational
+pub fn read_crate_meta(path: &Path, krate_name: &str, krate_version: &str) -> CrateMeta {
+    #[derive(Deserialize)]
+    struct Manifest {
+        package: ManifestPackage,
+    }
+
+    let manifest_path = path.join("Cargo.toml");
+    let package = fs::read_to_string(&manifest_path).map_err(anyhow::Error::from)
+        .and_then(|manifest| Ok(toml::from_str::<Manifest>(&manifest)?.package));
+    let package = match package {
+        Ok(package) => package,
+        Err(e) => {
+            warn!("failed to read crate metadata from {}: {:?}", manifest_path.display(), e);
+            ManifestPackage::default()
+        },
     };
+    new_crate_meta(krate_name, krate_version, package)
+}
 
-    let krates = Crate::all(hirdb);
-    for krate in krates {
-        let display_name = krate.display_name(hirdb).unwrap().to_string();
-        if krate_import_name != display_name {
-            continue
-        }
-        info!("found crate: {:?} {} (import name {})", krate_name, krate_version, display_name);
-        let mut moddefs = HashSet::new();
-        let import_map = defdb.import_map(krate.into());
-        let mut fndetails = vec![];
-        for (item, importinfo) in import_map.map.iter() {
-            let item: ItemInNs = item.to_owned().into();
-            // skip macros
-            let moddef = if let Some(moddef) = item.as_module_def() { moddef } else { continue };
-            let isnew = moddefs.insert(moddef);
-            if !isnew { continue }
-            let path = &importinfo.path.to_string();
-            let import_fndetails = match moddef {
-                ModuleDef::Function(f) => analyze_function(hirdb, &krate_name, f, path),
-                ModuleDef::Adt(a) => analyze_adt(hirdb, &krate_name, a, path),
-                ModuleDef::Trait(t) => analyze_trait(hirdb, &krate_name, t, path),
-                x @ ModuleDef::Variant(_) |
-                x @ ModuleDef::Const(_) |
-                x @ ModuleDef::Static(_) |
-                x @ ModuleDef::Module(_) |
-                x @ ModuleDef::TypeAlias(_) |
-                x @ ModuleDef::BuiltinType(_) => {
-                    trace!("skipping non-function {:?} {:?}", x.name(hirdb), x);
-                    vec![]
-                },
-            };
-            trace!("adding {} items", import_fndetails.len());
-            fndetails.extend(import_fndetails);
-        }
-        return (krate_name, krate_version, Ok(fndetails))
+// As if indexed now
+fn new_crate_meta(krate_name: &str, krate_version: &str, package: ManifestPackage) -> CrateMeta {
+    let ManifestPackage { license, repository, categories, keywords } = package;
+    CrateMeta {
+        name: krate_name.to_owned(),
+        version: krate_version.to_owned(),
+        license,
+        repository,
+        docsrs_url: format!("https://docs.rs/{}/{}", krate_name, krate_version),
+        indexed_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        categories,
+        keywords,
     }
-    panic!("didn't find crate {} (import name {})!", krate_name, krate_import_name)
 }
 
-pub fn search(db: &sled::Db, params_search: Option<Vec<String>>, ret_search: Option<String>) -> Vec<FnDetail> {
-    let client = meili::client::Client::new("http://localhost:7700", "no_key");
-    let param_types_search = client.assume_index(PARAM_TYPES_INDEX);
-    let ret_types_search = client.assume_index(RET_TYPES_INDEX);
+// Every analyzed crate version, with the number of fns indexed or the analysis error
+pub fn list_crates(db: &Db) -> Vec<(String, String, Result<usize, String>)> {
+    let crate_tree = db.open_tree(CRATE_TREE);
+    let error_tree = db.open_tree(ERROR_TREE);
+    let mut krates = vec![];
+    for (key, val) in crate_tree.iter() {
+        let (name, version) = parse_crate_key(&key);
+        let fn_ids: Vec<u64> = bincode::deserialize(&val).unwrap();
+        krates.push((name.to_owned(), version.to_owned(), Ok(fn_ids.len())))
+    }
+    for (key, val) in error_tree.iter() {
+        let (name, version) = parse_crate_key(&key);
+        let err: String = bincode::deserialize(&val).unwrap();
+        krates.push((name.to_owned(), version.to_owned(), Err(err)))
+    }
+    krates.sort_by(|(n1, v1, _), (n2, v2, _)| (n1, v1).cmp(&(n2, v2)));
+    krates
+}
 
-    let param_tree = db.open_tree(PARAM_TREE).unwrap();
-    let ret_tree = db.open_tree(RET_TREE).unwrap();
-    let fn_tree = db.open_tree(FN_TREE).unwrap();
+// Types known to implement the trait, e.g. `serde::Serialize` (only the last segment of the path is
+// matched, as traits are recorded by name). Any assoc types are part of the trait, e.g. `Iterator<Item = u8>`
+pub fn impls_of(db: &Db, trait_name: &str) -> Vec<TypeImpl> {
+    let trait_name = TypeRepr::parse(trait_name).name().unwrap_or(trait_name.trim()).to_owned();
+    let mut prefix = trait_name.into_bytes();
+    prefix.push(0);
+    let mut snapshot = ReadSnapshot::take(db);
+    let mut impls: Vec<TypeImpl> = db.open_tree(IMPLS_OF_TREE).scan_prefix(&prefix)
+        .map(|(key, _val)| {
+            let (_trait_name, rest) = split_impl_key(&key);
+            let (ty, rest) = split_impl_key(rest);
+            let (tr, crate_key) = split_impl_key(rest);
+            let (krate, version) = parse_crate_key(crate_key);
+            TypeImpl { ty: ty.to_owned(), trait_: tr.to_owned(), krate: krate.to_owned(), version: version.to_owned() }
+        })
+        .filter(|imp| snapshot.is_visible(&imp.krate, &imp.version))
+        .collect();
+    impls.sort_by(|i1, i2| (&i1.ty, &i1.trait_, &i1.krate, &i1.version).cmp(&(&i2.ty, &i2.trait_, &i2.krate, &i2.version)));
+    impls
+}
 
-    let mut candidate_types: Vec<(&sled::Tree, Vec<String>)> = vec![];
+// Traits the type is known to implement, e.g. for `Vec<u8>`. The type must match as displayed in
+// signatures, so generic types are found under their own params, e.g. `Vec<T>`
+pub fn traits_of(db: &Db, ty: &str) -> Vec<TypeImpl> {
+    let mut prefix = TypeRepr::parse(ty).to_string().into_bytes();
+    prefix.push(0);
+    let mut snapshot = ReadSnapshot::take(db);
+    let mut impls: Vec<TypeImpl> = db.open_tree(TRAITS_OF_TREE).scan_prefix(&prefix)
+        .map(|(key, _val)| {
+            let (ty, rest) = split_impl_key(&key);
+            let (tr, crate_key) = split_impl_key(rest);
+            let (krate, version) = parse_crate_key(crate_key);
+            TypeImpl { ty: ty.to_owned(), trait_: tr.to_owned(), krate: krate.to_owned(), version: version.to_owned() }
+        })
+        .filter(|imp| snapshot.is_visible(&imp.krate, &imp.version))
+        .collect();
+    impls.sort_by(|i1, i2| (&i1.trait_, &i1.krate, &i1.version).cmp(&(&i2.trait_, &i2.krate, &i2.version)));
+    impls
+}
 
-    if let Some(ret_search) = ret_search {
-        let ret_candidates = futures::executor::block_on(async {
-            ret_types_search.search()
-                .with_query(&ret_search)
-                .with_limit(FUZZY_SEARCH_LIMIT)
-                .execute::<TypeInFnResult>()
-                .await
-                .unwrap()
-        });
-        candidate_types.push((&ret_tree, ret_candidates.hits.into_iter().map(|c| c.result.orig_ty).collect()));
-    }
-
-    if let Some(mut params_search) = params_search {
-        if params_search.is_empty() {
-            params_search = vec!["<NOARGS>".into()];
-        }
-        for param in params_search {
-            let param_candidates = futures::executor::block_on(async {
-                param_types_search.search()
-                    .with_query(&param)
-                    .with_limit(FUZZY_SEARCH_LIMIT)
-                    .execute::<TypeInFnResult>()
-                    .await
-                    .unwrap()
-            });
-            candidate_types.push((&param_tree, param_candidates.hits.into_iter().map(|c| c.result.orig_ty).collect()));
-        }
-    }
-
-    // TODO: at each pass, reorder to have the most restrictive type candidates first
-    // TODO: at each pass, remember the sets we've built so far so we don't recreate and keep
-    // removing the fn ids that have been selected
-    let max_candidate_depth = candidate_types.iter().map(|(_, ct)| ct.len()).max().unwrap_or(0);
-    let mut fn_ids = vec![];
-    let mut fn_ids_set = HashSet::new();
-    let mut ranges = vec![];
-    for i in 1..max_candidate_depth {
-        let mut iteration_fn_ids: Option<HashSet<u64>> = None;
-        for (tree, ct_column) in candidate_types.iter() {
-            let mut ct_column_fn_ids = HashSet::new();
-            for ct in &ct_column[..cmp::min(i, ct_column.len())] {
-                let match_fns: HashSet<u64> = tree.get(ct).unwrap()
-                    .map(|ivec| bincode::deserialize(&ivec).unwrap())
-                    .expect("candidate type did not already have an entry in db");
-                ct_column_fn_ids.extend(match_fns)
-            }
-            // Update the fn ids for this iteration, or initialise them (if the first column)
-            if let Some(ifnids) = iteration_fn_ids.as_mut() {
-                *ifnids = ifnids.intersection(&ct_column_fn_ids).cloned().collect()
-            } else {
-                iteration_fn_ids = Some(ct_column_fn_ids)
-            }
-        }
+// Types the type can be converted to through `From`, `TryFrom`, `Into` or `AsRef` impls, e.g. `String` and
+// `PathBuf` for `&str`. As for `traits_of`, the type must match as displayed in signatures. `AsRef`
+// converts between references, e.g. `&String` to `&str`. Conversions of generic impls (e.g.
+// `impl<T> From<T> for Option<T>`) aren't recorded, so neither are those of the blanket `Into` impl
+pub fn conversions_from(db: &Db, ty: &str) -> Vec<Conversion> {
+    scan_conversions(db, CONVERSIONS_FROM_TREE, ty, |ty, other, trait_, krate, version| Conversion { from: ty, to: other, trait_, krate, version })
+}
+
+// Types that can be converted to the type, e.g. `&str` and `Vec<u8>` for `String`. See `conversions_from`
+pub fn conversions_to(db: &Db, ty: &str) -> Vec<Conversion> {
+    scan_conversions(db, CONVERSIONS_TO_TREE, ty, |ty, other, trait_, krate, version| Conversion { from: other, to: ty, trait_, krate, version })
+}
+
+fn scan_conversions(db: &Db, tree_name: &str, ty: &str, conversion: fn(String, String, String, String, String) -> Conversion) -> Vec<Conversion> {
+    let mut prefix = TypeRepr::parse(ty).to_string().into_bytes();
+    prefix.push(0);
+    let mut snapshot = ReadSnapshot::take(db);
+    let mut conversions: Vec<Conversion> = db.open_tree(tree_name).scan_prefix(&prefix)
+        .map(|(key, _val)| {
+            let (ty, rest) = split_impl_key(&key);
+            let (other, rest) = split_impl_key(rest);
+            let (tr, crate_key) = split_impl_key(rest);
+            let (krate, version) = parse_crate_key(crate_key);
+            conversion(ty.to_owned(), other.to_owned(), tr.to_owned(), krate.to_owned(), version.to_owned())
+        })
+        .filter(|conversion| snapshot.is_visible(&conversion.krate, &conversion.version))
+        .collect();
+    conversions.sort_by(|c1, c2| (&c1.from, &c1.to, &c1.trait_, &c1.krate, &c1.version).cmp(&(&c2.from, &c2.to, &c2.trait_, &c2.krate, &c2.version)));
+    conversions
+}
 
-        let ifnids = iteration_fn_ids.expect("unexpectedly ran out of fn ids");
-        let new_fn_ids: Vec<_> = ifnids.difference(&fn_ids_set).cloned().collect();
-        ranges.push(fn_ids.len()..fn_ids.len()+new_fn_ids.len());
-        fn_ids.extend_from_slice(&new_fn_ids);
-        fn_ids_set.extend(new_fn_ids);
+// The distinct types `conversions_from` finds, for searching with them in place of the type
+fn conversion_targets(db: &Db, ty: &str) -> Vec<String> {
+    let mut targets: Vec<String> = conversions_from(db, ty).into_iter().map(|conversion| conversion.to).collect();
+    targets.dedup();
+    targets
+}
+
+// Which crate defines a type (e.g. `regex::Captures`), and which fns produce and consume it. As for
+// `impls_of`, only the last segment of the path is matched
+pub fn type_info(db: &Db, ty: &str) -> TypeInfo {
+    let repr = TypeRepr::parse(ty);
+    let name = repr.name().unwrap_or(ty.trim()).to_owned();
+    let mut snapshot = ReadSnapshot::take(db);
+    let mut visible_fns = |tree: &str| -> Vec<(u64, FnRecord)> {
+        let fn_ids: FnIdSet = db.open_tree(tree).get(&name)
+            .map_or_else(FnIdSet::new, |bytes| decode_fn_ids(&bytes));
+        fn_ids.into_iter().filter_map(|fn_id| snapshot.get_fn_record(fn_id).map(|record| (fn_id, record))).collect()
+    };
+    let producers = visible_fns(PRODUCES_TREE);
+    let consumers = visible_fns(CONSUMES_TREE);
 
-        if fn_ids.len() >= MAX_RESULTS {
-            break
+    // Constructors and methods have paths under the type, e.g. `regex::Captures::get`
+    let type_segment = format!("::{}::", name);
+    let mut defined_in: Vec<String> = vec![];
+    for (_, record) in producers.iter().chain(consumers.iter()) {
+        let is_member = record.path().contains(&type_segment) || record.aliases().iter().any(|alias| alias.contains(&type_segment));
+        if is_member && !defined_in.iter().any(|krate| krate == record.krate()) {
+            defined_in.push(record.krate().to_owned())
         }
     }
-    let end = cmp::min(fn_ids.len(), MAX_RESULTS);
-    let fn_ids = &fn_ids[..end];
-    if let Some(range) = ranges.pop() {
-        ranges.push(range.start..end)
+    defined_in.sort();
+
+    let examples = |mut fns: Vec<(u64, FnRecord)>| -> Vec<FnDetail> {
+        fns.sort_by(|(id1, r1), (id2, r2)| {
+            let defined1 = defined_in.iter().any(|krate| krate == r1.krate());
+            let defined2 = defined_in.iter().any(|krate| krate == r2.krate());
+            defined2.cmp(&defined1)
+                .then_with(|| engine::result_order(r1.krate(), r1.s(), r1.version(), *id1)
+                    .cmp(&engine::result_order(r2.krate(), r2.s(), r2.version(), *id2)))
+        });
+        fns.into_iter().take(TYPE_INFO_EXAMPLES).map(|(_, record)| record.to_fndetail()).collect()
+    };
+    let (num_producers, num_consumers) = (producers.len(), consumers.len());
+    TypeInfo {
+        producers: examples(producers),
+        consumers: examples(consumers),
+        name,
+        defined_in,
+        num_producers,
+        num_consumers,
     }
+}
 
-    let mut ret = vec![];
-    for fn_id in fn_ids {
-        let fn_bytes = fn_tree.get(bincode::serialize(&fn_id).unwrap()).unwrap().unwrap();
-        let fndetail: FnDetail = bincode::deserialize(&fn_bytes).unwrap();
-        ret.push(fndetail);
+fn split_impl_key(key: &[u8]) -> (&str, &[u8]) {
+    let i = key.iter().position(|&b| b == 0).expect("malformed impl key");
+    (str::from_utf8(&key[..i]).unwrap(), &key[i+1..])
+}
+
+// Keys in IMPLS_OF_TREE and TRAITS_OF_TREE for the fn
+fn impl_keys(fndetail: &FnDetail) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let crate_key = crate_key(&fndetail.krate, &fndetail.version);
+    let mut impls_of_keys = vec![];
+    let mut traits_of_keys = vec![];
+    let sep: &[u8] = &[0];
+    for (ty, tr) in impl_pairs(fndetail) {
+        let trait_name = TypeRepr::parse(&tr).name().unwrap_or(&tr).to_owned();
+        let traits_of_key = [ty.as_bytes(), sep, tr.as_bytes(), sep, crate_key.as_slice()].concat();
+        impls_of_keys.push([trait_name.as_bytes(), sep, traits_of_key.as_slice()].concat());
+        traits_of_keys.push(traits_of_key);
     }
+    (impls_of_keys, traits_of_keys)
+}
 
-    for range in ranges {
-        ret[range].sort_by(|fd1, fd2| {
-            let krate_cmp = fd1.krate.cmp(&fd2.krate);
-            if krate_cmp.is_eq() { fd1.s.cmp(&fd2.s) } else { krate_cmp }
-        });
+// Keys in CONVERSIONS_FROM_TREE and CONVERSIONS_TO_TREE for the fn
+fn conversion_keys(fndetail: &FnDetail) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let crate_key = crate_key(&fndetail.krate, &fndetail.version);
+    let mut from_keys = vec![];
+    let mut to_keys = vec![];
+    let sep: &[u8] = &[0];
+    for (from, to, trait_name) in conversion_edges(fndetail) {
+        from_keys.push([from.as_bytes(), sep, to.as_bytes(), sep, trait_name.as_bytes(), sep, crate_key.as_slice()].concat());
+        to_keys.push([to.as_bytes(), sep, from.as_bytes(), sep, trait_name.as_bytes(), sep, crate_key.as_slice()].concat());
     }
+    (from_keys, to_keys)
+}
 
-    ret
+// (from, to, trait name) for the conversions the fn's ret type has impls for, e.g. `&str` to `String`
+// for `impl From<&str> for String`
+fn conversion_edges(fndetail: &FnDetail) -> Vec<(String, String, String)> {
+    let ty = TypeRepr::parse(&fndetail.ret);
+    impl_pairs(fndetail).into_iter()
+        .filter_map(|(_, tr)| {
+            let tr = TypeRepr::parse(&tr);
+            let arg = match &tr {
+                TypeRepr::Path { args, .. } if args.len() == 1 => args[0].clone(),
+                _ => return None,
+            };
+            let (from, to) = match tr.name()? {
+                "From" | "TryFrom" => (arg, ty.clone()),
+                "Into" => (ty.clone(), arg),
+                "AsRef" => (TypeRepr::Ref { mutable: false, inner: Box::new(ty.clone()) }, TypeRepr::Ref { mutable: false, inner: Box::new(arg) }),
+                _ => return None,
+            };
+            Some((from.to_string(), to.to_string(), tr.name()?.to_owned()))
+        })
+        .collect()
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct TypeInFn {
-    id: u64,
-    ty: String,
-    orig_ty: String,
+// The type an impl of one of `CONVERSION_TRAITS` converts from or to, e.g. `&str` for `From<&str>`, to
+// record with the trait. Not if it mentions the impl's generics (e.g. `From<T>`), as that'd say nothing
+// about which types convert
+fn conversion_arg(tr: &TypeRepr, generics: &[GenericParamDetail]) -> Option<String> {
+    fn mentions_generic(ty: &TypeRepr, generics: &[GenericParamDetail]) -> bool {
+        let is_generic = match ty {
+            TypeRepr::Path { segments, .. } if segments.len() == 1 => segments[0] == "Self" || generics.iter().any(|g| g.name == segments[0]),
+            _ => false,
+        };
+        is_generic || ty.children().into_iter().any(|child| mentions_generic(child, generics))
+    }
+    match tr {
+        TypeRepr::Path { args, .. } if args.len() == 1 && CONVERSION_TRAITS.contains(&tr.name()?) => {
+            Some(&args[0]).filter(|arg| !mentions_generic(arg, generics)).map(TypeRepr::to_string)
+        },
+        _ => None,
+    }
 }
 
-impl meili::document::Document for TypeInFn {
-    type UIDType = u64;
+// A trait as recorded in `FnDetail::ret_impls`, with the generic arg of a conversion (see `conversion_arg`)
+// and any assoc types, e.g. `TryFrom<&str, Error = ParseError>`
+fn impl_trait_str(name: String, conversion_arg: Option<String>, assoc_types: Vec<String>) -> String {
+    let args: Vec<String> = conversion_arg.into_iter().chain(assoc_types).collect();
+    if args.is_empty() { name } else { format!("{}<{}>", name, args.join(", ")) }
+}
 
-    fn get_uid(&self) -> &Self::UIDType {
-        &self.id
+// (type, trait) for the traits implemented by the fn's ret type, as resolved by HIR in the fn's crate.
+// Constructors make sure every constructible type is covered. Generic and opaque rets are left out, as
+// their traits are bounds rather than impls
+fn impl_pairs(fndetail: &FnDetail) -> Vec<(String, String)> {
+    let ret = &fndetail.ret;
+    let is_generic = fndetail.generics.iter().any(|g| &g.name == ret);
+    if is_generic || matches!(fndetail.ret_repr, TypeRepr::ImplTrait(_) | TypeRepr::DynTrait(_) | TypeRepr::Unknown(_)) {
+        return vec![]
     }
+    fndetail.ret_impls.iter().map(|tr| (ret.clone(), tr.clone())).collect()
 }
 
-#[derive(Serialize, Deserialize)]
-struct TypeInFnResult {
-    orig_ty: String,
+pub fn latest_crate_version(db: &Db, krate_name: &str) -> Option<String> {
+    crate_versions(db, krate_name).into_iter()
+        .max_by(|v1, v2| {
+            match (semver::Version::parse(v1), semver::Version::parse(v2)) {
+                (Ok(v1), Ok(v2)) => v1.cmp(&v2),
+                _ => v1.cmp(v2),
+            }
+        })
 }
 
-pub fn load_text_search(db: &sled::Db) {
-    let param_tree = db.open_tree(PARAM_TREE).unwrap();
-    let ret_tree = db.open_tree(RET_TREE).unwrap();
+#[derive(Debug, Clone)]
+pub enum FeatureSelection {
+    // Whatever the crate enables by default
+    Default,
+    All,
+    // Default features plus these
+    Some(Vec<String>),
+    // Analyze with default features, then once per feature, so we can tell which feature(s)
+    // enable each item
+    PerFeature,
+}
 
-    fn tokenize_type(s: &str) -> String {
-        let mut s = s
-            .replace('<', " < ")
-            .replace('>', " > ")
-            .replace('[', " [ ")
-            .replace(']', " ] ")
-            .replace('&', " & ");
-        loop {
-            let news = s.replace("  ", " ");
-            if news == s {
-                return s
-            }
-            s = news
+#[derive(Debug, Clone)]
+pub struct AnalysisOptions {
+    pub features: FeatureSelection,
+    // The target to analyze for (e.g. an embedded one, where a crate may be `no_std`) and whether
+    // without default features. Recorded on each fn found
+    pub profile: AnalysisProfile,
+    // Crates that generate APIs at build time (e.g. bindings, protobuf) are mostly empty without these,
+    // but they make loading much slower and run arbitrary code, so should only be used in a container
+    pub build_scripts: bool,
+    pub proc_macros: bool,
+    // Also index items only visible within the crate, for navigating a local project
+    pub include_private: bool,
+    // Also index methods reachable through `Deref` (e.g. slice methods for `Vec<T>`) as methods of
+    // each type that derefs to them. Off by default, as it duplicates a lot of methods
+    pub deref_methods: bool,
+    // Also index the crate's binaries and examples, so applications (which may have no lib) can be
+    // searched. Their items' paths start with the target's name
+    pub bins: bool,
+    // Give up on an analysis with an `AnalysisCancelled` error after this long, or once cancelled
+    pub timeout: Option<Duration>,
+    pub cancel: CancelToken,
+    pub frontend: Frontend,
+    // Where analyses of published crates (from crates.io or a .crate) are reused from and saved to
+    pub cache: Option<AnalysisCache>,
+}
+
+// How a crate is analyzed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frontend {
+    RustAnalyzer,
+    // From rustdoc's JSON output, see `rustdoc.rs`
+    Rustdoc,
+}
+
+impl AnalysisOptions {
+    fn items(&self) -> ItemSelection {
+        ItemSelection { include_private: self.include_private, deref_methods: self.deref_methods, bins: self.bins }
+    }
+}
+
+// Which items of a crate to index, from `AnalysisOptions`
+#[derive(Debug, Clone, Copy)]
+struct ItemSelection {
+    include_private: bool,
+    deref_methods: bool,
+    bins: bool,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            features: FeatureSelection::Default,
+            profile: AnalysisProfile::default(),
+            build_scripts: false,
+            proc_macros: false,
+            include_private: false,
+            deref_methods: false,
+            bins: false,
+            timeout: None,
+            cancel: CancelToken::new(),
+            frontend: Frontend::RustAnalyzer,
+            cache: None,
         }
     }
+}
 
-    let client = meili::client::Client::new("http://localhost:7700", "no_key");
+// Loading slower than this is worth knowing about, e.g. a build script doing a lot of work
+const SLOW_LOAD_WARNING: Duration = Duration::from_secs(120);
+// How often to check whether to stop waiting for a workspace load, or a child process
+const ABORT_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
-    futures::executor::block_on(async move {
-        let settings = meili::settings::Settings {
-            synonyms: None,
-            stop_words: Some(vec![]),
-            ranking_rules: None,
-            distinct_attribute: None,
-            filterable_attributes: Some(vec![]),
-            searchable_attributes: Some(vec!["ty".into()]),
-            displayed_attributes: Some(vec!["orig_ty".into()]),
+pub fn analyze_crate_path(path: &Path, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> (String, String, Result<Vec<FnDetail>>) {
+    if !path.is_dir() {
+        panic!("path is not a directory")
+    }
+    let (krate_name, krate_version, res) = if opts.frontend == Frontend::Rustdoc {
+        rustdoc::analyze_crate_path(path, opts, progress)
+    } else {
+        let (cargo_config, load_cargo_config) = cargo_configs(opts);
+        let abort = Abort::start(&opts.cancel, opts.timeout);
+        let ret = if let FeatureSelection::PerFeature = opts.features {
+            analyze_crate_path_per_feature(path, cargo_config, &load_cargo_config, opts.items(), &abort, progress)
+        } else {
+            analyze_crate_path_with_config(path, &cargo_config, &load_cargo_config, opts.items(), &abort, progress)
         };
-        client.delete_index_if_exists("param_types").await.unwrap();
-        let param_types = client.get_or_create("param_types").await.unwrap();
-        param_types.set_settings(&settings).await.unwrap().wait_for_pending_update(None, None).await.unwrap().unwrap();
-        client.delete_index_if_exists("ret_types").await.unwrap();
-        let ret_types = client.get_or_create("ret_types").await.unwrap();
-        ret_types.set_settings(&settings).await.unwrap().wait_for_pending_update(None, None).await.unwrap().unwrap();
-
-        async fn do_batch(entrytype: &str, index: &meili::indexes::Index, batch: &mut Vec<TypeInFn>, progress: &mut usize, total: usize) {
-            index.add_documents(batch, Some("id")).await.unwrap()
-                .wait_for_pending_update(None, None).await.unwrap().unwrap();
-            *progress += batch.len();
-            info!("Added {}/{} {} entries in total", progress, total, entrytype);
-            batch.clear();
-        }
-
-        let mut progress = 0;
-        let mut batch = vec![];
-        let num_params = param_tree.len();
-        for (i, kv) in param_tree.iter().enumerate() {
-            let (key, _val) = kv.unwrap();
-            let str_key = str::from_utf8(&key).unwrap();
-            let tokenized_key = tokenize_type(str_key);
-            batch.push(TypeInFn { id: i as u64, ty: tokenized_key, orig_ty: str_key.to_owned() });
-            if batch.len() >= 500 {
-                do_batch("param", &param_types, &mut batch, &mut progress, num_params).await;
-            }
-        }
-        do_batch("param", &param_types, &mut batch, &mut progress, num_params).await;
+        progress.report(Progress::Phase(Phase::Done));
+        ret
+    };
+    (krate_name, krate_version, res.map(|fndetails| with_profile(fndetails, &opts.profile)))
+}
 
-        let mut progress = 0;
-        let mut batch = vec![];
-        let num_rets = param_tree.len();
-        for (i, kv) in ret_tree.iter().enumerate() {
-            let (key, _val) = kv.unwrap();
-            let str_key = str::from_utf8(&key).unwrap();
-            let tokenized_key = tokenize_type(str_key);
-            batch.push(TypeInFn { id: i as u64, ty: tokenized_key, orig_ty: str_key.to_owned() });
-            if batch.len() >= 500 {
-                do_batch("ret", &ret_types, &mut batch, &mut progress, num_rets).await;
-            }
-        }
-        do_batch("ret", &ret_types, &mut batch, &mut progress, num_params).await;
-    })
+// Fns are found without knowing what they were analyzed for, so it's recorded on them afterwards
+pub(crate) fn with_profile(mut fndetails: Vec<FnDetail>, profile: &AnalysisProfile) -> Vec<FnDetail> {
+    for fndetail in fndetails.iter_mut() {
+        fndetail.profile = profile.clone()
+    }
+    fndetails
 }
 
-pub fn debugdb(db: &sled::Db) {
-    fn debugtree(name: &str, tree: &sled::Tree) {
-        for kv in tree.iter() {
-            let (key, val) = kv.unwrap();
-            let short_val_str = if val.len() > 16 {
-                format!("{:?}...", &val[..16])
-            } else {
-                format!("{:?}", val)
-            };
-            info!("tree: {}, key: ({:?} | {:?}) -> {}", name, String::from_utf8_lossy(&key), key, short_val_str)
-        }
+// Per-feature analysis needs multiple configs, so is left to the caller
+fn cargo_configs(opts: &AnalysisOptions) -> (CargoConfig, LoadCargoConfig) {
+    let mut cargo_config = CargoConfig::default();
+    cargo_config.no_sysroot = false;
+    cargo_config.target = opts.profile.target.clone();
+    cargo_config.no_default_features = opts.profile.no_default_features;
+    let load_cargo_config = LoadCargoConfig {
+        load_out_dirs_from_check: opts.build_scripts,
+        with_proc_macro: opts.proc_macros,
+        prefill_caches: false,
+    };
+    match &opts.features {
+        FeatureSelection::Default |
+        FeatureSelection::PerFeature => (),
+        FeatureSelection::All => cargo_config.all_features = true,
+        FeatureSelection::Some(features) => cargo_config.features = features.clone(),
     }
+    (cargo_config, load_cargo_config)
+}
 
-    for treename in db.tree_names() {
-        let namestr = str::from_utf8(&treename).unwrap();
-        info!("# tree: {:?}", namestr);
-        let tree = db.open_tree(&treename).unwrap();
-        debugtree(namestr, &tree);
+fn analyze_crate_path_per_feature(path: &Path, cargo_config: CargoConfig, load_cargo_config: &LoadCargoConfig, items: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> (String, String, Result<Vec<FnDetail>>) {
+    let (krate_name, krate_version, res) = analyze_crate_path_with_config(path, &cargo_config, load_cargo_config, items, abort, progress);
+    let mut fndetails = match res {
+        Ok(fndetails) => fndetails,
+        Err(e) => return (krate_name, krate_version, Err(e)),
+    };
+    let mut seen: HashMap<String, usize> = fndetails.iter().enumerate().map(|(i, fd)| (fd.s.clone(), i)).collect();
+    let num_default = fndetails.len();
+
+    use std::convert::TryInto;
+    let abspath: AbsPathBuf = path.canonicalize().unwrap().try_into().unwrap();
+    let features = with_member_package(&abspath, &cargo_config, |_cargo, package| {
+        package.features.keys().filter(|f| *f != "default").cloned().collect::<Vec<_>>()
+    });
+    info!("analyzing {} features of {} individually", features.len(), krate_name);
+    for feature in features {
+        // Everything found so far is complete for the features analyzed, so makes for good partial results
+        if let Some(reason) = abort.check() {
+            return (krate_name, krate_version, Err(AnalysisCancelled { reason, partial: fndetails }.into()))
+        }
+        let mut feature_cargo_config = cargo_config.clone();
+        feature_cargo_config.features = vec![feature.clone()];
+        let (_, _, res) = analyze_crate_path_with_config(path, &feature_cargo_config, load_cargo_config, items, abort, progress);
+        let feature_fndetails = match res {
+            Ok(fndetails) => fndetails,
+            Err(e) => match e.downcast::<AnalysisCancelled>() {
+                Ok(cancelled) => {
+                    let cancelled = AnalysisCancelled { reason: cancelled.reason, partial: fndetails };
+                    return (krate_name, krate_version, Err(cancelled.into()))
+                },
+                Err(e) => {
+                    warn!("failed to analyze {} with feature {}, skipping: {:?}", krate_name, feature, e);
+                    continue
+                },
+            },
+        };
+        for mut fndetail in feature_fndetails {
+            match seen.get(&fndetail.s) {
+                // Available by default, so no features needed
+                Some(&i) if i < num_default => (),
+                // Gated, but by some other feature as well
+                Some(&i) => fndetails[i].features.push(feature.clone()),
+                None => {
+                    fndetail.features = vec![feature.clone()];
+                    seen.insert(fndetail.s.clone(), fndetails.len());
+                    fndetails.push(fndetail)
+                },
+            }
+        }
     }
+    info!("found {} items gated behind features", fndetails.len() - num_default);
+    (krate_name, krate_version, Ok(fndetails))
 }
 
-enum LibCrateResult {
-    Ok(String, String, String), // name, import_name, version
-    Err(String, String, Error), // name, version, why not a lib
+fn analyze_crate_path_with_config(path: &Path, cargo_config: &CargoConfig, load_cargo_config: &LoadCargoConfig, items: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> (String, String, Result<Vec<FnDetail>>) {
+    let (krate_name, krate_version, res) = load_crate(path, cargo_config, load_cargo_config, items, abort, progress);
+    let loaded = match res {
+        Ok(loaded) => loaded,
+        Err(e) => return (krate_name, krate_version, Err(e)),
+    };
+    let res = analyze_loaded_crate(&loaded, &krate_name, &krate_version, items, abort, progress);
+    (krate_name, krate_version, res)
 }
-fn discover_lib_crate_import_name(path: &AbsPath, cargo_config: &CargoConfig) -> LibCrateResult {
-    // If you want to see some of the complexity here:
-    // - md-5 package name is 'md-5', but target name (and import name) is 'md5'
-    //
-    // We are taking crates from crates.io, so we can assume:
-    // - there is only one package (i.e. not a workspace)
-    // - there is only one lib
-    let root = ProjectManifest::discover_single(path).unwrap();
-    let ws = ProjectWorkspace::load(root, cargo_config, &|_| {}).unwrap();
-    let cargo = match ws {
-        ProjectWorkspace::Cargo { cargo, .. } => cargo,
-        _ => panic!("unexpected workspace type"),
+
+// A crate loaded into rust-analyzer, ready to analyze
+struct LoadedCrate {
+    host: AnalysisHost,
+    vfs: Vfs,
+    targets: Vec<CrateTarget>, // the lib (if any) first
+    root: PathBuf, // canonical
+}
+
+// A target of the crate to analyze - its lib, or (see `AnalysisOptions::bins`) a binary or example
+struct CrateTarget {
+    name: String, // as imported, e.g. `md5` for the lib of package `md-5`
+    root_file: PathBuf, // e.g. src/lib.rs
+    is_lib: bool,
+}
+
+// Unwinds a workspace loading thread once the analysis it's for is aborted, see `load_crate`
+struct LoadAborted;
+
+fn load_crate(path: &Path, cargo_config: &CargoConfig, load_cargo_config: &LoadCargoConfig, items: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> (String, String, Result<LoadedCrate>) {
+    use std::convert::TryInto;
+    let abspath: AbsPathBuf = path.canonicalize().unwrap().try_into().unwrap();
+    let (krate_name, krate_version, targets) = match discover_crate_targets(&abspath, cargo_config, items.bins) {
+        CrateTargetsResult::Ok(name, version, targets) => (name, version, targets),
+        CrateTargetsResult::Err(name, version, err) => return (name, version, Err(err.context("failed to find what to analyze in crate"))),
     };
-    let members = cargo.packages().map(|pd| &cargo[pd]).filter(|pd| pd.is_member).collect::<Vec<_>>();
-    assert_eq!(members.len(), 1, "{:?}", members);
-    let name = members[0].name.clone();
-    let version = members[0].version.to_string();
-    let lib_targets = members[0].targets.iter().map(|&t| &cargo[t]).filter(|t| t.kind == TargetKind::Lib).collect::<Vec<_>>();
-    if lib_targets.len() == 0 {
-        LibCrateResult::Err(name, version, anyhow!("no lib targets found in crate"))
-    } else if lib_targets.len() == 1 {
-        LibCrateResult::Ok(name, lib_targets[0].name.replace('-', "_"), version)
-    } else {
-        LibCrateResult::Err(name, version, anyhow!("multiple lib targets found in crate"))
+
+    progress.report(Progress::Phase(Phase::LoadingWorkspace));
+    let _span = info_span!("load_workspace", krate = krate_name.as_str(), version = krate_version.as_str()).entered();
+    let mut db_load_sw = stop_watch();
+    let load_start = Instant::now();
+    info!("loading workspace at path: {} (features: {:?}, all features: {}, build scripts: {}, proc macros: {})",
+          path.display(), cargo_config.features, cargo_config.all_features,
+          load_cargo_config.load_out_dirs_from_check, load_cargo_config.with_proc_macro);
+    // Loading happens on its own thread, so an aborted analysis returns straight away. rust-analyzer
+    // reports progress through loading (e.g. as each crate is built for its build scripts), which is
+    // where the thread checks the abort too, and unwinds - dropping the pipe from any cargo it's
+    // running, so that exits as well. Loading the files into the VFS has no progress, so that part is
+    // left to finish in the background
+    let (tx, rx) = mpsc::channel();
+    {
+        let path = path.to_owned();
+        let cargo_config = cargo_config.clone();
+        let load_cargo_config = LoadCargoConfig {
+            load_out_dirs_from_check: load_cargo_config.load_out_dirs_from_check,
+            with_proc_macro: load_cargo_config.with_proc_macro,
+            prefill_caches: load_cargo_config.prefill_caches,
+        };
+        let abort = abort.clone();
+        thread::spawn(move || {
+            let check_abort = |_progress: String| if abort.check().is_some() { panic::resume_unwind(Box::new(LoadAborted)) };
+            match panic::catch_unwind(AssertUnwindSafe(|| load_workspace_at(&path, &cargo_config, &load_cargo_config, &check_abort))) {
+                Ok(res) => { let _ = tx.send(res); },
+                // Nothing's waiting for it any more
+                Err(payload) if payload.is::<LoadAborted>() => (),
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        });
+    }
+    let res = loop {
+        match rx.recv_timeout(ABORT_POLL_INTERVAL) {
+            Ok(res) => break res,
+            Err(mpsc::RecvTimeoutError::Timeout) => if let Some(reason) = abort.check() {
+                warn!("stopping load of {} {}: {:?}", krate_name, krate_version, reason);
+                return (krate_name, krate_version, Err(AnalysisCancelled { reason, partial: vec![] }.into()))
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => break Err(anyhow!("workspace loading thread panicked")),
+        }
+    };
+    let (host, vfs, _proc_macro) = match res {
+        Ok(loaded) => loaded,
+        Err(e) => return (krate_name, krate_version, Err(e.context("failed to load workspace"))),
+    };
+    info!("{:<20} {}", "Database loaded:", db_load_sw.elapsed());
+    if load_start.elapsed() > SLOW_LOAD_WARNING {
+        warn!("loading {} {} was slow ({:?}), possibly due to build scripts or proc macros",
+              krate_name, krate_version, load_start.elapsed());
     }
+    let root = PathBuf::from(abspath);
+    (krate_name, krate_version, Ok(LoadedCrate { host, vfs, targets, root }))
 }
 
-fn add_crate(db: &sled::Db, name: &str, version: &str, fndetails: Vec<FnDetail>) {
-    let param_tree = db.open_tree(PARAM_TREE).unwrap();
-    let ret_tree = db.open_tree(RET_TREE).unwrap();
-    let fn_tree = db.open_tree(FN_TREE).unwrap();
-    let crate_tree = db.open_tree(CRATE_TREE).unwrap();
+fn analyze_loaded_crate(loaded: &LoadedCrate, krate_name: &str, krate_version: &str, items: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> Result<Vec<FnDetail>> {
+    let hirdb: &dyn HirDatabase = loaded.host.raw_database().upcast();
 
-    // Get a guaranteed-unique fn id range from the DB. Doesn't matter if it doesn't get used, u64 is
-    // pretty big :)
-    fn reserve_fn_id_range(db: &sled::Db, num: usize) -> u64 {
-        let ret: Result<u64, TransactionError<Void>> = db.transaction(|db| {
-            let fn_id: u64 = bincode::deserialize(&db.get(FN_ID_COUNTER).unwrap().unwrap()).unwrap();
-            let range_end = fn_id + num as u64;
-            db.insert(FN_ID_COUNTER, bincode::serialize(&range_end).unwrap()).unwrap();
-            Ok(fn_id)
+    let mut fndetails = vec![];
+    for target in &loaded.targets {
+        // A bin often has the same name as the lib, so crates are identified by their root file
+        let krate = Crate::all(hirdb).into_iter()
+            .find(|krate| {
+                let vfs_path = loaded.vfs.file_path(krate.root_file(hirdb));
+                vfs_path.as_path().map_or(false, |p| AsRef::<Path>::as_ref(p) == target.root_file)
+            })
+            .unwrap_or_else(|| panic!("didn't find crate {} (target {} at {})!", krate_name, target.name, target.root_file.display()));
+        info!("found crate: {:?} {} (target {})", krate_name, krate_version, target.name);
+        // Items of binaries and examples are qualified by the target, as each has its own root module
+        let qualify = |target_fndetails: Vec<FnDetail>| target_fndetails.into_iter().map(|fndetail| match target.is_lib {
+            true => fndetail,
+            false => FnDetail {
+                path: format!("{}::{}", target.name, fndetail.path),
+                import_path: format!("{}::{}", target.name, fndetail.import_path),
+                ..fndetail
+            },
         });
-        ret.unwrap()
+        match analyze_hir_crate(loaded, krate, krate_name, krate_version, &loaded.root, items, abort, progress) {
+            Ok(target_fndetails) => fndetails.extend(qualify(target_fndetails)),
+            Err(e) => return Err(match e.downcast::<AnalysisCancelled>() {
+                Ok(cancelled) => {
+                    fndetails.extend(qualify(cancelled.partial));
+                    AnalysisCancelled { reason: cancelled.reason, partial: fndetails }.into()
+                },
+                Err(e) => e,
+            }),
+        }
     }
+    Ok(fndetails)
+}
 
-    let start_fn_id = reserve_fn_id_range(db, fndetails.len());
-    // Calculate everything to update
-    let mut param_sets: HashMap<String, HashSet<u64>> = HashMap::new();
-    let mut ret_sets: HashMap<String, HashSet<u64>> = HashMap::new();
-    let mut fn_ids: Vec<u64> = vec![];
-    let nil_params: Vec<String> = vec![NIL_PARAMS.into()];
-    for (i, fndetail) in fndetails.iter().enumerate() {
-        let fn_id = start_fn_id + i as u64;
-        let mut params = &fndetail.params;
-        if params.is_empty() {
-            params = &nil_params;
+// `root` is the dir containing the crate's Cargo.toml, which locations are relative to
+fn analyze_hir_crate(loaded: &LoadedCrate, krate: Crate, krate_name: &str, krate_version: &str, root: &Path, selection: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> Result<Vec<FnDetail>> {
+    let _span = info_span!("analyze_crate", krate = krate_name, version = krate_version).entered();
+    let rootdb = loaded.host.raw_database();
+    let hirdb: &dyn HirDatabase = rootdb.upcast();
+    let defdb: &dyn DefDatabase = rootdb.upcast();
+
+    let mut ctx = CrateCtx {
+        hirdb,
+        name: krate_name,
+        version: krate_version,
+        include_private: selection.include_private,
+        deref_methods: selection.deref_methods,
+        impl_methods: HashMap::new(),
+        blanket_impl_bounds: HashMap::new(),
+    };
+    ctx.impl_methods = crate_impl_methods(&ctx, krate);
+    ctx.blanket_impl_bounds = crate_blanket_impl_bounds(&ctx, krate);
+    let mut moddefs = HashSet::new();
+    let mut items = vec![];
+    let import_map = defdb.import_map(krate.into());
+    for (item, importinfo) in import_map.map.iter() {
+        let item: ItemInNs = item.to_owned().into();
+        // skip macros
+        let moddef = if let Some(moddef) = item.as_module_def() { moddef } else { continue };
+        moddefs.insert(moddef);
+        items.push((moddef, importinfo.path.to_string()))
+    }
+    // Each path an item is re-exported under is analyzed too, so its fns are merged (see
+    // `merge_reexport`) and the other paths kept as aliases
+    for (moddef, path) in crate_reexports(hirdb, krate) {
+        if moddefs.contains(&moddef) && !items.iter().any(|(m, p)| *m == moddef && *p == path) {
+            items.push((moddef, path))
         }
-        for param in params.iter() {
-            let param_set = param_sets.entry(param.to_owned()).or_insert_with(HashSet::new);
-            param_set.insert(fn_id);
-            // May not be new if multiple params of the same type
-            let _isnew = param_set.insert(fn_id);
+    }
+    if ctx.include_private {
+        for (moddef, path) in crate_items(hirdb, krate) {
+            if moddefs.insert(moddef) { items.push((moddef, path)) }
         }
-        let ret_set = ret_sets.entry(fndetail.ret.to_owned()).or_insert_with(HashSet::new);
-        let isnew = ret_set.insert(fn_id);
-        assert!(isnew, "{:?}", fndetail.s);
-
-        fn_ids.push(fn_id);
     }
 
-    debug!("performed precomputation for crate {} with {} fns", name, fndetails.len());
-
-    let ret: Result<(), TransactionError<Void>> = (&param_tree, &ret_tree, &fn_tree, &crate_tree)
-        .transaction(|(param_tree, ret_tree, fn_tree, crate_tree)| {
-            debug!("inserting {} params for crate {}", param_sets.len(), name);
-            for (param, fn_ids) in param_sets.iter() {
-                let mut param_set: HashSet<u64> = param_tree.get(param).unwrap()
-                    .map(|d| bincode::deserialize(d.as_ref()).unwrap()).unwrap_or_else(HashSet::new);
-                param_set.extend(fn_ids);
-                param_tree.insert(param.as_bytes(), bincode::serialize(&param_set).unwrap()).unwrap();
-            }
-
-            debug!("inserting {} rets for crate {}", param_sets.len(), name);
-            for (ret, fn_ids) in ret_sets.iter() {
-                let mut ret_set: HashSet<u64> = ret_tree.get(ret).unwrap()
-                    .map(|d| bincode::deserialize(d.as_ref()).unwrap()).unwrap_or_else(HashSet::new);
-                ret_set.extend(fn_ids);
-                ret_tree.insert(ret.as_bytes(), bincode::serialize(&ret_set).unwrap()).unwrap();
+    let mut fndetails = vec![];
+    // Items can be reachable via multiple paths (re-exports), so track where we put each one
+    let mut fndetail_idxs: HashMap<ModuleDef, usize> = HashMap::new();
+    let tracker = ItemsTracker::start(progress, Phase::AnalyzingItems, items.len());
+    for (i, (moddef, path)) in items.iter().enumerate() {
+        tracker.update(i);
+        if let Some(reason) = abort.check() {
+            warn!("stopping analysis of {} {} after {} of {} items: {:?}", krate_name, krate_version, i, items.len(), reason);
+            return Err(AnalysisCancelled { reason, partial: fndetails }.into())
+        }
+        let path = path.as_str();
+        if !ctx.include_private && is_doc_hidden(hirdb, *moddef) {
+            trace!("skipping hidden {}", path);
+            continue
+        }
+        let import_fndetails = match *moddef {
+            ModuleDef::Function(f) => analyze_function(&ctx, f, path),
+            ModuleDef::Adt(a) => analyze_adt(&ctx, a, path),
+            ModuleDef::Trait(t) => analyze_trait(&ctx, t, path),
+            ModuleDef::Variant(v) => analyze_variant(&ctx, v, path),
+            ModuleDef::Const(c) => analyze_const(&ctx, c, path),
+            ModuleDef::Static(st) => analyze_static(&ctx, st, path),
+            x @ ModuleDef::Module(_) |
+            x @ ModuleDef::TypeAlias(_) |
+            x @ ModuleDef::BuiltinType(_) => {
+                trace!("skipping non-function {:?} {:?}", x.name(hirdb), x);
+                vec![]
+            },
+        };
+        trace!("adding {} items", import_fndetails.len());
+        for (def, mut fndetail) in import_fndetails {
+            // The def is the method on the deref target, which may be inherited by other types too
+            if fndetail.deref_from.is_some() {
+                fndetail.location = item_location(loaded, root, def);
+                fndetails.push(fndetail);
+                continue
             }
-
-            debug!("inserting {} fndetails for crate {}", fndetails.len(), name);
-            for (i, fndetail) in fndetails.iter().enumerate() {
-                let fn_id = start_fn_id + i as u64;
-                fn_tree.insert(bincode::serialize(&fn_id).unwrap(), bincode::serialize(fndetail).unwrap()).unwrap();
-                debug!("inserted fndetail {}/{}: [{}] {}", i+1, fndetails.len(), fndetail.krate, fndetail.s);
+            match fndetail_idxs.entry(def) {
+                Entry::Vacant(e) => {
+                    e.insert(fndetails.len());
+                    fndetail.location = item_location(loaded, root, def);
+                    fndetails.push(fndetail)
+                },
+                Entry::Occupied(e) => merge_reexport(&mut fndetails[*e.get()], fndetail),
             }
-            crate_tree.insert(name.as_bytes(), bincode::serialize(&(version, &fn_ids)).unwrap()).unwrap();
-            Ok(())
-        });
-
-    debug!("completed inserting crate {}", name);
-    ret.unwrap()
+        }
+        progress.report(Progress::Discovered { fns: fndetails.len() });
+    }
+    tracker.update(items.len());
+    debug!("{} unique items after deduplicating re-exports", fndetails.len());
+    Ok(fndetails)
 }
 
-fn add_crate_error(db: &sled::Db, name: &str, version: &str, err: &str) {
-    let error_tree = db.open_tree(ERROR_TREE).unwrap();
-    error_tree.insert(name.as_bytes(), bincode::serialize(&(version, err)).unwrap()).unwrap();
+#[derive(Debug, Clone)]
+pub enum DependencySelection {
+    All,
+    // By package name
+    Some(Vec<String>),
 }
 
-fn purge_crate(db: &sled::Db, name: &str) {
-    let param_tree = db.open_tree(PARAM_TREE).unwrap();
-    let ret_tree = db.open_tree(RET_TREE).unwrap();
-    let fn_tree = db.open_tree(FN_TREE).unwrap();
-    let crate_tree = db.open_tree(CRATE_TREE).unwrap();
-    let ret: Result<(), TransactionError<Void>> = (&**db, &param_tree, &ret_tree, &fn_tree, &crate_tree)
-        .transaction(|(_db, param_tree, ret_tree, fn_tree, crate_tree)| {
-            let (_version, fn_ids): (String, Vec<u64>) = match crate_tree.remove(name.as_bytes()).unwrap() {
-                Some(bs) => bincode::deserialize(&bs).unwrap(),
-                None => return Ok(()),
-            };
-            let fndetails: Vec<(u64, FnDetail)> = fn_ids.into_iter()
-                .map(|fn_id| (fn_id, fn_tree.remove(bincode::serialize(&fn_id).unwrap()).unwrap().unwrap()))
-                .map(|(fn_id, bytes)| (fn_id, bincode::deserialize(&bytes).unwrap()))
-                .collect();
-            for (fn_id, fndetail) in fndetails {
-                let mut params = fndetail.params;
-                if params.is_empty() {
-                    params = vec!["<NOARGS>".into()];
-                }
-                for param in params {
-                    let mut param_set: HashSet<u64> = param_tree.get(&param).unwrap()
-                        .map(|d| bincode::deserialize(d.as_ref()).unwrap()).unwrap_or_else(HashSet::new);
-                    // May not be deleted if multiple params of the same type
-                    let _didremove = param_set.remove(&fn_id);
-                    param_tree.insert(param.as_bytes(), bincode::serialize(&param_set).unwrap()).unwrap();
-                }
+// The results for one crate of a workspace
+pub struct CrateAnalysis {
+    pub name: String,
+    pub version: String,
+    pub root: PathBuf, // containing its Cargo.toml
+    pub fndetails: Vec<FnDetail>,
+}
 
-                let mut ret_set: HashSet<u64> = ret_tree.get(&fndetail.ret).unwrap()
-                    .map(|d| bincode::deserialize(d.as_ref()).unwrap()).unwrap_or_else(HashSet::new);
-                let didremove = ret_set.remove(&fn_id);
-                assert!(didremove, "{:?}", fndetail.s);
-                ret_tree.insert(fndetail.ret.as_bytes(), bincode::serialize(&ret_set).unwrap()).unwrap();
+// Like `analyze_crate_path`, but also analyzes the crate's (lib) dependencies, which are loaded
+// anyway - much cheaper than loading each dependency separately. The crate's own results come
+// first. Dependencies are always analyzed with the features the crate enables, so per-feature
+// analysis isn't supported
+pub fn analyze_with_dependencies(path: &Path, opts: &AnalysisOptions, dependencies: &DependencySelection, progress: &dyn ProgressSink) -> (String, String, Result<Vec<CrateAnalysis>>) {
+    if !path.is_dir() {
+        panic!("path is not a directory")
+    }
+    let (cargo_config, load_cargo_config) = cargo_configs(opts);
+    if let FeatureSelection::PerFeature = opts.features {
+        warn!("per-feature analysis is not supported with dependencies, using default features")
+    }
+    if opts.frontend == Frontend::Rustdoc {
+        warn!("analysis from rustdoc JSON is not supported with dependencies, using rust-analyzer")
+    }
+    let abort = Abort::start(&opts.cancel, opts.timeout);
+    let (krate_name, krate_version, res) = load_crate(path, &cargo_config, &load_cargo_config, opts.items(), &abort, progress);
+    let loaded = match res {
+        Ok(loaded) => loaded,
+        Err(e) => return (krate_name, krate_version, Err(e)),
+    };
+    let res = analyze_loaded_crate(&loaded, &krate_name, &krate_version, opts.items(), &abort, progress)
+        .and_then(|fndetails| {
+            let mut analyses = vec![CrateAnalysis { name: krate_name.clone(), version: krate_version.clone(), root: loaded.root.clone(), fndetails }];
+            analyses.extend(analyze_loaded_dependencies(&loaded, &cargo_config, &krate_name, dependencies, opts.items(), &abort, progress)?);
+            for analysis in analyses.iter_mut() {
+                analysis.fndetails = with_profile(mem::take(&mut analysis.fndetails), &opts.profile)
             }
-            Ok(())
+            Ok(analyses)
         });
-    let () = ret.unwrap();
+    progress.report(Progress::Phase(Phase::Done));
+    (krate_name, krate_version, res)
 }
 
-fn analyze_function(hirdb: &dyn HirDatabase, krate_name: &str, function: ra_hir::Function, path: &str) -> Vec<FnDetail> {
-    let assoc_params_pretty = function.assoc_fn_params(hirdb)
-        .into_iter().map(|param| param.ty().display(hirdb).to_string())
-        .collect::<Vec<_>>();
-    let ret_pretty = function.ret_type(hirdb).display(hirdb).to_string();
-    if log::log_enabled!(log::Level::Info) {
-        let self_param_pretty = function.self_param(hirdb)
-            .map(|param| param.display(hirdb).to_string());
-        let params_pretty = function.method_params(hirdb).map(|params| {
-            params.into_iter().map(|param| param.ty().display(hirdb).to_string())
-                .collect::<Vec<_>>()
-        });
-        trace!("fn {} ({:?} | {:?} | {:?} | {})", path,
-            self_param_pretty, assoc_params_pretty, params_pretty, ret_pretty);
+fn analyze_loaded_dependencies(loaded: &LoadedCrate, cargo_config: &CargoConfig, krate_name: &str, dependencies: &DependencySelection, items: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> Result<Vec<CrateAnalysis>> {
+    use std::convert::TryInto;
+    let hirdb: &dyn HirDatabase = loaded.host.raw_database().upcast();
+
+    // Crates in the rust-analyzer DB only know their root file, so identify packages by the root of
+    // their lib target (which also leaves out the sysroot, build scripts etc)
+    let abspath: AbsPathBuf = loaded.root.clone().try_into().unwrap();
+    let root = ProjectManifest::discover_single(&abspath)?;
+    let cargo = match ProjectWorkspace::load(root, cargo_config, &|_| {})? {
+        ProjectWorkspace::Cargo { cargo, .. } => cargo,
+        _ => panic!("unexpected workspace type"),
+    };
+    let mut lib_roots: HashMap<PathBuf, (String, String, PathBuf)> = HashMap::new(); // lib root file => (name, version, package root)
+    for package in cargo.packages().map(|pd| &cargo[pd]) {
+        let selected = match dependencies {
+            DependencySelection::All => true,
+            DependencySelection::Some(names) => names.contains(&package.name),
+        };
+        if !selected || package.name == krate_name {
+            continue
+        }
+        let package_root = PathBuf::from(package.manifest.parent().unwrap().to_path_buf());
+        for target in package.targets.iter().map(|&t| &cargo[t]).filter(|t| t.kind == TargetKind::Lib) {
+            lib_roots.insert(PathBuf::from(target.root.clone()), (package.name.clone(), package.version.to_string(), package_root.clone()));
+        }
     }
-    let assoc_params_str = assoc_params_pretty.join(", ");
-    let s = format!("fn {}({}) -> {}", path, assoc_params_str, ret_pretty);
-    vec![FnDetail {
-        krate: krate_name.to_owned(),
-        params: assoc_params_pretty,
-        ret: ret_pretty,
-        s,
-    }]
+
+    let mut analyses = vec![];
+    for krate in Crate::all(hirdb) {
+        let vfs_path = loaded.vfs.file_path(krate.root_file(hirdb));
+        let (name, version, package_root) = match vfs_path.as_path().and_then(|p| lib_roots.get(AsRef::<Path>::as_ref(p))) {
+            Some(package) => package,
+            None => continue,
+        };
+        info!("analyzing dependency {} {}", name, version);
+        // Dependencies are used through their public API, so there's no point in their private items
+        let fndetails = analyze_hir_crate(loaded, krate, name, version, package_root, ItemSelection { include_private: false, ..items }, abort, progress)?;
+        analyses.push(CrateAnalysis { name: name.clone(), version: version.clone(), root: package_root.clone(), fndetails })
+    }
+    info!("analyzed {} dependencies of {}", analyses.len(), krate_name);
+    Ok(analyses)
 }
 
-fn analyze_adt(hirdb: &dyn HirDatabase, krate_name: &str, adt: ra_hir::Adt, path: &str) -> Vec<FnDetail> {
-    let mut methods = vec![];
-    let ty = adt.ty(hirdb);
-    let krate = adt.module(hirdb).krate();
+// Where the item is defined, if it's in one of the crate's own files (i.e. under its root)
+fn item_location(loaded: &LoadedCrate, root: &Path, def: ModuleDef) -> Option<SourceLocation> {
+    let rootdb = loaded.host.raw_database();
+    let hirdb: &dyn HirDatabase = rootdb.upcast();
+    let node = match def {
+        ModuleDef::Function(f) => f.source(hirdb)?.map(|src| src.syntax().clone()),
+        ModuleDef::Adt(a) => a.source(hirdb)?.map(|src| src.syntax().clone()),
+        ModuleDef::Variant(v) => v.source(hirdb)?.map(|src| src.syntax().clone()),
+        ModuleDef::Const(c) => c.source(hirdb)?.map(|src| src.syntax().clone()),
+        ModuleDef::Static(st) => st.source(hirdb)?.map(|src| src.syntax().clone()),
+        ModuleDef::Trait(t) => t.source(hirdb)?.map(|src| src.syntax().clone()),
+        ModuleDef::Module(_) |
+        ModuleDef::TypeAlias(_) |
+        ModuleDef::BuiltinType(_) => return None,
+    };
+    // Items from macros are located at the macro call
+    let range = node.as_ref().original_file_range(rootdb);
+    let vfs_path = loaded.vfs.file_path(range.file_id);
+    let path: &Path = vfs_path.as_path()?.as_ref();
+    let file = path.strip_prefix(root).ok()?.to_str()?.to_owned();
+    let text = str::from_utf8(loaded.vfs.file_contents(range.file_id)).ok()?;
+    let before = text.get(..u32::from(range.range.start()) as usize)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some(SourceLocation {
+        file,
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    })
+}
+
+// Methods in every impl in the crate, by the adt they're for. Looking up a type's methods can miss
+// impls, e.g. in a different module to the type, so they're found from the impls instead. Impls of
+// the crate's own traits are included too, as such (extension) traits are there to add methods to
+// types - though only methods the impl defines, not defaults it doesn't override
+fn crate_impl_methods(ctx: &CrateCtx, krate: Crate) -> HashMap<ra_hir::Adt, Vec<ra_hir::Function>> {
+    let hirdb = ctx.hirdb;
+    let mut impl_methods: HashMap<ra_hir::Adt, Vec<ra_hir::Function>> = HashMap::new();
+    for imp in ra_hir::Impl::all_in_crate(hirdb, krate) {
+        let adt = match imp.target_ty(hirdb).as_adt() {
+            Some(adt) => adt,
+            None => continue,
+        };
+        // Methods of trait impls are as visible as the trait, whatever they say themselves
+        let via_trait = match imp.trait_(hirdb) {
+            None => false,
+            Some(tr) if tr.module(hirdb).krate() == krate && ctx.includes(tr.visibility(hirdb)) => true,
+            Some(_) => continue,
+        };
+        let methods = imp.items(hirdb).into_iter()
+            .filter_map(|item| match item {
+                ra_hir::AssocItem::Function(f) => Some(f),
+                ra_hir::AssocItem::Const(_) |
+                ra_hir::AssocItem::TypeAlias(_) => None,
+            })
+            .filter(|f| via_trait || ctx.includes(f.visibility(hirdb)));
+        impl_methods.entry(adt).or_default().extend(methods)
+    }
+    trace!("found methods in impls for {} adts", impl_methods.len());
+    impl_methods
+}
+
+// For each of the crate's traits with blanket impls, e.g. `impl<T: Iterator> Itertools for T`, the
+// bounds of the types it's implemented for
+fn crate_blanket_impl_bounds(ctx: &CrateCtx, krate: Crate) -> HashMap<ra_hir::Trait, Vec<String>> {
+    let hirdb = ctx.hirdb;
+    let mut blanket_impl_bounds: HashMap<ra_hir::Trait, Vec<String>> = HashMap::new();
+    for imp in ra_hir::Impl::all_in_crate(hirdb, krate) {
+        let tr = match imp.trait_(hirdb) {
+            Some(tr) if tr.module(hirdb).krate() == krate => tr,
+            _ => continue,
+        };
+        let target = imp.target_ty(hirdb).display(hirdb).to_string();
+        let generic = match analyze_generics(hirdb, imp.into()).into_iter().find(|g| g.name == target) {
+            Some(generic) => generic,
+            None => continue,
+        };
+        let bounds = blanket_impl_bounds.entry(tr).or_default();
+        for bound in generic.bounds {
+            if !bounds.contains(&bound) { bounds.push(bound) }
+        }
+    }
+    blanket_impl_bounds
+}
+
+// The paths items of the crate are exported under, which can be several (e.g. both `error::Error` and
+// a re-exported `Error`), where the import map only keeps one. Whether an entry is exported is checked
+// from outside the crate, from a dependency's root module (any other crate's module will do), so a
+// crate without dependencies has only the import map's paths
+fn crate_reexports(hirdb: &dyn HirDatabase, krate: Crate) -> Vec<(ModuleDef, String)> {
+    let outside = match krate.dependencies(hirdb).first() {
+        Some(dep) => dep.krate.root_module(hirdb),
+        None => return vec![],
+    };
+    let mut items = vec![];
+    let mut seen_modules = HashSet::new();
+    let mut modules = vec![(krate.root_module(hirdb), vec![])];
+    while let Some((module, module_path)) = modules.pop() {
+        // Modules can be re-exported too, and even re-export their parents
+        if !seen_modules.insert(module) {
+            continue
+        }
+        for (name, def) in module.scope(hirdb, Some(outside)) {
+            let def = match def {
+                ScopeDef::ModuleDef(def) => def,
+                _ => continue,
+            };
+            if def.module(hirdb).map(|m| m.krate()) != Some(krate) {
+                continue
+            }
+            let path: Vec<String> = module_path.iter().cloned().chain(Some(name.to_string())).collect();
+            match def {
+                ModuleDef::Module(m) => modules.push((m, path)),
+                def => items.push((def, path.join("::"))),
+            }
+        }
+    }
+    items
+}
+
+// All items declared in the crate (including those the import map doesn't cover, because they
+// aren't importable from outside it), with their paths within the crate
+fn crate_items(hirdb: &dyn HirDatabase, krate: Crate) -> Vec<(ModuleDef, String)> {
+    let mut items = vec![];
+    let mut modules = vec![krate.root_module(hirdb)];
+    while let Some(module) = modules.pop() {
+        let module_path: Vec<String> = module.path_to_root(hirdb).into_iter().rev()
+            .filter_map(|m| m.name(hirdb))
+            .map(|name| name.to_string())
+            .collect();
+        for decl in module.declarations(hirdb) {
+            let name = match decl.name(hirdb) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let path = module_path.iter().cloned().chain(Some(name)).collect::<Vec<_>>().join("::");
+            items.push((decl, path))
+        }
+        modules.extend(module.children(hirdb))
+    }
+    items
+}
+
+// How long to wait for more changes after a file changes, so (e.g.) saving many files at once
+// only re-indexes once
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// Keep the index of a local crate up to date as it's edited, until the watcher fails. Edits to
+// existing source files are applied to the already-loaded crate, but anything else (e.g. new files
+// or Cargo.toml changes) means loading the crate again from scratch
+pub fn watch(path: &Path, db: &Db, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> Result<()> {
+    let (cargo_config, load_cargo_config) = cargo_configs(opts);
+    if let FeatureSelection::PerFeature = opts.features {
+        warn!("per-feature analysis is not supported when watching, using default features")
+    }
+    if opts.frontend == Frontend::Rustdoc {
+        warn!("analysis from rustdoc JSON is not supported when watching, using rust-analyzer")
+    }
+    let abspath = path.canonicalize()?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, WATCH_DEBOUNCE)?;
+    watcher.watch(&abspath, notify::RecursiveMode::Recursive)?;
+
+    let mut loaded: Option<(String, String, LoadedCrate)> = None;
+    loop {
+        if opts.cancel.is_cancelled() {
+            info!("stopped watching {}", abspath.display());
+            return Ok(())
+        }
+        // Each (re)analysis gets the full timeout
+        let abort = Abort::start(&opts.cancel, opts.timeout);
+        if loaded.is_none() {
+            let (name, version, res) = load_crate(&abspath, &cargo_config, &load_cargo_config, opts.items(), &abort, progress);
+            match res {
+                Ok(loaded_crate) => loaded = Some((name, version, loaded_crate)),
+                Err(e) => {
+                    warn!("failed to load {}, will retry on next change: {:?}", abspath.display(), e);
+                    // Keep the last good index (e.g. a Cargo.toml mid-edit shouldn't empty it), and only
+                    // record the error if there isn't one
+                    if !db.open_tree(CRATE_TREE).contains_key(crate_key(&name, &version)) {
+                        save_analysis_error(db, &name, &version, &format!("{:?}", e))
+                    }
+                },
+            }
+        }
+        if let Some((name, version, loaded_crate)) = loaded.as_ref() {
+            match analyze_loaded_crate(loaded_crate, name, version, opts.items(), &abort, progress) {
+                Ok(fndetails) => {
+                    info!("indexing {} fns for {} {}", fndetails.len(), name, version);
+                    save_analysis(db, name, version, &read_crate_meta(&abspath, name, version), with_profile(fndetails, &opts.profile));
+                },
+                // Keep the previous results rather than replacing them with partial ones
+                Err(e) => warn!("failed to analyze {}, will retry on next change: {:?}", abspath.display(), e),
+            }
+        }
+
+        let changed_paths = wait_for_changes(&rx, &abspath)?;
+        let applied = match loaded.as_mut() {
+            Some((_, _, loaded_crate)) => apply_source_changes(loaded_crate, &changed_paths),
+            None => false,
+        };
+        if !applied {
+            info!("reloading {} from scratch", abspath.display());
+            loaded = None
+        }
+    }
+}
+
+// Blocks until there's a change to something that could affect the API of the crate
+fn wait_for_changes(rx: &mpsc::Receiver<notify::DebouncedEvent>, root: &Path) -> Result<Vec<PathBuf>> {
+    use notify::DebouncedEvent;
+    fn is_relevant(path: &Path, root: &Path) -> bool {
+        let in_target = path.strip_prefix(root).map_or(false, |p| p.starts_with("target"));
+        let is_rs = path.extension().map_or(false, |ext| ext == "rs");
+        !in_target && (is_rs || path.ends_with("Cargo.toml"))
+    }
+    loop {
+        let mut paths = vec![];
+        // Take everything pending at once, to avoid re-indexing once per file
+        let mut event = Some(rx.recv()?);
+        while let Some(ev) = event {
+            match ev {
+                DebouncedEvent::Create(p) |
+                DebouncedEvent::Write(p) |
+                DebouncedEvent::Remove(p) => paths.push(p),
+                DebouncedEvent::Rename(from, to) => paths.extend(vec![from, to]),
+                // Something was missed, so treat it like a change to the manifest (forcing a reload)
+                DebouncedEvent::Rescan => paths.push(root.join("Cargo.toml")),
+                DebouncedEvent::NoticeWrite(_) |
+                DebouncedEvent::NoticeRemove(_) |
+                DebouncedEvent::Chmod(_) => (),
+                DebouncedEvent::Error(e, p) => warn!("error watching {:?}: {}", p, e),
+            }
+            event = rx.try_recv().ok();
+        }
+        paths.retain(|p| is_relevant(p, root));
+        if !paths.is_empty() {
+            debug!("changed: {:?}", paths);
+            return Ok(paths)
+        }
+    }
+}
+
+// Update the contents of already-loaded source files, returning false (and changing nothing) if
+// a full reload is needed instead
+fn apply_source_changes(loaded: &mut LoadedCrate, paths: &[PathBuf]) -> bool {
+    let vfs_paths: Vec<(&PathBuf, VfsPath)> = paths.iter()
+        .map(|path| (path, VfsPath::from(AbsPathBuf::assert(path.clone()))))
+        .collect();
+    // New or removed files may change the module tree
+    let all_loaded_sources = vfs_paths.iter().all(|(path, vfs_path)| {
+        path.extension().map_or(false, |ext| ext == "rs") && path.exists() && loaded.vfs.file_id(vfs_path).is_some()
+    });
+    if !all_loaded_sources {
+        return false
+    }
+    for (path, vfs_path) in vfs_paths {
+        loaded.vfs.set_file_contents(vfs_path, fs::read(path).ok())
+    }
+    let mut change = Change::new();
+    for changed_file in loaded.vfs.take_changes() {
+        let text = if changed_file.exists() {
+            String::from_utf8(loaded.vfs.file_contents(changed_file.file_id).to_vec()).ok().map(Arc::new)
+        } else {
+            None
+        };
+        change.change_file(changed_file.file_id, text)
+    }
+    loaded.host.apply_change(change);
+    true
+}
+
+pub struct SearchResults {
+    pub fndetails: Vec<FnDetail>,
+    pub next_cursor: Option<String>, // None if this is the last page
+    pub warnings: Vec<String>,
+    pub crates: Vec<CrateMeta>, // for the crates of the fndetails, where known
+    pub explanations: Option<Vec<Option<Explanation>>>, // one per fndetail if requested, see `SearchStream::explain`
+    pub groups: Option<Vec<ResultGroup>>, // of the fndetails, if requested
+    pub facets: Option<Vec<FacetCount>>, // of all the results, if requested
+    pub suggestions: Vec<TypeSuggestion>, // if there are no results
+}
+
+pub struct SearchCache {
+    entries: Mutex<HashMap<u64, (Instant, Instant, Arc<Vec<u64>>)>>, // query hash => (when searched, when last used, fn ids in result order)
+    max_fn_ids: usize, // see `SEARCH_CACHE_MAX_FN_IDS`
+}
+
+impl SearchCache {
+    pub fn new() -> Self {
+        Self { entries: Mutex::new(HashMap::new()), max_fn_ids: SEARCH_CACHE_MAX_FN_IDS }
+    }
+
+    // `search` returns fn ids and any warnings. Results with warnings aren't cached, so searches
+    // recover as soon as possible
+    fn get_or_search(&self, query_hash: u64, search: impl FnOnce() -> (Vec<u64>, Vec<String>)) -> (Arc<Vec<u64>>, Vec<String>) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, (searched_at, _, _)| searched_at.elapsed() < SEARCH_CACHE_TTL);
+            if let Some((_, last_used, fn_ids)) = entries.get_mut(&query_hash) {
+                *last_used = Instant::now();
+                return (fn_ids.clone(), vec![])
+            }
+        }
+        // Don't hold the lock while searching - at worst, concurrent identical searches both run
+        let (fn_ids, warnings) = search();
+        let fn_ids = Arc::new(fn_ids);
+        if warnings.is_empty() && fn_ids.len() <= self.max_fn_ids {
+            let mut entries = self.entries.lock().unwrap();
+            let size = |fn_ids: &[u64]| cmp::max(fn_ids.len(), 1);
+            let mut total: usize = entries.values().map(|(_, _, fn_ids)| size(fn_ids)).sum();
+            while total + size(&fn_ids) > self.max_fn_ids {
+                let lru = *entries.iter().min_by_key(|(_, (_, last_used, _))| *last_used).unwrap().0;
+                let (_, _, evicted) = entries.remove(&lru).unwrap();
+                total -= size(&evicted);
+            }
+            let now = Instant::now();
+            entries.insert(query_hash, (now, now, fn_ids.clone()));
+        }
+        (fn_ids, warnings)
+    }
+}
+
+// Results are in a stable order (see `search_stream`), so a page is just a slice of them
+pub fn search(db: &Db, cache: &SearchCache, query: &Query, opts: &SearchOptions) -> SearchResults {
+    let _timer = metrics::SEARCH_SECONDS.start_timer();
+    let query_hash = {
+        let mut hasher = DefaultHasher::new();
+        bincode::serialize(&(query, opts.ordered)).unwrap().hash(&mut hasher);
+        hasher.finish()
+    };
+    let offset = match &opts.cursor {
+        Some(cursor) => match parse_cursor(cursor) {
+            Some((cursor_hash, offset)) if cursor_hash == query_hash => offset,
+            _ => {
+                warn!("ignoring cursor {:?}, which isn't from this search", cursor);
+                opts.offset
+            },
+        },
+        None => opts.offset,
+    };
+    let limit = opts.limit.map_or(MAX_RESULTS, |limit| cmp::min(limit, MAX_RESULTS));
+    let max_results = max_results(&query.filters, MAX_PAGINATED_RESULTS);
+
+    fn collect_fn_ids(stream: &mut SearchStream, max_results: usize) -> Vec<u64> {
+        let mut fn_ids = vec![];
+        while let Some(batch) = stream.next_with_ids() {
+            fn_ids.extend(batch.into_iter().map(|(fn_id, _)| fn_id));
+            if fn_ids.len() >= max_results {
+                break
+            }
+        }
+        fn_ids.truncate(max_results);
+        fn_ids
+    }
+
+    // Explaining needs the state of the search, so isn't possible for a cached search
+    let mut explain_stream = None;
+    let (fn_ids, warnings) = if opts.explain {
+        let mut stream = search_stream(db, query, opts.ordered);
+        let fn_ids = collect_fn_ids(&mut stream, max_results);
+        let warnings = stream.warnings().to_vec();
+        explain_stream = Some(stream);
+        (Arc::new(fn_ids), warnings)
+    } else {
+        cache.get_or_search(query_hash, || {
+            let mut stream = search_stream(db, query, opts.ordered);
+            (collect_fn_ids(&mut stream, max_results), stream.warnings().to_vec())
+        })
+    };
+
+    let fn_tree = db.open_tree(FN_TREE);
+    let (page_fn_ids, fndetails): (Vec<u64>, Vec<FnDetail>) = fn_ids.iter().skip(offset).take(limit)
+        // The crate may have been purged since the search was cached
+        .filter_map(|&fn_id| fn_tree.get(bincode::serialize(&fn_id).unwrap()).map(|fn_bytes| (fn_id, fn_bytes)))
+        .map(|(fn_id, fn_bytes)| (fn_id, FnRecord::new(fn_bytes).to_fndetail()))
+        .unzip();
+    let crates = crate_metas_for(db, &fndetails);
+    let explanations = explain_stream.map(|stream| stream.explain(&page_fn_ids));
+    let groups = opts.group_by.map(|by| group_results(&fndetails, by));
+    let facets = if opts.facets { Some(count_facets(db, &fn_ids)) } else { None };
+    let suggestions = if fn_ids.is_empty() { type_suggestions(db, query) } else { vec![] };
+    let next_offset = offset + limit;
+    // An empty page (e.g. just for the facets) has no next page, or following cursors would never end
+    let next_cursor = if limit > 0 && next_offset < fn_ids.len() {
+        Some(format!("{:016x}-{}", query_hash, next_offset))
+    } else {
+        None
+    };
+    SearchResults { fndetails, next_cursor, warnings, crates, explanations, groups, facets, suggestions }
+}
+
+// For each type of the query that isn't indexed as it is (e.g. misspelt), the closest types that are
+// (see `closest_types`), so a search finding nothing can say what was probably meant. Only worth it
+// when a search finds nothing, as every type key of the trees is compared - the keys are cached
+// until a crate is added or purged (see `Tree::keys`), but comparing them still takes a while
+fn type_suggestions(db: &Db, query: &Query) -> Vec<TypeSuggestion> {
+    let passes = normalization(db);
+    let params = query.receiver_str().into_iter().chain(query.param_strs().into_iter().flatten());
+    let types: Vec<(String, &str)> = params.map(|param| (param, PARAM_TREE))
+        // Returning nothing is a marker rather than a type (see `SigMarker`)
+        .chain(query.ret_str().filter(|_| !query.returns_nothing()).map(|ret| (ret, RET_TREE)))
+        .collect();
+    let mut suggestions: Vec<TypeSuggestion> = vec![];
+    for (ty, tree_name) in types {
+        let tree = db.open_tree(tree_name);
+        if tree.contains_key(normalize_type(&ty, &passes).as_bytes()) || suggestions.iter().any(|s| s.ty == ty) {
+            continue
+        }
+        let keys = tree.keys();
+        let closest = engine::closest_types(&ty, keys.iter().map(String::as_str), MAX_TYPE_SUGGESTIONS);
+        if !closest.is_empty() {
+            suggestions.push(TypeSuggestion { ty, suggestions: closest })
+        }
+    }
+    suggestions
+}
+
+// Most common first. Only the crate of each fn is decoded, and each crate's facets are only loaded once
+fn count_facets(db: &Db, fn_ids: &[u64]) -> Vec<FacetCount> {
+    let fn_tree = db.open_tree(FN_TREE);
+    let facet_tree = db.open_tree(CRATE_FACET_TREE);
+    let mut crate_facets: HashMap<Vec<u8>, Vec<String>> = HashMap::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for fn_id in fn_ids {
+        // The crate may have been purged since the search was cached
+        let record = match fn_tree.get(bincode::serialize(fn_id).unwrap()) {
+            Some(bytes) => FnRecord::new(bytes),
+            None => continue,
+        };
+        let key = crate_key(record.krate(), record.version());
+        let facets = crate_facets.entry(key.clone()).or_insert_with(|| {
+            facet_tree.get(&key).map_or_else(Vec::new, |bytes| bincode::deserialize(&bytes).unwrap())
+        });
+        for facet in facets.iter() {
+            *counts.entry(facet.clone()).or_insert(0) += 1
+        }
+    }
+    let mut counts: Vec<FacetCount> = counts.into_iter().map(|(facet, count)| FacetCount { facet, count }).collect();
+    counts.sort_by(|c1, c2| c2.count.cmp(&c1.count).then_with(|| c1.facet.cmp(&c2.facet)));
+    counts
+}
+
+// The most results to find for the query, as it asks or otherwise the default
+fn max_results(filters: &SearchFilters, default: usize) -> usize {
+    filters.max_results.map_or(default, |max_results| cmp::min(max_results, MAX_RESULTS_LIMIT))
+}
+
+// Fns that can be called with just the types in scope, for "what can I do with what I have?" -
+// every param must be one of the types, or a generic bounded by one given as `impl Trait`. A type
+// may satisfy more than one param, and fns without params aren't included. Fns using the most
+// distinct in scope types come first
+pub fn search_applicable(db: &Db, in_scope_types: Vec<String>, filters: &SearchFilters) -> Vec<FnDetail> {
+    let _span = metrics::span("applicable");
+    let passes = normalization(db);
+    let normalize = |ty: &str| if filters.ignore_refs { without_refs(ty) } else { normalize_type(ty, &passes) };
+    let in_scope: HashSet<String> = in_scope_types.iter().map(|ty| normalize(ty)).collect();
+
+    let mut snapshot = ReadSnapshot::take(db);
+    // Any applicable fn has a param of an in scope type, so it's enough to check fns with at least one
+    let param_tree = db.open_tree(if filters.ignore_refs { PARAM_NO_REFS_TREE } else { PARAM_TREE });
+    let mut candidate_fn_ids = FnIdSet::new();
+    for ty in in_scope.iter() {
+        if let Some(bytes) = param_tree.get(ty) {
+            candidate_fn_ids |= decode_fn_ids(&bytes)
+        }
+    }
+    debug!("checking {} candidate fns for applicability", candidate_fn_ids.len());
+
+    let mut latest_versions: HashMap<String, Option<String>> = HashMap::new();
+    let mut results: Vec<(usize, FnDetail)> = vec![];
+    for fn_id in candidate_fn_ids {
+        let fndetail = match snapshot.get_fn(fn_id) {
+            Some(fndetail) => fndetail,
+            None => continue,
+        };
+        // A param is satisfied by its type as analyzed or as written
+        let mut used_types = HashSet::new();
+        let satisfied = fndetail.params.iter().enumerate().all(|(i, param)| {
+            let written_param = written_param(&fndetail, i);
+            let used_type = iter::once(param).chain(written_param)
+                .flat_map(|ty| type_keys(&normalize(ty), &fndetail.generics))
+                .find(|key| in_scope.contains(key));
+            match used_type {
+                Some(ty) => { used_types.insert(ty); true },
+                None => false,
+            }
+        });
+        if !satisfied || fndetail.params.is_empty() || !filters.matches(&fndetail) {
+            continue
+        }
+        if let VersionFilter::Latest = filters.versions {
+            let latest = latest_versions.entry(fndetail.krate.clone())
+                .or_insert_with(|| latest_crate_version(db, &fndetail.krate));
+            if latest.as_ref() != Some(&fndetail.version) {
+                continue
+            }
+        }
+        results.push((used_types.len(), fndetail))
+    }
+
+    results.sort_by(|(n1, fd1), (n2, fd2)| {
+        n2.cmp(n1)
+            .then_with(|| fd1.krate.cmp(&fd2.krate))
+            .then_with(|| fd1.s.cmp(&fd2.s))
+            .then_with(|| fd1.version.cmp(&fd2.version))
+    });
+    results.into_iter().take(max_results(filters, MAX_RESULTS)).map(|(_, fndetail)| fndetail).collect()
+}
+
+// Limits on how much of the graph of types to explore when composing fns
+const MAX_COMPOSITION_STEPS: usize = 4;
+const MAX_COMPOSITION_FANOUT: usize = 500; // fns tried from each type
+const MAX_COMPOSITION_FRONTIER: usize = 1000; // types explored from on each step
+const MAX_ROUTES_PER_TYPE: usize = 3; // partial chains kept that end at each type
+
+// Chains of up to `max_steps` fn calls that get from a value of one type to a value of another, e.g.
+// from `&str` to `SocketAddr` - each fn takes the value so far as one of its params, and `Option`s and
+// `Result`s may be unwrapped between calls. Types are matched regardless of references (as with
+// `ignore_refs`). The graph is explored breadth first, so the shortest chains are found first, and
+// they're ranked by how many calls, other params and unwraps they need
+pub fn search_compositions(db: &Db, from: &str, to: &str, max_steps: usize, filters: &SearchFilters) -> Vec<Vec<CompositionStep>> {
+    let _span = metrics::span("compositions");
+    #[derive(Clone)]
+    enum Step {
+        Call(u64),
+        Unwrap(String, String),
+    }
+
+    let max_steps = cmp::min(max_steps, MAX_COMPOSITION_STEPS);
+    let from = without_refs(from);
+    let to = without_refs(to);
+    let mut snapshot = ReadSnapshot::take(db);
+    let param_tree = db.open_tree(PARAM_NO_REFS_TREE);
+    // Single param fns are the most natural steps, so are tried first
+    let single_param_fn_ids: FnIdSet = db.open_tree(ARITY_TREE).get("1")
+        .map(|bytes| decode_fn_ids(&bytes))
+        .unwrap_or_else(FnIdSet::new);
+
+    let mut fndetails: HashMap<u64, Option<FnDetail>> = HashMap::new(); // None if filtered out
+    let mut latest_versions: HashMap<String, Option<String>> = HashMap::new();
+    let mut routes: HashMap<String, usize> = HashMap::new(); // type => partial chains ending at it
+    routes.insert(from.clone(), MAX_ROUTES_PER_TYPE);
+    let mut frontier: Vec<(String, Vec<Step>)> = vec![(from, vec![])];
+    let mut chains: Vec<Vec<Step>> = vec![];
+    for _ in 0..max_steps {
+        let mut next_frontier = vec![];
+        for (ty, chain) in frontier {
+            let mut fn_ids: Vec<u64> = param_tree.get(&ty)
+                .map(|bytes| decode_fn_ids(&bytes))
+                .unwrap_or_else(FnIdSet::new)
+                .into_iter().collect();
+            fn_ids.sort_by_key(|&fn_id| (!single_param_fn_ids.contains(fn_id), fn_id));
+            fn_ids.truncate(MAX_COMPOSITION_FANOUT);
+            for fn_id in fn_ids {
+                if chain.iter().any(|step| matches!(step, Step::Call(id) if *id == fn_id)) {
+                    continue
+                }
+                let fndetail = fndetails.entry(fn_id).or_insert_with(|| {
+                    let fndetail = snapshot.get_fn(fn_id)?;
+                    if !filters.matches(&fndetail) {
+                        return None
+                    }
+                    if let VersionFilter::Latest = filters.versions {
+                        let latest = latest_versions.entry(fndetail.krate.clone())
+                            .or_insert_with(|| latest_crate_version(db, &fndetail.krate));
+                        if latest.as_ref() != Some(&fndetail.version) {
+                            return None
+                        }
+                    }
+                    Some(fndetail)
+                });
+                let fndetail = match fndetail {
+                    Some(fndetail) => fndetail,
+                    None => continue,
+                };
+
+                // The ret type, then each success type inside it
+                let mut call = chain.clone();
+                call.push(Step::Call(fn_id));
+                let mut outputs = vec![(without_refs(&fndetail.ret), call)];
+                let mut wrapped = fndetail.ret.clone();
+                for inner in unwrapped_ret_keys(fndetail) {
+                    let mut unwrapped = outputs.last().unwrap().1.clone();
+                    unwrapped.push(Step::Unwrap(wrapped, inner.clone()));
+                    outputs.push((without_refs(&inner), unwrapped));
+                    wrapped = inner;
+                }
+                for (out_ty, out_chain) in outputs {
+                    if out_ty == to {
+                        chains.push(out_chain);
+                        break
+                    }
+                    let num_routes = routes.entry(out_ty.clone()).or_insert(0);
+                    if out_ty == "()" || *num_routes >= MAX_ROUTES_PER_TYPE || next_frontier.len() >= MAX_COMPOSITION_FRONTIER {
+                        continue
+                    }
+                    *num_routes += 1;
+                    next_frontier.push((out_ty, out_chain))
+                }
+            }
+        }
+        frontier = next_frontier;
+    }
+    debug!("found {} chains to {}", chains.len(), to);
+
+    let fndetail = |fn_id: &u64| fndetails[fn_id].as_ref().unwrap();
+    chains.sort_by_cached_key(|chain| {
+        let mut calls = 0;
+        let mut other_params = 0;
+        let mut unwraps = 0;
+        let mut sigs = vec![];
+        for step in chain {
+            match step {
+                Step::Call(fn_id) => {
+                    calls += 1;
+                    other_params += fndetail(fn_id).params.len() - 1;
+                    sigs.push(fndetail(fn_id).s.clone())
+                },
+                Step::Unwrap(..) => unwraps += 1,
+            }
+        }
+        (calls, other_params, unwraps, sigs)
+    });
+    chains.truncate(max_results(filters, MAX_RESULTS));
+    chains.into_iter()
+        .map(|chain| chain.into_iter().map(|step| match step {
+            Step::Call(fn_id) => CompositionStep::Call(Box::new(fndetail(&fn_id).clone())),
+            Step::Unwrap(from, to) => CompositionStep::Unwrap { from, to },
+        }).collect())
+        .collect()
+}
+
+// A cursor is the query hash and the offset of the next page
+fn parse_cursor(cursor: &str) -> Option<(u64, usize)> {
+    let (query_hash, offset) = cursor.split_once('-')?;
+    Some((u64::from_str_radix(query_hash, 16).ok()?, offset.parse().ok()?))
+}
+
+// Returns an iterator of result batches, one per pass over the fuzzy candidates - earlier passes
+// only consider the closest candidates, so the results are (approximately) best first. Each batch
+// is sorted by crate, signature, version and then fn id, so the order is stable for a given DB
+// `ordered` means each param must match the fn's param in the same position, rather than any of them
+pub fn search_stream<'a>(db: &Db, query: &'a Query, ordered: bool) -> SearchStream<'a> {
+    let filters = &query.filters;
+    let params_search = query.param_strs();
+    let ret_search = query.ret_str();
+    let receiver_search = query.receiver_str();
+    let ret_impl_search = query.ret_impl.clone();
+    let name_query = query.name.clone();
+    let doc_query = query.doc.clone();
+    let param_index = if filters.ignore_refs { PARAM_TYPES_NO_REFS_INDEX } else { PARAM_TYPES_INDEX };
+    let ret_index = if filters.ignore_refs { RET_TYPES_NO_REFS_INDEX } else { RET_TYPES_INDEX };
+
+    let param_tree = db.open_tree(if filters.ignore_refs { PARAM_NO_REFS_TREE } else { PARAM_TREE });
+    let ret_tree = db.open_tree(if filters.ignore_refs { RET_NO_REFS_TREE } else { RET_TREE });
+    let unwrapped_ret_index = if filters.ignore_refs { UNWRAPPED_RET_TYPES_NO_REFS_INDEX } else { UNWRAPPED_RET_TYPES_INDEX };
+    let unwrapped_ret_tree = db.open_tree(if filters.ignore_refs { UNWRAPPED_RET_NO_REFS_TREE } else { UNWRAPPED_RET_TREE });
+    let param_components_tree = db.open_tree(PARAM_COMPONENTS_TREE);
+    let ret_impls_tree = db.open_tree(RET_IMPLS_TREE);
+    let name_tree = db.open_tree(NAME_TREE);
+    let doc_tree = db.open_tree(DOC_TREE);
+    // Before reading anything else, so crates committed part way through reading aren't seen
+    let snapshot = ReadSnapshot::take(db);
+    let passes = normalization(db);
+
+    let candidate_limit = filters.candidate_limit.map_or(FUZZY_SEARCH_LIMIT, |limit| cmp::min(limit, MAX_FUZZY_SEARCH_LIMIT));
+    let mut warnings = vec![];
+    let mut column = |kind: &'static str, term: &str, tree: Tree, index: &'static str, fuzzy_query: &str, excluded: fn(&SearchFilters, &str) -> bool| {
+        let text_index = if db.has_text_search() { Some(db.text_index_name(index)) } else { None };
+        let source = CandidateSource { index, text_index, query: term.to_owned(), fuzzy_query: fuzzy_query.to_owned(), excluded, exact: false };
+        let candidates = find_candidates(&source, &tree, candidate_limit, filters, &mut warnings);
+        let normalization = if index == PARAM_TYPES_INDEX || index == RET_TYPES_INDEX { passes.clone() } else { vec![] };
+        CandidateColumn { kind, term: term.to_owned(), tree, source, candidates, fn_ids: FnIdSet::new(), loaded: 0, param_position: None, normalization }
+    };
+    let not_excluded: fn(&SearchFilters, &str) -> bool = |_, _| false;
+
+    // Normalize the query the same way as the keys
+    let (params_search, ret_search, receiver_search) = if filters.ignore_refs {
+        let params_search = params_search.map(|params| params.iter().map(|param| without_refs(param)).collect());
+        (params_search, ret_search.map(|ret| without_refs(&ret)), receiver_search.map(|receiver| without_refs(&receiver)))
+    } else {
+        let params_search = params_search.map(|params| params.iter().map(|param| normalize_type(param, &passes)).collect());
+        (params_search, ret_search.map(|ret| normalize_type(&ret, &passes)), receiver_search.map(|receiver| normalize_type(&receiver, &passes)))
+    };
+    let synonyms = if filters.ignore_synonyms { vec![] } else { synonyms::load_synonyms(db) };
+    let normalize = |ty: &str| if filters.ignore_refs { without_refs(ty) } else { normalize_type(ty, &passes) };
+
+    let mut columns: Vec<CandidateColumn> = vec![];
+    let mut relaxed = vec![];
+
+    if let Some(ret_search) = ret_search {
+        // e.g. `Result<(), io::Error>` for returning nothing
+        let unwrapped_ret_column = column("unwrapped ret", &ret_search, unwrapped_ret_tree, unwrapped_ret_index, &tokenize_type(&ret_search), SearchFilters::excludes_ret_type);
+        relaxed.push((columns.len(), unwrapped_ret_column));
+        if query.returns_nothing() {
+            columns.push(marker_column(db, SigMarker::NoRet));
+        } else {
+            for alternative in synonyms::expand(&ret_search, &synonyms, normalize) {
+                let synonym_column = column("synonym", &alternative, ret_tree.clone(), ret_index, &tokenize_type(&alternative), SearchFilters::excludes_ret_type);
+                relaxed.push((columns.len(), synonym_column));
+            }
+            columns.push(column("ret", &ret_search, ret_tree, ret_index, &tokenize_type(&ret_search), SearchFilters::excludes_ret_type));
+        }
+    }
+
+    if let Some(ret_impl_search) = ret_impl_search {
+        columns.push(column("ret impl", &ret_impl_search, ret_impls_tree, RET_IMPLS_INDEX, &tokenize_type(&ret_impl_search), not_excluded));
+    }
+
+    let position_keys: fn(&FnDetail, usize) -> Vec<String> = if filters.ignore_refs { param_no_refs_position_keys } else { param_position_keys };
+    // Other params come after the receiver, if there is one
+    let first_param = if receiver_search.is_some() { 1 } else { 0 };
+    if let Some(receiver_search) = receiver_search.as_ref() {
+        if query.autoref {
+            // As a method call would find them - methods of what the receiver derefs to (e.g. `[u8]` for
+            // `Vec<u8>`, or `str` for `String`) through any references, then methods of the type itself
+            // that aren't indexed under what it derefs to (e.g. `Vec::push`)
+            let param_no_refs_tree = db.open_tree(PARAM_NO_REFS_TREE);
+            let derefed = without_refs(receiver_search);
+            let mut owned = TypeRepr::parse(receiver_search);
+            while let TypeRepr::Ref { inner, .. } = owned {
+                owned = *inner
+            }
+            let owned = owned.to_string();
+            if owned != derefed {
+                let mut owned_column = column("autoref receiver", &owned, param_no_refs_tree.clone(), PARAM_TYPES_NO_REFS_INDEX, &tokenize_type(&owned), SearchFilters::excludes_param_type);
+                owned_column.param_position = Some((0, param_no_refs_position_keys));
+                relaxed.push((columns.len(), owned_column));
+            }
+            let mut receiver_column = column("receiver", &derefed, param_no_refs_tree, PARAM_TYPES_NO_REFS_INDEX, &tokenize_type(&derefed), SearchFilters::excludes_param_type);
+            receiver_column.param_position = Some((0, param_no_refs_position_keys));
+            columns.push(receiver_column);
+        } else {
+            let mut receiver_column = column("receiver", receiver_search, param_tree.clone(), param_index, &tokenize_type(receiver_search), SearchFilters::excludes_param_type);
+            receiver_column.param_position = Some((0, position_keys));
+            columns.push(receiver_column);
+        }
+    }
+
+    if query.takes_no_params() {
+        columns.push(marker_column(db, SigMarker::NoParams));
+    }
+    if let Some(params_search) = params_search {
+        for (i, param) in params_search.into_iter().enumerate() {
+            let position = if ordered { Some(first_param + i) } else { None };
+            let mut component_column = column("param component", &param, param_components_tree.clone(), PARAM_COMPONENT_TYPES_INDEX, &tokenize_type(&param), not_excluded);
+            component_column.param_position = position.map(|i| (i, param_component_position_keys as fn(&FnDetail, usize) -> Vec<String>));
+            relaxed.push((columns.len(), component_column));
+            for alternative in synonyms::expand(&param, &synonyms, normalize) {
+                let mut synonym_column = column("synonym", &alternative, param_tree.clone(), param_index, &tokenize_type(&alternative), SearchFilters::excludes_param_type);
+                synonym_column.param_position = position.map(|i| (i, position_keys));
+                relaxed.push((columns.len(), synonym_column));
+            }
+            // Fns taking any kind of callable with the same signature, e.g. `impl FnMut(&str) -> bool`
+            // or `fn(&str) -> bool` for `impl Fn(&str) -> bool` (see `type_keys`)
+            if let Some(sig) = TypeRepr::parse(&param).callable_sig().map(|sig| normalize(&sig.to_string())).filter(|sig| *sig != param) {
+                let mut callable_column = column("callable", &sig, param_tree.clone(), param_index, &tokenize_type(&sig), SearchFilters::excludes_param_type);
+                callable_column.param_position = position.map(|i| (i, position_keys));
+                relaxed.push((columns.len(), callable_column));
+            }
+            // Fns taking what the param converts to, which it can be passed to with a `.into()` etc
+            if !filters.ignore_conversions {
+                for target in conversion_targets(db, &param) {
+                    let target = normalize(&target);
+                    let mut conversion_column = column("conversion", &target, param_tree.clone(), param_index, &tokenize_type(&target), SearchFilters::excludes_param_type);
+                    conversion_column.param_position = position.map(|i| (i, position_keys));
+                    relaxed.push((columns.len(), conversion_column));
+                }
+            }
+            let mut param_column = column("param", &param, param_tree.clone(), param_index, &tokenize_type(&param), SearchFilters::excludes_param_type);
+            param_column.param_position = position.map(|i| (i, position_keys));
+            columns.push(param_column);
+        }
+    }
+
+    if let Some(name_query) = name_query {
+        columns.push(column("name", &name_query, name_tree, NAMES_INDEX, &tokenize_name(&name_query), not_excluded));
+    }
+
+    if let Some(doc_query) = doc_query {
+        columns.push(column("doc", &doc_query, doc_tree, DOCS_INDEX, &doc_query, not_excluded));
+    }
+
+    // A part of the query nothing in the index is like (e.g. a misspelt type) would rule out every fn,
+    // so it's left out of the search instead - unless it can still match relaxed, or is a marker (e.g.
+    // no params). Widening never finds candidates for a column that had none
+    let mut c = 0;
+    while c < columns.len() {
+        let unmatched = columns[c].candidates.is_empty() && !columns[c].source.exact &&
+            relaxed.iter().all(|(relaxed_c, relaxed_column)| *relaxed_c != c || relaxed_column.candidates.is_empty());
+        if !unmatched {
+            c += 1;
+            continue
+        }
+        let dropped = columns.remove(c);
+        warnings.push(format!("Nothing in the index matched the {} `{}`, so it was left out of the search", dropped.kind, dropped.term));
+        relaxed.retain(|(relaxed_c, _)| *relaxed_c != c);
+        for (relaxed_c, _) in relaxed.iter_mut() {
+            if *relaxed_c > c { *relaxed_c -= 1 }
+        }
+    }
+
+    // Rather than loading details of fns in excluded (or not included) crates, just never consider them
+    let crate_tree = db.open_tree(CRATE_TREE);
+    // Each spec's requirement is parsed once here, and an invalid one warned of rather than quietly
+    // matching nothing
+    let mut crate_fn_ids = |specs: &[String], unmatched: &str| {
+        let mut fn_ids = FnIdSet::new();
+        for spec in specs {
+            let (krate, req) = split_crate_spec(spec);
+            let req = match req.map(semver::VersionReq::parse).transpose() {
+                Ok(req) => req,
+                Err(e) => {
+                    warnings.push(format!("The crate `{}` has an invalid version requirement ({}), so {}", spec, e, unmatched));
+                    continue
+                },
+            };
+            for (key, val) in crate_tree.scan_prefix(crate_key_prefix(krate)) {
+                let (key_krate, version) = parse_crate_key(&key);
+                let version_ok = req.as_ref().map_or(true, |req| semver::Version::parse(version).map_or(false, |version| req.matches(&version)));
+                if key_krate != krate || !version_ok {
+                    continue
+                }
+                let krate_fn_ids: Vec<u64> = bincode::deserialize(&val).unwrap();
+                fn_ids.extend(krate_fn_ids)
+            }
+        }
+        fn_ids
+    };
+    let mut excluded_fn_ids = crate_fn_ids(&filters.exclude_crates, "none of its versions were excluded");
+    let mut allowed_fn_ids = filters.crates.as_ref().map(|krates| crate_fn_ids(krates, "none of its versions were searched"));
+
+    // Crates with every facet asked for
+    if !filters.categories.is_empty() || !filters.keywords.is_empty() {
+        let wanted: Vec<String> = filters.categories.iter().map(|category| category_facet(category))
+            .chain(filters.keywords.iter().map(|keyword| keyword_facet(keyword)))
+            .collect();
+        let mut facet_fn_ids = FnIdSet::new();
+        for (key, val) in db.open_tree(CRATE_FACET_TREE).iter() {
+            let facets: Vec<String> = bincode::deserialize(&val).unwrap();
+            if !wanted.iter().all(|facet| facets.contains(facet)) {
+                continue
+            }
+            if let Some(bytes) = crate_tree.get(&key) {
+                let krate_fn_ids: Vec<u64> = bincode::deserialize(&bytes).unwrap();
+                facet_fn_ids.extend(krate_fn_ids)
+            }
+        }
+        allowed_fn_ids = Some(match allowed_fn_ids {
+            Some(fn_ids) => fn_ids & facet_fn_ids,
+            None => facet_fn_ids,
+        })
+    }
+
+    // Likewise for fns with (or without) flags, e.g. for only searching safe fns
+    let flag_tree = db.open_tree(FLAG_TREE);
+    for &(filter, flag) in [(filters.is_unsafe, UNSAFE_FLAG), (filters.raw_pointers, RAW_POINTERS_FLAG)].iter() {
+        let flag_fn_ids: FnIdSet = flag_tree.get(flag)
+            .map(|bytes| decode_fn_ids(&bytes))
+            .unwrap_or_else(FnIdSet::new);
+        match filter {
+            Some(false) => excluded_fn_ids |= flag_fn_ids,
+            Some(true) => allowed_fn_ids = Some(match allowed_fn_ids {
+                Some(fn_ids) => fn_ids & flag_fn_ids,
+                None => flag_fn_ids,
+            }),
+            None => (),
+        }
+    }
+
+    // There are few distinct arities, so it's cheap to find all fns with an allowed one up front
+    if filters.min_params.is_some() || filters.max_params.is_some() {
+        let arity_tree = db.open_tree(ARITY_TREE);
+        let mut arity_fn_ids = FnIdSet::new();
+        for (key, val) in arity_tree.iter() {
+            let arity: usize = str::from_utf8(&key).unwrap().parse().unwrap();
+            if filters.matches_arity(arity) {
+                arity_fn_ids |= decode_fn_ids(&val)
+            }
+        }
+        allowed_fn_ids = Some(match allowed_fn_ids {
+            Some(crate_fn_ids) => crate_fn_ids & arity_fn_ids,
+            None => arity_fn_ids,
+        })
+    }
+
+    // The crate filters are applied by fn id above, so needn't be checked (and parsed) again for each fn
+    let filters = if filters.crates.is_none() && filters.exclude_crates.is_empty() {
+        Cow::Borrowed(filters)
+    } else {
+        Cow::Owned(SearchFilters { crates: None, exclude_crates: vec![], ..filters.clone() })
+    };
+    SearchStream {
+        db: db.clone(),
+        snapshot,
+        columns,
+        relaxed,
+        filters,
+        latest_versions: HashMap::new(),
+        depth: 1,
+        candidate_limit,
+        num_results: 0,
+        seen_fn_ids: excluded_fn_ids,
+        allowed_fn_ids,
+        warnings,
+        found: HashMap::new(),
+        check_positions: ordered || first_param == 1,
+        misordered: HashMap::new(),
+        crate_results: HashMap::new(),
+    }
+}
+
+pub struct SearchStream<'a> {
+    db: Db,
+    snapshot: ReadSnapshot,
+    columns: Vec<CandidateColumn>,
+    // Alternatives to columns that match less exactly, with the index of the column each replaces -
+    // e.g. matching the success type of a wrapped ret, or a component of a tuple param
+    relaxed: Vec<(usize, CandidateColumn)>,
+    filters: Cow<'a, SearchFilters>,
+    latest_versions: HashMap<String, Option<String>>, // crate name => latest version, populated on demand
+    depth: usize, // the next pass, from 1 - each pass considers the next candidate of every column
+    candidate_limit: usize, // how many candidates each column was fetched with
+    num_results: usize, // returned so far
+    seen_fn_ids: FnIdSet, // includes excluded fn ids, so they're skipped
+    allowed_fn_ids: Option<FnIdSet>, // None if not restricted to particular crates or arities
+    warnings: Vec<String>, // ways the results may be degraded, for showing to the user
+    found: HashMap<u64, (usize, Option<usize>, usize)>, // fn id => (pass, index of the relaxed column used, crate rank)
+    check_positions: bool, // for `ordered`, or a receiver, see `CandidateColumn::param_position`
+    // Fns in every column but with params in the wrong places, which later candidates may put right
+    misordered: HashMap<u64, Option<usize>>, // fn id => index of the relaxed column used
+    crate_results: HashMap<String, usize>, // crate name => results returned so far, see `diversify`
+}
+
+// The fuzzy candidates for one part of the query (e.g. one param), and the fns they match so far
+struct CandidateColumn {
+    kind: &'static str, // what the column matches on, for explaining results
+    term: String, // the part of the query
+    tree: Tree,
+    source: CandidateSource,
+    candidates: Vec<String>, // candidate keys in order of closeness
+    fn_ids: FnIdSet, // union of the fns of the candidates considered so far
+    // How many candidates have been considered - one a pass, so it's behind the pass for candidates
+    // added by widening (see `SearchStream::widen_candidates`)
+    loaded: usize,
+    // For a param that must be in a particular position (e.g. a receiver), the position and the keys
+    // of the fn param there
+    param_position: Option<(usize, fn(&FnDetail, usize) -> Vec<String>)>,
+    // The index's normalization, if the column's keys are normalized, to apply to those of the fn param
+    normalization: Vec<NormalizationPass>,
+}
+
+// Where a column's candidates come from, so more can be fetched
+struct CandidateSource {
+    index: &'static str,
+    text_index: Option<String>, // of the index searched (see `Db::text_index_name`), if the DB has one
+    query: String,
+    fuzzy_query: String, // the query as the index is tokenized
+    excluded: fn(&SearchFilters, &str) -> bool, // candidates the filters rule out
+    exact: bool, // only the query itself, for markers (see `marker_column`)
+}
+
+// Fns with the marker, which is its only candidate - markers aren't in the text search
+fn marker_column(db: &Db, marker: SigMarker) -> CandidateColumn {
+    let tree = db.open_tree(MARKER_TREE);
+    let key = marker.key().to_owned();
+    let source = CandidateSource { index: MARKER_TREE, text_index: None, query: key.clone(), fuzzy_query: key.clone(), excluded: |_, _| false, exact: true };
+    let candidates = if tree.contains_key(&key) { vec![key] } else { vec![] };
+    CandidateColumn { kind: marker.describe(), term: "()".to_owned(), tree, source, candidates, fn_ids: FnIdSet::new(), loaded: 0, param_position: None, normalization: vec![] }
+}
+
+impl CandidateColumn {
+    // Fetches up to the new limit, appending any candidates not already in the column - they're
+    // further from the query than all the existing ones, so passes so far are unaffected, and they're
+    // considered on the passes after. Returns whether there were any
+    fn widen(&mut self, limit: usize, filters: &SearchFilters, warnings: &mut Vec<String>) -> bool {
+        let mut widened = false;
+        for candidate in find_candidates(&self.source, &self.tree, limit, filters, warnings) {
+            if !self.candidates.contains(&candidate) {
+                self.candidates.push(candidate);
+                widened = true
+            }
+        }
+        widened
+    }
+
+    fn candidate_fn_ids(&self, i: usize) -> FnIdSet {
+        self.tree.get_fn_ids(&self.candidates[i])
+            .map_or_else(FnIdSet::new, |fn_ids| (*fn_ids).clone())
+    }
+
+    // Considers the next candidate, if there are any left, returning the fn ids it added to the
+    // column. Fns that aren't allowed are never added, keeping the column small
+    fn load_next_candidate(&mut self, allowed_fn_ids: Option<&FnIdSet>) -> FnIdSet {
+        let ct = match self.candidates.get(self.loaded) {
+            Some(ct) => ct,
+            None => return FnIdSet::new(),
+        };
+        self.loaded += 1;
+        // The text index may have keys that are no longer in the DB, if it wasn't fully reloaded
+        let fn_ids = match self.tree.get_fn_ids(ct) {
+            Some(fn_ids) => fn_ids,
+            None => return FnIdSet::new(),
+        };
+        let mut delta = match allowed_fn_ids {
+            Some(allowed) => &*fn_ids & allowed,
+            None => (*fn_ids).clone(),
+        };
+        delta -= &self.fn_ids;
+        self.fn_ids |= &delta;
+        delta
+    }
+}
+
+// The unseen fns from the deltas (what each column gained this pass) that are now in every column, as
+// for `engine::matching_fn_ids` - but intersecting whole bitmaps is quicker than checking fns one by one
+fn matching_fn_ids(columns: &[CandidateColumn], deltas: &[FnIdSet], seen_fn_ids: &FnIdSet) -> FnIdSet {
+    let mut fn_ids = FnIdSet::new();
+    for delta in deltas {
+        fn_ids |= delta
+    }
+    for column in columns {
+        fn_ids &= &column.fn_ids
+    }
+    fn_ids -= seen_fn_ids;
+    fn_ids
+}
+
+impl<'a> SearchStream<'a> {
+    // Only the fns the columns gained need checking, see `engine::matching_fn_ids`
+    // Fns only matching with a relaxed column come after the others from the same pass
+    fn next_pass(&mut self) -> Vec<(u64, FnRecord)> {
+        let _span = metrics::span("pass");
+        self.depth += 1;
+
+        let allowed_fn_ids = self.allowed_fn_ids.as_ref();
+        let mut deltas: Vec<FnIdSet> = self.columns.iter_mut().map(|column| column.load_next_candidate(allowed_fn_ids)).collect();
+
+        let new_fn_ids = matching_fn_ids(&self.columns, &deltas, &self.seen_fn_ids);
+        self.seen_fn_ids |= &new_fn_ids;
+        let mut batch = self.load_batch(new_fn_ids, None);
+
+        // Relaxed matches come after exact ones, each relaxing one column at a time. The batch is
+        // loaded with the relaxed column in place, so params can be checked against it
+        let mut relaxed = mem::take(&mut self.relaxed);
+        for (r, (c, relaxed_column)) in relaxed.iter_mut().enumerate() {
+            let exact_delta = mem::replace(&mut deltas[*c], relaxed_column.load_next_candidate(self.allowed_fn_ids.as_ref()));
+            mem::swap(&mut self.columns[*c], relaxed_column);
+            let relaxed_fn_ids = matching_fn_ids(&self.columns, &deltas, &self.seen_fn_ids);
+            self.seen_fn_ids |= &relaxed_fn_ids;
+            batch.extend(self.load_batch(relaxed_fn_ids, Some(r)));
+            mem::swap(&mut self.columns[*c], relaxed_column);
+            deltas[*c] = exact_delta;
+        }
+        self.relaxed = relaxed;
+        batch
+    }
+
+    // Only what's needed to filter and order the fns is decoded, as a search may find far more fns
+    // than it returns
+    // Misordered fns found with the same relaxed column (if any) are checked again, against the
+    // candidates considered so far
+    fn load_batch(&mut self, mut fn_ids: FnIdSet, relaxed: Option<usize>) -> Vec<(u64, FnRecord)> {
+        let _span = metrics::span("load");
+        let misordered: Vec<u64> = self.misordered.iter().filter(|(_, r)| **r == relaxed).map(|(&fn_id, _)| fn_id).collect();
+        for fn_id in misordered {
+            self.misordered.remove(&fn_id);
+            fn_ids.insert(fn_id);
+        }
+        metrics::SEARCH_LOADED_FNS.observe(fn_ids.len() as f64);
+        let pass = self.depth - 1;
+        let mut batch = vec![];
+        for fn_id in fn_ids {
+            // e.g. the crate was still being added when the search started
+            let record = match self.snapshot.get_fn_record(fn_id) {
+                Some(record) => record,
+                None => continue,
+            };
+            if !record.matches(&self.filters) || !self.matches_version(record.krate(), record.version()) {
+                continue
+            }
+            if self.check_positions && !self.params_in_order(&record.to_fndetail()) {
+                self.misordered.insert(fn_id, relaxed);
+                continue
+            }
+            self.found.insert(fn_id, (pass, relaxed, 0));
+            batch.push((fn_id, record));
+        }
+
+        batch.sort_by(|(id1, r1), (id2, r2)| {
+            engine::result_order(r1.krate(), r1.s(), r1.version(), *id1)
+                .cmp(&engine::result_order(r2.krate(), r2.s(), r2.version(), *id2))
+        });
+        self.diversify(batch)
+    }
+
+    // Applies `max_per_crate` and `prefer_distinct_crates` to a sorted batch, counting results from
+    // each crate across the whole stream. Preferring distinct crates reorders the batch by how many
+    // results each fn's crate had before it, so it can't move results between passes
+    fn diversify(&mut self, batch: Vec<(u64, FnRecord)>) -> Vec<(u64, FnRecord)> {
+        if self.filters.max_per_crate.is_none() && !self.filters.prefer_distinct_crates {
+            return batch
+        }
+        let mut ranked = vec![];
+        for (fn_id, record) in batch {
+            let n = self.crate_results.entry(record.krate().to_owned()).or_insert(0);
+            if self.filters.max_per_crate.map_or(false, |max| *n >= max) {
+                continue
+            }
+            if let Some(found) = self.found.get_mut(&fn_id) {
+                found.2 = *n
+            }
+            ranked.push((*n, fn_id, record));
+            *n += 1;
+        }
+        if self.filters.prefer_distinct_crates {
+            // Stable, so each crate's results stay in order
+            ranked.sort_by_key(|(n, _, _)| *n);
+        }
+        ranked.into_iter().map(|(_, fn_id, record)| (fn_id, record)).collect()
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // For fns already returned by the stream - for each column, the closest candidate with the fn.
+    // Candidate fn sets are only loaded once, as many fns usually share candidates. Candidate sets are
+    // read again, so a fn isn't explained (None) if a crate was added, purged or compacted since
+    pub fn explain(&self, fn_ids: &[u64]) -> Vec<Option<Explanation>> {
+        let mut candidate_fn_ids: HashMap<(&str, &str), FnIdSet> = HashMap::new(); // (kind, candidate) => fn ids
+        fn_ids.iter().map(|fn_id| self.explain_fn(*fn_id, &mut candidate_fn_ids)).collect()
+    }
+
+    fn explain_fn<'s>(&'s self, fn_id: u64, candidate_fn_ids: &mut HashMap<(&'s str, &'s str), FnIdSet>) -> Option<Explanation> {
+        let &(pass, relaxed, crate_rank) = match self.found.get(&fn_id) {
+            Some(found) => found,
+            None => {
+                warn!("not explaining fn {}, which the search didn't find", fn_id);
+                return None
+            },
+        };
+        let mut terms = vec![];
+        for (c, column) in self.columns.iter().enumerate() {
+            let column = match relaxed.map(|r| &self.relaxed[r]) {
+                Some((relaxed_c, relaxed_column)) if *relaxed_c == c => relaxed_column,
+                _ => column,
+            };
+            // Found on this pass, so in one of the candidates considered by then, which come before any
+            // considered since - unless the candidate's fns changed since
+            let i = (0..column.loaded)
+                .find(|&i| {
+                    candidate_fn_ids.entry((column.kind, &column.candidates[i]))
+                        .or_insert_with(|| column.candidate_fn_ids(i))
+                        .contains(fn_id)
+                });
+            let i = match i {
+                Some(i) => i,
+                None => {
+                    warn!("not explaining fn {}, which is no longer in any {} candidate", fn_id, column.kind);
+                    return None
+                },
+            };
+            terms.push(TermMatch {
+                kind: column.kind.to_owned(),
+                term: column.term.clone(),
+                candidate: column.candidates[i].clone(),
+                depth: i + 1,
+            })
+        }
+        let score = Score { pass, relaxed: relaxed.map_or(0, |r| r + 1), crate_rank };
+        let relaxed = relaxed.map(|r| self.relaxed[r].1.kind.to_owned());
+        Some(Explanation { pass, relaxed, terms, score })
+    }
+
+    fn next_with_ids(&mut self) -> Option<Vec<(u64, FnRecord)>> {
+        loop {
+            while self.has_unloaded_candidates() {
+                let batch = self.next_pass();
+                if !batch.is_empty() {
+                    self.num_results += batch.len();
+                    return Some(batch)
+                }
+            }
+            let widen = self.filters.adaptive_candidates && self.num_results < ADAPTIVE_MIN_RESULTS;
+            if !widen || !self.widen_candidates() {
+                return None
+            }
+        }
+    }
+
+    // Doubles the candidates of every column, for when the closest ones don't have enough fns in
+    // common. Returns whether any column gained candidates
+    fn widen_candidates(&mut self) -> bool {
+        if self.candidate_limit >= MAX_FUZZY_SEARCH_LIMIT {
+            return false
+        }
+        self.candidate_limit = cmp::min(self.candidate_limit * 2, MAX_FUZZY_SEARCH_LIMIT);
+        info!("only {} results, widening search to {} candidates", self.num_results, self.candidate_limit);
+        let mut widened = false;
+        for column in self.columns.iter_mut().chain(self.relaxed.iter_mut().map(|(_, column)| column)) {
+            widened |= column.widen(self.candidate_limit, &self.filters, &mut self.warnings);
+        }
+        widened
+    }
+
+    // Columns run out of candidates at different passes, and widening may add to any of them
+    fn has_unloaded_candidates(&self) -> bool {
+        self.columns.iter().chain(self.relaxed.iter().map(|(_, column)| column))
+            .any(|column| column.loaded < column.candidates.len())
+    }
+}
+
+impl<'a> SearchStream<'a> {
+    // Whether each param of the query is matched by the fn param in the same position, by one of
+    // the candidates considered so far. Relaxed columns must be in place, see `next_pass`
+    fn params_in_order(&self, fndetail: &FnDetail) -> bool {
+        self.columns.iter().all(|column| match column.param_position {
+            Some((i, position_keys)) => {
+                let candidates = &column.candidates[..column.loaded];
+                i < fndetail.params.len() && normalize_keys(&column.normalization, position_keys(fndetail, i)).iter().any(|key| candidates.contains(key))
+            },
+            None => true,
+        })
+    }
+
+    // Filters that need to look at the DB, rather than just the FnDetail
+    fn matches_version(&mut self, krate: &str, version: &str) -> bool {
+        match self.filters.versions {
+            VersionFilter::Latest => {
+                let db = &self.db;
+                let latest = self.latest_versions.entry(krate.to_owned())
+                    .or_insert_with(|| latest_crate_version(db, krate));
+                latest.as_deref() == Some(version)
+            },
+            VersionFilter::Any |
+            VersionFilter::Req(_) => true,
+        }
+    }
+}
+
+impl<'a> Iterator for SearchStream<'a> {
+    type Item = Vec<FnDetail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_ids().map(|batch| batch.into_iter().map(|(_, record)| record.to_fndetail()).collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TypeInFn {
+    id: u64,
+    ty: String,
+    orig_ty: String,
+}
+
+impl meili::document::Document for TypeInFn {
+    type UIDType = u64;
+
+    fn get_uid(&self) -> &Self::UIDType {
+        &self.id
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TypeInFnResult {
+    orig_ty: String,
+}
+
+// The candidates for the source, up to the limit - exact matches, then the closest keys from the text
+// search (within the typos allowed), without any the filters exclude. Degrades to simple matching of
+// keys if the text search is down (or the DB has none), rather than failing entirely
+fn find_candidates(source: &CandidateSource, tree: &Tree, limit: usize, filters: &SearchFilters, warnings: &mut Vec<String>) -> Vec<String> {
+    let _span = metrics::span("candidates");
+    if source.exact {
+        return if tree.contains_key(&source.query) { vec![source.query.clone()] } else { vec![] }
+    }
+    // Holes are matched exactly rather than by text similarity, which would treat them as typos
+    let pattern = TypeRepr::parse(&source.query);
+    if pattern.has_holes() {
+        let mut candidates = exact_candidates(tree, &source.query);
+        for candidate in hole_candidates(tree, &pattern, limit) {
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate)
+            }
+        }
+        candidates.retain(|candidate| !(source.excluded)(filters, candidate));
+        metrics::SEARCH_CANDIDATES.with_label_values(&[source.index]).observe(candidates.len() as f64);
+        return candidates
+    }
+    // A DB without text search (see `Db::in_memory`) just has its keys scanned
+    let fuzzy = match &source.text_index {
+        Some(text_index) => text_search_candidates(source, text_index, tree, limit, warnings),
+        None => scan_candidates(tree, &source.query, limit),
+    };
+    // The text search may rank neighbours (e.g. `&OsStr` for `&str`) above an exact match
+    let mut candidates = exact_candidates(tree, &source.query);
+    for candidate in fuzzy {
+        let within_typos = filters.max_typos.map_or(true, |max_typos| within_typos(&source.query, &candidate, max_typos));
+        if within_typos && !candidates.contains(&candidate) {
+            candidates.push(candidate)
+        }
+    }
+    candidates.retain(|candidate| !(source.excluded)(filters, candidate));
+    metrics::SEARCH_CANDIDATES.with_label_values(&[source.index]).observe(candidates.len() as f64);
+    candidates
+}
+
+// Falls back to scanning keys if the text search is down
+fn text_search_candidates(source: &CandidateSource, text_index: &str, tree: &Tree, limit: usize, warnings: &mut Vec<String>) -> Vec<String> {
+    let client = meili::client::Client::new("http://localhost:7700", "no_key");
+    let index = client.assume_index(text_index);
+    let text_search_span = debug_span!("text_search", index = text_index, query = source.fuzzy_query.as_str());
+    match text_search_span.in_scope(|| fuzzy_candidates(&index, &source.fuzzy_query, limit)) {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            warn!("text search failed for {:?}, falling back to scanning keys: {:?}", source.fuzzy_query, e);
+            let warning = "Text search is unavailable, so only exact and partial matches of types and names were considered".to_owned();
+            if !warnings.contains(&warning) {
+                warnings.push(warning)
+            }
+            scan_candidates(tree, &source.query, limit)
+        },
+    }
+}
+
+// The closest keys in the index to the query, closest first
+fn fuzzy_candidates(index: &meili::indexes::Index, query: &str, limit: usize) -> Result<Vec<String>, meili::errors::Error> {
+    let timer = metrics::TEXT_SEARCH_SECONDS.start_timer();
+    let ret = futures::executor::block_on(async {
+        index.search()
+            .with_query(query)
+            .with_limit(limit)
+            .execute::<TypeInFnResult>()
+            .await
+    });
+    timer.observe_duration();
+    metrics::TEXT_SEARCH_REQUESTS.with_label_values(&[if ret.is_ok() { "ok" } else { "error" }]).inc();
+    let candidates = ret?;
+    Ok(candidates.hits.into_iter().map(|c| c.result.orig_ty).collect())
+}
+
+// Keys of the tree with the structure of a query with `_` holes (e.g. `HashMap<_, String>`), in key
+// order. Only keys starting with the text before the first hole are scanned, so one at the start
+// (e.g. `_` or `&_`) means scanning most of the tree
+fn hole_candidates(tree: &Tree, pattern: &TypeRepr, limit: usize) -> Vec<String> {
+    let text = pattern.to_string();
+    let is_ident = |b: Option<&u8>| b.map_or(false, |&b| b.is_ascii_alphanumeric() || b == b'_');
+    let bytes = text.as_bytes();
+    let first_hole = (0..bytes.len())
+        .find(|&i| bytes[i] == b'_' && !is_ident(i.checked_sub(1).and_then(|j| bytes.get(j))) && !is_ident(bytes.get(i + 1)))
+        .unwrap_or(bytes.len());
+    tree.scan_prefix(&text[..first_hole])
+        .map(|(key, _val)| String::from_utf8(key).unwrap())
+        .filter(|key| TypeRepr::parse(key).matches(pattern))
+        .take(limit)
+        .collect()
+}
+
+// Keys of the tree equal to the query, as given or in the form types are indexed in (e.g. `HashMap<K,V>`
+// is indexed as `HashMap<K, V>`)
+fn exact_candidates(tree: &Tree, query: &str) -> Vec<String> {
+    engine::exact_forms(query).into_iter().filter(|key| tree.contains_key(key)).collect()
+}
+
+// For when the text search is unavailable - keys of the tree equal to the query, then those starting
+// with it, then those containing it (ignoring whitespace, e.g. `HashMap<K,V>` finds `HashMap<K, V>`)
+fn scan_candidates(tree: &Tree, query: &str, limit: usize) -> Vec<String> {
+    fn normalize(s: &str) -> String {
+        s.chars().filter(|c| !c.is_whitespace()).collect()
+    }
+    let query = query.trim();
+    let normalized_query = normalize(query);
+    let mut candidates: Vec<String> = vec![];
+    // Returns whether there are enough candidates, so the (full tree) scans can stop early
+    let mut add = |key: &[u8]| {
+        let key = str::from_utf8(key).unwrap().to_owned();
+        if !candidates.contains(&key) && candidates.len() < limit {
+            candidates.push(key)
+        }
+        candidates.len() >= limit
+    };
+    if tree.contains_key(query) && add(query.as_bytes()) {
+        return candidates
+    }
+    for (key, _val) in tree.scan_prefix(query) {
+        if add(&key) {
+            return candidates
+        }
+    }
+    for (key, _val) in tree.iter() {
+        if normalize(str::from_utf8(&key).unwrap()).contains(&normalized_query) && add(&key) {
+            return candidates
+        }
+    }
+    candidates
+}
+
+// Documents are keyed by a hash of the tree key, so reloading without `full` just adds any new keys.
+// A `full` reload recreates the indexes, dropping keys of purged fns
+pub fn load_text_search(db: &Db, full: bool, progress: &dyn ProgressSink) {
+    let _span = info_span!("load_text_search", full).entered();
+    let client = meili::client::Client::new("http://localhost:7700", "no_key");
+
+    futures::executor::block_on(async move {
+        let settings = textsettings::meili_settings(&text_search_settings(db));
+        for &(entrytype, tree_name, index_name, tokenize) in TEXT_INDEXED_TREES {
+            let index_name = db.text_index_name(index_name);
+            if full {
+                client.delete_index_if_exists(&index_name).await.unwrap();
+            }
+            let index = client.get_or_create(&index_name).await.unwrap();
+            index.set_settings(&settings).await.unwrap().wait_for_pending_update(None, None).await.unwrap().unwrap();
+            load_type_index(entrytype, &db.open_tree(tree_name), &index, tokenize, progress).await;
+        }
+    });
+    progress.report(Progress::Phase(Phase::Done));
+}
+
+// Upserts documents for keys new to the trees and deletes them for keys no longer in the trees, so
+// the text search doesn't need reloading after each crate. Failure isn't fatal, as search copes
+// with a stale or unavailable text index
+fn sync_text_search(db: &Db, added_keys: &HashMap<&str, Vec<String>>, removed_keys: &HashMap<&str, Vec<String>>) {
+    if !db.has_text_search() {
+        return
+    }
+    let _span = debug_span!("sync_text_search").entered();
+    let client = meili::client::Client::new("http://localhost:7700", "no_key");
+
+    let ret: Result<(), meili::errors::Error> = futures::executor::block_on(async {
+        for &(entrytype, tree_name, index_name, tokenize) in TEXT_INDEXED_TREES {
+            let index = client.assume_index(&db.text_index_name(index_name));
+            if let Some(keys) = removed_keys.get(tree_name).filter(|keys| !keys.is_empty()) {
+                debug!("removing {} {} entries from text search", keys.len(), entrytype);
+                let ids: Vec<u64> = keys.iter().map(|key| stable_hash(&[key.as_str()])).collect();
+                index.delete_documents(&ids).await?;
+            }
+            if let Some(keys) = added_keys.get(tree_name).filter(|keys| !keys.is_empty()) {
+                debug!("adding {} {} entries to text search", keys.len(), entrytype);
+                let docs: Vec<TypeInFn> = keys.iter()
+                    .map(|key| TypeInFn { id: stable_hash(&[key.as_str()]), ty: tokenize(key), orig_ty: key.clone() })
+                    .collect();
+                index.add_documents(&docs, Some("id")).await?;
+            }
+        }
+        Ok(())
+    });
+    if let Err(e) = ret {
+        warn!("failed to update text search, it will need reloading: {:?}", e)
+    }
+}
+
+async fn load_type_index(entrytype: &'static str, tree: &Tree, index: &meili::indexes::Index, tokenize: fn(&str) -> String, progress: &dyn ProgressSink) {
+    async fn do_batch(entrytype: &str, index: &meili::indexes::Index, batch: &mut Vec<TypeInFn>, done: &mut usize, total: usize, tracker: &ItemsTracker<'_>) {
+        index.add_documents(batch, Some("id")).await.unwrap()
+            .wait_for_pending_update(None, None).await.unwrap().unwrap();
+        *done += batch.len();
+        info!("Added {}/{} {} entries in total", done, total, entrytype);
+        tracker.update(*done);
+        batch.clear();
+    }
+
+    let mut done = 0;
+    let mut batch = vec![];
+    let total = tree.len();
+    let tracker = ItemsTracker::start(progress, Phase::LoadingTextIndex(entrytype), total);
+    for (key, _val) in tree.iter() {
+        let str_key = str::from_utf8(&key).unwrap();
+        let tokenized_key = tokenize(str_key);
+        batch.push(TypeInFn { id: stable_hash(&[str_key]), ty: tokenized_key, orig_ty: str_key.to_owned() });
+        if batch.len() >= 500 {
+            do_batch(entrytype, index, &mut batch, &mut done, total, &tracker).await;
+        }
+    }
+    do_batch(entrytype, index, &mut batch, &mut done, total, &tracker).await;
+}
+
+pub fn debugdb(db: &Db) {
+    fn debugtree(name: &str, tree: &Tree) {
+        for (key, val) in tree.iter() {
+            let short_val_str = if val.len() > 16 {
+                format!("{:?}...", &val[..16])
+            } else {
+                format!("{:?}", val)
+            };
+            info!("tree: {}, key: ({:?} | {:?}) -> {}", name, String::from_utf8_lossy(&key), key, short_val_str)
+        }
+    }
+
+    for treename in db.tree_names() {
+        info!("# tree: {:?}", treename);
+        let tree = db.open_tree(&treename);
+        debugtree(&treename, &tree);
+    }
+}
+
+enum CrateTargetsResult {
+    Ok(String, String, Vec<CrateTarget>), // name, version, targets
+    Err(String, String, Error), // name, version, why there's nothing to analyze
+}
+// The lib, plus binaries and examples if `bins`
+fn discover_crate_targets(path: &AbsPath, cargo_config: &CargoConfig, bins: bool) -> CrateTargetsResult {
+    // If you want to see some of the complexity here:
+    // - md-5 package name is 'md-5', but target name (and import name) is 'md5'
+    with_member_package(path, cargo_config, |cargo, package| {
+        let name = package.name.clone();
+        let version = package.version.to_string();
+        let mut targets = package.targets.iter().map(|&t| &cargo[t])
+            .filter(|t| t.kind == TargetKind::Lib || (bins && matches!(t.kind, TargetKind::Bin | TargetKind::Example)))
+            .map(|t| CrateTarget { name: t.name.replace('-', "_"), root_file: PathBuf::from(t.root.clone()), is_lib: t.kind == TargetKind::Lib })
+            .collect::<Vec<_>>();
+        targets.sort_by_key(|target| !target.is_lib);
+        let num_libs = targets.iter().filter(|target| target.is_lib).count();
+        if num_libs > 1 {
+            CrateTargetsResult::Err(name, version, anyhow!("multiple lib targets found in crate"))
+        } else if targets.is_empty() && bins {
+            CrateTargetsResult::Err(name, version, anyhow!("no lib, bin or example targets found in crate"))
+        } else if targets.is_empty() {
+            CrateTargetsResult::Err(name, version, anyhow!("no lib targets found in crate, analyze with --bins to index its binaries and examples"))
+        } else {
+            CrateTargetsResult::Ok(name, version, targets)
+        }
+    })
+}
+
+fn with_member_package<T>(path: &AbsPath, cargo_config: &CargoConfig, f: impl FnOnce(&CargoWorkspace, &PackageData) -> T) -> T {
+    // We are taking crates from crates.io, so we can assume:
+    // - there is only one package (i.e. not a workspace)
+    // - there is at most one lib
+    let root = ProjectManifest::discover_single(path).unwrap();
+    let ws = ProjectWorkspace::load(root, cargo_config, &|_| {}).unwrap();
+    let cargo = match ws {
+        ProjectWorkspace::Cargo { cargo, .. } => cargo,
+        _ => panic!("unexpected workspace type"),
+    };
+    let members = cargo.packages().map(|pd| &cargo[pd]).filter(|pd| pd.is_member).collect::<Vec<_>>();
+    assert_eq!(members.len(), 1, "{:?}", members);
+    f(&cargo, members[0])
+}
+
+// Very roughly, how many times its serialized size a fn takes up in memory while being added or
+// removed (its keys in each fn set tree, the sets, and the store's copy of everything written)
+const ADD_CRATE_MEMORY_PER_BYTE: usize = 16;
+
+// Fns are added in chunks that fit in the DB write budget, each atomically. The crate is only
+// committed (i.e. visible to `has_crate`, searches etc.) once all chunks are in - until then its fn
+// ids are recorded as pending, so `purge_crate` can clean up an add that was interrupted
+fn add_crate(db: &Db, name: &str, version: &str, meta: &CrateMeta, fndetails: Vec<FnDetail>) {
+    let key = crate_key(name, version);
+    let mut trees: Vec<&str> = FN_SET_TREES.iter().map(|(tree_name, _)| *tree_name).collect();
+    trees.push(FN_TREE);
+    trees.push(PENDING_CRATE_TREE);
+    trees.push(IMPLS_OF_TREE);
+    trees.push(TRAITS_OF_TREE);
+    trees.push(CONVERSIONS_FROM_TREE);
+    trees.push(CONVERSIONS_TO_TREE);
+
+    let passes = normalization(db);
+    let journal_id = db.generate_id();
+    let chunks = add_crate_chunks(&fndetails, db.write_budget());
+    let mut fn_ids: Vec<u64> = vec![];
+    let mut added_keys: HashMap<&str, Vec<String>> = HashMap::new();
+    for (chunk_i, chunk) in chunks.iter().enumerate() {
+        debug!("adding chunk {}/{} of {} fns for crate {}", chunk_i+1, chunks.len(), chunk.len(), name);
+        let (chunk_fn_ids, chunk_added_keys) = db.transaction(&trees, |tx| {
+            // Pick ids, stepping past any collision with a fn that already has the id - only the low half
+            // is per fn, so crates with 100k or so items (e.g. `windows`) can expect one. Steps wrap
+            // within the low half, keeping the crate's high half (see `stable_fn_id`)
+            let mut chunk_fn_ids: Vec<u64> = vec![];
+            let mut taken_fn_ids = FnIdSet::new();
+            for fndetail in chunk.iter() {
+                let mut fn_id = stable_fn_id(fndetail);
+                while taken_fn_ids.contains(fn_id) || tx.get(FN_TREE, &bincode::serialize(&fn_id).unwrap())?.is_some() {
+                    fn_id = (fn_id & 0xffff_ffff_0000_0000) | ((fn_id as u32).wrapping_add(1) as u64)
+                }
+                taken_fn_ids.insert(fn_id);
+                chunk_fn_ids.push(fn_id);
+            }
+
+            // Calculate everything to update
+            let mut sets: Vec<HashMap<String, FnIdSet>> = vec![HashMap::new(); FN_SET_TREES.len()];
+            for (fndetail, &fn_id) in chunk.iter().zip(chunk_fn_ids.iter()) {
+                for ((tree_name, keys_fn), tree_sets) in FN_SET_TREES.iter().zip(sets.iter_mut()) {
+                    for key in fn_set_keys(&passes, tree_name, *keys_fn, fndetail) {
+                        // May not be new if (e.g.) multiple params of the same type
+                        let _isnew = tree_sets.entry(key).or_insert_with(FnIdSet::new).insert(fn_id);
+                    }
+                }
+            }
+
+            let mut chunk_added_keys: HashMap<&str, Vec<String>> = HashMap::new();
+            for ((tree_name, _), tree_sets) in FN_SET_TREES.iter().zip(sets.iter()) {
+                debug!("inserting {} {} keys for crate {}", tree_sets.len(), tree_name, name);
+                for (key, fn_ids) in tree_sets.iter() {
+                    let mut set: FnIdSet = match tx.get(tree_name, key.as_bytes())? {
+                        Some(d) => decode_fn_ids(&d),
+                        None => {
+                            chunk_added_keys.entry(*tree_name).or_insert_with(Vec::new).push(key.clone());
+                            FnIdSet::new()
+                        },
+                    };
+                    set |= fn_ids;
+                    tx.insert(tree_name, key.as_bytes(), &encode_fn_ids(&set))?;
+                }
+            }
+
+            for (fndetail, fn_id) in chunk.iter().zip(chunk_fn_ids.iter()) {
+                tx.insert(FN_TREE, &bincode::serialize(fn_id).unwrap(), &fnrecord::encode_fn(fndetail))?;
+                debug!("inserted fndetail: [{}] {}", fndetail.krate, fndetail.s);
+                let (impls_of_keys, traits_of_keys) = impl_keys(fndetail);
+                for key in impls_of_keys {
+                    tx.insert(IMPLS_OF_TREE, &key, &[])?;
+                }
+                for key in traits_of_keys {
+                    tx.insert(TRAITS_OF_TREE, &key, &[])?;
+                }
+                let (conversions_from_keys, conversions_to_keys) = conversion_keys(fndetail);
+                for key in conversions_from_keys {
+                    tx.insert(CONVERSIONS_FROM_TREE, &key, &[])?;
+                }
+                for key in conversions_to_keys {
+                    tx.insert(CONVERSIONS_TO_TREE, &key, &[])?;
+                }
+            }
+            let mut pending_fn_ids: Vec<u64> = match tx.get(PENDING_CRATE_TREE, &key)? {
+                Some(bs) => bincode::deserialize(&bs).unwrap(),
+                None => vec![],
+            };
+            pending_fn_ids.extend(&chunk_fn_ids);
+            tx.insert(PENDING_CRATE_TREE, &key, &bincode::serialize(&pending_fn_ids).unwrap())?;
+            Ok((chunk_fn_ids, chunk_added_keys))
+        });
+        fn_ids.extend(chunk_fn_ids);
+        for (tree_name, keys) in chunk_added_keys {
+            added_keys.entry(tree_name).or_insert_with(Vec::new).extend(keys)
+        }
+    }
+
+    db.transaction(&[PENDING_CRATE_TREE, CRATE_TREE, CRATE_META_TREE, CRATE_FACET_TREE, CRATE_GENERATION_TREE, JOURNAL_TREE], |tx| {
+        tx.remove(PENDING_CRATE_TREE, &key)?;
+        tx.insert(CRATE_TREE, &key, &bincode::serialize(&fn_ids).unwrap())?;
+        tx.insert(CRATE_META_TREE, &key, &bincode::serialize(meta).unwrap())?;
+        tx.insert(CRATE_FACET_TREE, &key, &bincode::serialize(&meta.facets()).unwrap())?;
+        snapshot::commit_generation(tx, &key)?;
+        journal_change(tx, journal_id, JournalOp::Add, name, version, fn_ids.len())?;
+        Ok(())
+    });
+    sync_text_search(db, &added_keys, &HashMap::new());
+    metrics::INDEXED_CRATES.inc();
+    metrics::INDEXED_FNS.add(fndetails.len() as i64);
+
+    debug!("completed inserting crate {}", name);
+}
+
+// The keys of the fn in a tree of `FN_SET_TREES`, normalized as the index says for the trees it applies to
+fn fn_set_keys(passes: &[NormalizationPass], tree_name: &str, keys_fn: fn(&FnDetail) -> Vec<String>, fndetail: &FnDetail) -> Vec<String> {
+    let keys = keys_fn(fndetail);
+    if normalization::is_normalized_tree(tree_name) { normalize_keys(passes, keys) } else { keys }
+}
+
+// Splits fns into chunks estimated to fit in the memory budget (each with at least one fn)
+fn add_crate_chunks(fndetails: &[FnDetail], budget: usize) -> Vec<&[FnDetail]> {
+    let mut chunks = vec![];
+    let mut chunk_start = 0;
+    let mut chunk_size = 0;
+    for (i, fndetail) in fndetails.iter().enumerate() {
+        let size = bincode::serialized_size(fndetail).unwrap() as usize * ADD_CRATE_MEMORY_PER_BYTE;
+        if i > chunk_start && chunk_size + size > budget {
+            chunks.push(&fndetails[chunk_start..i]);
+            chunk_start = i;
+            chunk_size = 0;
+        }
+        chunk_size += size;
+    }
+    if chunk_start < fndetails.len() {
+        chunks.push(&fndetails[chunk_start..])
+    }
+    chunks
+}
+
+fn add_crate_error(db: &Db, name: &str, version: &str, err: &str) {
+    let error_tree = db.open_tree(ERROR_TREE);
+    error_tree.insert(crate_key(name, version), bincode::serialize(err).unwrap());
+}
+
+// Like adding, fns are removed in chunks that fit in the DB write budget, each atomically. The crate
+// is uncommitted first, all at once, with its fn ids moved to pending - so it disappears from searches
+// atomically, and a purge that's interrupted is finished by the next one (as is an interrupted add)
+fn purge_crate(db: &Db, name: &str, version: &str) {
+    let error_tree = db.open_tree(ERROR_TREE);
+    let key = crate_key(name, version);
+    error_tree.remove(&key);
+
+    let journal_id = db.generate_id();
+    let num_fns = db.transaction(&[CRATE_TREE, CRATE_META_TREE, CRATE_FACET_TREE, CRATE_GENERATION_TREE, PENDING_CRATE_TREE, JOURNAL_TREE], |tx| {
+        tx.remove(CRATE_META_TREE, &key)?;
+        tx.remove(CRATE_FACET_TREE, &key)?;
+        tx.remove(CRATE_GENERATION_TREE, &key)?;
+        let mut pending_fn_ids: Vec<u64> = match tx.get(PENDING_CRATE_TREE, &key)? {
+            Some(bs) => {
+                info!("cleaning up interrupted add or purge of crate {} {}", name, version);
+                bincode::deserialize(&bs).unwrap()
+            },
+            None => vec![],
+        };
+        // Only committed crates were journaled as added, and are counted in the metrics
+        let num_fns = match tx.remove(CRATE_TREE, &key)? {
+            Some(bs) => {
+                let fn_ids: Vec<u64> = bincode::deserialize(&bs).unwrap();
+                journal_change(tx, journal_id, JournalOp::Purge, name, version, fn_ids.len())?;
+                let num_fns = fn_ids.len();
+                pending_fn_ids.extend(fn_ids);
+                Some(num_fns)
+            },
+            None => None,
+        };
+        if !pending_fn_ids.is_empty() {
+            tx.insert(PENDING_CRATE_TREE, &key, &bincode::serialize(&pending_fn_ids).unwrap())?;
+        }
+        Ok(num_fns)
+    });
+    if let Some(num_fns) = num_fns {
+        metrics::INDEXED_CRATES.dec();
+        metrics::INDEXED_FNS.sub(num_fns as i64);
+    }
+
+    let mut trees: Vec<&str> = FN_SET_TREES.iter().map(|(tree_name, _)| *tree_name).collect();
+    trees.push(FN_TREE);
+    trees.push(PENDING_CRATE_TREE);
+    trees.push(IMPLS_OF_TREE);
+    trees.push(TRAITS_OF_TREE);
+    trees.push(CONVERSIONS_FROM_TREE);
+    trees.push(CONVERSIONS_TO_TREE);
+    let passes = normalization(db);
+    let fn_tree = db.open_tree(FN_TREE);
+    let pending_tree = db.open_tree(PENDING_CRATE_TREE);
+    let mut removed_keys: HashMap<&str, Vec<String>> = HashMap::new();
+    while let Some(bs) = pending_tree.get(&key) {
+        let pending_fn_ids: Vec<u64> = bincode::deserialize(&bs).unwrap();
+        let chunk_len = purge_crate_chunk_len(&fn_tree, &pending_fn_ids, db.write_budget());
+        debug!("removing {} of {} fns left for crate {}", chunk_len, pending_fn_ids.len(), name);
+        let chunk_removed_keys = db.transaction(&trees, |tx| {
+            let mut removed_keys: HashMap<&str, Vec<String>> = HashMap::new();
+            let mut fn_ids: Vec<u64> = match tx.get(PENDING_CRATE_TREE, &key)? {
+                Some(bs) => bincode::deserialize(&bs).unwrap(),
+                None => return Ok(removed_keys),
+            };
+            let rest = fn_ids.split_off(cmp::min(chunk_len, fn_ids.len()));
+            let mut fndetails: Vec<(u64, FnDetail)> = vec![];
+            for fn_id in fn_ids {
+                if let Some(bytes) = tx.remove(FN_TREE, &bincode::serialize(&fn_id).unwrap())? {
+                    fndetails.push((fn_id, FnRecord::new(bytes).to_fndetail()))
+                }
+            }
+            for (fn_id, fndetail) in fndetails {
+                // Fns may share impls, so the keys may already be gone
+                let (impls_of_keys, traits_of_keys) = impl_keys(&fndetail);
+                for key in impls_of_keys {
+                    tx.remove(IMPLS_OF_TREE, &key)?;
+                }
+                for key in traits_of_keys {
+                    tx.remove(TRAITS_OF_TREE, &key)?;
+                }
+                let (conversions_from_keys, conversions_to_keys) = conversion_keys(&fndetail);
+                for key in conversions_from_keys {
+                    tx.remove(CONVERSIONS_FROM_TREE, &key)?;
+                }
+                for key in conversions_to_keys {
+                    tx.remove(CONVERSIONS_TO_TREE, &key)?;
+                }
+                for (tree_name, keys_fn) in FN_SET_TREES.iter() {
+                    for key in fn_set_keys(&passes, tree_name, *keys_fn, &fndetail) {
+                        let mut set: FnIdSet = match tx.get(tree_name, key.as_bytes())? {
+                            Some(d) => decode_fn_ids(&d),
+                            // Already removed along with its last fn, e.g. multiple params of the same type
+                            None => continue,
+                        };
+                        // May not be deleted if (e.g.) multiple params of the same type
+                        let _didremove = set.remove(fn_id);
+                        if set.is_empty() {
+                            tx.remove(tree_name, key.as_bytes())?;
+                            removed_keys.entry(*tree_name).or_insert_with(Vec::new).push(key);
+                        } else {
+                            tx.insert(tree_name, key.as_bytes(), &encode_fn_ids(&set))?;
+                        }
+                    }
+                }
+            }
+            if rest.is_empty() {
+                tx.remove(PENDING_CRATE_TREE, &key)?;
+            } else {
+                tx.insert(PENDING_CRATE_TREE, &key, &bincode::serialize(&rest).unwrap())?;
+            }
+            Ok(removed_keys)
+        });
+        for (tree_name, keys) in chunk_removed_keys {
+            removed_keys.entry(tree_name).or_insert_with(Vec::new).extend(keys)
+        }
+    }
+    sync_text_search(db, &HashMap::new(), &removed_keys);
+}
+
+// How many of the fns to remove fit in the memory budget, estimated as for adding (at least one)
+fn purge_crate_chunk_len(fn_tree: &Tree, fn_ids: &[u64], budget: usize) -> usize {
+    let mut chunk_size = 0;
+    for (i, fn_id) in fn_ids.iter().enumerate() {
+        let size = fn_tree.get(bincode::serialize(fn_id).unwrap()).map_or(0, |bytes| bytes.len()) * ADD_CRATE_MEMORY_PER_BYTE;
+        if i > 0 && chunk_size + size > budget {
+            return i
+        }
+        chunk_size += size;
+    }
+    fn_ids.len()
+}
+
+// The keys a type gets indexed under in the param and ret trees - the type itself, the type with any
+// lengths wildcarded (so `[u8; _]` finds fns taking any byte array) and, if it's a bare generic param
+// or a trait object, the traits it's bounded by (so `fn parse<T: FromStr>() -> T` can be found by
+// searching for `impl FromStr`, and `impl Error` finds `Box<dyn Error + Send>` and `&dyn Error` too).
+// Callables are also indexed by their signature as a fn pointer (see `TypeRepr::callable_sig`), so
+// `fn(&str) -> bool` finds fns taking any kind of predicate over `&str`
+fn type_keys(ty: &str, generics: &[GenericParamDetail]) -> Vec<String> {
+    let mut keys = vec![ty.to_owned()];
+    let repr = TypeRepr::parse(ty);
+    let without_lengths = repr.without_lengths();
+    if without_lengths != repr {
+        keys.push(without_lengths.to_string())
+    }
+    let bounds: Vec<String> = match generics.iter().find(|g| g.name == ty) {
+        Some(generic) => generic.bounds.clone(),
+        None => repr.trait_bounds().unwrap_or_default().into_iter().map(TypeRepr::to_string).collect(),
+    };
+    if bounds.len() > 1 {
+        keys.push(format!("impl {}", bounds.join(" + ")))
+    }
+    let mut callable_sig = repr.callable_sig();
+    for bound in bounds.iter() {
+        keys.push(format!("impl {}", bound));
+        let bound = TypeRepr::parse(bound);
+        if let Some(sig) = bound.callable_sig() {
+            // e.g. `impl FnMut` for `impl FnMut(&str) -> bool`
+            keys.push(format!("impl {}", bound.name().unwrap()));
+            callable_sig.get_or_insert(sig);
+        }
+    }
+    keys.extend(callable_sig.map(|sig| sig.to_string()));
+    // e.g. `impl Error` is its own bound
+    let mut seen = HashSet::new();
+    keys.retain(|key| seen.insert(key.clone()));
+    keys
+}
+
+// Includes the types as written, so aliases are searchable
+fn param_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    for i in 0..fndetail.params.len() {
+        for key in param_position_keys(fndetail, i) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+// The keys of just the param at the index, for matching params in order
+fn param_position_keys(fndetail: &FnDetail, i: usize) -> Vec<String> {
+    let written_param = written_param(fndetail, i);
+    let mut keys = vec![];
+    for param in iter::once(&fndetail.params[i]).chain(written_param) {
+        for key in type_keys(param, &fndetail.generics) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+// The param as written, if it's worth indexing too - not if it has `Self` in it (e.g. `other: &Self`),
+// which only means something within the impl, and is indexed under the concrete type already. Trait
+// methods are the exception, as there `Self` is a generic (see `analyze_trait`)
+fn written_param(fndetail: &FnDetail, i: usize) -> Option<&String> {
+    fndetail.written_params.as_ref()
+        .and_then(|written_params| written_params.get(i))
+        .filter(|written_param| !has_unresolved_self(fndetail, written_param))
+}
+
+fn written_ret(fndetail: &FnDetail) -> Option<&String> {
+    fndetail.written_ret.as_ref().filter(|written_ret| !has_unresolved_self(fndetail, written_ret))
+}
+
+fn has_unresolved_self(fndetail: &FnDetail, ty: &str) -> bool {
+    fn has_self(ty: &TypeRepr) -> bool {
+        matches!(ty, TypeRepr::Path { segments, .. } if segments.first().map(String::as_str) == Some("Self"))
+            || ty.children().into_iter().any(has_self)
+    }
+    !fndetail.generics.iter().any(|g| g.name == "Self") && has_self(&TypeRepr::parse(ty))
+}
+
+// Returning nothing is a marker rather than a type (see `marker_keys`)
+fn ret_keys(fndetail: &FnDetail) -> Vec<String> {
+    if fndetail.ret_repr.is_unit() {
+        return vec![]
+    }
+    let mut keys = type_keys(&fndetail.ret, &fndetail.generics);
+    if let Some(written_ret) = written_ret(fndetail) {
+        for key in type_keys(written_ret, &fndetail.generics) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+fn param_no_refs_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    for i in 0..fndetail.params.len() {
+        for key in param_no_refs_position_keys(fndetail, i) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+fn param_no_refs_position_keys(fndetail: &FnDetail, i: usize) -> Vec<String> {
+    let written_param = written_param(fndetail, i);
+    let mut keys = vec![];
+    for param in iter::once(&fndetail.params[i]).chain(written_param) {
+        for key in type_keys(&without_refs(param), &fndetail.generics) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+fn ret_no_refs_keys(fndetail: &FnDetail) -> Vec<String> {
+    if fndetail.ret_repr.is_unit() {
+        return vec![]
+    }
+    let mut keys = vec![];
+    for ret in Some(&fndetail.ret).into_iter().chain(written_ret(fndetail)) {
+        for key in type_keys(&without_refs(ret), &fndetail.generics) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+fn without_refs(ty: &str) -> String {
+    TypeRepr::parse(ty).without_refs().to_string()
+}
+
+// The success types inside the ret type, e.g. both `Option<PathBuf>` and `PathBuf` for
+// `io::Result<Option<PathBuf>>`
+fn unwrapped_ret_keys(fndetail: &FnDetail) -> Vec<String> {
+    unwrapped_keys(&fndetail.ret_repr)
+}
+
+// For searches ignoring references, e.g. `str` for `Option<&str>` and `&Option<&str>`
+fn unwrapped_ret_no_refs_keys(fndetail: &FnDetail) -> Vec<String> {
+    unwrapped_keys(&fndetail.ret_repr.without_refs())
+}
+
+fn unwrapped_keys(mut ty: &TypeRepr) -> Vec<String> {
+    let mut keys = vec![];
+    while let TypeRepr::Path { args, .. } = ty {
+        match (ty.name(), args.first()) {
+            (Some(name), Some(inner)) if SUCCESS_WRAPPERS.contains(&name) => {
+                keys.push(inner.to_string());
+                ty = inner
+            },
+            _ => break,
+        }
+    }
+    keys
+}
+
+// The types compound params are made of, e.g. `usize` for `(usize, usize)` and `&str` for both
+// `impl Fn(&str) -> bool` and `F` where `F: Fn(&str) -> bool`
+fn param_component_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    for i in 0..fndetail.param_reprs.len() {
+        for key in param_component_position_keys(fndetail, i) {
+            if !keys.contains(&key) { keys.push(key) }
+        }
+    }
+    keys
+}
+
+fn param_component_position_keys(fndetail: &FnDetail, i: usize) -> Vec<String> {
+    let param_repr = &fndetail.param_reprs[i];
+    let mut reprs = vec![param_repr.clone()];
+    if let Some(generic) = param_repr.name().and_then(|name| fndetail.generics.iter().find(|g| g.name == name)) {
+        reprs.extend(generic.bounds.iter().map(|bound| TypeRepr::ImplTrait(vec![TypeRepr::parse(bound)])));
+    }
+    let mut keys = vec![];
+    for component in reprs.iter().flat_map(TypeRepr::components) {
+        let key = component.to_string();
+        if !keys.contains(&key) { keys.push(key) }
+    }
+    keys
+}
+
+const UNSAFE_FLAG: &str = "unsafe";
+const RAW_POINTERS_FLAG: &str = "raw-pointers";
+
+fn flag_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    if fndetail.is_unsafe { keys.push(UNSAFE_FLAG.to_owned()) }
+    if fndetail.has_raw_pointers() { keys.push(RAW_POINTERS_FLAG.to_owned()) }
+    keys
+}
+
+fn marker_keys(fndetail: &FnDetail) -> Vec<String> {
+    fndetail.markers().into_iter().map(|marker| marker.key().to_owned()).collect()
+}
+
+fn arity_keys(fndetail: &FnDetail) -> Vec<String> {
+    vec![fndetail.params.len().to_string()]
+}
+
+fn doc_keys(fndetail: &FnDetail) -> Vec<String> {
+    fndetail.doc.iter().cloned().collect()
+}
+
+// The fn path and any paths it's re-exported under
+fn name_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![fndetail.path.clone()];
+    for alias in fndetail.aliases.iter() {
+        if !keys.contains(alias) { keys.push(alias.clone()) }
+    }
+    keys
+}
+
+// Names of the types making up the ret, other than generic params
+fn produces_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    add_type_names(&fndetail.ret_repr, &fndetail.generics, &mut keys);
+    keys
+}
+
+fn consumes_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    for param in fndetail.param_reprs.iter() {
+        add_type_names(param, &fndetail.generics, &mut keys)
+    }
+    keys
+}
+
+fn add_type_names(ty: &TypeRepr, generics: &[GenericParamDetail], names: &mut Vec<String>) {
+    if let Some(name) = ty.name() {
+        if !generics.iter().any(|g| g.name == name) && !names.iter().any(|n| n == name) {
+            names.push(name.to_owned())
+        }
+    }
+    for child in ty.children() {
+        add_type_names(child, generics, names)
+    }
+}
+
+// Both the full trait (e.g. `Iterator<Item = u8>`) and the bare trait name
+fn ret_impl_keys(fndetail: &FnDetail) -> Vec<String> {
+    let mut keys = vec![];
+    for ret_impl in fndetail.ret_impls.iter() {
+        let bare = ret_impl.split(|c| c == '<' || c == '(').next().unwrap().trim();
+        for key in [ret_impl.as_str(), bare].iter() {
+            if !keys.iter().any(|k| k == key) { keys.push(key.to_string()) }
+        }
+    }
+    keys
+}
+
+// The traits a return type implements, as far as we can tell from this crate
+fn analyze_ret_impls(hirdb: &dyn HirDatabase, ret_ty: &ra_hir::Type, ret_pretty: &str, generics: &[GenericParamDetail]) -> Vec<String> {
+    let mut impls = vec![];
+    // Opaque types and bounded generics tell us directly
+    if let Some(bounds) = ret_pretty.strip_prefix("impl ") {
+        impls.extend(split_top_level(bounds, '+').into_iter().map(|b| b.trim().to_owned()))
+    }
+    if let Some(generic) = generics.iter().find(|g| g.name == ret_pretty) {
+        impls.extend(generic.bounds.iter().cloned())
+    }
+    for imp in ra_hir::Impl::all_for_type(hirdb, ret_ty.clone()) {
+        let tr = match imp.trait_(hirdb) {
+            Some(tr) => tr,
+            None => continue, // inherent impl
+        };
+        let assoc_types: Vec<String> = tr.items(hirdb).into_iter()
+            .filter_map(|item| match item {
+                ra_hir::AssocItem::TypeAlias(alias) => {
+                    let normalized = ret_ty.normalize_trait_assoc_type(hirdb, &[], alias)?;
+                    Some(format!("{} = {}", alias.name(hirdb), normalized.display(hirdb)))
+                },
+                ra_hir::AssocItem::Function(_) |
+                ra_hir::AssocItem::Const(_) => None,
+            })
+            .collect();
+        let name = tr.name(hirdb).to_string();
+        let conversion_arg = match CONVERSION_TRAITS.contains(&name.as_str()) {
+            true => analyze_conversion_arg(hirdb, imp),
+            false => None,
+        };
+        impls.push(impl_trait_str(name, conversion_arg, assoc_types))
+    }
+    impls.sort();
+    impls.dedup();
+    impls
+}
+
+// HIR only tells us the trait of an impl, so its generic arg is read from the source, e.g. `From<&str>`
+fn analyze_conversion_arg(hirdb: &dyn HirDatabase, imp: ra_hir::Impl) -> Option<String> {
+    let tr = imp.source(hirdb)?.value.trait_()?;
+    conversion_arg(&TypeRepr::parse(&tr.syntax().text().to_string()), &analyze_generics(hirdb, imp.into()))
+}
+
+// Keep the shortest path as the canonical one, remembering the others
+fn merge_reexport(existing: &mut FnDetail, mut other: FnDetail) {
+    fn path_rank(path: &str) -> (usize, usize) {
+        (path.matches("::").count(), path.len())
+    }
+    if other.path == existing.path || existing.aliases.contains(&other.path) {
+        return
+    }
+    if path_rank(&other.path) < path_rank(&existing.path) {
+        other.aliases = mem::take(&mut existing.aliases);
+        mem::swap(existing, &mut other);
+    }
+    existing.aliases.push(other.path);
+}
+
+fn analyze_generics(hirdb: &dyn HirDatabase, def: ra_hir::GenericDef) -> Vec<GenericParamDetail> {
+    def.params(hirdb).into_iter()
+        .filter_map(|param| match param {
+            ra_hir::GenericParam::TypeParam(tp) => Some(tp),
+            ra_hir::GenericParam::LifetimeParam(_) |
+            ra_hir::GenericParam::ConstParam(_) => None,
+        })
+        .map(|tp| GenericParamDetail {
+            name: tp.name(hirdb).to_string(),
+            bounds: tp.trait_bounds(hirdb).into_iter().map(|tr| tr.name(hirdb).to_string()).collect(),
+        })
+        .collect()
+}
+
+// HIR only tells us the traits generic params are bounded by, so the signatures of `Fn` trait bounds
+// (e.g. `F: FnMut(&str) -> bool`, in the param list or a where clause) are read from the source
+fn analyze_fn_bounds(hirdb: &dyn HirDatabase, function: ra_hir::Function, generics: &mut [GenericParamDetail]) {
+    let src = match function.source(hirdb) {
+        Some(src) => src.value,
+        None => return,
+    };
+    let mut bounded: Vec<(String, ast::TypeBoundList)> = vec![];
+    if let Some(param_list) = src.generic_param_list() {
+        for tp in param_list.type_params() {
+            if let (Some(name), Some(bounds)) = (tp.name(), tp.type_bound_list()) {
+                bounded.push((name.text().to_string(), bounds))
+            }
+        }
+    }
+    if let Some(where_clause) = src.where_clause() {
+        for pred in where_clause.predicates() {
+            if let (Some(ty), Some(bounds)) = (pred.ty(), pred.type_bound_list()) {
+                bounded.push((ty.syntax().text().to_string(), bounds))
+            }
+        }
+    }
+    for (name, bounds) in bounded {
+        let generic = match generics.iter_mut().find(|g| g.name == name) {
+            Some(generic) => generic,
+            None => continue,
+        };
+        for bound in bounds.bounds() {
+            let bound = TypeRepr::parse(&bound.syntax().text().to_string());
+            if bound.callable_sig().is_none() {
+                continue
+            }
+            let (name, sig) = (bound.name().unwrap().to_owned(), bound.to_string());
+            match generic.bounds.iter().position(|b| *b == name) {
+                Some(i) => generic.bounds[i] = sig,
+                None => if !generic.bounds.contains(&sig) { generic.bounds.push(sig) },
+            }
+        }
+    }
+}
+
+// What we're currently analyzing
+struct CrateCtx<'a> {
+    hirdb: &'a dyn HirDatabase,
+    name: &'a str,
+    version: &'a str,
+    include_private: bool,
+    deref_methods: bool,
+    impl_methods: HashMap<ra_hir::Adt, Vec<ra_hir::Function>>, // see `crate_impl_methods`
+    blanket_impl_bounds: HashMap<ra_hir::Trait, Vec<String>>, // see `crate_blanket_impl_bounds`
+}
+
+impl<'a> CrateCtx<'a> {
+    fn includes(&self, vis: Visibility) -> bool {
+        self.include_private || vis == Visibility::Public
+    }
+}
+
+fn item_visibility(hirdb: &dyn HirDatabase, def: ModuleDef) -> ItemVisibility {
+    match (def.visibility(hirdb), def.module(hirdb)) {
+        (Visibility::Public, _) => ItemVisibility::Public,
+        (Visibility::Module(id), Some(module)) if ra_hir::Module::from(id) == module => ItemVisibility::Private,
+        // e.g. `pub(crate)`, `pub(super)`
+        (Visibility::Module(_), _) => ItemVisibility::Crate,
+    }
+}
+
+fn item_attrs(hirdb: &dyn HirDatabase, def: ModuleDef) -> Option<ra_hir::Attrs> {
+    Some(match def {
+        ModuleDef::Function(f) => f.attrs(hirdb),
+        ModuleDef::Adt(adt) => adt.attrs(hirdb),
+        ModuleDef::Variant(v) => v.attrs(hirdb),
+        ModuleDef::Const(c) => c.attrs(hirdb),
+        ModuleDef::Static(st) => st.attrs(hirdb),
+        ModuleDef::Trait(t) => t.attrs(hirdb),
+        ModuleDef::Module(m) => m.attrs(hirdb),
+        _ => return None,
+    })
+}
+
+// `#[doc(hidden)]`, or in a hidden module - public only so the crate's macros can reach it (e.g.
+// `__private` helpers), so not part of the API, and left out unless indexing private items too
+fn is_doc_hidden(hirdb: &dyn HirDatabase, def: ModuleDef) -> bool {
+    let hidden = |attrs: ra_hir::Attrs| attrs.by_key("doc").tt_values().any(|args| {
+        args.to_string().trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+            .split(',').any(|arg| arg.trim() == "hidden")
+    });
+    if item_attrs(hirdb, def).map_or(false, hidden) {
+        return true
+    }
+    let mut module = def.module(hirdb);
+    while let Some(m) = module {
+        if hidden(m.attrs(hirdb)) {
+            return true
+        }
+        module = m.parent(hirdb);
+    }
+    false
+}
+
+// The first line of the item's docs, e.g. `Creates a new GNU header`
+fn item_doc(hirdb: &dyn HirDatabase, def: ModuleDef) -> Option<String> {
+    let docs = item_attrs(hirdb, def)?.docs()?;
+    docs.as_str().lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_owned)
+}
+
+// (deprecation note, unstable feature) - std uses `#[rustc_deprecated(reason = "...")]` rather than
+// `#[deprecated(note = "...")]`. Either may just be `#[deprecated]`, or `#[deprecated = "note"]`
+fn item_stability(hirdb: &dyn HirDatabase, def: ModuleDef) -> (Option<String>, Option<String>) {
+    let attrs = match item_attrs(hirdb, def) {
+        Some(attrs) => attrs,
+        None => return (None, None),
+    };
+    let arg = |key: &'static str, arg_key: &str| -> Option<String> {
+        attrs.by_key(key).tt_values().find_map(|args| attr_arg(&args.to_string(), arg_key))
+    };
+    let deprecated = if attrs.by_key("deprecated").exists() {
+        let note = attrs.by_key("deprecated").string_value().map(|note| note.to_string());
+        Some(note.or_else(|| arg("deprecated", "note")).unwrap_or_default())
+    } else if attrs.by_key("rustc_deprecated").exists() {
+        Some(arg("rustc_deprecated", "reason").unwrap_or_default())
+    } else {
+        None
+    };
+    let unstable = if attrs.by_key("unstable").exists() {
+        Some(arg("unstable", "feature").unwrap_or_default())
+    } else {
+        None
+    };
+    (deprecated, unstable)
+}
+
+// The string value of `key = "value"` in the args of an attribute, e.g. `(since = "1.0", note = "...")`
+fn attr_arg(args: &str, key: &str) -> Option<String> {
+    let mut rest = args;
+    while let Some(i) = rest.find(key) {
+        let is_word = rest[..i].chars().last().map_or(true, |c| !c.is_alphanumeric() && c != '_');
+        let after = rest[i + key.len()..].trim_start();
+        if let (true, Some(value)) = (is_word, after.strip_prefix('=')) {
+            let value = value.trim_start().strip_prefix('"')?;
+            return value.find('"').map(|end| value[..end].to_owned())
+        }
+        rest = &rest[i + key.len()..];
+    }
+    None
+}
+
+fn analyze_function(ctx: &CrateCtx, function: ra_hir::Function, path: &str) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
+    // e.g. a hidden method of a public type
+    if !ctx.include_private && is_doc_hidden(hirdb, function.into()) {
+        return vec![]
+    }
+    let assoc_params_pretty = function.assoc_fn_params(hirdb)
+        .into_iter().map(|param| param.ty().display(hirdb).to_string())
+        .collect::<Vec<_>>();
+    let ret_ty = function.ret_type(hirdb);
+    let ret_pretty = ret_ty.display(hirdb).to_string();
+    // Only displayed if tracing is enabled
+    trace!("fn {} ({:?} | {:?} | {:?} | {})", path,
+        function.self_param(hirdb).map(|param| param.display(hirdb).to_string()),
+        assoc_params_pretty,
+        function.method_params(hirdb).map(|params| {
+            params.into_iter().map(|param| param.ty().display(hirdb).to_string())
+                .collect::<Vec<_>>()
+        }),
+        ret_pretty);
+    let mut generics = analyze_generics(hirdb, function.into());
+    analyze_fn_bounds(hirdb, function, &mut generics);
+    let ret_impls = analyze_ret_impls(hirdb, &ret_ty, &ret_pretty, &generics);
+    let (written_params, written_ret) = analyze_written_types(hirdb, function, &assoc_params_pretty, &ret_pretty);
+    let (deprecated, unstable) = item_stability(hirdb, function.into());
+    let mut fndetail = FnDetail {
+        krate: ctx.name.to_owned(),
+        version: ctx.version.to_owned(),
+        kind: ItemKind::Fn,
+        visibility: item_visibility(hirdb, function.into()),
+        path: path.to_owned(),
+        aliases: vec![],
+        import_path: path.to_owned(),
+        param_reprs: assoc_params_pretty.iter().map(|p| TypeRepr::parse(p)).collect(),
+        ret_repr: TypeRepr::parse(&ret_pretty),
+        params: assoc_params_pretty,
+        ret: ret_pretty,
+        written_params,
+        written_ret,
+        ret_impls,
+        generics,
+        is_const: function.is_const(hirdb),
+        is_async: function.is_async(hirdb),
+        is_unsafe: function.is_unsafe(hirdb),
+        features: vec![],
+        profile: AnalysisProfile::default(),
+        location: None,
+        deref_from: None,
+        deprecated,
+        unstable,
+        doc: item_doc(hirdb, function.into()),
+        s: String::new(),
+    };
+    fndetail.s = fn_signature(&fndetail);
+    vec![(function.into(), fndetail)]
+}
+
+fn fn_signature(fndetail: &FnDetail) -> String {
+    let mut qualifiers = String::new();
+    if fndetail.is_const { qualifiers.push_str("const ") }
+    if fndetail.is_async { qualifiers.push_str("async ") }
+    if fndetail.is_unsafe { qualifiers.push_str("unsafe ") }
+    format!("{}fn {}{}({}) -> {}", qualifiers, fndetail.path, generics_str(&fndetail.generics), fndetail.params.join(", "), fndetail.ret)
+}
+
+// Analysis expands type aliases (e.g. `io::Result<()>` becomes `Result<(), Error>`), but people
+// search for what they see in docs, so also get the types as written in the source
+fn analyze_written_types(hirdb: &dyn HirDatabase, function: ra_hir::Function, params: &[String], ret: &str) -> (Option<Vec<String>>, Option<String>) {
+    fn text(node: &impl AstNode) -> String {
+        node.syntax().text().to_string().split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+    let src = match function.source(hirdb) {
+        Some(src) => src.value,
+        None => return (None, None),
+    };
+    let written_ret = match src.ret_type().and_then(|rt| rt.ty()) {
+        Some(ty) => text(&ty),
+        None => "()".to_owned(),
+    };
+    let written_params = src.param_list().and_then(|param_list| {
+        let mut written = vec![];
+        if param_list.self_param().is_some() {
+            // No alias to expand in `self`
+            written.push(params.first()?.clone())
+        }
+        for param in param_list.params() {
+            written.push(text(&param.ty()?))
+        }
+        if written.len() == params.len() { Some(written) } else { None }
+    });
+    (written_params.filter(|w| w != params), Some(written_ret).filter(|w| w != ret))
+}
+
+fn analyze_adt(ctx: &CrateCtx, adt: ra_hir::Adt, path: &str) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
+    let mut methods = vec![];
+    let mut consts = vec![];
+    let ty = adt.ty(hirdb);
+    let krate = adt.module(hirdb).krate();
     let _: Option<()> = ty.clone().iterate_assoc_items(hirdb, krate, |associtem| {
-        if let ra_hir::AssocItem::Function(f) = associtem { methods.push(f) }
+        match associtem {
+            ra_hir::AssocItem::Function(f) => methods.push(f),
+            ra_hir::AssocItem::Const(c) => consts.push(c),
+            ra_hir::AssocItem::TypeAlias(_) => (),
+        }
         None
     });
     let _: Option<()> = ty.iterate_method_candidates(hirdb, krate, &Default::default(), None, |_ty, f| {
         methods.push(f);
         None
     });
-    let methods: Vec<_> = methods.into_iter()
-        .filter(|m| m.visibility(hirdb) == Visibility::Public).collect();
-    trace!("adt {} {:?}", path, methods);
+    // Every method of the adt shadows those of what it derefs to, including trait methods and ones
+    // that aren't visible
+    let mut method_names: HashSet<String> = methods.iter().map(|m| m.name(hirdb).to_string()).collect();
+    let mut methods: Vec<_> = methods.into_iter()
+        .filter(|m| ctx.includes(m.visibility(hirdb))).collect();
+    for &method in ctx.impl_methods.get(&adt).into_iter().flatten() {
+        if !methods.contains(&method) {
+            method_names.insert(method.name(hirdb).to_string());
+            methods.push(method)
+        }
+    }
+    let consts: Vec<_> = consts.into_iter()
+        .filter(|c| ctx.includes(c.visibility(hirdb))).collect();
+    trace!("adt {} {:?} {:?}", path, methods, consts);
     let mut fndetails = vec![];
+    let self_ty = adt_pretty(hirdb, adt, &analyze_generics(hirdb, adt.into()));
     for method in methods {
-        fndetails.extend(analyze_function(hirdb, krate_name, method, &(path.to_owned() + "::" + &method.name(hirdb).to_string())));
+        for (def, mut fndetail) in analyze_function(ctx, method, &(path.to_owned() + "::" + &method.name(hirdb).to_string())) {
+            resolve_self(&mut fndetail, &self_ty);
+            fndetail.import_path = path.to_owned();
+            fndetails.push((def, fndetail))
+        }
+    }
+    for c in consts {
+        let name = match c.name(hirdb) { Some(name) => name, None => continue };
+        for (def, mut fndetail) in analyze_const(ctx, c, &(path.to_owned() + "::" + &name.to_string())) {
+            fndetail.import_path = path.to_owned();
+            fndetails.push((def, fndetail))
+        }
+    }
+    if ctx.deref_methods {
+        fndetails.extend(analyze_deref_methods(ctx, adt, path, method_names));
+    }
+    match adt {
+        ra_hir::Adt::Struct(st) => {
+            let fields = st.fields(hirdb);
+            // Can only construct it if all the fields are visible
+            if fields.iter().all(|f| ctx.includes(f.visibility(hirdb))) {
+                fndetails.extend(analyze_constructor(ctx, adt.into(), path, adt, st.kind(hirdb), fields))
+            }
+        },
+        ra_hir::Adt::Enum(e) => {
+            for variant in e.variants(hirdb) {
+                for (def, mut fndetail) in analyze_variant(ctx, variant, &(path.to_owned() + "::" + &variant.name(hirdb).to_string())) {
+                    fndetail.import_path = path.to_owned();
+                    fndetails.push((def, fndetail))
+                }
+            }
+        },
+        ra_hir::Adt::Union(_) => (),
+    }
+    fndetails
+}
+
+// Methods of impls are displayed with the concrete self type, but those found through traits (e.g.
+// a default method of `Iterator`) with `Self`, so they're made the same for indexing. The form with
+// `Self` is kept as the written one, for display. Assoc types like `Self::Item` are left alone
+fn resolve_self(fndetail: &mut FnDetail, self_ty: &str) {
+    let (from, to) = (TypeRepr::parse("Self"), TypeRepr::parse(self_ty));
+    let param_reprs: Vec<TypeRepr> = fndetail.param_reprs.iter().map(|param| param.replace(&from, &to)).collect();
+    let ret_repr = fndetail.ret_repr.replace(&from, &to);
+    if param_reprs != fndetail.param_reprs {
+        if fndetail.written_params.is_none() {
+            fndetail.written_params = Some(fndetail.params.clone())
+        }
+        for (i, param_repr) in param_reprs.into_iter().enumerate() {
+            if param_repr != fndetail.param_reprs[i] {
+                fndetail.params[i] = param_repr.to_string();
+                fndetail.param_reprs[i] = param_repr;
+            }
+        }
+    }
+    if ret_repr != fndetail.ret_repr {
+        if fndetail.written_ret.is_none() {
+            fndetail.written_ret = Some(fndetail.ret.clone())
+        }
+        fndetail.ret = ret_repr.to_string();
+        fndetail.ret_repr = ret_repr;
+    }
+    fndetail.s = fn_signature(fndetail);
+}
+
+// How many derefs to follow from a type, e.g. `String` to `str` is one
+const MAX_DEREF_DEPTH: usize = 3;
+
+// Methods of the types the adt derefs to, indexed as methods of the adt. The receiver becomes a
+// reference to the adt, so searching for methods on `Vec` finds slice methods. Methods taking `self`
+// by value can't be called through a deref, and methods shadowed by those of nearer types are left out
+fn analyze_deref_methods(ctx: &CrateCtx, adt: ra_hir::Adt, path: &str, mut seen_names: HashSet<String>) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
+    let krate = adt.module(hirdb).krate();
+    let adt_ty = adt_pretty(hirdb, adt, &analyze_generics(hirdb, adt.into()));
+    let mut fndetails = vec![];
+    // The first type is the adt itself
+    for target in adt.ty(hirdb).autoderef(hirdb).skip(1).take(MAX_DEREF_DEPTH) {
+        let target_pretty = target.display(hirdb).to_string();
+        let mut methods = vec![];
+        let _: Option<()> = target.iterate_assoc_items(hirdb, krate, |associtem| {
+            if let ra_hir::AssocItem::Function(f) = associtem {
+                methods.push(f)
+            }
+            None
+        });
+        trace!("{} derefs to {} with {} methods", path, target_pretty, methods.len());
+        for method in methods {
+            let name = method.name(hirdb).to_string();
+            if !seen_names.insert(name.clone()) || !ctx.includes(method.visibility(hirdb)) {
+                continue
+            }
+            let receiver = match method.self_param(hirdb).map(|self_param| self_param.access(hirdb)) {
+                Some(ra_hir::Access::Shared) => format!("&{}", adt_ty),
+                Some(ra_hir::Access::Exclusive) => format!("&mut {}", adt_ty),
+                Some(ra_hir::Access::Owned) |
+                None => continue,
+            };
+            for (def, mut fndetail) in analyze_function(ctx, method, &format!("{}::{}", path, name)) {
+                // As for direct methods, before the receiver (which may be `&Self`) is replaced
+                resolve_self(&mut fndetail, &target_pretty);
+                fndetail.params[0] = receiver.clone();
+                fndetail.param_reprs[0] = TypeRepr::parse(&receiver);
+                if let Some(written_params) = fndetail.written_params.as_mut() {
+                    written_params[0] = receiver.clone()
+                }
+                fndetail.deref_from = Some(target_pretty.clone());
+                fndetail.import_path = path.to_owned();
+                fndetail.s = fn_signature(&fndetail);
+                fndetails.push((def, fndetail))
+            }
+        }
     }
     fndetails
 }
 
-fn analyze_trait(hirdb: &dyn HirDatabase, _krate_name: &str, tr: ra_hir::Trait, path: &str) -> Vec<FnDetail> {
+// The type of the adt itself has unknown generics, so name them explicitly
+fn adt_pretty(hirdb: &dyn HirDatabase, adt: ra_hir::Adt, generics: &[GenericParamDetail]) -> String {
+    if generics.is_empty() {
+        adt.name(hirdb).to_string()
+    } else {
+        let generic_names: Vec<_> = generics.iter().map(|g| g.name.as_str()).collect();
+        format!("{}<{}>", adt.name(hirdb), generic_names.join(", "))
+    }
+}
+
+fn analyze_variant(ctx: &CrateCtx, variant: ra_hir::Variant, path: &str) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
+    let adt = ra_hir::Adt::Enum(variant.parent_enum(hirdb));
+    analyze_constructor(ctx, variant.into(), path, adt, variant.kind(hirdb), variant.fields(hirdb))
+}
+
+// Constructing a struct or enum variant produces a value of the type from the fields, so index it
+// like a (const) fn from the field types to the type - e.g. `Some(T) -> Option<T>`
+fn analyze_constructor(ctx: &CrateCtx, def: ModuleDef, path: &str, adt: ra_hir::Adt, kind: ra_hir::StructKind, fields: Vec<ra_hir::Field>) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
+    let generics = analyze_generics(hirdb, adt.into());
+    let params: Vec<String> = fields.iter().map(|f| f.ty(hirdb).display(hirdb).to_string()).collect();
+    let ret_pretty = adt_pretty(hirdb, adt, &generics);
+    let ret_impls = analyze_ret_impls(hirdb, &adt.ty(hirdb), &ret_pretty, &generics);
+    let fields_str = match kind {
+        ra_hir::StructKind::Record => {
+            let fields_strs: Vec<_> = fields.iter().zip(params.iter())
+                .map(|(f, ty)| format!("{}: {}", f.name(hirdb), ty))
+                .collect();
+            format!(" {{ {} }}", fields_strs.join(", "))
+        },
+        ra_hir::StructKind::Tuple => format!("({})", params.join(", ")),
+        ra_hir::StructKind::Unit => String::new(),
+    };
+    let s = format!("{}{} -> {}", path, fields_str, ret_pretty);
+    let (deprecated, unstable) = item_stability(hirdb, def);
+    vec![(def, FnDetail {
+        krate: ctx.name.to_owned(),
+        version: ctx.version.to_owned(),
+        kind: ItemKind::Constructor,
+        visibility: item_visibility(hirdb, def),
+        path: path.to_owned(),
+        aliases: vec![],
+        import_path: path.to_owned(),
+        param_reprs: params.iter().map(|p| TypeRepr::parse(p)).collect(),
+        ret_repr: TypeRepr::parse(&ret_pretty),
+        params,
+        ret: ret_pretty,
+        written_params: None,
+        written_ret: None,
+        ret_impls,
+        generics,
+        is_const: true,
+        is_async: false,
+        is_unsafe: false,
+        features: vec![],
+        profile: AnalysisProfile::default(),
+        location: None,
+        deref_from: None,
+        deprecated,
+        unstable,
+        doc: item_doc(hirdb, def),
+        s,
+    })]
+}
+
+// Consts and statics are values ready to use, like a fn with no params - e.g. `Ipv4Addr::LOCALHOST`
+fn analyze_const(ctx: &CrateCtx, c: ra_hir::Const, path: &str) -> Vec<(ModuleDef, FnDetail)> {
+    analyze_value(ctx, c.into(), path, c.ty(ctx.hirdb), ItemKind::Const, false)
+}
+
+fn analyze_static(ctx: &CrateCtx, st: ra_hir::Static, path: &str) -> Vec<(ModuleDef, FnDetail)> {
+    // Accessing a mutable static is unsafe
+    let is_mut = st.is_mut(ctx.hirdb);
+    analyze_value(ctx, st.into(), path, st.ty(ctx.hirdb), ItemKind::Static, is_mut)
+}
+
+fn analyze_value(ctx: &CrateCtx, def: ModuleDef, path: &str, ty: ra_hir::Type, kind: ItemKind, is_unsafe: bool) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
+    let ret_pretty = ty.display(hirdb).to_string();
+    let ret_impls = analyze_ret_impls(hirdb, &ty, &ret_pretty, &[]);
+    let item_str = match kind {
+        ItemKind::Static if is_unsafe => "static mut",
+        ItemKind::Static => "static",
+        ItemKind::Const => "const",
+        ItemKind::Fn |
+        ItemKind::Constructor => unreachable!("not a value"),
+    };
+    let s = format!("{} {}: {}", item_str, path, ret_pretty);
+    let (deprecated, unstable) = item_stability(hirdb, def);
+    vec![(def, FnDetail {
+        krate: ctx.name.to_owned(),
+        version: ctx.version.to_owned(),
+        kind,
+        visibility: item_visibility(hirdb, def),
+        path: path.to_owned(),
+        aliases: vec![],
+        import_path: path.to_owned(),
+        params: vec![],
+        param_reprs: vec![],
+        ret_repr: TypeRepr::parse(&ret_pretty),
+        ret: ret_pretty,
+        written_params: None,
+        written_ret: None,
+        ret_impls,
+        generics: vec![],
+        is_const: kind == ItemKind::Const,
+        is_async: false,
+        is_unsafe,
+        features: vec![],
+        profile: AnalysisProfile::default(),
+        location: None,
+        deref_from: None,
+        deprecated,
+        unstable,
+        doc: item_doc(hirdb, def),
+        s,
+    })]
+}
+
+// Methods of extension traits, which add methods to types with some other trait (e.g. `Itertools` to
+// `Iterator`s) - with it as a supertrait, or with a blanket impl for types bounded by it. `Self` is
+// given those bounds as a generic, so the methods are keyed like fns taking an `impl Iterator`
+fn analyze_trait(ctx: &CrateCtx, tr: ra_hir::Trait, path: &str) -> Vec<(ModuleDef, FnDetail)> {
+    let hirdb = ctx.hirdb;
     trace!("trait {} {:?}", path, tr.items(hirdb));
-    vec![]
+    let mut self_bounds: Vec<String> = analyze_generics(hirdb, tr.into()).into_iter()
+        .filter(|g| g.name == "Self")
+        .flat_map(|g| g.bounds)
+        .collect();
+    for bound in ctx.blanket_impl_bounds.get(&tr).into_iter().flatten() {
+        if !self_bounds.contains(bound) { self_bounds.push(bound.clone()) }
+    }
+    if self_bounds.is_empty() {
+        return vec![]
+    }
+    let mut fndetails = vec![];
+    for item in tr.items(hirdb) {
+        let method = match item {
+            ra_hir::AssocItem::Function(f) if f.self_param(hirdb).is_some() => f,
+            _ => continue,
+        };
+        for (def, mut fndetail) in analyze_function(ctx, method, &(path.to_owned() + "::" + &method.name(hirdb).to_string())) {
+            fndetail.generics.insert(0, GenericParamDetail { name: "Self".to_owned(), bounds: self_bounds.clone() });
+            fndetail.import_path = path.to_owned();
+            fndetail.s = fn_signature(&fndetail);
+            fndetails.push((def, fndetail))
+        }
+    }
+    fndetails
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A public fn of version 1.0.0 of the crate, as analysis would find it
+    pub(crate) fn fndetail(krate: &str, path: &str, params: &[&str], ret: &str) -> FnDetail {
+        let mut fndetail = FnDetail {
+            krate: krate.to_owned(),
+            version: "1.0.0".to_owned(),
+            kind: ItemKind::Fn,
+            visibility: ItemVisibility::Public,
+            path: format!("{}::{}", krate, path),
+            aliases: vec![],
+            import_path: format!("{}::{}", krate, path),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            ret: ret.to_owned(),
+            param_reprs: params.iter().map(|param| TypeRepr::parse(param)).collect(),
+            ret_repr: TypeRepr::parse(ret),
+            written_params: None,
+            written_ret: None,
+            ret_impls: vec![],
+            generics: vec![],
+            is_const: false,
+            is_async: false,
+            is_unsafe: false,
+            features: vec![],
+            profile: AnalysisProfile::default(),
+            doc: None,
+            location: None,
+            deref_from: None,
+            deprecated: None,
+            unstable: None,
+            s: String::new(),
+        };
+        fndetail.s = fn_signature(&fndetail);
+        fndetail
+    }
+
+    fn meta(krate: &str) -> CrateMeta {
+        CrateMeta {
+            name: krate.to_owned(),
+            version: "1.0.0".to_owned(),
+            license: None,
+            repository: None,
+            docsrs_url: format!("https://docs.rs/{}/1.0.0", krate),
+            indexed_at: 0,
+            categories: vec![],
+            keywords: vec![],
+        }
+    }
+
+    fn add(db: &Db, krate: &str, fndetails: Vec<FnDetail>) {
+        save_analysis(db, krate, "1.0.0", &meta(krate), fndetails)
+    }
+
+    // Paths of the exact matches, in result order
+    fn exact_matches(db: &Db, query: &str) -> Vec<String> {
+        let opts = SearchOptions { explain: true, ..SearchOptions::default() };
+        let results = search(db, &SearchCache::new(), &Query::parse(query), &opts);
+        let explanations = results.explanations.unwrap();
+        results.fndetails.into_iter().zip(explanations)
+            .filter(|(_, explanation)| explanation.as_ref().map_or(false, |explanation| explanation.relaxed.is_none()))
+            .map(|(fndetail, _)| fndetail.path)
+            .collect()
+    }
+
+    fn fixture() -> Vec<FnDetail> {
+        vec![
+            fndetail("a", "parse", &["&str"], "u32"),
+            fndetail("a", "len", &["&str"], "usize"),
+            fndetail("a", "widen", &["u8"], "u32"),
+            fndetail("a", "reset", &[], "()"),
+        ]
+    }
+
+    #[test]
+    fn search_intersects_params_and_ret() {
+        let db = Db::in_memory();
+        add(&db, "a", fixture());
+        assert_eq!(exact_matches(&db, "&str -> u32"), vec!["a::parse"]);
+        assert_eq!(exact_matches(&db, "u8 -> u32"), vec!["a::widen"]);
+        let mut str_fns = exact_matches(&db, "&str");
+        str_fns.sort();
+        assert_eq!(str_fns, vec!["a::len", "a::parse"]);
+        assert_eq!(exact_matches(&db, "() -> ()"), vec!["a::reset"]);
+        assert!(exact_matches(&db, "u8 -> usize").is_empty());
+    }
+
+    #[test]
+    fn widening_considers_new_candidates_of_shorter_columns() {
+        let db = Db::in_memory();
+        add(&db, "a", vec![
+            fndetail("a", "foo", &["Foo"], "bool"),
+            fndetail("a", "foo1", &["Foo1"], "bool"),
+            fndetail("a", "foo2", &["Foo2"], "bool"),
+            fndetail("a", "target", &["Foo3"], "u32"),
+            fndetail("a", "many", &["u8"], "u32s"),
+        ]);
+        // The param column starts with `Foo` alone (`Foo1` is fetched then excluded), and the ret column
+        // with `u32` and `u32s`. Widening adds `Foo3` to the param column after the ret column's last
+        // candidate was considered
+        let mut query = Query::parse("Foo -> u32");
+        query.filters.candidate_limit = Some(2);
+        query.filters.adaptive_candidates = true;
+        query.filters.exclude_param_types = vec!["Foo1".to_owned(), "Foo2".to_owned()];
+        let results = search(&db, &SearchCache::new(), &query, &SearchOptions::default());
+        // Scanning keys, as in-memory DBs never use the text search
+        assert!(results.warnings.is_empty(), "{:?}", results.warnings);
+        let paths: Vec<String> = results.fndetails.into_iter().map(|fndetail| fndetail.path).collect();
+        assert_eq!(paths, vec!["a::target"]);
+    }
+
+    #[test]
+    fn add_and_purge_round_trip() {
+        // A fn at a time, so adding and purging are split into chunks
+        let db = Db::in_memory().with_write_budget(1);
+        add(&db, "a", fixture());
+        add(&db, "b", vec![fndetail("b", "parse", &["&str"], "u32")]);
+        let fn_ids_before: Vec<Vec<u8>> = db.open_tree(FN_TREE).iter().map(|(key, _)| key).collect();
+
+        // Re-adding replaces the crate, with the same ids
+        add(&db, "a", fixture());
+        let fn_ids: Vec<Vec<u8>> = db.open_tree(FN_TREE).iter().map(|(key, _)| key).collect();
+        assert_eq!(fn_ids, fn_ids_before);
+        assert_eq!(list_crates(&db), vec![
+            ("a".to_owned(), "1.0.0".to_owned(), Ok(4)),
+            ("b".to_owned(), "1.0.0".to_owned(), Ok(1)),
+        ]);
+        let mut parses = exact_matches(&db, "&str -> u32");
+        parses.sort();
+        assert_eq!(parses, vec!["a::parse", "b::parse"]);
+
+        // Nothing of a purged crate is left behind, and other crates are untouched
+        purge_crate(&db, "a", "1.0.0");
+        assert_eq!(list_crates(&db), vec![("b".to_owned(), "1.0.0".to_owned(), Ok(1))]);
+        assert_eq!(db.open_tree(FN_TREE).len(), 1);
+        assert!(db.open_tree(PENDING_CRATE_TREE).iter().next().is_none());
+        for (tree_name, _) in FN_SET_TREES {
+            for (key, val) in db.open_tree(tree_name).iter() {
+                let fn_ids = decode_fn_ids(&val);
+                assert!(fn_ids.iter().all(|fn_id| db.open_tree(FN_TREE).contains_key(bincode::serialize(&fn_id).unwrap())),
+                        "{} {:?} has a purged fn", tree_name, String::from_utf8_lossy(&key));
+            }
+        }
+        assert_eq!(exact_matches(&db, "&str -> u32"), vec!["b::parse"]);
+
+        purge_crate(&db, "b", "1.0.0");
+        assert!(list_crates(&db).is_empty());
+        for (tree_name, _) in FN_SET_TREES {
+            assert_eq!(db.open_tree(tree_name).len(), 0, "{} isn't empty", tree_name);
+        }
+    }
+
+    #[test]
+    fn unwrapped_ret_keys_with_and_without_refs() {
+        let path = fndetail("a", "path", &[], "io::Result<Option<&Path>>");
+        assert_eq!(unwrapped_ret_keys(&path), vec!["Option<&Path>", "&Path"]);
+        assert_eq!(unwrapped_ret_no_refs_keys(&path), vec!["Option<Path>", "Path"]);
+        // A ref to a wrapper is only unwrapped ignoring refs
+        let names = fndetail("a", "names", &[], "&Option<Vec<&str>>");
+        assert!(unwrapped_ret_keys(&names).is_empty());
+        assert_eq!(unwrapped_ret_no_refs_keys(&names), vec!["Vec<str>"]);
+    }
+
+    #[test]
+    fn search_cache_evicts_least_recently_used() {
+        let cache = SearchCache { max_fn_ids: 4, ..SearchCache::new() };
+        let cached = |query_hash: u64| cache.entries.lock().unwrap().contains_key(&query_hash);
+        cache.get_or_search(1, || (vec![1, 2], vec![]));
+        cache.get_or_search(2, || (vec![3], vec![]));
+        // Using the first search makes the second the least recently used
+        let (fn_ids, _) = cache.get_or_search(1, || unreachable!());
+        assert_eq!(*fn_ids, vec![1, 2]);
+        cache.get_or_search(3, || (vec![4, 5], vec![]));
+        assert!(cached(1) && !cached(2) && cached(3));
+        // Searches too large to ever fit, or with warnings, aren't cached at all
+        cache.get_or_search(4, || (vec![1, 2, 3, 4, 5], vec![]));
+        cache.get_or_search(5, || (vec![], vec!["degraded".to_owned()]));
+        assert!(cached(1) && cached(3) && !cached(4) && !cached(5));
+    }
 }
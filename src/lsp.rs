@@ -0,0 +1,151 @@
+use anyhow::{Result, anyhow, bail};
+use lsp_server::{Connection, ErrorCode, Message, Request, Response};
+use lsp_types::{ExecuteCommandOptions, ExecuteCommandParams, InitializeParams, Location, Position, Range, ServerCapabilities, Url};
+use lsp_types::request::{ExecuteCommand, Request as _};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+use reeves_types::*;
+
+use crate::{Db, SearchCache, search};
+
+// Custom request, for clients that can handle the response themselves
+const SEARCH_METHOD: &str = "reeves/search";
+// The same, via `workspace/executeCommand` with the params as the only argument
+const SEARCH_COMMAND: &str = "reeves.search";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchParams {
+    // Same syntax as the CLI, e.g. `&str, usize -> Option<char>`
+    query: String,
+    // Search every indexed crate, not just the workspace and its dependencies
+    #[serde(default)]
+    all_crates: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchMatch {
+    signature: String,
+    krate: String,
+    version: String,
+    path: String,
+    import: String, // the use line for it, e.g. `use regex::Regex;`
+    doc: Option<String>,
+    // Only if the crate is the workspace or one of its dependencies, and the location is known
+    location: Option<Location>,
+}
+
+// Serves type searches to an editor over stdio, for use alongside the editor's usual language server.
+// The index should contain the workspace (see `--local`) and its dependencies - searches are
+// limited to those crates, and results have locations in their sources
+pub fn serve_lsp(db: Db) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: vec![SEARCH_COMMAND.to_owned()],
+            work_done_progress_options: Default::default(),
+        }),
+        ..ServerCapabilities::default()
+    };
+    let init_params: InitializeParams = serde_json::from_value(connection.initialize(serde_json::to_value(capabilities)?)?)?;
+    let root = init_params.root_uri.and_then(|uri| uri.to_file_path().ok());
+    let package_roots = match &root {
+        Some(root) => workspace_package_roots(root).unwrap_or_else(|e| {
+            warn!("failed to find packages of workspace at {}, searching all crates: {:?}", root.display(), e);
+            HashMap::new()
+        }),
+        None => HashMap::new(),
+    };
+    info!("serving type search over LSP for {} packages", package_roots.len());
+
+    let cache = SearchCache::new();
+    for msg in &connection.receiver {
+        let req = match msg {
+            Message::Request(req) => req,
+            Message::Response(_) |
+            Message::Notification(_) => continue,
+        };
+        if connection.handle_shutdown(&req)? {
+            break
+        }
+        let resp = handle_request(&db, &cache, &package_roots, req);
+        connection.sender.send(Message::Response(resp))?;
+    }
+    io_threads.join()?;
+    Ok(())
+}
+
+fn handle_request(db: &Db, cache: &SearchCache, package_roots: &HashMap<(String, String), PathBuf>, req: Request) -> Response {
+    let params: Result<SearchParams> = match req.method.as_str() {
+        SEARCH_METHOD => serde_json::from_value(req.params).map_err(Into::into),
+        ExecuteCommand::METHOD => serde_json::from_value(req.params).map_err(Into::into)
+            .and_then(|params: ExecuteCommandParams| {
+                if params.command != SEARCH_COMMAND {
+                    bail!("unknown command {}", params.command)
+                }
+                let arg = params.arguments.into_iter().next().ok_or_else(|| anyhow!("missing search params"))?;
+                Ok(serde_json::from_value(arg)?)
+            }),
+        method => return Response::new_err(req.id, ErrorCode::MethodNotFound as i32, format!("unknown method {}", method)),
+    };
+    let params = match params {
+        Ok(params) => params,
+        Err(e) => return Response::new_err(req.id, ErrorCode::InvalidParams as i32, format!("{:?}", e)),
+    };
+
+    let mut query = Query::parse(&params.query);
+    // Pinned to the versions the project uses
+    let mut crates: Vec<String> = package_roots.keys().map(|(name, version)| format!("{}@={}", name, version)).collect();
+    crates.sort();
+    crates.dedup();
+    query.filters.crates = if params.all_crates || crates.is_empty() { None } else { Some(crates) };
+    let results = search(db, cache, &query, &SearchOptions::default());
+    let matches: Vec<SearchMatch> = results.fndetails.into_iter()
+        .map(|fndetail| {
+            let location = package_roots.get(&(fndetail.krate.clone(), fndetail.version.clone()))
+                .zip(fndetail.location.as_ref())
+                .and_then(|(root, location)| lsp_location(root, location));
+            SearchMatch { signature: render_signature(&fndetail, &RenderOptions::default()), import: use_line(&fndetail), krate: fndetail.krate, version: fndetail.version, path: fndetail.path, doc: fndetail.doc, location }
+        })
+        .collect();
+    Response::new_ok(req.id, matches)
+}
+
+fn lsp_location(package_root: &Path, location: &SourceLocation) -> Option<Location> {
+    let uri = Url::from_file_path(package_root.join(&location.file)).ok()?;
+    let position = Position { line: location.line, character: location.character };
+    Some(Location { uri, range: Range { start: position, end: position } })
+}
+
+// (name, version) => dir containing the Cargo.toml, for the workspace members and all their dependencies
+fn workspace_package_roots(root: &Path) -> Result<HashMap<(String, String), PathBuf>> {
+    #[derive(Deserialize)]
+    struct Metadata {
+        packages: Vec<Package>,
+    }
+    #[derive(Deserialize)]
+    struct Package {
+        name: String,
+        version: String,
+        manifest_path: PathBuf,
+    }
+    let output = Command::new("cargo")
+        .args(&["metadata", "--format-version", "1"])
+        .current_dir(root)
+        .output()?;
+    if !output.status.success() {
+        bail!("cargo metadata failed: {}", String::from_utf8_lossy(&output.stderr))
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)?;
+    Ok(metadata.packages.into_iter()
+        .filter_map(|package| {
+            let package_root = package.manifest_path.parent()?.to_owned();
+            Some(((package.name, package.version), package_root))
+        })
+        .collect())
+}
@@ -1,4 +1,4 @@
-use reeves;
+use reeves::{self, Db};
 
 use anyhow::{Context, Result, bail};
 use either::Either;
@@ -6,7 +6,6 @@ use futures::executor::ThreadPool;
 use futures::stream::{FuturesUnordered, StreamExt};
 use futures::task::SpawnExt;
 use isahc::prelude::*;
-use log::{debug, info, warn};
 use serde::{Serialize, Deserialize};
 use std::cmp;
 use std::env;
@@ -14,17 +13,51 @@ use std::fs;
 use std::io::{self, Write};
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Command};
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+use tracing::{debug, info, warn};
 
 use reeves_types::*;
 
+#[cfg(feature = "grpc")]
+mod grpc;
+mod repl;
 mod server;
 
 // We re-exec this in a container, so need to know how to invoke it
 const ANALYZE_AND_PRINT_COMMAND: &str = "analyze-and-print";
 
+#[derive(Serialize)]
+struct AnalyzeAndSaveOutput {
+    crate_name: String,
+    crate_version: String,
+    res: Either<usize, String>, // number of fns saved OR err
+}
+
+#[derive(Serialize)]
+struct SearchOutput {
+    fndetails: Vec<FnDetail>,
+    next_cursor: Option<String>,
+    warnings: Vec<String>,
+    crates: Vec<CrateMeta>,
+    explanations: Option<Vec<Option<Explanation>>>,
+    groups: Option<Vec<ResultGroup>>,
+    facets: Option<Vec<FacetCount>>,
+    suggestions: Vec<TypeSuggestion>,
+}
+
+#[derive(Serialize)]
+struct BenchSearchOutput {
+    query: String,
+    num_results: usize,
+    min_ms: f64,
+    mean_ms: f64,
+    max_ms: f64,
+}
+
 #[derive(Serialize, Deserialize)]
 struct AnalyzeAndPrintOutput {
     crate_name: String,
@@ -47,41 +80,606 @@ const CRATE_WORK_DIR: &str = "/tmp/crate";
 #[derive(Debug, StructOpt)]
 #[structopt(name = "reeves", about = "A tool for indexing and searching crates")]
 struct ReevesOpt {
-    #[structopt(long, default_value = "reeves.db")]
+    #[structopt(long, default_value = "reeves.db", help = "Path to the reeves DB, a path ending in .sqlite uses SQLite rather than sled, and one ending in .snapshot is loaded into memory (changes aren't saved)")]
     db: PathBuf,
     #[structopt(long, default_value = "panamax-mirror")]
     panamax_mirror: PathBuf,
     #[structopt(long, default_value = "rust-analyzer/target/release/rust-analyzer")]
     rust_analyzer: PathBuf,
+    #[structopt(long, default_value = "human", possible_values = &["human", "json"])]
+    output: OutputFormat,
+    #[structopt(long, default_value = "64", help = "Split large writes to the reeves DB (e.g. adding a crate) to use roughly this much memory at once")]
+    write_budget_mb: usize,
+    #[structopt(long, help = "Log as JSON, including how long analysis phases, DB transactions and text searches take (filtered by RUST_LOG)")]
+    trace_json: bool,
+    #[structopt(long, help = "Use this named index in the reeves DB (see create-index) rather than the default one")]
+    index: Option<String>,
+    #[structopt(long, help = "Make DB changes (saving analyses, purge, enqueue, loading text search, compaction) via a server started with --admin-socket at this path, as the server has the DB locked")]
+    server: Option<PathBuf>,
     #[structopt(subcommand)]
     cmd: ReevesCmd,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Human,
+    Json,
+}
+
+fn parse_group_by(s: &str) -> Result<GroupBy, String> {
+    match s {
+        "type" => Ok(GroupBy::Type),
+        "module" => Ok(GroupBy::Module),
+        _ => Err(format!("unknown grouping {:?}", s)),
+    }
+}
+
+fn parse_normalization_pass(s: &str) -> Result<NormalizationPass, String> {
+    NormalizationPass::parse(s).ok_or_else(|| format!("unknown normalization pass {:?}", s))
+}
+
+fn parse_signature_style(s: &str) -> Result<SignatureStyle, String> {
+    match s {
+        "impl" => Ok(SignatureStyle::Impl),
+        "ufcs" => Ok(SignatureStyle::Ufcs),
+        "raw" => Ok(SignatureStyle::Raw),
+        _ => Err(format!("unknown signature style {:?}", s)),
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format {:?}", s)),
+        }
+    }
+}
+
+// Where DB changes go - with `--server`, the DB is locked by a running server, so it makes them
+enum DbWriter {
+    Local(Db),
+    Server(reeves::AdminClient),
+}
+
+// Save the results of analyzing a crate (including failure), and tell the user
+fn save_analysis_and_report(writer: &mut DbWriter, output: OutputFormat, meta: CrateMeta, fndetails: Result<Vec<FnDetail>>) -> Result<()> {
+    let crate_name = meta.name.clone();
+    let crate_version = meta.version.clone();
+    let summary = match fndetails {
+        Ok(fndetails) => {
+            info!("finished analysing functions, inserting {} function details into db", fndetails.len());
+            let num_fns = fndetails.len();
+            match writer {
+                DbWriter::Local(db) => reeves::save_analysis(db, &crate_name, &crate_version, &meta, fndetails),
+                DbWriter::Server(client) => client.save_analysis(&meta, fndetails)?,
+            }
+            Either::Left(num_fns)
+        },
+        Err(err) => {
+            let err = format!("{:?}", err);
+            warn!("analysis failed, saving error to db: {}", err);
+            match writer {
+                DbWriter::Local(db) => reeves::save_analysis_error(db, &crate_name, &crate_version, &err),
+                DbWriter::Server(client) => client.save_analysis_error(&crate_name, &crate_version, &err)?,
+            }
+            Either::Right(err)
+        },
+    };
+    info!("finished inserting into db");
+    match output {
+        OutputFormat::Human => match summary {
+            Either::Left(num_fns) => println!("saved {} fns for {} {}", num_fns, crate_name, crate_version),
+            Either::Right(err) => println!("saved analysis error for {} {}: {}", crate_name, crate_version, err),
+        },
+        OutputFormat::Json => print_json(&AnalyzeAndSaveOutput { crate_name, crate_version, res: summary }),
+    }
+    Ok(())
+}
+
+fn print_json(val: &impl Serialize) {
+    let out = serde_json::to_vec(val).unwrap();
+    io::stdout().write_all(&out).unwrap();
+    println!();
+}
+
+#[derive(Debug, StructOpt)]
+struct SearchFilterOpt {
+    #[structopt(long)]
+    is_const: Option<bool>,
+    #[structopt(long)]
+    is_async: Option<bool>,
+    #[structopt(long)]
+    is_unsafe: Option<bool>,
+    #[structopt(long, help = "Only fns with (true) or without (false) raw pointers in their params or ret")]
+    raw_pointers: Option<bool>,
+    #[structopt(long, help = "Exclude unsafe fns and fns with raw pointers, e.g. to avoid FFI bindings")]
+    safe_only: bool,
+    #[structopt(long, help = "Only fns with at least this many params (defaults to exact if query params are in parens)")]
+    min_params: Option<usize>,
+    #[structopt(long, help = "Only fns with at most this many params (defaults to exact if query params are in parens)")]
+    max_params: Option<usize>,
+    #[structopt(long, help = "Only search the newest indexed version of each crate")]
+    latest_only: bool,
+    #[structopt(long, conflicts_with = "latest-only", help = "Only search crate versions matching this semver requirement")]
+    version_req: Option<semver::VersionReq>,
+    #[structopt(long = "crate", help = "Only search within this crate, optionally at versions matching a semver requirement, e.g. 'tokio@^1' (may be repeated)")]
+    krate: Vec<String>,
+    #[structopt(long, help = "Exclude a crate from the results (may be repeated)")]
+    exclude_crate: Vec<String>,
+    #[structopt(long, help = "Exclude fns with a param type matching this glob (may be repeated)")]
+    exclude_param_type: Vec<String>,
+    #[structopt(long, help = "Exclude fns with a ret type matching this glob (may be repeated)")]
+    exclude_ret_type: Vec<String>,
+    #[structopt(long, help = "Match types regardless of references and ownership, e.g. so String finds &str")]
+    ignore_refs: bool,
+    #[structopt(long, help = "Only fuzzy match words in the query with at most this many typos (0 for none)")]
+    max_typos: Option<usize>,
+    #[structopt(long, help = "Consider this many fuzzy candidates for each part of the query (default 100, at most 1000)")]
+    candidate_limit: Option<usize>,
+    #[structopt(long, help = "Consider more fuzzy candidates while the search finds too few results")]
+    adaptive_candidates: bool,
+    #[structopt(long, help = "Find at most this many results in total")]
+    max_results: Option<usize>,
+    #[structopt(long, help = "Include deprecated items")]
+    include_deprecated: bool,
+    #[structopt(long, help = "Don't also match types via configured synonyms")]
+    no_synonyms: bool,
+    #[structopt(long, help = "Don't also match fns taking what a param converts to, e.g. String for &str")]
+    no_conversions: bool,
+    #[structopt(long, help = "Find at most this many results from any one crate")]
+    max_per_crate: Option<usize>,
+    #[structopt(long, help = "Rank results so each crate's first results come before any crate's later ones")]
+    prefer_distinct_crates: bool,
+    #[structopt(long = "category", help = "Only search crates in this crates.io category, e.g. 'parsing' (may be repeated)")]
+    categories: Vec<String>,
+    #[structopt(long = "keyword", help = "Only search crates with this keyword, e.g. 'json' (may be repeated)")]
+    keywords: Vec<String>,
+}
+
+impl SearchFilterOpt {
+    fn to_filters(&self) -> SearchFilters {
+        let versions = match (self.latest_only, &self.version_req) {
+            (true, _) => VersionFilter::Latest,
+            (false, Some(req)) => VersionFilter::Req(req.clone()),
+            (false, None) => VersionFilter::Any,
+        };
+        SearchFilters {
+            is_const: self.is_const,
+            is_async: self.is_async,
+            is_unsafe: if self.safe_only { Some(false) } else { self.is_unsafe },
+            raw_pointers: if self.safe_only { Some(false) } else { self.raw_pointers },
+            min_params: self.min_params,
+            max_params: self.max_params,
+            versions,
+            crates: if self.krate.is_empty() { None } else { Some(self.krate.clone()) },
+            exclude_crates: self.exclude_crate.clone(),
+            exclude_param_types: self.exclude_param_type.clone(),
+            exclude_ret_types: self.exclude_ret_type.clone(),
+            ignore_refs: self.ignore_refs,
+            max_typos: self.max_typos,
+            candidate_limit: self.candidate_limit,
+            max_results: self.max_results,
+            adaptive_candidates: self.adaptive_candidates,
+            include_deprecated: self.include_deprecated,
+            ignore_synonyms: self.no_synonyms,
+            ignore_conversions: self.no_conversions,
+            max_per_crate: self.max_per_crate,
+            prefer_distinct_crates: self.prefer_distinct_crates,
+            categories: self.categories.clone(),
+            keywords: self.keywords.clone(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RenderOpt {
+    #[structopt(long, default_value = "impl", parse(try_from_str = parse_signature_style),
+                help = "Show methods in their impl ('impl'), as plain fns ('ufcs') or as stored ('raw')")]
+    signature_style: SignatureStyle,
+    #[structopt(long, help = "Show paths starting with their crate")]
+    qualified_paths: bool,
+    #[structopt(long, help = "Show the use line needed to call each result")]
+    imports: bool,
+}
+
+impl RenderOpt {
+    fn to_options(&self) -> RenderOptions {
+        RenderOptions { style: self.signature_style, qualified: self.qualified_paths }
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct AnalysisOpt {
+    #[structopt(long, help = "Analyze with all features enabled")]
+    all_features: bool,
+    #[structopt(long, use_delimiter = true, help = "Analyze with these features enabled, in addition to the defaults")]
+    features: Vec<String>,
+    #[structopt(long, help = "Analyze without the default features, e.g. for a no_std build")]
+    no_default_features: bool,
+    #[structopt(long, help = "Analyze for this target triple rather than the host's, e.g. 'thumbv7em-none-eabihf' (needs the target's std, or a no_std crate)")]
+    target: Option<String>,
+    #[structopt(long, conflicts_with_all = &["all-features", "features"],
+                help = "Analyze once per feature to record which features gate each item (slow)")]
+    per_feature: bool,
+    #[structopt(long, help = "Run build scripts, for crates that generate APIs at build time (slow, runs crate code)")]
+    build_scripts: bool,
+    #[structopt(long, help = "Expand proc macros (slow, runs crate code)")]
+    proc_macros: bool,
+    #[structopt(long, help = "Also index private and #[doc(hidden)] items, for navigating a local project")]
+    local: bool,
+    #[structopt(long, help = "Also index methods reachable through Deref as methods of each type that derefs to them")]
+    deref_methods: bool,
+    #[structopt(long, help = "Also index public items of the crate's binaries and examples, e.g. to search an application")]
+    bins: bool,
+    #[structopt(long, help = "Give up on analyzing a crate after this many seconds, saving an error")]
+    analysis_timeout_secs: Option<u64>,
+    #[structopt(long, help = "Analyze from rustdoc's JSON output rather than with rust analyzer (requires a nightly toolchain, runs build scripts and proc macros)")]
+    rustdoc: bool,
+    #[structopt(long, help = "Reuse analyses of published crates from this dir, and cache new ones there")]
+    analysis_cache: Option<PathBuf>,
+}
+
+impl AnalysisOpt {
+    fn to_options(&self) -> reeves::AnalysisOptions {
+        let features = if self.per_feature {
+            reeves::FeatureSelection::PerFeature
+        } else if self.all_features {
+            reeves::FeatureSelection::All
+        } else if !self.features.is_empty() {
+            reeves::FeatureSelection::Some(self.features.clone())
+        } else {
+            reeves::FeatureSelection::Default
+        };
+        reeves::AnalysisOptions {
+            features,
+            profile: AnalysisProfile { target: self.target.clone(), no_default_features: self.no_default_features },
+            build_scripts: self.build_scripts,
+            proc_macros: self.proc_macros,
+            include_private: self.local,
+            deref_methods: self.deref_methods,
+            bins: self.bins,
+            timeout: self.analysis_timeout_secs.map(Duration::from_secs),
+            cancel: reeves::CancelToken::new(),
+            frontend: if self.rustdoc { reeves::Frontend::Rustdoc } else { reeves::Frontend::RustAnalyzer },
+            cache: self.analysis_cache.as_deref().map(reeves::AnalysisCache::new),
+        }
+    }
+
+    // To pass on to an analysis in a container, which can't see the analysis cache
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if self.all_features { args.push("--all-features".to_owned()) }
+        if !self.features.is_empty() { args.push(format!("--features={}", self.features.join(","))) }
+        if self.per_feature { args.push("--per-feature".to_owned()) }
+        if self.no_default_features { args.push("--no-default-features".to_owned()) }
+        if let Some(target) = self.target.as_ref() { args.push(format!("--target={}", target)) }
+        if self.build_scripts { args.push("--build-scripts".to_owned()) }
+        if self.proc_macros { args.push("--proc-macros".to_owned()) }
+        if self.local { args.push("--local".to_owned()) }
+        if self.deref_methods { args.push("--deref-methods".to_owned()) }
+        if self.bins { args.push("--bins".to_owned()) }
+        if let Some(secs) = self.analysis_timeout_secs { args.push(format!("--analysis-timeout-secs={}", secs)) }
+        if self.rustdoc { args.push("--rustdoc".to_owned()) }
+        args
+    }
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct ContainerOpt {
+    #[structopt(long, default_value = "600", help = "Give up on analyzing a crate after this many seconds")]
+    timeout_secs: u64,
+    #[structopt(long, help = "Give up on analyzing a crate once it uses this much memory")]
+    memory_limit_mb: Option<u64>,
+    #[structopt(long, help = "Analyze each crate in a child process rather than a container, e.g. without podman (crate code isn't sandboxed)")]
+    subprocess: bool,
+}
+
 #[derive(Debug, StructOpt)]
 enum ReevesCmd {
-    #[structopt(about = "Analyze a crate and save results (requires: rust analyzer)")]
+    #[structopt(about = "Analyze a crate (a directory or .crate file) and save results (requires: rust analyzer)")]
+    #[structopt(alias = "analyze")]
     AnalyzeAndSave {
         crate_path: PathBuf,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(long, help = "Also analyze and save all dependencies of the crate, reusing its load")]
+        with_dependencies: bool,
+        #[structopt(long = "dependency", conflicts_with = "with-dependencies",
+                    help = "Also analyze and save this dependency of the crate, reusing its load (may be repeated)")]
+        dependencies: Vec<String>,
+    },
+    #[structopt(about = "Download a crate from crates.io, analyze it and save results (requires: rust analyzer)")]
+    AnalyzeCratesIoAndSave {
+        crate_name: String,
+        crate_version: String,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+    },
+    #[structopt(about = "Analyze rustdoc JSON output for a crate (e.g. downloaded from docs.rs, optionally gzipped) and save results")]
+    AnalyzeRustdocJsonAndSave {
+        json_path: PathBuf,
+        crate_name: String,
+        crate_version: String,
+        #[structopt(long, help = "Also index private items, if the JSON includes them")]
+        local: bool,
+        #[structopt(long, help = "The target triple the JSON was generated for, if not the host's, to record on its fns")]
+        target: Option<String>,
+        #[structopt(long, help = "The JSON was generated without default features, to record on its fns")]
+        no_default_features: bool,
+    },
+    #[structopt(about = "Analyze a local crate and keep its results up to date as it changes (requires: rust analyzer, reeves DB, running text search)")]
+    Watch {
+        crate_path: PathBuf,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
     },
     #[structopt(name = ANALYZE_AND_PRINT_COMMAND)]
     #[structopt(about = "Analyze a crate and print JSON output (requires: rust analyzer)")]
     AnalyzeAndPrint {
         crate_path: PathBuf,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+    },
+    #[structopt(about = "Analyze a crate and show how its fns differ from the indexed version, without saving anything (requires: rust analyzer, reeves DB)")]
+    AnalyzeAndDiff {
+        crate_path: PathBuf,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
     },
     #[structopt(about = "Analyze a crate in a secure container and print JSON output (requires: container state)")]
     ContainerAnalyzeAndPrint {
         crate_path: PathBuf,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(flatten)]
+        container_opt: ContainerOpt,
     },
     #[structopt(about = "Analyze top 100 crates from play.rust-lang.org in containers and save results (requires: container state, panamax mirror, reeves DB)")]
-    AnalyzeTop100Crates,
+    AnalyzeTop100Crates {
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(flatten)]
+        container_opt: ContainerOpt,
+    },
     #[structopt(about = "Analyze all crates (latest version) from crates.io in containers and save results (requires: container state, panamax mirror, reeves DB)")]
-    AnalyzeAllCrates,
+    AnalyzeAllCrates {
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(flatten)]
+        container_opt: ContainerOpt,
+    },
     #[structopt(about = "Populate the text search backend, using the reeves DB (requires: reeves DB, running text search)")]
-    LoadTextSearch,
-    #[structopt(about = "Perform a search for some comma-separated param types and a ret type (requires: reeves DB, running+loaded text search)")]
+    LoadTextSearch {
+        #[structopt(long, help = "Recreate the text search indexes rather than only adding new entries, dropping entries for purged crates")]
+        full: bool,
+    },
+    #[structopt(about = "Apply text search settings (synonyms, stop words, ranking rules) from a JSON file to the loaded text search, without reloading it (requires: reeves DB, running text search)")]
+    UpdateSearchSettings {
+        config: PathBuf,
+    },
+    #[structopt(about = "Search interactively, refining a query a part at a time, e.g. 'param + &str' or 'crate tokio' (requires: reeves DB, running+loaded text search)")]
+    Repl {
+        #[structopt(flatten)]
+        filter_opt: SearchFilterOpt,
+        #[structopt(flatten)]
+        render_opt: RenderOpt,
+    },
+    #[structopt(about = "Perform a search for a query like '<comma-separated params> -> <ret>', using * for 'any' and '(<params>)' for exactly those params (requires: reeves DB, running+loaded text search)")]
     Search {
-        params_search: String,
-        ret_search: String,
+        #[structopt(required_unless = "replay")]
+        query: Option<String>,
+        #[structopt(long, conflicts_with = "query", help = "Run a query from the search history again, by its id")]
+        replay: Option<String>,
+        #[structopt(long, help = "Only fns whose return type implements this trait, e.g. 'Iterator<Item = u8>'")]
+        ret_impl: Option<String>,
+        #[structopt(long, help = "Only fns with a path like this, e.g. 'read_to_str'")]
+        name: Option<String>,
+        #[structopt(long, help = "Only fns with docs like this, e.g. 'parse a header'")]
+        doc: Option<String>,
+        #[structopt(long, help = "Only fns whose first param is this type, before any in the query, e.g. '&Header'")]
+        receiver: Option<String>,
+        #[structopt(flatten)]
+        filter_opt: SearchFilterOpt,
+        #[structopt(long, default_value = "0", help = "Skip this many results")]
+        offset: usize,
+        #[structopt(long, help = "Return at most this many results (capped at 500)")]
+        limit: Option<usize>,
+        #[structopt(long, help = "Continue from a previous page of results for the same query")]
+        cursor: Option<String>,
+        #[structopt(long, help = "Show why each result matched")]
+        explain: bool,
+        #[structopt(long, parse(try_from_str = parse_group_by), help = "Group results by the type they belong to ('type') or their module ('module')")]
+        group_by: Option<GroupBy>,
+        #[structopt(long, help = "Match params in the order given, rather than in any order")]
+        ordered: bool,
+        #[structopt(long, help = "Count all the results by the categories and keywords of their crates")]
+        facets: bool,
+        #[structopt(flatten)]
+        render_opt: RenderOpt,
+    },
+    #[structopt(about = "Find fns that can be called using only values of the given types, e.g. '&Path' 'u32' (requires: reeves DB)")]
+    SearchApplicable {
+        #[structopt(required = true)]
+        types: Vec<String>,
+        #[structopt(flatten)]
+        filter_opt: SearchFilterOpt,
+        #[structopt(flatten)]
+        render_opt: RenderOpt,
+    },
+    #[structopt(about = "Find chains of fn calls that get from a value of one type to another, e.g. '&str' 'SocketAddr' (requires: reeves DB)")]
+    SearchCompositions {
+        from: String,
+        to: String,
+        #[structopt(long, default_value = "3", help = "Most fn calls in a chain (at most 4)")]
+        max_steps: usize,
+        #[structopt(flatten)]
+        filter_opt: SearchFilterOpt,
+        #[structopt(flatten)]
+        render_opt: RenderOpt,
+    },
+    #[structopt(about = "List searches that have been made, most recent first, with ids to replay them with 'search --replay' (requires: reeves DB)")]
+    SearchHistory {
+        #[structopt(long, help = "Most searched first")]
+        popular: bool,
+        #[structopt(long, help = "Only pinned queries")]
+        pinned: bool,
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
+    #[structopt(about = "List the most searched types, or those in searches with no results, once enabled with --enable (requires: reeves DB)")]
+    TypeStats {
+        #[structopt(long, help = "Start counting how often each type is searched for (only totals are kept)")]
+        enable: bool,
+        #[structopt(long, conflicts_with = "enable", help = "Stop counting, keeping the counts so far")]
+        disable: bool,
+        #[structopt(long, help = "Only types in searches with no results, most such searches first")]
+        no_results: bool,
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
+    #[structopt(about = "Keep a query from the search history as a saved query (requires: reeves DB)")]
+    PinQuery {
+        id: String,
+    },
+    #[structopt(about = "Stop keeping a query from the search history as a saved query (requires: reeves DB)")]
+    UnpinQuery {
+        id: String,
+    },
+    #[structopt(about = "List types known to implement a trait, e.g. 'serde::Serialize' (requires: reeves DB)")]
+    ImplsOf {
+        trait_name: String,
+    },
+    #[structopt(about = "List traits a type is known to implement, e.g. 'Vec<u8>' (requires: reeves DB)")]
+    TraitsOf {
+        ty: String,
+    },
+    #[structopt(about = "List types a type converts to through From, TryFrom, Into and AsRef impls, e.g. '&str' (requires: reeves DB)")]
+    ConversionsFrom {
+        ty: String,
+    },
+    #[structopt(about = "List types that convert to a type through From, TryFrom, Into and AsRef impls, e.g. 'PathBuf' (requires: reeves DB)")]
+    ConversionsTo {
+        ty: String,
+    },
+    #[structopt(about = "Show which crate defines a type and which fns produce and consume it, e.g. 'regex::Captures' (requires: reeves DB)")]
+    TypeInfo {
+        ty: String,
+    },
+    #[structopt(about = "List fns with signatures like a fn's, e.g. 'regex::Regex::new', or like a signature, e.g. '&str -> Result<Regex, Error>' (requires: reeves DB)")]
+    SimilarTo {
+        #[structopt(help = "A fn path, or a signature in the query syntax")]
+        target: String,
+        #[structopt(long, help = "Leave out fns of the same crate as the fn")]
+        other_crates: bool,
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
+    #[structopt(about = "Treat two types as equivalent when searching, e.g. 'str' and 'String' (requires: reeves DB)")]
+    AddSynonym {
+        ty: String,
+        synonym: String,
+    },
+    #[structopt(about = "Stop treating two types as equivalent when searching (requires: reeves DB)")]
+    RemoveSynonym {
+        ty: String,
+        synonym: String,
+    },
+    #[structopt(about = "List types treated as equivalent when searching (requires: reeves DB)")]
+    ListSynonyms,
+    #[structopt(about = "Create a named index in the reeves DB, to build and search with --index separately from the default one (requires: reeves DB)")]
+    CreateIndex {
+        name: String,
+        #[structopt(long = "normalize", parse(try_from_str = parse_normalization_pass),
+                    help = "Also normalize param and ret types with this pass, 'erase-refs' or 'unwrap-wrappers' (may be repeated, applied in order)")]
+        normalization: Vec<NormalizationPass>,
+    },
+    #[structopt(about = "Set how the index normalizes param and ret types, e.g. 'erase-refs', while it's empty (requires: reeves DB)")]
+    SetNormalization {
+        #[structopt(parse(try_from_str = parse_normalization_pass))]
+        passes: Vec<NormalizationPass>,
+    },
+    #[structopt(about = "List the named indexes in the reeves DB (requires: reeves DB)")]
+    ListIndexes,
+    #[structopt(about = "Remove a named index and everything in it from the reeves DB (requires: reeves DB)")]
+    DropIndex {
+        name: String,
+    },
+    #[structopt(about = "Time decoding stored fns in full and just what searches filter and order by (requires: reeves DB)")]
+    BenchDecode {
+        #[structopt(long, default_value = "100000")]
+        max_fns: usize,
+        #[structopt(long, default_value = "5")]
+        iterations: usize,
+    },
+    #[structopt(about = "Time searches for some queries, e.g. to compare search changes against an index of 1000 crates (requires: reeves DB, running+loaded text search)")]
+    BenchSearch {
+        #[structopt(required = true)]
+        queries: Vec<String>,
+        #[structopt(long, default_value = "10")]
+        iterations: usize,
+    },
+    #[structopt(about = "Queue a crate for analysis by a worker (requires: reeves DB)")]
+    Enqueue {
+        crate_name: String,
+        crate_version: String,
+    },
+    #[structopt(about = "Show the analysis job for a crate, or all queued jobs if no crate is given (requires: reeves DB)")]
+    JobStatus {
+        crate_name: Option<String>,
+        #[structopt(requires = "crate-name")]
+        crate_version: Option<String>,
+    },
+    #[structopt(about = "Analyze queued crates in containers and save results, until killed (requires: container state, panamax mirror, reeves DB)")]
+    Worker {
+        #[structopt(long, default_value = "1", help = "Number of crates to analyze at once")]
+        workers: usize,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(flatten)]
+        container_opt: ContainerOpt,
+    },
+    #[structopt(about = "Keep a crate indexed at its newest version, analyzing new versions as they're published (requires: reeves DB)")]
+    Track {
+        crate_name: String,
+        #[structopt(long, help = "Purge older versions once a new one is indexed")]
+        purge_old: bool,
+    },
+    #[structopt(about = "Stop indexing new versions of a crate as they're published, keeping those already indexed (requires: reeves DB)")]
+    Untrack {
+        crate_name: String,
+    },
+    #[structopt(about = "List tracked crates and the outcome of their last check for a new version (requires: reeves DB)")]
+    ListTracked,
+    #[structopt(about = "Check tracked crates for new versions on crates.io, analyzing them in containers and saving results, until killed (requires: container state, panamax mirror, reeves DB)")]
+    Scheduler {
+        #[structopt(long, default_value = "3600", help = "Seconds between checks of each tracked crate")]
+        interval: u64,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(flatten)]
+        container_opt: ContainerOpt,
+    },
+    #[structopt(about = "Remove a crate from the reeves DB - all versions, unless one is specified (requires: reeves DB)")]
+    Purge {
+        crate_name: String,
+        crate_version: Option<String>,
+    },
+    #[structopt(about = "List crate versions added to and purged from the reeves DB, oldest first, with entry ids to roll back to (requires: reeves DB)")]
+    Journal {
+        #[structopt(long = "crate", help = "Only changes to this crate")]
+        crate_name: Option<String>,
+        #[structopt(long, help = "Only changes after this entry id, e.g. the last one seen")]
+        after: Option<u64>,
+    },
+    #[structopt(about = "Put a crate's versions back as they were after a journal entry, adding purged versions back from the analysis cache (requires: reeves DB)")]
+    Rollback {
+        crate_name: String,
+        to_entry: u64,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
     },
     #[structopt(about = "Start the reeves server (requires: wasm built, reeves db, loaded+running text search)")]
     Serve {
@@ -91,16 +689,77 @@ enum ReevesCmd {
         ip: String,
         #[structopt(long)]
         port: String,
+        #[structopt(long, help = "Accept DB changes from other reeves processes (run with --server) on this unix socket")]
+        admin_socket: Option<PathBuf>,
+        #[structopt(long, help = "Compact the DB in the background every this many seconds, as for compact-db")]
+        compact_interval: Option<u64>,
+        #[structopt(long, help = "Load the largest sets of fns (e.g. of &str) into memory in the background on startup, so early searches are as quick as later ones")]
+        warm_cache: bool,
+    },
+    #[cfg(feature = "grpc")]
+    #[structopt(about = "Start the reeves gRPC server, submitted analyses are queued for workers (requires: reeves db, loaded+running text search, container state and panamax mirror if running workers)")]
+    ServeGrpc {
+        #[structopt(long, default_value = "127.0.0.1")]
+        ip: String,
+        #[structopt(long)]
+        port: String,
+        // The DB can only be opened by one process, so this is the way to analyze submitted crates
+        // while serving
+        #[structopt(long, default_value = "0", help = "Number of workers to run alongside the server")]
+        workers: usize,
+        #[structopt(long, help = "Serve Prometheus metrics over HTTP at /metrics on this port")]
+        metrics_port: Option<String>,
+        #[structopt(long, help = "Accept DB changes from other reeves processes (run with --server) on this unix socket")]
+        admin_socket: Option<PathBuf>,
+        #[structopt(flatten)]
+        analysis_opt: AnalysisOpt,
+        #[structopt(flatten)]
+        container_opt: ContainerOpt,
     },
     #[structopt(about = "Dump contents of the reeves DB (requires: reeves DB)")]
     DebugDB,
+    #[structopt(about = "Serve type searches to an editor over LSP on stdio, for the workspace it's started in and its dependencies (requires: reeves DB, running+loaded text search)")]
+    ServeLsp,
+    #[structopt(about = "Render the index as a static website with in-browser search, e.g. for GitHub Pages (requires: reeves DB)")]
+    GenerateSite {
+        out_dir: PathBuf,
+    },
+    #[structopt(about = "Write a compact snapshot of the index for searching without a server, e.g. in the browser (requires: reeves DB)")]
+    ExportCompactIndex {
+        out: PathBuf,
+    },
+    #[structopt(about = "Write everything in the reeves DB to a file, which can be loaded into memory with --db <file>.snapshot (requires: reeves DB)")]
+    ExportSnapshot {
+        out: PathBuf,
+    },
+    #[structopt(about = "Drop removed fns from the indexes of fn ids and re-encode them, to shrink the DB (requires: reeves DB)")]
+    CompactDb,
+    #[structopt(about = "Show the number and size of keys and values in each tree of the reeves DB, and the largest fn id sets (requires: reeves DB)")]
+    DbStats,
+    #[structopt(about = "Report the most common param and ret types, the largest crates, and types produced but never consumed (requires: reeves DB)")]
+    Report {
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
+// Logs go to stderr, filtered by RUST_LOG as before. As JSON, spans (e.g. loading a workspace) are
+// logged as they close with how long they took, for profiling
+fn init_tracing(json: bool) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(io::stderr);
+    if json {
+        builder.json().with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE).init()
+    } else {
+        builder.init()
+    }
+}
 
+fn main() -> Result<()> {
     // See comment on ENV_RUST_ANALYZER_EXEC
     if env::var_os(ENV_RUST_ANALYZER_EXEC).is_some() {
+        init_tracing(false);
         debug!("Re-executing rust-analyzer");
         let mut cmd = Command::new(env::var_os(ENV_RUST_ANALYZER_BINARY).unwrap());
         cmd.args(env::args_os().skip(1)).exec();
@@ -110,31 +769,94 @@ fn main() -> Result<()> {
     }
 
     let opt = ReevesOpt::from_args();
+    init_tracing(opt.trace_json);
 
     env::set_var(ENV_RUST_ANALYZER_BINARY, opt.rust_analyzer);
 
+    let (db_path, write_budget_mb, index) = (opt.db.clone(), opt.write_budget_mb, opt.index.clone());
+    let open_db = move || -> Result<Db> {
+        let db = reeves::open_db(&db_path)?.with_write_budget(write_budget_mb * 1024 * 1024);
+        match &index {
+            Some(index) => reeves::open_index(&db, index),
+            None => Ok(db),
+        }
+    };
+    let server_socket = opt.server.clone();
+    let open_db_writer = || -> Result<DbWriter> {
+        Ok(match &server_socket {
+            Some(socket) => DbWriter::Server(reeves::AdminClient::connect(socket)?),
+            None => DbWriter::Local(open_db()?),
+        })
+    };
+
     match opt.cmd {
 
-        ReevesCmd::AnalyzeAndSave { crate_path } => {
+        ReevesCmd::AnalyzeAndSave { crate_path, analysis_opt, with_dependencies, dependencies } => {
             info!("analyzing crate path {}", crate_path.display());
-            let (crate_name, crate_version, fndetails) = reeves::analyze_crate_path(&crate_path);
-            let db = reeves::open_db(&opt.db);
-            match fndetails {
-                Ok(fndetails) => {
-                    info!("finished analysing functions, inserting {} function details into db", fndetails.len());
-                    reeves::save_analysis(&db, &crate_name, &crate_version, fndetails);
-                },
-                Err(err) => {
-                    let err = format!("{:?}", err);
-                    warn!("analysis failed, saving error to db: {}", err);
-                    reeves::save_analysis_error(&db, &crate_name, &crate_version, &err);
-                },
+            let dependencies = if with_dependencies {
+                Some(reeves::DependencySelection::All)
+            } else if !dependencies.is_empty() {
+                Some(reeves::DependencySelection::Some(dependencies))
+            } else {
+                None
+            };
+            if let Some(dependencies) = dependencies {
+                if !crate_path.is_dir() {
+                    bail!("dependencies can only be analyzed for a crate directory")
+                }
+                let (crate_name, crate_version, res) = reeves::analyze_with_dependencies(&crate_path, &analysis_opt.to_options(), &dependencies, &cli_progress);
+                let mut writer = open_db_writer()?;
+                match res {
+                    Ok(analyses) => for analysis in analyses {
+                        let meta = reeves::read_crate_meta(&analysis.root, &analysis.name, &analysis.version);
+                        save_analysis_and_report(&mut writer, opt.output, meta, Ok(analysis.fndetails))?
+                    },
+                    Err(e) => {
+                        let meta = reeves::read_crate_meta(&crate_path, &crate_name, &crate_version);
+                        save_analysis_and_report(&mut writer, opt.output, meta, Err(e))?
+                    },
+                }
+                return Ok(())
             }
-            info!("finished inserting into db");
+            let (meta, fndetails) = if crate_path.is_file() && crate_path.extension() == Some("crate".as_ref()) {
+                reeves::analyze_from_tarball(&crate_path, &analysis_opt.to_options(), &cli_progress)
+                    .with_context(|| format!("failed to analyze {}", crate_path.display()))?
+            } else {
+                let (crate_name, crate_version, fndetails) = reeves::analyze_crate_path(&crate_path, &analysis_opt.to_options(), &cli_progress);
+                (reeves::read_crate_meta(&crate_path, &crate_name, &crate_version), fndetails)
+            };
+            let mut writer = open_db_writer()?;
+            save_analysis_and_report(&mut writer, opt.output, meta, fndetails)?
+        },
+
+        ReevesCmd::AnalyzeCratesIoAndSave { crate_name, crate_version, analysis_opt } => {
+            info!("analyzing crate {} {} from crates.io", crate_name, crate_version);
+            let (meta, fndetails) = reeves::analyze_from_crates_io(&crate_name, &crate_version, &analysis_opt.to_options(), &cli_progress)
+                .with_context(|| format!("failed to analyze {} {}", crate_name, crate_version))?;
+            let mut writer = open_db_writer()?;
+            save_analysis_and_report(&mut writer, opt.output, meta, fndetails)?
+        },
+
+        ReevesCmd::AnalyzeRustdocJsonAndSave { json_path, crate_name, crate_version, local, target, no_default_features } => {
+            info!("analyzing rustdoc JSON {} for {} {}", json_path.display(), crate_name, crate_version);
+            let profile = AnalysisProfile { target, no_default_features };
+            let opts = reeves::AnalysisOptions { include_private: local, profile, frontend: reeves::Frontend::Rustdoc, ..Default::default() };
+            let (meta, fndetails) = reeves::analyze_rustdoc_json(&json_path, &crate_name, &crate_version, &opts, &cli_progress)
+                .with_context(|| format!("failed to analyze {}", json_path.display()))?;
+            let mut writer = open_db_writer()?;
+            save_analysis_and_report(&mut writer, opt.output, meta, fndetails)?
+        },
+
+        ReevesCmd::Watch { crate_path, analysis_opt } => {
+            info!("watching crate path {}", crate_path.display());
+            let db = open_db()?;
+            reeves::watch(&crate_path, &db, &analysis_opt.to_options(), &cli_progress)
+                .with_context(|| format!("failed to watch {}", crate_path.display()))?
         },
 
-        ReevesCmd::AnalyzeAndPrint { crate_path } => {
-            let (crate_name, crate_version, res) = reeves::analyze_crate_path(&crate_path);
+        ReevesCmd::AnalyzeAndPrint { crate_path, analysis_opt } => {
+            // Progress output would get mixed up with the JSON
+            let (crate_name, crate_version, res) = reeves::analyze_crate_path(&crate_path, &analysis_opt.to_options(), &reeves::NoProgress);
             let res = match res {
                 Ok(fndetails) => Either::Left(fndetails),
                 Err(e) => Either::Right(format!("{:?}", e)),
@@ -144,14 +866,39 @@ fn main() -> Result<()> {
             io::stdout().write_all(&out).unwrap();
         },
 
-        ReevesCmd::ContainerAnalyzeAndPrint { crate_path } => {
-            let res: AnalyzeAndPrintOutput = container_analyze_crate_path(&crate_path)
+        ReevesCmd::AnalyzeAndDiff { crate_path, analysis_opt } => {
+            let db = open_db()?;
+            let diff = reeves::analyze_diff(&db, &crate_path, &analysis_opt.to_options(), &cli_progress)
+                .with_context(|| format!("failed to analyze {}", crate_path.display()))?;
+            match opt.output {
+                OutputFormat::Human => {
+                    match &diff.base_version {
+                        Some(base_version) => println!("{} {} compared to indexed {}", diff.name, diff.version, base_version),
+                        None => println!("{} {} has no indexed version, all fns are new", diff.name, diff.version),
+                    }
+                    for fndetail in diff.removed.iter() {
+                        println!("- {}", fndetail.s)
+                    }
+                    for fndetail in diff.added.iter() {
+                        println!("+ {}", fndetail.s)
+                    }
+                    for change in diff.changed.iter() {
+                        println!("~ {}\n  -> {}", change.old.s, change.new.s)
+                    }
+                    println!("{} added, {} removed, {} changed", diff.added.len(), diff.removed.len(), diff.changed.len())
+                },
+                OutputFormat::Json => print_json(&diff),
+            }
+        },
+
+        ReevesCmd::ContainerAnalyzeAndPrint { crate_path, analysis_opt, container_opt } => {
+            let res: AnalyzeAndPrintOutput = container_analyze_crate_path(&crate_path, &analysis_opt, &container_opt)
                 .with_context(|| format!("failed to analyze path {} in a container", crate_path.display()))?;
             let out = serde_json::to_vec(&res).unwrap();
             io::stdout().write_all(&out).unwrap();
         },
 
-        ReevesCmd::AnalyzeTop100Crates => {
+        ReevesCmd::AnalyzeTop100Crates { analysis_opt, container_opt } => {
             let panamax_mirror_path = &opt.panamax_mirror;
 
             #[derive(Deserialize)]
@@ -168,16 +915,16 @@ fn main() -> Result<()> {
             let mut res = isahc::get("https://play.rust-lang.org/meta/crates").unwrap();
             let crates: PlayCrates = res.json().unwrap();
 
-            let db = reeves::open_db(&opt.db);
+            let db = open_db()?;
 
             info!("considering {} crates", crates.crates.len());
-            cli_container_parallel_process_crates(&db, panamax_mirror_path, &mut crates.crates.into_iter().map(|krate| (krate.name, krate.version)));
+            cli_container_parallel_process_crates(&db, panamax_mirror_path, &mut crates.crates.into_iter().map(|krate| (krate.name, krate.version)), &analysis_opt, &container_opt);
         }
 
-        ReevesCmd::AnalyzeAllCrates => {
+        ReevesCmd::AnalyzeAllCrates { analysis_opt, container_opt } => {
             let panamax_mirror_path = &opt.panamax_mirror;
 
-            let db = reeves::open_db(&opt.db);
+            let db = open_db()?;
 
             let index = crates_index::Index::new(panamax_mirror_path.join("crates.io-index"));
             assert!(index.exists());
@@ -190,48 +937,699 @@ fn main() -> Result<()> {
             let crates: Vec<_> = crates.into_iter().filter(|(name, version)| !reeves::has_crate(&db, name, version)).collect();
 
             info!("considering {} crates", crates.len());
-            cli_container_parallel_process_crates(&db, panamax_mirror_path, &mut crates.into_iter());
+            cli_container_parallel_process_crates(&db, panamax_mirror_path, &mut crates.into_iter(), &analysis_opt, &container_opt);
         }
 
-        ReevesCmd::LoadTextSearch => {
-            let db = reeves::open_db(&opt.db);
-            reeves::load_text_search(&db)
+        ReevesCmd::LoadTextSearch { full } => {
+            match open_db_writer()? {
+                DbWriter::Local(db) => reeves::load_text_search(&db, full, &cli_progress),
+                DbWriter::Server(mut client) => client.load_text_search(full)?,
+            }
         },
 
-        ReevesCmd::Search { params_search, ret_search } => {
-            let params_search: Vec<_> = if params_search.is_empty() {
-                vec![]
-            } else {
-                params_search.split(",").map(|s| s.trim().to_owned()).collect()
+        ReevesCmd::UpdateSearchSettings { config } => {
+            let settings: reeves::TextSearchSettings = serde_json::from_slice(&fs::read(&config)?)
+                .with_context(|| format!("failed to parse text search settings from {}", config.display()))?;
+            match open_db_writer()? {
+                DbWriter::Local(db) => reeves::update_search_settings(&db, &settings)?,
+                DbWriter::Server(mut client) => client.update_search_settings(&settings)?,
+            }
+            match opt.output {
+                OutputFormat::Human => println!("updated text search settings"),
+                OutputFormat::Json => print_json(&settings),
+            }
+        },
+
+        ReevesCmd::Repl { filter_opt, render_opt } => {
+            let db = open_db()?;
+            repl::run_repl(&db, filter_opt.to_filters(), &render_opt.to_options(), render_opt.imports)?
+        },
+
+        ReevesCmd::Search { query, replay, ret_impl, name, doc, receiver, filter_opt, offset, limit, cursor, explain, group_by, ordered, facets, render_opt } => {
+            let db = open_db()?;
+            let mut search_query = match replay {
+                Some(id) => {
+                    let entry = reeves::parse_history_id(&id).and_then(|id| reeves::history_entry(&db, id))
+                        .with_context(|| format!("no query with id {} in the search history", id))?;
+                    entry.query.to_query()
+                },
+                None => Query::parse(&query.expect("no query")),
             };
-            let ret_search = if ret_search.is_empty() {
-                None
-            } else {
-                Some(ret_search.to_owned())
+            // Unless given again when replaying
+            search_query.ret_impl = ret_impl.or(search_query.ret_impl);
+            search_query.name = name.or(search_query.name);
+            search_query.doc = doc.or(search_query.doc);
+            search_query.receiver = receiver.map(|receiver| TypeRepr::parse(&receiver));
+            let mut filters = filter_opt.to_filters();
+            filters.min_params = filters.min_params.or(search_query.filters.min_params);
+            filters.max_params = filters.max_params.or(search_query.filters.max_params);
+            search_query.filters = filters;
+            let first_page = cursor.is_none() && offset == 0;
+            let options = SearchOptions { offset, limit, cursor, explain, group_by, ordered, facets };
+            let reeves::SearchResults { fndetails, next_cursor, warnings, crates, explanations, groups, facets, suggestions } = reeves::search(&db, &reeves::SearchCache::new(), &search_query, &options);
+            if first_page {
+                reeves::record_search(&db, &reeves::SavedQuery::from_query(&search_query), fndetails.len())
+            }
+            match opt.output {
+                OutputFormat::Human => {
+                    for warning in &warnings {
+                        eprintln!("warning: {}", warning)
+                    }
+                    let print_result = |i: usize, indent: &str| {
+                        let fndetail = &fndetails[i];
+                        println!("{}[{} {}] {}", indent, fndetail.krate, fndetail.version, render_signature(fndetail, &render_opt.to_options()));
+                        if render_opt.imports {
+                            println!("{}    {}", indent, use_line(fndetail))
+                        }
+                        if let Some(doc) = &fndetail.doc {
+                            println!("{}    {}", indent, doc)
+                        }
+                        if let Some(note) = &fndetail.deprecated {
+                            println!("{}    deprecated{}", indent, if note.is_empty() { String::new() } else { format!(": {}", note) })
+                        }
+                        if let Some(explanation) = explanations.as_ref().and_then(|explanations| explanations[i].as_ref()) {
+                            let relaxed = explanation.relaxed.as_ref().map_or(String::new(), |kind| format!(", relaxed {}", kind));
+                            let score = &explanation.score;
+                            println!("{}    matched on pass {}{} (score: pass {}, relaxed {}, crate rank {})", indent, explanation.pass, relaxed, score.pass, score.relaxed, score.crate_rank);
+                            for term in explanation.terms.iter() {
+                                println!("{}      {} {:?} => {:?} (candidate {})", indent, term.kind, term.term, term.candidate, term.depth)
+                            }
+                        }
+                    };
+                    match &groups {
+                        Some(groups) => for group in groups {
+                            println!("{} ({})", group.key, group.count);
+                            for &i in group.indices.iter() {
+                                print_result(i, "  ")
+                            }
+                        },
+                        None => for i in 0..fndetails.len() {
+                            print_result(i, "")
+                        },
+                    }
+                    if let Some(facets) = &facets {
+                        println!("results by facet:");
+                        for facet in facets {
+                            println!("  {} ({})", facet.facet, facet.count)
+                        }
+                    }
+                    for suggestion in suggestions.iter() {
+                        println!("no fns with {}, did you mean {}?", suggestion.ty, suggestion.suggestions.join(", "))
+                    }
+                    if let Some(next_cursor) = next_cursor {
+                        println!("more results available with --cursor {}", next_cursor)
+                    }
+                },
+                OutputFormat::Json => print_json(&SearchOutput { fndetails, next_cursor, warnings, crates, explanations, groups, facets, suggestions }),
+            }
+        }
+
+        ReevesCmd::SearchApplicable { types, filter_opt, render_opt } => {
+            let filters = filter_opt.to_filters();
+            let db = open_db()?;
+            let fndetails = reeves::search_applicable(&db, types, &filters);
+            match opt.output {
+                OutputFormat::Human => {
+                    for fndetail in fndetails {
+                        println!("[{} {}] {}", fndetail.krate, fndetail.version, render_signature(&fndetail, &render_opt.to_options()));
+                        if render_opt.imports {
+                            println!("    {}", use_line(&fndetail))
+                        }
+                        if let Some(doc) = fndetail.doc {
+                            println!("    {}", doc)
+                        }
+                    }
+                },
+                OutputFormat::Json => print_json(&fndetails),
+            }
+        }
+
+        ReevesCmd::SearchCompositions { from, to, max_steps, filter_opt, render_opt } => {
+            let filters = filter_opt.to_filters();
+            let db = open_db()?;
+            let chains = reeves::search_compositions(&db, &from, &to, max_steps, &filters);
+            match opt.output {
+                OutputFormat::Human => {
+                    for (i, chain) in chains.iter().enumerate() {
+                        println!("{}. {}", i + 1, from);
+                        for step in chain {
+                            match step {
+                                CompositionStep::Call(fndetail) => println!("    -> [{} {}] {}", fndetail.krate, fndetail.version, render_signature(fndetail, &render_opt.to_options())),
+                                CompositionStep::Unwrap { from, to } => println!("    -> unwrap {} to {}", from, to),
+                            }
+                        }
+                    }
+                },
+                OutputFormat::Json => print_json(&chains),
+            }
+        }
+
+        ReevesCmd::SearchHistory { popular, pinned, limit } => {
+            let db = open_db()?;
+            let order = if popular { reeves::HistoryOrder::Popular } else { reeves::HistoryOrder::Recent };
+            let entries = reeves::search_history(&db, order, pinned, limit);
+            match opt.output {
+                OutputFormat::Human => for entry in entries {
+                    let pinned = if entry.pinned { " (pinned)" } else { "" };
+                    let mut query = entry.query.query();
+                    for (flag, val) in &[("ret-impl", &entry.query.ret_impl), ("name", &entry.query.name), ("doc", &entry.query.doc)] {
+                        if let Some(val) = val {
+                            query.push_str(&format!(" --{} {:?}", flag, val))
+                        }
+                    }
+                    println!("{} {}{}", reeves::format_history_id(entry.id), query, pinned);
+                    println!("    searched {} times, last found {} results", entry.count, entry.last_num_results)
+                },
+                OutputFormat::Json => print_json(&entries),
+            }
+        }
+
+        ReevesCmd::TypeStats { enable, disable, no_results, limit } => {
+            let db = open_db()?;
+            if enable || disable {
+                reeves::set_type_stats_enabled(&db, enable);
+            }
+            let enabled = reeves::type_stats_enabled(&db);
+            let stats = reeves::type_search_stats(&db, no_results, limit);
+            match opt.output {
+                OutputFormat::Human => {
+                    if !enabled {
+                        eprintln!("type stats are disabled, enable them with --enable")
+                    }
+                    for stats in stats {
+                        println!("{}: searched {} times, {} with no results", stats.ty, stats.searches, stats.empty_searches)
+                    }
+                },
+                OutputFormat::Json => print_json(&stats),
+            }
+        }
+
+        ReevesCmd::PinQuery { id } => {
+            let db = open_db()?;
+            let found = reeves::parse_history_id(&id).map_or(false, |id| reeves::set_query_pinned(&db, id, true));
+            if !found {
+                bail!("no query with id {} in the search history", id)
+            }
+        }
+
+        ReevesCmd::UnpinQuery { id } => {
+            let db = open_db()?;
+            let found = reeves::parse_history_id(&id).map_or(false, |id| reeves::set_query_pinned(&db, id, false));
+            if !found {
+                bail!("no query with id {} in the search history", id)
+            }
+        }
+
+        ReevesCmd::ImplsOf { trait_name } => {
+            let db = open_db()?;
+            let impls = reeves::impls_of(&db, &trait_name);
+            match opt.output {
+                OutputFormat::Human => for imp in impls {
+                    println!("[{} {}] impl {} for {}", imp.krate, imp.version, imp.trait_, imp.ty)
+                },
+                OutputFormat::Json => print_json(&impls),
+            }
+        }
+
+        ReevesCmd::TraitsOf { ty } => {
+            let db = open_db()?;
+            let impls = reeves::traits_of(&db, &ty);
+            match opt.output {
+                OutputFormat::Human => for imp in impls {
+                    println!("[{} {}] impl {} for {}", imp.krate, imp.version, imp.trait_, imp.ty)
+                },
+                OutputFormat::Json => print_json(&impls),
+            }
+        }
+
+        ReevesCmd::ConversionsFrom { ty } => {
+            let db = open_db()?;
+            let conversions = reeves::conversions_from(&db, &ty);
+            match opt.output {
+                OutputFormat::Human => for conversion in conversions {
+                    println!("[{} {}] {} -> {} ({})", conversion.krate, conversion.version, conversion.from, conversion.to, conversion.trait_)
+                },
+                OutputFormat::Json => print_json(&conversions),
+            }
+        }
+
+        ReevesCmd::ConversionsTo { ty } => {
+            let db = open_db()?;
+            let conversions = reeves::conversions_to(&db, &ty);
+            match opt.output {
+                OutputFormat::Human => for conversion in conversions {
+                    println!("[{} {}] {} -> {} ({})", conversion.krate, conversion.version, conversion.from, conversion.to, conversion.trait_)
+                },
+                OutputFormat::Json => print_json(&conversions),
+            }
+        }
+
+        ReevesCmd::TypeInfo { ty } => {
+            let db = open_db()?;
+            let info = reeves::type_info(&db, &ty);
+            match opt.output {
+                OutputFormat::Human => {
+                    match info.defined_in.as_slice() {
+                        [] => println!("{}: not defined in any indexed crate", info.name),
+                        krates => println!("{}: defined in {}", info.name, krates.join(", ")),
+                    }
+                    for (what, num, fndetails) in [("produced", info.num_producers, &info.producers), ("consumed", info.num_consumers, &info.consumers)].iter() {
+                        println!("{} by {} fns{}", what, num, if fndetails.is_empty() { "" } else { ", e.g." });
+                        for fndetail in fndetails.iter() {
+                            println!("  [{} {}] {}", fndetail.krate, fndetail.version, fndetail.s)
+                        }
+                    }
+                },
+                OutputFormat::Json => print_json(&info),
+            }
+        }
+
+        ReevesCmd::SimilarTo { target, other_crates, limit } => {
+            let db = open_db()?;
+            // Anything that isn't just path segments is a signature, e.g. `&str` or `u8 -> char`
+            let is_path = target.chars().all(|c| c.is_alphanumeric() || c == '_' || c == ':');
+            let target = if is_path { reeves::SimilarTarget::Path(target) } else { reeves::SimilarTarget::Signature(target) };
+            let similar = reeves::similar_to(&db, &target, other_crates, limit)?;
+            match opt.output {
+                OutputFormat::Human => for similar_fn in similar {
+                    let fndetail = &similar_fn.fndetail;
+                    println!("{:.2} [{} {}] {}", similar_fn.score, fndetail.krate, fndetail.version, fndetail.s)
+                },
+                OutputFormat::Json => print_json(&similar),
+            }
+        }
+
+        ReevesCmd::AddSynonym { ty, synonym } => {
+            let db = open_db()?;
+            reeves::add_synonym(&db, &ty, &synonym)?;
+            if let OutputFormat::Human = opt.output {
+                println!("added synonym {} = {}", ty, synonym)
+            }
+        }
+
+        ReevesCmd::RemoveSynonym { ty, synonym } => {
+            let db = open_db()?;
+            let removed = reeves::remove_synonym(&db, &ty, &synonym);
+            match opt.output {
+                OutputFormat::Human => if removed {
+                    println!("removed synonym {} = {}", ty, synonym)
+                } else {
+                    println!("{} and {} weren't synonyms", ty, synonym)
+                },
+                OutputFormat::Json => print_json(&removed),
+            }
+        }
+
+        ReevesCmd::ListSynonyms => {
+            let db = open_db()?;
+            let synonyms = reeves::list_synonyms(&db);
+            match opt.output {
+                OutputFormat::Human => for (ty, synonym) in synonyms {
+                    println!("{} = {}", ty, synonym)
+                },
+                OutputFormat::Json => print_json(&synonyms),
+            }
+        }
+
+        ReevesCmd::CreateIndex { name, normalization } => {
+            let db = reeves::open_db(&opt.db)?;
+            let index_db = reeves::create_index(&db, &name)?;
+            reeves::set_normalization(&index_db, &normalization)?;
+            match opt.output {
+                OutputFormat::Human => println!("created index {}, use it with --index {}", name, name),
+                OutputFormat::Json => print_json(&name),
+            }
+        }
+
+        ReevesCmd::SetNormalization { passes } => {
+            let db = open_db()?;
+            reeves::set_normalization(&db, &passes)?;
+            match opt.output {
+                OutputFormat::Human => println!("set normalization to {}", if passes.is_empty() { "none".to_owned() } else { passes.iter().map(|pass| pass.name()).collect::<Vec<_>>().join(", ") }),
+                OutputFormat::Json => print_json(&passes),
+            }
+        }
+
+        ReevesCmd::ListIndexes => {
+            let db = reeves::open_db(&opt.db)?;
+            let indexes = reeves::list_indexes(&db);
+            match opt.output {
+                OutputFormat::Human => {
+                    if indexes.is_empty() {
+                        println!("no named indexes")
+                    }
+                    for index in indexes {
+                        let passes: Vec<&str> = reeves::normalization(&reeves::open_index(&db, &index.name)?).into_iter().map(NormalizationPass::name).collect();
+                        match passes.is_empty() {
+                            true => println!("{} (created at {})", index.name, index.created_at),
+                            false => println!("{} (created at {}, normalizing with {})", index.name, index.created_at, passes.join(", ")),
+                        }
+                    }
+                },
+                OutputFormat::Json => print_json(&indexes),
+            }
+        }
+
+        ReevesCmd::DropIndex { name } => {
+            let db = reeves::open_db(&opt.db)?;
+            reeves::drop_index(&db, &name)?;
+            match opt.output {
+                OutputFormat::Human => println!("dropped index {}", name),
+                OutputFormat::Json => print_json(&name),
+            }
+        }
+
+        ReevesCmd::BenchDecode { max_fns, iterations } => {
+            let db = open_db()?;
+            let bench = reeves::bench_fn_decoding(&db, max_fns, cmp::max(iterations, 1));
+            match opt.output {
+                OutputFormat::Human => {
+                    println!("{} fns: full {:.2}ms, filter fields only {:.2}ms", bench.num_fns, bench.full_ms, bench.filter_ms)
+                },
+                OutputFormat::Json => print_json(&bench),
+            }
+        }
+
+        ReevesCmd::BenchSearch { queries, iterations } => {
+            let iterations = cmp::max(iterations, 1);
+            let db = open_db()?;
+            let mut results = vec![];
+            for query in queries {
+                let mut times = vec![];
+                let mut num_results = 0;
+                for _ in 0..iterations {
+                    let search_query = Query::parse(&query);
+                    // A fresh cache each time, so searches aren't just cache hits
+                    let cache = reeves::SearchCache::new();
+                    let start = Instant::now();
+                    num_results = reeves::search(&db, &cache, &search_query, &SearchOptions::default()).fndetails.len();
+                    times.push(start.elapsed());
+                }
+                times.sort();
+                let total: Duration = times.iter().sum();
+                results.push(BenchSearchOutput {
+                    query,
+                    num_results,
+                    min_ms: times[0].as_secs_f64() * 1000.,
+                    mean_ms: total.as_secs_f64() * 1000. / times.len() as f64,
+                    max_ms: times[times.len()-1].as_secs_f64() * 1000.,
+                })
+            }
+            match opt.output {
+                OutputFormat::Human => {
+                    for r in results {
+                        println!("{:?}: {} results, min {:.2}ms, mean {:.2}ms, max {:.2}ms", r.query, r.num_results, r.min_ms, r.mean_ms, r.max_ms)
+                    }
+                },
+                OutputFormat::Json => print_json(&results),
+            }
+        }
+
+        ReevesCmd::Purge { crate_name, crate_version } => {
+            let versions = match open_db_writer()? {
+                DbWriter::Local(db) => reeves::remove_crate(&db, &crate_name, crate_version.as_deref()),
+                DbWriter::Server(mut client) => client.remove_crate(&crate_name, crate_version.as_deref())?,
+            };
+            match opt.output {
+                OutputFormat::Human => {
+                    if versions.is_empty() {
+                        println!("no matching versions of {} found", crate_name)
+                    }
+                    for version in versions {
+                        println!("purged {} {}", crate_name, version)
+                    }
+                },
+                OutputFormat::Json => print_json(&versions),
+            }
+        }
+
+        ReevesCmd::Journal { crate_name, after } => {
+            let db = open_db()?;
+            let entries = reeves::journal(&db, crate_name.as_deref(), after);
+            match opt.output {
+                OutputFormat::Human => for entry in entries {
+                    let op = match entry.op { JournalOp::Add => "added", JournalOp::Purge => "purged" };
+                    println!("{} {} {} {} ({} fns) at {}, by reeves {} (schema {})", entry.id, op, entry.krate, entry.version,
+                             entry.num_fns, entry.timestamp, entry.tool_version, entry.schema_version)
+                },
+                OutputFormat::Json => print_json(&entries),
+            }
+        }
+
+        ReevesCmd::Rollback { crate_name, to_entry, analysis_opt } => {
+            // Adding versions back reads the analysis cache, which a server may not see
+            let db = match open_db_writer()? {
+                DbWriter::Local(db) => db,
+                DbWriter::Server(_) => bail!("rollback can't go through a server, stop it and run without --server"),
+            };
+            let res = reeves::rollback_crate(&db, &analysis_opt.to_options(), &crate_name, to_entry)?;
+            match opt.output {
+                OutputFormat::Human => {
+                    for version in res.purged.iter() {
+                        println!("purged {} {}", crate_name, version)
+                    }
+                    for version in res.restored.iter() {
+                        println!("restored {} {}", crate_name, version)
+                    }
+                    if res.purged.is_empty() && res.restored.is_empty() {
+                        println!("{} is already as it was after entry {}", crate_name, to_entry)
+                    }
+                },
+                OutputFormat::Json => print_json(&res),
+            }
+        }
+
+        ReevesCmd::Enqueue { crate_name, crate_version } => {
+            let res = match open_db_writer()? {
+                DbWriter::Local(db) => reeves::enqueue_analysis(&db, &crate_name, &crate_version),
+                DbWriter::Server(mut client) => client.enqueue_analysis(&crate_name, &crate_version)?,
             };
-            let db = reeves::open_db(&opt.db);
-            let fndetails = reeves::search(&db, Some(params_search), ret_search);
-            for fndetail in fndetails {
-                println!("res: {}", fndetail.s)
+            match opt.output {
+                OutputFormat::Human => match res {
+                    reeves::EnqueueResult::Queued => println!("queued {} {}", crate_name, crate_version),
+                    reeves::EnqueueResult::AlreadyQueued => println!("{} {} is already queued", crate_name, crate_version),
+                    reeves::EnqueueResult::AlreadyAnalyzed => println!("{} {} is already analyzed", crate_name, crate_version),
+                },
+                OutputFormat::Json => print_json(&res),
             }
         }
 
-        ReevesCmd::Serve { ip, port, static_tar } => {
-            let db = reeves::open_db(&opt.db);
+        ReevesCmd::JobStatus { crate_name, crate_version } => {
+            let db = open_db()?;
+            let records = match (crate_name, crate_version) {
+                (Some(name), Some(version)) => reeves::job_status(&db, &name, &version).into_iter().collect(),
+                (Some(name), None) => reeves::queued_jobs(&db).into_iter().filter(|r| r.name == name).collect(),
+                (None, _) => reeves::queued_jobs(&db),
+            };
+            match opt.output {
+                OutputFormat::Human => {
+                    if records.is_empty() {
+                        println!("no matching jobs")
+                    }
+                    for record in records {
+                        println!("{} {}: {:?}", record.name, record.version, record.status)
+                    }
+                },
+                OutputFormat::Json => print_json(&records),
+            }
+        }
+
+        ReevesCmd::Worker { workers, analysis_opt, container_opt } => {
+            let db = open_db()?;
+            let handles = cli_start_workers(&db, &opt.panamax_mirror, cmp::max(workers, 1), &analysis_opt, &container_opt);
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        }
+
+        ReevesCmd::Track { crate_name, purge_old } => {
+            let db = open_db()?;
+            let old_versions = if purge_old { reeves::OldVersions::Purge } else { reeves::OldVersions::Retain };
+            let is_new = reeves::track_crate(&db, &crate_name, old_versions);
+            match opt.output {
+                OutputFormat::Human => if is_new {
+                    println!("tracking {}", crate_name)
+                } else {
+                    println!("already tracking {}, updated what happens to old versions", crate_name)
+                },
+                OutputFormat::Json => print_json(&is_new),
+            }
+        }
+
+        ReevesCmd::Untrack { crate_name } => {
+            let db = open_db()?;
+            let was_tracked = reeves::untrack_crate(&db, &crate_name);
+            match opt.output {
+                OutputFormat::Human => if was_tracked {
+                    println!("stopped tracking {}", crate_name)
+                } else {
+                    println!("{} isn't tracked", crate_name)
+                },
+                OutputFormat::Json => print_json(&was_tracked),
+            }
+        }
+
+        ReevesCmd::ListTracked => {
+            let db = open_db()?;
+            let tracked = reeves::tracked_crates(&db);
+            match opt.output {
+                OutputFormat::Human => {
+                    if tracked.is_empty() {
+                        println!("no tracked crates")
+                    }
+                    for tracked in tracked {
+                        let outcome = tracked.last_outcome.map_or_else(|| "not checked yet".to_owned(), |outcome| format!("{:?}", outcome));
+                        println!("{} ({:?} old versions): {}", tracked.name, tracked.old_versions, outcome)
+                    }
+                },
+                OutputFormat::Json => print_json(&tracked),
+            }
+        }
+
+        ReevesCmd::Scheduler { interval, analysis_opt, container_opt } => {
+            let db = open_db()?;
+            let panamax_mirror_path = &opt.panamax_mirror;
+            info!("checking tracked crates every {}s", interval);
+            reeves::run_scheduler(&db, Duration::from_secs(interval), &reeves::crates_io_latest_version, &|name, version| {
+                container_analyze_crate(panamax_mirror_path, name, version, &analysis_opt, &container_opt)
+            })
+        }
+
+        ReevesCmd::Serve { ip, port, static_tar, admin_socket, compact_interval, warm_cache } => {
+            let db = open_db()?;
+            if let Some(admin_socket) = admin_socket {
+                reeves::serve_admin(db.clone(), &admin_socket)?
+            }
+            if let Some(compact_interval) = compact_interval {
+                let db = db.clone();
+                thread::spawn(move || reeves::run_compaction(&db, Duration::from_secs(compact_interval)));
+            }
+            if warm_cache {
+                let db = db.clone();
+                thread::spawn(move || reeves::warm_fn_set_cache(&db));
+            }
             let addr = format!("{}:{}", ip, port);
             server::serve(db, addr, static_tar)
         },
 
+        #[cfg(feature = "grpc")]
+        ReevesCmd::ServeGrpc { ip, port, workers, metrics_port, admin_socket, analysis_opt, container_opt } => {
+            let db = open_db()?;
+            if let Some(admin_socket) = admin_socket {
+                reeves::serve_admin(db.clone(), &admin_socket)?
+            }
+            if let Some(metrics_port) = metrics_port {
+                reeves::init_index_size_metrics(&db);
+                let metrics_addr = format!("{}:{}", ip, metrics_port);
+                thread::spawn(move || server::serve_metrics(metrics_addr));
+            }
+            if workers > 0 {
+                cli_start_workers(&db, &opt.panamax_mirror, workers, &analysis_opt, &container_opt);
+            }
+            let addr = format!("{}:{}", ip, port);
+            grpc::serve(db, addr)
+        },
+
         ReevesCmd::DebugDB => {
-            let db = reeves::open_db(&opt.db);
+            let db = open_db()?;
             reeves::debugdb(&db)
         }
 
+        ReevesCmd::ServeLsp => {
+            let db = open_db()?;
+            reeves::serve_lsp(db)?
+        },
+
+        ReevesCmd::GenerateSite { out_dir } => {
+            let db = open_db()?;
+            reeves::generate_site(&db, &out_dir)
+                .with_context(|| format!("failed to generate site in {}", out_dir.display()))?;
+            println!("generated site in {}", out_dir.display())
+        },
+
+        ReevesCmd::ExportCompactIndex { out } => {
+            let db = open_db()?;
+            let index = reeves::compact_index(&db);
+            fs::write(&out, index.to_bytes()).with_context(|| format!("failed to write {}", out.display()))?;
+            println!("exported {} fns to {}", index.fns.len(), out.display())
+        },
+
+        ReevesCmd::ExportSnapshot { out } => {
+            let db = open_db()?;
+            let snapshot = reeves::export_snapshot(&db);
+            fs::write(&out, &snapshot).with_context(|| format!("failed to write {}", out.display()))?;
+            println!("exported {} bytes to {}", snapshot.len(), out.display())
+        },
+
+        ReevesCmd::CompactDb => {
+            let stats = match open_db_writer()? {
+                DbWriter::Local(db) => reeves::compact_fn_sets(&db),
+                DbWriter::Server(mut client) => client.compact_fn_sets()?,
+            };
+            match opt.output {
+                OutputFormat::Human => println!(
+                    "compacted {} fn id sets from {} to {} bytes, removing {} stale fn ids and {} empty sets",
+                    stats.num_sets, stats.bytes_before, stats.bytes_after, stats.removed_fn_ids, stats.removed_sets,
+                ),
+                OutputFormat::Json => print_json(&stats),
+            }
+        },
+
+        ReevesCmd::DbStats => {
+            let stats = match open_db_writer()? {
+                DbWriter::Local(db) => reeves::db_stats(&db),
+                DbWriter::Server(mut client) => client.db_stats()?,
+            };
+            match opt.output {
+                OutputFormat::Human => for tree in stats.trees {
+                    println!("{}: {} keys, {} key bytes, {} value bytes", tree.name, tree.num_keys, tree.key_bytes, tree.value_bytes);
+                    for (key, num_fns) in tree.largest_sets {
+                        println!("  {} fns: {}", num_fns, key)
+                    }
+                },
+                OutputFormat::Json => print_json(&stats),
+            }
+        },
+
+        ReevesCmd::Report { limit } => {
+            let db = open_db()?;
+            let report = reeves::report(&db, limit);
+            match opt.output {
+                OutputFormat::Human => {
+                    println!("{} fns in {} crate versions", report.num_fns, report.num_crates);
+                    println!("largest crates:");
+                    for (name, version, num_fns) in report.largest_crates.iter() {
+                        println!("  {} fns: {} {}", num_fns, name, version)
+                    }
+                    for (what, tys) in [("param types", &report.param_types), ("ret types", &report.ret_types), ("produced but not consumed", &report.produced_not_consumed)].iter() {
+                        println!("{}:", what);
+                        for (ty, num_fns) in tys.iter() {
+                            println!("  {} fns: {}", num_fns, ty)
+                        }
+                    }
+                },
+                OutputFormat::Json => print_json(&report),
+            }
+        },
+
     }
 
     Ok(())
 }
 
+// Progress through a phase is shown on a single line, which gets overwritten
+fn cli_progress(progress: reeves::Progress) {
+    match progress {
+        reeves::Progress::Phase(reeves::Phase::Done) => eprintln!(),
+        reeves::Progress::Phase(phase) => eprintln!("\n== {:?}", phase),
+        reeves::Progress::Discovered { .. } => (),
+        reeves::Progress::Items { done, total, eta } => {
+            let eta = eta.map_or_else(|| "?".to_owned(), |eta| format!("{}s", eta.as_secs()));
+            eprint!("\r{}/{} (eta {})   ", done, total, eta)
+        },
+    }
+}
+
 #[derive(Debug)]
 struct CratesProgressCounter {
     errored: usize,
@@ -239,16 +1637,18 @@ struct CratesProgressCounter {
     total: usize,
 }
 
-fn cli_container_parallel_process_crates(db: &sled::Db, panamax_mirror_path: &Path, crates: &mut dyn ExactSizeIterator<Item=(String, String)>) {
+fn cli_container_parallel_process_crates(db: &Db, panamax_mirror_path: &Path, crates: &mut dyn ExactSizeIterator<Item=(String, String)>, analysis_opt: &AnalysisOpt, container_opt: &ContainerOpt) {
     let count = Mutex::new(CratesProgressCounter { errored: 0, processed: 0, total: crates.len() });
     let pool = ThreadPool::new().unwrap();
     // TODO: stop iteration on panic or report somehow?
     let mut futs: FuturesUnordered<_> = crates.into_iter()
         .map(|(name, version)| {
             let panamax_mirror_path = panamax_mirror_path.to_owned();
+            let analysis_opt = analysis_opt.clone();
+            let container_opt = container_opt.clone();
             pool.spawn_with_handle(futures::future::lazy(move |_| {
                 info!("analyzing crate {}-{}", name, version);
-                let res = container_analyze_crate(&panamax_mirror_path, &name, &version);
+                let res = container_analyze_crate(&panamax_mirror_path, &name, &version, &analysis_opt, &container_opt);
                 ((name, version), res)
             })).unwrap()
         })
@@ -261,15 +1661,37 @@ fn cli_container_parallel_process_crates(db: &sled::Db, panamax_mirror_path: &Pa
     info!("finished: {:?}", count);
 }
 
-fn cli_finish_and_save_analysis(db: &sled::Db, res: Result<Either<Vec<FnDetail>, String>>, name: &str, version: &str, count: &Mutex<CratesProgressCounter>) {
+// Workers run until the process exits
+fn cli_start_workers(db: &Db, panamax_mirror_path: &Path, workers: usize, analysis_opt: &AnalysisOpt, container_opt: &ContainerOpt) -> Vec<thread::JoinHandle<()>> {
+    let num_requeued = reeves::requeue_interrupted_jobs(db);
+    if num_requeued > 0 {
+        info!("requeued {} interrupted jobs", num_requeued)
+    }
+    info!("starting {} workers", workers);
+    (0..workers)
+        .map(|_| {
+            let db = db.clone();
+            let panamax_mirror_path = panamax_mirror_path.to_owned();
+            let analysis_opt = analysis_opt.clone();
+            let container_opt = container_opt.clone();
+            thread::spawn(move || {
+                reeves::run_worker(&db, &|name, version| {
+                    container_analyze_crate(&panamax_mirror_path, name, version, &analysis_opt, &container_opt)
+                })
+            })
+        })
+        .collect()
+}
+
+fn cli_finish_and_save_analysis(db: &Db, res: Result<(CrateMeta, Either<Vec<FnDetail>, String>)>, name: &str, version: &str, count: &Mutex<CratesProgressCounter>) {
     info!("analyzing crate {}-{}", name, version);
     match res {
-        Ok(Either::Left(fndetails)) => {
+        Ok((meta, Either::Left(fndetails))) => {
             info!("finished analysing functions for {} {}, inserting {} function details into db",
                   name, version, fndetails.len());
-            reeves::save_analysis(db, &name, &version, fndetails);
+            reeves::save_analysis(db, &name, &version, &meta, fndetails);
         },
-        Ok(Either::Right(err)) => {
+        Ok((_, Either::Right(err))) => {
             warn!("analysis reported error for {} {}, saving to db", name, version);
             reeves::save_analysis_error(db, &name, &version, &err);
         },
@@ -291,7 +1713,7 @@ fn cli_finish_and_save_analysis(db: &sled::Db, res: Result<Either<Vec<FnDetail>,
     }
 }
 
-fn container_analyze_crate(panamax_mirror_path: &Path, crate_name: &str, crate_version: &str) -> Result<Either<Vec<FnDetail>, String>> {
+fn container_analyze_crate(panamax_mirror_path: &Path, crate_name: &str, crate_version: &str, analysis_opt: &AnalysisOpt, container_opt: &ContainerOpt) -> Result<(CrateMeta, Either<Vec<FnDetail>, String>)> {
     let crate_tar_path = crate_to_tar_path(panamax_mirror_path, crate_name, crate_version);
     let crate_tar_path = crate_tar_path.to_str().unwrap(); // where the crate tar currently is
     let crate_path = format!("{}/{}-{}", CRATE_WORK_DIR, crate_name, crate_version); // where it will get extracted to
@@ -308,30 +1730,38 @@ fn container_analyze_crate(panamax_mirror_path: &Path, crate_name: &str, crate_v
         bail!("failed to create extracted crate")
     }
 
-    let res = container_analyze_crate_path(crate_path.as_ref());
+    // Parsing the manifest doesn't run any of the crate's code, so doesn't need the container
+    let meta = reeves::read_crate_meta(crate_path.as_ref(), crate_name, crate_version);
+    let res = container_analyze_crate_path(crate_path.as_ref(), analysis_opt, container_opt);
     fs::remove_dir_all(crate_path).unwrap();
 
     let res = res.context("failed to analyze crate")?;
     assert_eq!((crate_name, crate_version), (res.crate_name.as_str(), res.crate_version.as_str()));
 
-    Ok(res.res)
+    Ok((meta, res.res))
 }
 
-fn container_analyze_crate_path(path: &Path) -> Result<AnalyzeAndPrintOutput> {
-    const OUTPUT_LIMIT: usize = 500;
-    fn snip_output(mut s: &[u8]) -> String {
-        let mut didsnip = false;
-        if s.len() > OUTPUT_LIMIT {
-            s = &s[..OUTPUT_LIMIT];
-            didsnip = true;
-        }
-        let mut out = String::from_utf8_lossy(s).into_owned();
-        if didsnip {
-            out.push_str("[...snipped...]");
-        }
-        out
+const ANALYSIS_OUTPUT_LIMIT: usize = 500;
+// What `timeout` exits with when the command times out
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+fn snip_output(mut s: &[u8]) -> String {
+    let mut didsnip = false;
+    if s.len() > ANALYSIS_OUTPUT_LIMIT {
+        s = &s[..ANALYSIS_OUTPUT_LIMIT];
+        didsnip = true;
+    }
+    let mut out = String::from_utf8_lossy(s).into_owned();
+    if didsnip {
+        out.push_str("[...snipped...]");
     }
+    out
+}
 
+fn container_analyze_crate_path(path: &Path, analysis_opt: &AnalysisOpt, container_opt: &ContainerOpt) -> Result<AnalyzeAndPrintOutput> {
+    if container_opt.subprocess {
+        return subprocess_analyze_crate_path(path, analysis_opt, container_opt)
+    }
     let cwd = env::current_dir().unwrap();
     let cwd = cwd.to_str().unwrap();
 
@@ -361,12 +1791,47 @@ fn container_analyze_crate_path(path: &Path) -> Result<AnalyzeAndPrintOutput> {
         .args(&["-e=RUSTUP_HOME=/work/rustup", "-e=CARGO_HOME=/work/cargo"])
         // Custom
         .args(&["-w=/work", "--net=none"])
+        .args(container_opt.memory_limit_mb.map(|mb| format!("--memory={}m", mb)))
         .args(&["-v", &format!("{}:/reeves:ro", &env::current_exe().unwrap().to_str().unwrap())])
         // Command
         .args(&["ubuntu:20.04", "bash", "-c"])
-        .arg(format!("PATH=$PATH:/work/cargo/bin /reeves --rust-analyzer /work/rust-analyzer {} /crate", ANALYZE_AND_PRINT_COMMAND))
+        .arg(format!("PATH=$PATH:/work/cargo/bin timeout {} /reeves --rust-analyzer /work/rust-analyzer {} /crate {}",
+                     container_opt.timeout_secs, ANALYZE_AND_PRINT_COMMAND, analysis_opt.to_args().join(" ")))
+        .output().unwrap();
+
+    read_analysis_output(path, res, container_opt)
+}
+
+// Re-executes reeves to analyze the crate, so rust-analyzer panicking, hanging or running out of memory
+// (limited with `prlimit`) only loses this crate. Unlike in a container, build scripts and proc macros
+// can do anything reeves can
+fn subprocess_analyze_crate_path(path: &Path, analysis_opt: &AnalysisOpt, container_opt: &ContainerOpt) -> Result<AnalyzeAndPrintOutput> {
+    // Published crates don't include a lockfile for libraries
+    let res = Command::new("cargo").arg("generate-lockfile").current_dir(path).output().unwrap();
+    if !res.status.success() {
+        bail!("failed to prep for analysis {}:\n====\n{}\n====", path.display(), snip_output(&res.stderr))
+    }
+
+    let mut cmd = Command::new("timeout");
+    cmd.arg(container_opt.timeout_secs.to_string());
+    if let Some(mb) = container_opt.memory_limit_mb {
+        cmd.arg("prlimit").arg(format!("--as={}", mb * 1024 * 1024)).arg("--");
+    }
+    let res = cmd.arg(env::current_exe().unwrap())
+        .arg("--rust-analyzer").arg(env::var_os(ENV_RUST_ANALYZER_BINARY).unwrap())
+        .arg(ANALYZE_AND_PRINT_COMMAND).arg(path)
+        .args(analysis_opt.to_args())
+        // The child is reeves, not rust-analyzer, see ENV_RUST_ANALYZER_EXEC
+        .env_remove(ENV_RUST_ANALYZER_EXEC)
         .output().unwrap();
 
+    read_analysis_output(path, res, container_opt)
+}
+
+fn read_analysis_output(path: &Path, res: process::Output, container_opt: &ContainerOpt) -> Result<AnalyzeAndPrintOutput> {
+    if res.status.code() == Some(TIMEOUT_EXIT_CODE) {
+        bail!("timed out analyzing {} after {}s", path.display(), container_opt.timeout_secs)
+    }
     if !res.status.success() {
         bail!("failed to analyze {}:\n====\n{}\n====\n{}\n====", path.display(), snip_output(&res.stdout), snip_output(&res.stderr))
     }
@@ -374,8 +1839,8 @@ fn container_analyze_crate_path(path: &Path) -> Result<AnalyzeAndPrintOutput> {
     match serde_json::from_slice(&res.stdout) {
         Ok(r) => Ok(r),
         Err(e) => {
-            bail!("failed to deserialize output from analysis in container: {}\n====\n{}\n====",
-                   e, String::from_utf8_lossy(&res.stdout[..cmp::min(res.stdout.len(), OUTPUT_LIMIT)]))
+            bail!("failed to deserialize output from analysis: {}\n====\n{}\n====",
+                   e, String::from_utf8_lossy(&res.stdout[..cmp::min(res.stdout.len(), ANALYSIS_OUTPUT_LIMIT)]))
         },
     }
 }
@@ -0,0 +1,74 @@
+use lazy_static::lazy_static;
+use prometheus::{Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder};
+use prometheus::{exponential_buckets, register_histogram, register_histogram_vec, register_int_counter_vec, register_int_gauge};
+use std::time::Instant;
+use tracing::debug_span;
+use tracing::span::EnteredSpan;
+
+// Metrics are process-wide, and only exposed when running a server (see `render_metrics`)
+lazy_static! {
+    pub(crate) static ref SEARCH_SECONDS: Histogram = register_histogram!(
+        "reeves_search_seconds", "Time to perform a search, including fetching the page of results",
+        exponential_buckets(0.001, 2., 15).unwrap()
+    ).unwrap();
+    // Where slow searches spend their time, see `span`
+    pub(crate) static ref SEARCH_PHASE_SECONDS: HistogramVec = register_histogram_vec!(
+        "reeves_search_phase_seconds", "Time spent in each phase of a search", &["phase"],
+        exponential_buckets(0.0001, 2., 18).unwrap()
+    ).unwrap();
+    pub(crate) static ref SEARCH_CANDIDATES: HistogramVec = register_histogram_vec!(
+        "reeves_search_candidates", "Fuzzy candidates for each part of a search", &["column"],
+        exponential_buckets(1., 2., 10).unwrap()
+    ).unwrap();
+    pub(crate) static ref SEARCH_LOADED_FNS: Histogram = register_histogram!(
+        "reeves_search_loaded_fns", "Fn details loaded from the DB to check against filters, per search pass",
+        exponential_buckets(1., 2., 14).unwrap()
+    ).unwrap();
+    pub(crate) static ref TEXT_SEARCH_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "reeves_text_search_requests_total", "Round trips to the text search, by whether they succeeded", &["outcome"]
+    ).unwrap();
+    pub(crate) static ref TEXT_SEARCH_SECONDS: Histogram = register_histogram!(
+        "reeves_text_search_seconds", "Time for a round trip to the text search",
+        exponential_buckets(0.0005, 2., 15).unwrap()
+    ).unwrap();
+    pub(crate) static ref DB_READ_SECONDS: HistogramVec = register_histogram_vec!(
+        "reeves_db_read_seconds", "Time to read from the DB, by operation", &["op"],
+        exponential_buckets(0.00001, 2., 18).unwrap()
+    ).unwrap();
+    // Set when a server starts (see `set_index_size`) and kept up to date as crates are added and purged
+    pub(crate) static ref INDEXED_CRATES: IntGauge = register_int_gauge!(
+        "reeves_indexed_crates", "Crate versions with analyzed fns in the DB"
+    ).unwrap();
+    pub(crate) static ref INDEXED_FNS: IntGauge = register_int_gauge!(
+        "reeves_indexed_fns", "Fns in the DB"
+    ).unwrap();
+}
+
+// Times a phase of a search until dropped
+pub(crate) struct Span {
+    phase: &'static str,
+    start: Instant,
+    _entered: EnteredSpan, // so the phase shows up in traces too
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        SEARCH_PHASE_SECONDS.with_label_values(&[self.phase]).observe(self.start.elapsed().as_secs_f64())
+    }
+}
+
+pub(crate) fn span(phase: &'static str) -> Span {
+    Span { phase, start: Instant::now(), _entered: debug_span!("search_phase", phase).entered() }
+}
+
+pub(crate) fn set_index_size(num_crates: usize, num_fns: usize) {
+    INDEXED_CRATES.set(num_crates as i64);
+    INDEXED_FNS.set(num_fns as i64);
+}
+
+// In the Prometheus text format, for serving at `/metrics`
+pub fn render_metrics() -> String {
+    let mut buf = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
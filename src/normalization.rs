@@ -0,0 +1,44 @@
+use anyhow::{Result, bail};
+
+use reeves_types::*;
+
+use crate::{CRATE_TREE, Db, PARAM_TREE, PENDING_CRATE_TREE, RET_TREE};
+
+// How the index canonicalizes param and ret types beyond what every index does, as a pipeline of
+// passes (see `NormalizationPass`), e.g. so one index finds `&Path` and `PathBuf` alike while another
+// keeps them apart. Searches normalize queries the same way. Each index has its own, and it can only
+// be set while the index is empty, as the keys of fns already indexed were built without it
+const NORMALIZATION_TREE: &str = "normalization"; // NORMALIZATION_KEY => bincode::serialize(Vec<NormalizationPass>)
+const NORMALIZATION_KEY: &[u8] = b"passes";
+
+pub fn set_normalization(db: &Db, passes: &[NormalizationPass]) -> Result<()> {
+    if db.open_tree(CRATE_TREE).iter().next().is_some() || db.open_tree(PENDING_CRATE_TREE).iter().next().is_some() {
+        bail!("the index already has crates, normalization can only be set on an empty index")
+    }
+    db.open_tree(NORMALIZATION_TREE).insert(NORMALIZATION_KEY, bincode::serialize(passes).unwrap());
+    Ok(())
+}
+
+// Empty if the index only drops lifetimes, as every index does
+pub fn normalization(db: &Db) -> Vec<NormalizationPass> {
+    db.open_tree(NORMALIZATION_TREE).get(NORMALIZATION_KEY)
+        .map_or_else(Vec::new, |bytes| bincode::deserialize(&bytes).unwrap())
+}
+
+// Whether keys of the tree are normalized - only the param and ret types, which are searched for
+// exactly. Other trees (e.g. of params without references) are alternatives a search can fall back to
+pub(crate) fn is_normalized_tree(tree_name: &str) -> bool {
+    tree_name == PARAM_TREE || tree_name == RET_TREE
+}
+
+pub(crate) fn normalize_keys(passes: &[NormalizationPass], keys: Vec<String>) -> Vec<String> {
+    if passes.is_empty() {
+        return keys
+    }
+    let mut normalized = vec![];
+    for key in keys {
+        let key = normalize_type(&key, passes);
+        if !normalized.contains(&key) { normalized.push(key) }
+    }
+    normalized
+}
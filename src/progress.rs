@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    LoadingWorkspace,
+    AnalyzingItems,
+    LoadingTextIndex(&'static str), // which index
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub enum Progress {
+    Phase(Phase),
+    // Running total of fns found so far during analysis
+    Discovered { fns: usize },
+    // Progress through the current phase
+    Items { done: usize, total: usize, eta: Option<Duration> },
+}
+
+pub trait ProgressSink {
+    fn report(&self, progress: Progress);
+}
+
+impl<F: Fn(Progress)> ProgressSink for F {
+    fn report(&self, progress: Progress) {
+        self(progress)
+    }
+}
+
+// For when nobody is interested
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn report(&self, _progress: Progress) {}
+}
+
+// Tracks progress through a phase, estimating time remaining from the rate so far
+pub(crate) struct ItemsTracker<'a> {
+    sink: &'a dyn ProgressSink,
+    start: Instant,
+    total: usize,
+}
+
+impl<'a> ItemsTracker<'a> {
+    pub(crate) fn start(sink: &'a dyn ProgressSink, phase: Phase, total: usize) -> Self {
+        sink.report(Progress::Phase(phase));
+        Self { sink, start: Instant::now(), total }
+    }
+
+    pub(crate) fn update(&self, done: usize) {
+        let eta = if done == 0 {
+            None
+        } else {
+            let remaining = self.total.saturating_sub(done) as u32;
+            Some(self.start.elapsed() / done as u32 * remaining)
+        };
+        self.sink.report(Progress::Items { done, total: self.total, eta })
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Result;
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use std::env;
+use std::path::PathBuf;
+
+use reeves::{Db, SavedQuery, SearchCache};
+use reeves_types::*;
+
+// Results shown at a time, `next` shows the page after
+const PAGE_SIZE: usize = 20;
+// In the home directory, so history is kept across sessions
+const HISTORY_FILE: &str = ".reeves_history";
+
+const HELP: &str = "\
+  <query>             search for a new signature, e.g. '&str, usize -> Option<char>' or 'Vec<u8> . ? -> String'
+  param + <type>      also require a param of the type
+  param - <type>      stop requiring a param of the type
+  params <params>     require exactly these params, e.g. '&str, usize', '()' for none or '*' for any
+  ret <type>          require the ret type, e.g. 'Result<_, io::Error>' ('*' for any)
+  receiver <type>     require the first param to be the type, as a method call would ('*' for any)
+  impl <trait>        require the ret type to implement the trait ('*' for any)
+  name <text>         require a path like the text ('*' for any)
+  doc <text>          require docs like the text ('*' for any)
+  crate <name>        only search this crate as well as any others given, e.g. 'tokio' or 'tokio@^1'
+  crate - <name>      stop searching only within the crate ('*' for all crates)
+  next                show the next page of results
+  show                show the query being refined
+  reset               start again from an empty query
+  help                show this
+  quit                exit (or ctrl-D)";
+
+// What the line asked for, once the query has been refined by it
+enum Action {
+    Search,
+    NextPage,
+    Show,
+    Help,
+    Quit,
+}
+
+// Colors, left out with NO_COLOR set (see https://no-color.org)
+struct Style {
+    bold: &'static str,
+    dim: &'static str,
+    crate_: &'static str,
+    warning: &'static str,
+    reset: &'static str,
+}
+
+impl Style {
+    fn from_env() -> Self {
+        match env::var_os("NO_COLOR") {
+            Some(_) => Style { bold: "", dim: "", crate_: "", warning: "", reset: "" },
+            None => Style { bold: "\x1b[1m", dim: "\x1b[2m", crate_: "\x1b[36m", warning: "\x1b[33m", reset: "\x1b[0m" },
+        }
+    }
+}
+
+// Reads lines refining a query, searching after each change and showing results a page at a time.
+// `filters` are what the query starts with (e.g. from the command line), and are kept by `reset`
+pub fn run_repl(db: &Db, filters: SearchFilters, render_options: &RenderOptions, imports: bool) -> Result<()> {
+    let mut editor = Editor::<()>::new();
+    let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(path) = history_path.as_ref() {
+        // Not there on the first run
+        let _ = editor.load_history(path);
+    }
+    let style = Style::from_env();
+    let cache = SearchCache::new();
+    let initial = Query { filters, ..Query::default() };
+    let mut query = initial.clone();
+    let mut next_cursor: Option<String> = None;
+    println!("type a query to search, then refine it - 'help' lists the commands");
+    loop {
+        let line = match editor.readline("reeves> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        editor.add_history_entry(line);
+        if line == "reset" {
+            query = initial.clone();
+            next_cursor = None;
+            continue
+        }
+        match refine(&mut query, line) {
+            Ok(Action::Search) => next_cursor = show_page(db, &cache, &query, None, render_options, imports, &style),
+            Ok(Action::NextPage) => match next_cursor.take() {
+                Some(cursor) => next_cursor = show_page(db, &cache, &query, Some(cursor), render_options, imports, &style),
+                None => println!("no more results"),
+            },
+            Ok(Action::Show) => println!("{}", describe(&query)),
+            Ok(Action::Help) => println!("{}", HELP),
+            Ok(Action::Quit) => break,
+            Err(msg) => eprintln!("{}{}{}", style.warning, msg, style.reset),
+        }
+    }
+    if let Some(path) = history_path.as_ref() {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+fn refine(query: &mut Query, line: &str) -> Result<Action, String> {
+    let (command, arg) = match line.split_once(' ') {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+    // `*` clears a part of the query
+    let opt = |arg: &str| Some(arg.to_owned()).filter(|arg| arg != "*");
+    match command {
+        "param" => {
+            let (op, ty) = arg.split_at(arg.find(|c: char| c != '+' && c != '-').unwrap_or(arg.len()));
+            let ty = TypeRepr::parse(ty.trim());
+            let params = query.params.get_or_insert_with(Vec::new);
+            match op.trim() {
+                "+" => params.push(ty),
+                "-" => match params.iter().position(|param| *param == ty) {
+                    Some(i) => { params.remove(i); },
+                    None => return Err(format!("the query has no param {}", ty)),
+                },
+                _ => return Err("expected 'param + <type>' or 'param - <type>'".to_owned()),
+            }
+        },
+        "params" => {
+            let parsed = Query::parse(&format!("{} -> *", arg));
+            query.params = parsed.params;
+            query.filters.min_params = parsed.filters.min_params;
+            query.filters.max_params = parsed.filters.max_params;
+        },
+        "ret" => query.ret = opt(arg).map(|ret| TypeRepr::parse(&ret)),
+        "receiver" => {
+            query.receiver = opt(arg).map(|receiver| TypeRepr::parse(&receiver));
+            query.autoref = query.receiver.is_some();
+        },
+        "impl" => query.ret_impl = opt(arg),
+        "name" => query.name = opt(arg),
+        "doc" => query.doc = opt(arg),
+        "crate" => match arg.strip_prefix('-').map(str::trim) {
+            _ if arg == "*" => query.filters.crates = None,
+            Some(krate) => {
+                let crates = query.filters.crates.get_or_insert_with(Vec::new);
+                match crates.iter().position(|spec| spec == krate) {
+                    Some(i) => { crates.remove(i); },
+                    None => return Err(format!("not only searching crate {}", krate)),
+                }
+                if crates.is_empty() {
+                    query.filters.crates = None
+                }
+            },
+            None if arg.is_empty() => return Err("expected 'crate <name>'".to_owned()),
+            None => query.filters.crates.get_or_insert_with(Vec::new).push(arg.to_owned()),
+        },
+        "next" | "n" => return Ok(Action::NextPage),
+        "show" => return Ok(Action::Show),
+        "help" | "?" => return Ok(Action::Help),
+        "quit" | "exit" | "q" => return Ok(Action::Quit),
+        // Anything else is a whole new signature, keeping the rest of the query
+        _ => {
+            let parsed = Query::parse(line);
+            query.params = parsed.params;
+            query.ret = parsed.ret;
+            query.receiver = parsed.receiver;
+            query.autoref = parsed.autoref;
+            query.filters.min_params = parsed.filters.min_params;
+            query.filters.max_params = parsed.filters.max_params;
+        },
+    }
+    Ok(Action::Search)
+}
+
+fn describe(query: &Query) -> String {
+    let mut parts = vec![SavedQuery::from_query(query).query()];
+    if let Some(receiver) = query.receiver.as_ref() {
+        parts.push(format!("receiver {}", receiver))
+    }
+    if let Some(ret_impl) = query.ret_impl.as_ref() {
+        parts.push(format!("impl {}", ret_impl))
+    }
+    if let Some(name) = query.name.as_ref() {
+        parts.push(format!("name {}", name))
+    }
+    if let Some(doc) = query.doc.as_ref() {
+        parts.push(format!("doc {}", doc))
+    }
+    if let Some(crates) = query.filters.crates.as_ref() {
+        parts.push(format!("crates {}", crates.join(", ")))
+    }
+    parts.join("; ")
+}
+
+// Returns the cursor of the page after, if there is one
+fn show_page(db: &Db, cache: &SearchCache, query: &Query, cursor: Option<String>, render_options: &RenderOptions, imports: bool, style: &Style) -> Option<String> {
+    let first_page = cursor.is_none();
+    let options = SearchOptions { offset: 0, limit: Some(PAGE_SIZE), cursor, explain: false, group_by: None, ordered: false, facets: false };
+    let results = reeves::search(db, cache, query, &options);
+    if first_page {
+        reeves::record_search(db, &SavedQuery::from_query(query), results.fndetails.len())
+    }
+    for warning in results.warnings.iter() {
+        println!("{}warning: {}{}", style.warning, warning, style.reset)
+    }
+    if results.fndetails.is_empty() && first_page {
+        println!("no results for {}", describe(query))
+    }
+    for suggestion in results.suggestions.iter() {
+        println!("{}did you mean {} for {}?{}", style.warning, suggestion.suggestions.join(", "), suggestion.ty, style.reset)
+    }
+    for fndetail in results.fndetails.iter() {
+        println!("{}[{} {}]{} {}{}{}", style.crate_, fndetail.krate, fndetail.version, style.reset,
+                 style.bold, render_signature(fndetail, render_options), style.reset);
+        if imports {
+            println!("    {}{}{}", style.dim, use_line(fndetail), style.reset)
+        }
+        if let Some(doc) = fndetail.doc.as_ref() {
+            println!("    {}{}{}", style.dim, doc, style.reset)
+        }
+    }
+    if results.next_cursor.is_some() {
+        println!("{}('next' for more){}", style.dim, style.reset)
+    }
+    results.next_cursor
+}
@@ -0,0 +1,55 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+
+use crate::{CONSUMES_TREE, CRATE_TREE, Db, PARAM_TREE, PRODUCES_TREE, RET_TREE, parse_crate_key};
+use crate::fnidset::decode_fn_ids;
+
+// What's in the index as a whole, e.g. for a dashboard. Fn counts of types come from the fn id sets,
+// so may include fns of crates purged since they were last compacted (see `compact_fn_sets`)
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub num_crates: usize, // crate versions
+    pub num_fns: usize,
+    // Each the first `limit`, most fns first
+    pub param_types: Vec<(String, u64)>, // (type, num fns taking it)
+    pub ret_types: Vec<(String, u64)>, // (type, num fns returning it)
+    pub largest_crates: Vec<(String, String, usize)>, // (name, version, num fns)
+    // Named types fns return (anywhere in their ret) but no fn takes, most producers first - often
+    // types only useful through their traits, or a sign of crates missing from the index
+    pub produced_not_consumed: Vec<(String, u64)>, // (type name, num fns producing it)
+}
+
+pub fn report(db: &Db, limit: usize) -> Report {
+    let largest = |tree_name: &str, filter: &dyn Fn(&[u8]) -> bool| -> Vec<(String, u64)> {
+        let mut sizes: Vec<(String, u64)> = db.open_tree(tree_name).iter()
+            .filter(|(key, _)| filter(key))
+            .map(|(key, val)| (String::from_utf8_lossy(&key).into_owned(), decode_fn_ids(&val).len()))
+            .collect();
+        sizes.sort_by_key(|(key, num_fns)| (Reverse(*num_fns), key.clone()));
+        sizes.truncate(limit);
+        sizes
+    };
+
+    let mut largest_crates: Vec<(String, String, usize)> = db.open_tree(CRATE_TREE).iter()
+        .map(|(key, val)| {
+            let (name, version) = parse_crate_key(&key);
+            let fn_ids: Vec<u64> = bincode::deserialize(&val).unwrap();
+            (name.to_owned(), version.to_owned(), fn_ids.len())
+        })
+        .collect();
+    let num_crates = largest_crates.len();
+    let num_fns = largest_crates.iter().map(|(_, _, num_fns)| num_fns).sum();
+    largest_crates.sort_by(|(n1, v1, f1), (n2, v2, f2)| f2.cmp(f1).then_with(|| (n1, v1).cmp(&(n2, v2))));
+    largest_crates.truncate(limit);
+
+    let consumes_tree = db.open_tree(CONSUMES_TREE);
+    Report {
+        num_crates,
+        num_fns,
+        param_types: largest(PARAM_TREE, &|_| true),
+        ret_types: largest(RET_TREE, &|_| true),
+        largest_crates,
+        produced_not_consumed: largest(PRODUCES_TREE, &|key| !consumes_tree.contains_key(key)),
+    }
+}
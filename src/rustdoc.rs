@@ -0,0 +1,797 @@
+// Analysis from rustdoc's JSON output (`--output-format json`), as an alternative to loading the crate
+// into rust-analyzer - generating it needs a nightly toolchain and a build of the crate's deps, but it
+// copes with crates rust-analyzer struggles to load, and JSON built elsewhere (e.g. downloaded from
+// docs.rs) can be indexed without building anything locally. Types are as rustdoc describes them,
+// which is close to but not quite how rust-analyzer displays them - e.g. defaulted generic args
+// aren't filled in, so `HashMap<K, V>` rather than `HashMap<K, V, RandomState>`
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use tracing::{debug, info, warn};
+
+use reeves_types::*;
+
+use crate::{ABORT_POLL_INTERVAL, AnalysisCancelled, AnalysisOptions, FeatureSelection, ItemSelection, attr_arg, conversion_arg, fn_signature, impl_trait_str, new_crate_meta, with_profile};
+use crate::cancel::Abort;
+use crate::progress::{ItemsTracker, Phase, Progress, ProgressSink};
+
+// Older formats tag types and items differently, and name a lot of fields differently
+const MIN_FORMAT_VERSION: u64 = 30;
+const RUSTDOC_TOOLCHAIN: &str = "+nightly";
+
+// As for `crate::analyze_crate_path`. Cargo runs build scripts and proc macros to document a crate,
+// so this is only as safe as analyzing with those enabled
+pub fn analyze_crate_path(path: &Path, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> (String, String, Result<Vec<FnDetail>>) {
+    let (krate_name, krate_version) = match manifest_name_version(path) {
+        Ok(name_version) => name_version,
+        Err(e) => {
+            let dir_name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+            return (dir_name, String::new(), Err(e))
+        },
+    };
+    if let FeatureSelection::PerFeature = opts.features {
+        warn!("per-feature analysis is not supported from rustdoc JSON, using default features")
+    }
+    if opts.bins {
+        warn!("binaries and examples can't be analyzed from rustdoc JSON, only analyzing the lib")
+    }
+    let abort = Abort::start(&opts.cancel, opts.timeout);
+    let res = run_rustdoc(path, opts, &abort, progress)
+        .and_then(|json| analyze_json(&json, &krate_name, &krate_version, opts.items(), &abort, progress));
+    progress.report(Progress::Phase(Phase::Done));
+    (krate_name, krate_version, res)
+}
+
+// Analyze rustdoc JSON generated elsewhere, e.g. as downloaded from docs.rs - optionally gzipped.
+// Errors reading the file are returned in the outer result, analysis errors in the inner one (as for
+// `analyze_from_tarball`)
+pub fn analyze_rustdoc_json(path: &Path, krate_name: &str, krate_version: &str, opts: &AnalysisOptions, progress: &dyn ProgressSink) -> Result<(CrateMeta, Result<Vec<FnDetail>>)> {
+    let mut file = fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut json = vec![];
+    if path.extension().map_or(false, |ext| ext == "gz") {
+        flate2::read::GzDecoder::new(file).read_to_end(&mut json)
+    } else {
+        file.read_to_end(&mut json)
+    }.with_context(|| format!("failed to read {}", path.display()))?;
+    let abort = Abort::start(&opts.cancel, opts.timeout);
+    // Generated for whatever the options say
+    let fndetails = analyze_json(&json, krate_name, krate_version, opts.items(), &abort, progress)
+        .map(|fndetails| with_profile(fndetails, &opts.profile));
+    progress.report(Progress::Phase(Phase::Done));
+    Ok((new_crate_meta(krate_name, krate_version, None, None), fndetails))
+}
+
+fn manifest_name_version(path: &Path) -> Result<(String, String)> {
+    #[derive(Deserialize)]
+    struct Manifest {
+        package: ManifestPackage,
+    }
+    #[derive(Deserialize)]
+    struct ManifestPackage {
+        name: String,
+        version: toml::Value, // may be inherited from the workspace, which isn't supported
+    }
+    let manifest_path = path.join("Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path).with_context(|| format!("failed to read {}", manifest_path.display()))?;
+    let package = toml::from_str::<Manifest>(&manifest)?.package;
+    match package.version.as_str() {
+        Some(version) => Ok((package.name, version.to_owned())),
+        None => bail!("unsupported version in {}: {}", manifest_path.display(), package.version),
+    }
+}
+
+// Generate the JSON into a temporary target dir, returning its contents
+fn run_rustdoc(path: &Path, opts: &AnalysisOptions, abort: &Abort, progress: &dyn ProgressSink) -> Result<Vec<u8>> {
+    progress.report(Progress::Phase(Phase::LoadingWorkspace));
+    let target_dir = tempfile::tempdir()?;
+    let mut cmd = Command::new("cargo");
+    cmd.arg(RUSTDOC_TOOLCHAIN).arg("rustdoc").arg("--lib").arg("--target-dir").arg(target_dir.path()).current_dir(path);
+    match &opts.features {
+        FeatureSelection::Default |
+        FeatureSelection::PerFeature => (),
+        FeatureSelection::All => { cmd.arg("--all-features"); },
+        FeatureSelection::Some(features) => { cmd.arg("--features").arg(features.join(",")); },
+    }
+    if opts.profile.no_default_features {
+        cmd.arg("--no-default-features");
+    }
+    if let Some(target) = opts.profile.target.as_ref() {
+        cmd.arg("--target").arg(target);
+    }
+    cmd.args(&["--", "-Z", "unstable-options", "--output-format", "json"]);
+    if opts.include_private {
+        cmd.args(&["--document-private-items", "--document-hidden-items"]);
+    }
+    info!("running {:?}", cmd);
+    let mut child = cmd.spawn().context("failed to run cargo")?;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("failed to generate rustdoc JSON for {}: {}", path.display(), status)
+            }
+            break
+        }
+        if let Some(reason) = abort.check() {
+            let _ = child.kill();
+            return Err(AnalysisCancelled { reason, partial: vec![] }.into())
+        }
+        thread::sleep(ABORT_POLL_INTERVAL)
+    }
+    // Named after the lib target, which may not be the package name. Cross-compiled docs are under the target
+    let doc_dir = match opts.profile.target.as_ref() {
+        Some(target) => target_dir.path().join(target).join("doc"),
+        None => target_dir.path().join("doc"),
+    };
+    let json_paths: Vec<PathBuf> = fs::read_dir(&doc_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter().filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    match json_paths.as_slice() {
+        [json_path] => Ok(fs::read(json_path)?),
+        _ => bail!("expected a single JSON file in {}, found {}", doc_dir.display(), json_paths.len()),
+    }
+}
+
+fn analyze_json(json: &[u8], krate_name: &str, krate_version: &str, selection: ItemSelection, abort: &Abort, progress: &dyn ProgressSink) -> Result<Vec<FnDetail>> {
+    let krate: Value = serde_json::from_slice(json).context("invalid rustdoc JSON")?;
+    let format_version = krate["format_version"].as_u64().unwrap_or(0);
+    if format_version < MIN_FORMAT_VERSION {
+        bail!("rustdoc JSON format version {} is too old, at least {} is needed", format_version, MIN_FORMAT_VERSION)
+    }
+    if selection.deref_methods {
+        warn!("methods reachable through Deref are not indexed from rustdoc JSON")
+    }
+    let ctx = JsonCtx {
+        index: krate["index"].as_object().ok_or_else(|| anyhow!("rustdoc JSON has no index"))?,
+        paths: krate["paths"].as_object().ok_or_else(|| anyhow!("rustdoc JSON has no paths"))?,
+        name: krate_name,
+        version: krate_version,
+        include_private: selection.include_private,
+    };
+
+    // Items with a path of their own, i.e. not in an impl (those are found through their type) - in
+    // path order, so results are stable
+    let impl_items: HashSet<String> = ctx.index.values()
+        .filter_map(|item| tagged(&item["inner"]).filter(|(kind, _)| *kind == "impl"))
+        .flat_map(|(_, imp)| imp["items"].as_array().into_iter().flatten().map(id_key))
+        .collect();
+    let mut items: Vec<(String, &Value)> = ctx.index.iter()
+        .filter(|(id, item)| item["crate_id"].as_u64() == Some(0) && !impl_items.contains(*id))
+        .filter_map(|(id, item)| Some((ctx.path_of(id)?, item)))
+        .collect();
+    items.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
+
+    let mut fndetails = vec![];
+    let mut seen = HashSet::new();
+    let tracker = ItemsTracker::start(progress, Phase::AnalyzingItems, items.len());
+    for (i, (path, item)) in items.iter().enumerate() {
+        tracker.update(i);
+        if let Some(reason) = abort.check() {
+            warn!("stopping analysis of {} {} after {} of {} items: {:?}", krate_name, krate_version, i, items.len(), reason);
+            return Err(AnalysisCancelled { reason, partial: fndetails }.into())
+        }
+        if !ctx.includes(item) {
+            continue
+        }
+        let item_fndetails = match tagged(&item["inner"]) {
+            Some(("function", f)) => vec![ctx.function(item, f, path, None, &[])],
+            Some(("struct", st)) => ctx.analyze_struct(item, st, path),
+            Some(("enum", e)) => ctx.analyze_enum(item, e, path),
+            Some(("union", u)) => ctx.analyze_impls(u, path),
+            Some(("constant", c)) => vec![ctx.value(item, c, path, ItemKind::Const)],
+            Some(("static", st)) => vec![ctx.value(item, st, path, ItemKind::Static)],
+            _ => vec![],
+        };
+        // The same method can come from more than one impl, e.g. with different bounds
+        fndetails.extend(item_fndetails.into_iter().filter(|fndetail| seen.insert(fndetail.s.clone())));
+        progress.report(Progress::Discovered { fns: fndetails.len() });
+    }
+    tracker.update(items.len());
+    debug!("{} items from rustdoc JSON", fndetails.len());
+    Ok(fndetails)
+}
+
+struct JsonCtx<'a> {
+    index: &'a Map<String, Value>, // id => item
+    paths: &'a Map<String, Value>, // id => summary with a path
+    name: &'a str,
+    version: &'a str,
+    include_private: bool,
+}
+
+impl<'a> JsonCtx<'a> {
+    fn item(&self, id: &Value) -> Option<&'a Value> {
+        self.index.get(&id_key(id))
+    }
+
+    fn path_of(&self, id: &str) -> Option<String> {
+        let summary = self.paths.get(id)?;
+        if summary["crate_id"].as_u64() != Some(0) {
+            return None
+        }
+        let segments: Vec<&str> = summary["path"].as_array()?.iter().filter_map(Value::as_str).collect();
+        Some(segments.join("::"))
+    }
+
+    fn includes(&self, item: &Value) -> bool {
+        self.include_private || item_visibility(item) == ItemVisibility::Public
+    }
+
+    fn analyze_struct(&self, item: &Value, st: &Value, path: &str) -> Vec<FnDetail> {
+        let mut fndetails = self.analyze_impls(st, path);
+        let generics = generics(&st["generics"]);
+        let ret = adt_pretty(item["name"].as_str().unwrap_or_default(), &generics);
+        // Can only construct it if all the fields are visible
+        let fields = match tagged(&st["kind"]) {
+            Some(("unit", _)) => Some((StructKind::Unit, vec![])),
+            Some(("tuple", fields)) => self.fields(fields).map(|fields| (StructKind::Tuple, fields)),
+            Some(("plain", plain)) if plain["has_stripped_fields"] != Value::Bool(true) => {
+                self.fields(&plain["fields"]).map(|fields| (StructKind::Record, fields))
+            },
+            _ => None,
+        };
+        if let Some((kind, fields)) = fields {
+            fndetails.push(self.constructor(item, path, kind, fields, &ret, &generics, st))
+        }
+        fndetails
+    }
+
+    fn analyze_enum(&self, item: &Value, e: &Value, path: &str) -> Vec<FnDetail> {
+        let mut fndetails = self.analyze_impls(e, path);
+        let generics = generics(&e["generics"]);
+        let ret = adt_pretty(item["name"].as_str().unwrap_or_default(), &generics);
+        for variant in e["variants"].as_array().into_iter().flatten().filter_map(|id| self.item(id)) {
+            let variant_path = format!("{}::{}", path, variant["name"].as_str().unwrap_or_default());
+            let fields = match tagged(&variant["inner"]["variant"]["kind"]) {
+                Some(("plain", _)) => Some((StructKind::Unit, vec![])),
+                Some(("tuple", fields)) => self.fields(fields).map(|fields| (StructKind::Tuple, fields)),
+                Some(("struct", st)) if st["has_stripped_fields"] != Value::Bool(true) => {
+                    self.fields(&st["fields"]).map(|fields| (StructKind::Record, fields))
+                },
+                _ => None,
+            };
+            if let Some((kind, fields)) = fields {
+                let mut fndetail = self.constructor(variant, &variant_path, kind, fields, &ret, &generics, e);
+                fndetail.import_path = path.to_owned();
+                fndetails.push(fndetail)
+            }
+        }
+        fndetails
+    }
+
+    // (name, type) of each field, unless some are hidden (stripped, or left out as they're private)
+    fn fields(&self, ids: &Value) -> Option<Vec<(String, String)>> {
+        ids.as_array()?.iter()
+            .map(|id| {
+                let field = self.item(id)?;
+                if !self.includes(field) {
+                    return None
+                }
+                let (_, ty) = tagged(&field["inner"]).filter(|(kind, _)| *kind == "struct_field")?;
+                Some((field["name"].as_str().unwrap_or_default().to_owned(), type_str(ty, None)))
+            })
+            .collect()
+    }
+
+    // Inherent methods, and methods of trait impls (but not blanket impls, which would add every
+    // method of e.g. `Into` to every type)
+    fn analyze_impls(&self, adt: &Value, path: &str) -> Vec<FnDetail> {
+        let mut fndetails = vec![];
+        for (imp, self_ty) in self.impls(adt) {
+            let impl_generics = generics(&imp["generics"]);
+            for assoc in imp["items"].as_array().into_iter().flatten().filter_map(|id| self.item(id)) {
+                // Trait impl items have the visibility of the trait
+                if !self.includes(assoc) {
+                    continue
+                }
+                let assoc_path = format!("{}::{}", path, assoc["name"].as_str().unwrap_or_default());
+                let mut fndetail = match tagged(&assoc["inner"]) {
+                    Some(("function", f)) => self.function(assoc, f, &assoc_path, Some(self_ty.as_str()), &impl_generics),
+                    Some(("assoc_const", c)) |
+                    Some(("constant", c)) => self.value(assoc, c, &assoc_path, ItemKind::Const),
+                    _ => continue,
+                };
+                // Called through the type
+                fndetail.import_path = path.to_owned();
+                fndetails.push(fndetail)
+            }
+        }
+        fndetails
+    }
+
+    // The impls of an adt (struct, enum or union), with how they name the type
+    fn impls(&self, adt: &Value) -> Vec<(&'a Value, String)> {
+        adt["impls"].as_array().into_iter().flatten()
+            .filter_map(|id| self.item(id))
+            .filter_map(|imp| tagged(&imp["inner"]).filter(|(kind, _)| *kind == "impl").map(|(_, imp)| imp))
+            .filter(|imp| imp["is_synthetic"] != Value::Bool(true) && imp["is_negative"] != Value::Bool(true) && imp["blanket_impl"].is_null())
+            .map(|imp| (imp, type_str(&imp["for"], None)))
+            .collect()
+    }
+
+    fn function(&self, item: &Value, f: &Value, path: &str, self_ty: Option<&str>, impl_generics: &[GenericParamDetail]) -> FnDetail {
+        let sig = if f["sig"].is_null() { &f["decl"] } else { &f["sig"] };
+        let params: Vec<String> = sig["inputs"].as_array().into_iter().flatten()
+            .map(|input| type_str(&input[1], self_ty))
+            .collect();
+        let ret = if sig["output"].is_null() { "()".to_owned() } else { type_str(&sig["output"], self_ty) };
+        let generics = generics(&f["generics"]);
+        let bounded: Vec<GenericParamDetail> = generics.iter().chain(impl_generics).cloned().collect();
+        let ret_impls = self.ret_impls(&sig["output"], &ret, &bounded);
+        let header = &f["header"];
+        let mut fndetail = FnDetail {
+            param_reprs: params.iter().map(|p| TypeRepr::parse(p)).collect(),
+            ret_repr: TypeRepr::parse(&ret),
+            params,
+            ret,
+            ret_impls,
+            generics,
+            is_const: qualifier(header, "const"),
+            is_async: qualifier(header, "async"),
+            is_unsafe: qualifier(header, "unsafe"),
+            ..self.fndetail(item, path, ItemKind::Fn)
+        };
+        fndetail.s = fn_signature(&fndetail);
+        fndetail
+    }
+
+    fn constructor(&self, item: &Value, path: &str, kind: StructKind, fields: Vec<(String, String)>, ret: &str, generics: &[GenericParamDetail], adt: &Value) -> FnDetail {
+        let fields_str = match kind {
+            StructKind::Record => {
+                let fields_strs: Vec<_> = fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+                format!(" {{ {} }}", fields_strs.join(", "))
+            },
+            StructKind::Tuple => format!("({})", fields.iter().map(|(_, ty)| ty.as_str()).collect::<Vec<_>>().join(", ")),
+            StructKind::Unit => String::new(),
+        };
+        let params: Vec<String> = fields.into_iter().map(|(_, ty)| ty).collect();
+        FnDetail {
+            param_reprs: params.iter().map(|p| TypeRepr::parse(p)).collect(),
+            ret_repr: TypeRepr::parse(ret),
+            params,
+            ret: ret.to_owned(),
+            ret_impls: self.adt_impls(adt),
+            generics: generics.to_vec(),
+            is_const: true,
+            s: format!("{}{} -> {}", path, fields_str, ret),
+            ..self.fndetail(item, path, ItemKind::Constructor)
+        }
+    }
+
+    // Consts and statics are values ready to use, like a fn with no params
+    fn value(&self, item: &Value, v: &Value, path: &str, kind: ItemKind) -> FnDetail {
+        let ret = type_str(&v["type"], None);
+        let is_unsafe = kind == ItemKind::Static && (v["is_mutable"] == Value::Bool(true) || v["mutable"] == Value::Bool(true));
+        let item_str = match kind {
+            ItemKind::Static if is_unsafe => "static mut",
+            ItemKind::Static => "static",
+            ItemKind::Const => "const",
+            ItemKind::Fn |
+            ItemKind::Constructor => unreachable!("not a value"),
+        };
+        FnDetail {
+            ret_repr: TypeRepr::parse(&ret),
+            ret_impls: self.ret_impls(&v["type"], &ret, &[]),
+            is_const: kind == ItemKind::Const,
+            is_unsafe,
+            s: format!("{} {}: {}", item_str, path, ret),
+            ret,
+            ..self.fndetail(item, path, kind)
+        }
+    }
+
+    // The parts common to every kind of item, to fill in the rest of
+    fn fndetail(&self, item: &Value, path: &str, kind: ItemKind) -> FnDetail {
+        let attrs: Vec<&str> = item["attrs"].as_array().into_iter().flatten()
+            .filter_map(|attr| attr.as_str().or_else(|| attr["other"].as_str()))
+            .collect();
+        FnDetail {
+            krate: self.name.to_owned(),
+            version: self.version.to_owned(),
+            kind,
+            visibility: item_visibility(item),
+            path: path.to_owned(),
+            aliases: vec![],
+            import_path: path.to_owned(),
+            params: vec![],
+            ret: String::new(),
+            param_reprs: vec![],
+            ret_repr: TypeRepr::unit(),
+            written_params: None,
+            written_ret: None,
+            ret_impls: vec![],
+            generics: vec![],
+            is_const: false,
+            is_async: false,
+            is_unsafe: false,
+            features: vec![],
+            profile: AnalysisProfile::default(),
+            doc: item["docs"].as_str().and_then(|docs| docs.lines().map(str::trim).find(|line| !line.is_empty())).map(str::to_owned),
+            location: item_location(item),
+            deref_from: None,
+            deprecated: item["deprecation"].as_object().map(|deprecation| deprecation.get("note").and_then(Value::as_str).unwrap_or_default().to_owned()),
+            unstable: attrs.iter().find(|attr| attr.starts_with("#[unstable"))
+                .map(|attr| attr_arg(attr, "feature").unwrap_or_default()),
+            s: String::new(),
+        }
+    }
+
+    // As for `crate::analyze_ret_impls`, but only types of this crate have impls to look at
+    fn ret_impls(&self, ret_ty: &Value, ret_pretty: &str, generics: &[GenericParamDetail]) -> Vec<String> {
+        let mut impls = vec![];
+        if let Some(bounds) = ret_pretty.strip_prefix("impl ") {
+            impls.extend(split_top_level(bounds, '+').into_iter().map(|b| b.trim().to_owned()))
+        }
+        if let Some(generic) = generics.iter().find(|g| g.name == ret_pretty) {
+            impls.extend(generic.bounds.iter().cloned())
+        }
+        if let Some(("resolved_path", path)) = tagged(ret_ty) {
+            if let Some(adt) = self.item(&path["id"]).and_then(|item| tagged(&item["inner"])).map(|(_, adt)| adt) {
+                impls.extend(self.adt_impls(adt))
+            }
+        }
+        impls.sort();
+        impls.dedup();
+        impls
+    }
+
+    // The traits an adt implements, with any assoc types - e.g. `Iterator<Item = u8>` - and the generic
+    // arg of conversions, e.g. `From<&str>`
+    fn adt_impls(&self, adt: &Value) -> Vec<String> {
+        self.impls(adt).into_iter()
+            .filter(|(imp, _)| !imp["trait"].is_null())
+            .map(|(imp, _)| {
+                let name = last_segment(path_name(&imp["trait"])).to_owned();
+                let conversion_arg = conversion_arg(&TypeRepr::parse(&path_str(&imp["trait"], None)), &generics(&imp["generics"]));
+                let assoc_types: Vec<String> = imp["items"].as_array().into_iter().flatten()
+                    .filter_map(|id| self.item(id))
+                    .filter_map(|assoc| {
+                        let (_, assoc_type) = tagged(&assoc["inner"]).filter(|(kind, _)| *kind == "assoc_type")?;
+                        let ty = if assoc_type["type"].is_null() { &assoc_type["default"] } else { &assoc_type["type"] };
+                        Some(format!("{} = {}", assoc["name"].as_str()?, type_str(ty, None)))
+                    })
+                    .collect();
+                impl_trait_str(name, conversion_arg, assoc_types)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StructKind {
+    Record,
+    Tuple,
+    Unit,
+}
+
+// Ids are numbers in recent formats and strings in older ones, but always strings as keys
+fn id_key(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.clone(),
+        id => id.to_string(),
+    }
+}
+
+static NULL: Value = Value::Null;
+
+// Most enums are serialized as `{"variant": contents}`, or just `"variant"` without contents
+fn tagged(v: &Value) -> Option<(&str, &Value)> {
+    match v {
+        Value::Object(map) if map.len() == 1 => map.iter().next().map(|(tag, inner)| (tag.as_str(), inner)),
+        Value::String(tag) => Some((tag.as_str(), &NULL)),
+        _ => None,
+    }
+}
+
+fn item_visibility(item: &Value) -> ItemVisibility {
+    match tagged(&item["visibility"]) {
+        // `default` is for items that take the visibility of something else, e.g. trait impl methods
+        Some(("public", _)) |
+        Some(("default", _)) => ItemVisibility::Public,
+        // rustdoc doesn't say where the item is, so can't tell whether a restriction is to its own
+        // module (i.e. private)
+        _ => ItemVisibility::Crate,
+    }
+}
+
+// Spans are usually relative to where rustdoc was run, i.e. the crate root. Lines are one-based
+fn item_location(item: &Value) -> Option<SourceLocation> {
+    let span = &item["span"];
+    let file = span["filename"].as_str()?;
+    if Path::new(file).is_absolute() {
+        return None
+    }
+    Some(SourceLocation {
+        file: file.to_owned(),
+        line: (span["begin"][0].as_u64()?.saturating_sub(1)) as u32,
+        character: span["begin"][1].as_u64()? as u32,
+    })
+}
+
+// Header qualifiers were a list of names, now flags
+fn qualifier(header: &Value, name: &str) -> bool {
+    match header {
+        Value::Array(qualifiers) => qualifiers.iter().any(|q| q.as_str() == Some(name)),
+        header => header[format!("is_{}", name)] == Value::Bool(true) || header[format!("{}_", name)] == Value::Bool(true),
+    }
+}
+
+fn generics(g: &Value) -> Vec<GenericParamDetail> {
+    let mut params: Vec<GenericParamDetail> = g["params"].as_array().into_iter().flatten()
+        .filter_map(|param| {
+            let (_, ty) = tagged(&param["kind"]).filter(|(kind, _)| *kind == "type")?;
+            // `impl Trait` params
+            if ty["is_synthetic"] == Value::Bool(true) || ty["synthetic"] == Value::Bool(true) {
+                return None
+            }
+            Some(GenericParamDetail { name: param["name"].as_str()?.to_owned(), bounds: trait_names(&ty["bounds"]) })
+        })
+        .collect();
+    for predicate in g["where_predicates"].as_array().into_iter().flatten() {
+        if let Some(("bound_predicate", bound)) = tagged(predicate) {
+            if let Some(("generic", name)) = tagged(&bound["type"]) {
+                if let Some(param) = params.iter_mut().find(|p| Some(p.name.as_str()) == name.as_str()) {
+                    param.bounds.extend(trait_names(&bound["bounds"]))
+                }
+            }
+        }
+    }
+    params
+}
+
+// With the signature for `Fn` traits, as `crate::analyze_fn_bounds` does
+fn trait_names(bounds: &Value) -> Vec<String> {
+    bounds.as_array().into_iter().flatten()
+        .filter_map(|bound| match tagged(bound) {
+            Some(("trait_bound", tb)) => match tagged(&tb["trait"]["args"]) {
+                Some(("parenthesized", _)) => Some(TypeRepr::parse(&path_str(&tb["trait"], None)).to_string()),
+                _ => Some(last_segment(path_name(&tb["trait"])).to_owned()),
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn adt_pretty(name: &str, generics: &[GenericParamDetail]) -> String {
+    if generics.is_empty() {
+        name.to_owned()
+    } else {
+        let generic_names: Vec<_> = generics.iter().map(|g| g.name.as_str()).collect();
+        format!("{}<{}>", name, generic_names.join(", "))
+    }
+}
+
+fn path_name(path: &Value) -> &str {
+    path["path"].as_str().or_else(|| path["name"].as_str()).unwrap_or_default()
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+// A type as rust-analyzer would display it. `Self` is replaced by the impl's type, if in one
+fn type_str(ty: &Value, self_ty: Option<&str>) -> String {
+    let (kind, inner) = match tagged(ty) {
+        Some(tagged) => tagged,
+        None => return "{unknown}".to_owned(),
+    };
+    let mutability = |v: &Value| v["is_mutable"] == Value::Bool(true) || v["mutable"] == Value::Bool(true);
+    match kind {
+        "resolved_path" => path_str(inner, self_ty),
+        "generic" => match (inner.as_str(), self_ty) {
+            (Some("Self"), Some(self_ty)) => self_ty.to_owned(),
+            (name, _) => name.unwrap_or_default().to_owned(),
+        },
+        "primitive" => inner.as_str().unwrap_or_default().to_owned(),
+        "tuple" => {
+            let tys: Vec<String> = inner.as_array().into_iter().flatten().map(|ty| type_str(ty, self_ty)).collect();
+            if tys.len() == 1 { format!("({},)", tys[0]) } else { format!("({})", tys.join(", ")) }
+        },
+        "slice" => format!("[{}]", type_str(inner, self_ty)),
+        "array" => format!("[{}; {}]", type_str(&inner["type"], self_ty), inner["len"].as_str().unwrap_or("_")),
+        "borrowed_ref" => format!("&{}{}", if mutability(inner) { "mut " } else { "" }, type_str(&inner["type"], self_ty)),
+        "raw_pointer" => format!("*{} {}", if mutability(inner) { "mut" } else { "const" }, type_str(&inner["type"], self_ty)),
+        "impl_trait" => format!("impl {}", bounds_str(inner, self_ty)),
+        "dyn_trait" => {
+            let traits: Vec<String> = inner["traits"].as_array().into_iter().flatten().map(|t| path_str(&t["trait"], self_ty)).collect();
+            format!("dyn {}", traits.join(" + "))
+        },
+        "function_pointer" => {
+            let sig = if inner["sig"].is_null() { &inner["decl"] } else { &inner["sig"] };
+            let params: Vec<String> = sig["inputs"].as_array().into_iter().flatten().map(|input| type_str(&input[1], self_ty)).collect();
+            let ret = if sig["output"].is_null() { "()".to_owned() } else { type_str(&sig["output"], self_ty) };
+            format!("fn({}) -> {}", params.join(", "), ret)
+        },
+        "qualified_path" => {
+            let self_type = type_str(&inner["self_type"], self_ty);
+            let name = inner["name"].as_str().unwrap_or_default();
+            if inner["trait"].is_null() {
+                format!("{}::{}", self_type, name)
+            } else {
+                format!("<{} as {}>::{}", self_type, path_str(&inner["trait"], self_ty), name)
+            }
+        },
+        "never" => "!".to_owned(),
+        "infer" => "_".to_owned(),
+        _ => "{unknown}".to_owned(),
+    }
+}
+
+fn path_str(path: &Value, self_ty: Option<&str>) -> String {
+    let name = last_segment(path_name(path));
+    let (kind, args) = match tagged(&path["args"]) {
+        Some(tagged) => tagged,
+        None => return name.to_owned(),
+    };
+    match kind {
+        "angle_bracketed" => {
+            let mut arg_strs: Vec<String> = args["args"].as_array().into_iter().flatten()
+                .filter_map(|arg| match tagged(arg) {
+                    Some(("type", ty)) => Some(type_str(ty, self_ty)),
+                    Some(("const", c)) => Some(c["expr"].as_str().unwrap_or("_").to_owned()),
+                    Some(("infer", _)) => Some("_".to_owned()),
+                    _ => None, // lifetimes
+                })
+                .collect();
+            let constraints = if args["constraints"].is_null() { &args["bindings"] } else { &args["constraints"] };
+            for constraint in constraints.as_array().into_iter().flatten() {
+                if let Some(("equality", term)) = tagged(&constraint["binding"]) {
+                    // A term in recent formats, a type in older ones
+                    let ty = match tagged(term) {
+                        Some(("type", ty)) => ty,
+                        _ => term,
+                    };
+                    arg_strs.push(format!("{} = {}", constraint["name"].as_str().unwrap_or_default(), type_str(ty, self_ty)))
+                }
+            }
+            if arg_strs.is_empty() { name.to_owned() } else { format!("{}<{}>", name, arg_strs.join(", ")) }
+        },
+        // e.g. `Fn(u8) -> u8`
+        "parenthesized" => {
+            let inputs: Vec<String> = args["inputs"].as_array().into_iter().flatten().map(|ty| type_str(ty, self_ty)).collect();
+            let output = if args["output"].is_null() { String::new() } else { format!(" -> {}", type_str(&args["output"], self_ty)) };
+            format!("{}({}){}", name, inputs.join(", "), output)
+        },
+        _ => name.to_owned(),
+    }
+}
+
+fn bounds_str(bounds: &Value, self_ty: Option<&str>) -> String {
+    let bound_strs: Vec<String> = bounds.as_array().into_iter().flatten()
+        .filter_map(|bound| match tagged(bound) {
+            Some(("trait_bound", tb)) => {
+                let maybe = if tb["modifier"].as_str() == Some("maybe") { "?" } else { "" };
+                Some(format!("{}{}", maybe, path_str(&tb["trait"], self_ty)))
+            },
+            _ => None, // lifetimes
+        })
+        .collect();
+    bound_strs.join(" + ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::cancel::CancelToken;
+    use crate::progress::NoProgress;
+
+    // A fn, a struct with a method taking and returning `Self`, and a generic fn with bounds - with ids
+    // as numbers (recent formats) or strings (older ones), and the matching fn header and signature
+    fn krate_json(string_ids: bool) -> Vec<u8> {
+        let id = |n: u64| if string_ids { json!(n.to_string()) } else { json!(n) };
+        let sig = if string_ids { "decl" } else { "sig" };
+        let no_generics = || json!({"params": [], "where_predicates": []});
+        let header = |is_const: bool| if string_ids {
+            json!(if is_const { vec!["const"] } else { vec![] })
+        } else {
+            json!({"is_const": is_const, "is_unsafe": false, "is_async": false, "abi": "Rust"})
+        };
+        let trait_bound = |path: &str, args: Value| json!({"trait_bound": {
+            "trait": {"path": path, "id": id(90), "args": args}, "generic_params": [], "modifier": "none",
+        }});
+        let index = json!({
+            "0": {"crate_id": 0, "name": "parse", "visibility": "public", "docs": "Parses it.\n\nAt length.", "inner": {"function": {
+                sig: {
+                    "inputs": [["s", {"borrowed_ref": {"lifetime": null, "is_mutable": false, "type": {"primitive": "str"}}}]],
+                    "output": {"primitive": "u32"},
+                },
+                "generics": no_generics(), "header": header(false),
+            }}},
+            "1": {"crate_id": 0, "name": "Thing", "visibility": "public", "inner": {"struct": {
+                "kind": {"plain": {"fields": [], "has_stripped_fields": true}},
+                "generics": no_generics(), "impls": [id(2)],
+            }}},
+            "2": {"crate_id": 0, "name": null, "visibility": "default", "inner": {"impl": {
+                "is_synthetic": false, "is_negative": false, "blanket_impl": null, "trait": null,
+                "for": {"resolved_path": {"path": "Thing", "id": id(1), "args": null}},
+                "generics": no_generics(), "items": [id(3)],
+            }}},
+            "3": {"crate_id": 0, "name": "merge", "visibility": "public", "inner": {"function": {
+                sig: {
+                    "inputs": [
+                        ["self", {"borrowed_ref": {"lifetime": null, "is_mutable": false, "type": {"generic": "Self"}}}],
+                        ["other", {"generic": "Self"}],
+                    ],
+                    "output": {"generic": "Self"},
+                },
+                "generics": no_generics(), "header": header(false),
+            }}},
+            "4": {"crate_id": 0, "name": "apply", "visibility": "public", "inner": {"function": {
+                sig: {
+                    "inputs": [["f", {"generic": "F"}], ["t", {"generic": "T"}]],
+                    "output": {"resolved_path": {"path": "Vec", "id": id(91), "args": {"angle_bracketed": {
+                        "args": [{"type": {"generic": "T"}}], "constraints": [],
+                    }}}},
+                },
+                "generics": {
+                    "params": [
+                        {"name": "F", "kind": {"type": {"bounds": [trait_bound("Fn", json!({"parenthesized": {
+                            "inputs": [{"primitive": "u8"}], "output": {"primitive": "bool"},
+                        }}))], "default": null, "is_synthetic": false}}},
+                        {"name": "T", "kind": {"type": {"bounds": [], "default": null, "is_synthetic": false}}},
+                    ],
+                    "where_predicates": [{"bound_predicate": {
+                        "type": {"generic": "T"},
+                        "bounds": [trait_bound("Clone", json!({"angle_bracketed": {"args": [], "constraints": []}}))],
+                        "generic_params": [],
+                    }}],
+                },
+                "header": header(true),
+            }}},
+        });
+        let paths = json!({
+            "0": {"crate_id": 0, "path": ["a", "parse"], "kind": "function"},
+            "1": {"crate_id": 0, "path": ["a", "Thing"], "kind": "struct"},
+            "4": {"crate_id": 0, "path": ["a", "apply"], "kind": "function"},
+        });
+        let format_version = if string_ids { MIN_FORMAT_VERSION } else { 39 };
+        serde_json::to_vec(&json!({"format_version": format_version, "index": index, "paths": paths})).unwrap()
+    }
+
+    fn analyze(json: &[u8]) -> Result<Vec<FnDetail>> {
+        let abort = Abort::start(&CancelToken::new(), None);
+        analyze_json(json, "a", "1.0.0", AnalysisOptions::default().items(), &abort, &NoProgress)
+    }
+
+    #[test]
+    fn analyzes_items() {
+        for &string_ids in [false, true].iter() {
+            let fndetails = analyze(&krate_json(string_ids)).unwrap();
+            let sigs: Vec<(&str, Vec<&str>, &str)> = fndetails.iter()
+                .map(|fndetail| (fndetail.path.as_str(), fndetail.params.iter().map(String::as_str).collect(), fndetail.ret.as_str()))
+                .collect();
+            assert_eq!(sigs, vec![
+                ("a::Thing::merge", vec!["&Thing", "Thing"], "Thing"),
+                ("a::apply", vec!["F", "T"], "Vec<T>"),
+                ("a::parse", vec!["&str"], "u32"),
+            ], "string ids: {}", string_ids);
+
+            let merge = &fndetails[0];
+            assert_eq!(merge.import_path, "a::Thing");
+            let apply = &fndetails[1];
+            assert_eq!(apply.generics, vec![
+                GenericParamDetail { name: "F".to_owned(), bounds: vec!["Fn(u8) -> bool".to_owned()] },
+                GenericParamDetail { name: "T".to_owned(), bounds: vec!["Clone".to_owned()] },
+            ]);
+            assert!(apply.is_const && !apply.is_unsafe, "string ids: {}", string_ids);
+            let parse = &fndetails[2];
+            assert!(!parse.is_const);
+            assert_eq!(parse.doc.as_deref(), Some("Parses it."));
+        }
+    }
+
+    #[test]
+    fn rejects_old_formats() {
+        let json = serde_json::to_vec(&json!({"format_version": MIN_FORMAT_VERSION - 1, "index": {}, "paths": {}})).unwrap();
+        assert!(analyze(&json).is_err());
+    }
+}
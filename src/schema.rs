@@ -0,0 +1,555 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::str;
+use tracing::info;
+
+use reeves_types::*;
+
+use crate::fnidset::{decode_fn_ids, encode_fn_ids};
+use crate::indexes;
+use crate::fnrecord::{FnRecord, encode_fn};
+use crate::{CONSUMES_TREE, CRATE_META_TREE, CheckOutcome, Db, FLAG_TREE, FN_SET_TREES, FN_TREE, FnIdSet, MARKER_TREE, PARAM_NO_REFS_TREE, PARAM_TREE, PRODUCES_TREE, RET_IMPLS_TREE, RET_NO_REFS_TREE, RET_TREE, UNWRAPPED_RET_NO_REFS_TREE, consumes_keys, fn_set_keys, flag_keys, marker_keys, normalization, param_keys, param_no_refs_keys, produces_keys, ret_impl_keys, ret_keys, ret_no_refs_keys, unwrapped_ret_no_refs_keys, HistoryEntry, JobRecord, JobStatus, OldVersions, SavedQuery, TrackedCrate, stable_hash};
+
+// Bumped whenever what's stored changes in a way old DBs need migrating for (add a `MIGRATIONS`
+// entry from the old version at the same time)
+pub(crate) const SCHEMA_VERSION: u32 = 20;
+
+const DB_META_TREE: &str = "db-meta"; // DB_META_KEY => bincode::serialize(DbMeta)
+const DB_META_KEY: &[u8] = b"meta";
+
+// Written when a DB is created, so a DB from another version of reeves is noticed on open rather
+// than its records being misread (bincode has no field names or tags to catch that on its own)
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub(crate) struct DbMeta {
+    schema_version: u32,
+    layout_hash: u64, // see `layout_hash`
+    tool_version: String, // of reeves when the DB was created
+}
+
+// Brings a DB at `from` up to `from + 1`, run in order by `check_schema`
+struct Migration {
+    from: u32,
+    description: &'static str,
+    migrate: fn(&Db) -> Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "record the schema of DBs created before it was recorded",
+        // Nothing changed in the stored records when the version was introduced
+        migrate: |_db| Ok(()),
+    },
+    Migration {
+        from: 1,
+        description: "add deprecation and stability to fns",
+        migrate: migrate_fn_stability,
+    },
+    Migration {
+        from: 2,
+        description: "add search history",
+        // A new tree, which starts empty
+        migrate: |_db| Ok(()),
+    },
+    Migration {
+        from: 3,
+        description: "store fns with lazily decodable fields",
+        migrate: migrate_fn_records,
+    },
+    Migration {
+        from: 4,
+        description: "index unsafe fns and fns with raw pointers",
+        migrate: migrate_fn_flags,
+    },
+    Migration {
+        from: 5,
+        description: "add categories and keywords to crates",
+        migrate: migrate_crate_facets,
+    },
+    Migration {
+        from: 6,
+        description: "track crates to re-index",
+        // A new tree, which starts empty
+        migrate: |_db| Ok(()),
+    },
+    Migration {
+        from: 7,
+        description: "index which fns produce and consume each type",
+        migrate: |db| {
+            index_fn_sets(db, CONSUMES_TREE, consumes_keys)?;
+            index_fn_sets(db, PRODUCES_TREE, produces_keys)
+        },
+    },
+    Migration {
+        from: 8,
+        description: "store fn id sets as delta encoded varints",
+        migrate: migrate_fn_set_encoding,
+    },
+    Migration {
+        from: 9,
+        description: "store fn id sets as roaring bitmaps",
+        migrate: |db| {
+            for (tree_name, _) in FN_SET_TREES.iter() {
+                let tree = db.open_tree(tree_name);
+                for (key, val) in tree.iter() {
+                    tree.insert(&key, encode_fn_ids(&decode_fn_ids(&val)));
+                }
+                info!("re-encoded fn id sets in {}", tree_name);
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        from: 10,
+        description: "index types with array lengths and const generic args wildcarded",
+        migrate: |db| {
+            reindex_fn_sets(db, PARAM_TREE, param_keys);
+            reindex_fn_sets(db, RET_TREE, ret_keys);
+            reindex_fn_sets(db, PARAM_NO_REFS_TREE, param_no_refs_keys);
+            reindex_fn_sets(db, RET_NO_REFS_TREE, ret_no_refs_keys);
+            Ok(())
+        },
+    },
+    Migration {
+        from: 11,
+        description: "index trait objects by their traits",
+        migrate: |db| {
+            reindex_fn_sets(db, PARAM_TREE, param_keys);
+            reindex_fn_sets(db, RET_TREE, ret_keys);
+            reindex_fn_sets(db, PARAM_NO_REFS_TREE, param_no_refs_keys);
+            reindex_fn_sets(db, RET_NO_REFS_TREE, ret_no_refs_keys);
+            Ok(())
+        },
+    },
+    Migration {
+        from: 12,
+        description: "add the path to import to fns",
+        migrate: migrate_fn_import_paths,
+    },
+    Migration {
+        from: 13,
+        description: "add the conversion graph",
+        // New trees, which start empty - traits were recorded without the types they convert, so crates
+        // have to be analyzed again to be in the graph
+        migrate: |_db| Ok(()),
+    },
+    Migration {
+        from: 14,
+        description: "journal crates added and purged",
+        // A new tree, which starts empty - crates indexed before it have no entries
+        migrate: |_db| Ok(()),
+    },
+    Migration {
+        from: 15,
+        description: "add the analysis profile to fns",
+        migrate: migrate_fn_profiles,
+    },
+    Migration {
+        from: 16,
+        description: "index fns taking no params and returning nothing by markers rather than as types",
+        migrate: migrate_sig_markers,
+    },
+    Migration {
+        from: 17,
+        description: "index callables by their signature as a fn pointer",
+        // Fn bound signatures of generics are only recorded by analysis, so generic callables need
+        // their crates analyzed again
+        migrate: |db| {
+            reindex_fn_sets(db, PARAM_TREE, param_keys);
+            reindex_fn_sets(db, RET_TREE, ret_keys);
+            reindex_fn_sets(db, PARAM_NO_REFS_TREE, param_no_refs_keys);
+            reindex_fn_sets(db, RET_NO_REFS_TREE, ret_no_refs_keys);
+            reindex_fn_sets(db, RET_IMPLS_TREE, ret_impl_keys);
+            Ok(())
+        },
+    },
+    Migration {
+        from: 18,
+        description: "stop indexing types as written with an unresolved Self",
+        // Params and rets with `Self` in them are only resolved by analysis, so methods need their
+        // crates analyzed again to be found by their type
+        migrate: |db| {
+            reindex_fn_sets(db, PARAM_TREE, param_keys);
+            reindex_fn_sets(db, RET_TREE, ret_keys);
+            reindex_fn_sets(db, PARAM_NO_REFS_TREE, param_no_refs_keys);
+            reindex_fn_sets(db, RET_NO_REFS_TREE, ret_no_refs_keys);
+            Ok(())
+        },
+    },
+    Migration {
+        from: 19,
+        description: "index unwrapped ret types with references removed",
+        migrate: |db| {
+            reindex_fn_sets(db, UNWRAPPED_RET_NO_REFS_TREE, unwrapped_ret_no_refs_keys);
+            Ok(())
+        },
+    },
+];
+
+// `FnDetail` at schema version 1
+#[derive(Deserialize)]
+struct FnDetailV1 {
+    krate: String,
+    version: String,
+    kind: ItemKind,
+    visibility: ItemVisibility,
+    path: String,
+    aliases: Vec<String>,
+    params: Vec<String>,
+    ret: String,
+    param_reprs: Vec<TypeRepr>,
+    ret_repr: TypeRepr,
+    written_params: Option<Vec<String>>,
+    written_ret: Option<String>,
+    ret_impls: Vec<String>,
+    generics: Vec<GenericParamDetail>,
+    is_const: bool,
+    is_async: bool,
+    is_unsafe: bool,
+    features: Vec<String>,
+    doc: Option<String>,
+    location: Option<SourceLocation>,
+    deref_from: Option<String>,
+    s: String,
+}
+
+// Crates need analyzing again to know what's deprecated or unstable, until then nothing is
+fn migrate_fn_stability(db: &Db) -> Result<()> {
+    let fn_tree = db.open_tree(FN_TREE);
+    let fn_ids: Vec<Vec<u8>> = fn_tree.iter().map(|(key, _val)| key).collect();
+    for key in fn_ids {
+        let old: FnDetailV1 = bincode::deserialize(&fn_tree.get(&key).unwrap())?;
+        let fndetail = FnDetail {
+            krate: old.krate,
+            version: old.version,
+            kind: old.kind,
+            visibility: old.visibility,
+            import_path: guess_import_path(&old.path),
+            path: old.path,
+            aliases: old.aliases,
+            params: old.params,
+            ret: old.ret,
+            param_reprs: old.param_reprs,
+            ret_repr: old.ret_repr,
+            written_params: old.written_params,
+            written_ret: old.written_ret,
+            ret_impls: old.ret_impls,
+            generics: old.generics,
+            is_const: old.is_const,
+            is_async: old.is_async,
+            is_unsafe: old.is_unsafe,
+            features: old.features,
+            profile: AnalysisProfile::default(),
+            doc: old.doc,
+            location: old.location,
+            deref_from: old.deref_from,
+            deprecated: None,
+            unstable: None,
+            s: old.s,
+        };
+        fn_tree.insert(&key, bincode::serialize(&fndetail).unwrap());
+    }
+    Ok(())
+}
+
+// Until crates are analyzed again, the path to import is guessed from the fn's path - the type or
+// trait for members of one (going by their capitalized names), otherwise the fn itself
+fn guess_import_path(path: &str) -> String {
+    match path.rsplit_once("::") {
+        Some((parent, _)) if parent.rsplit("::").next().map_or(false, |name| name.starts_with(char::is_uppercase)) => parent.to_owned(),
+        _ => path.to_owned(),
+    }
+}
+
+// Fns gained `import_path` as a last field of their records at version 13
+fn migrate_fn_import_paths(db: &Db) -> Result<()> {
+    append_fn_field(db, 24, |field| {
+        let path: String = bincode::deserialize(field(3))?;
+        Ok(bincode::serialize(&guess_import_path(&path)).unwrap())
+    })?;
+    info!("added import paths to fns");
+    Ok(())
+}
+
+// Fns gained `profile` as a last field of their records at version 16. Until crates are analyzed
+// again, they're taken to be for the host target with default features, as that was all there was
+fn migrate_fn_profiles(db: &Db) -> Result<()> {
+    append_fn_field(db, 25, |_field| Ok(bincode::serialize(&AnalysisProfile::default()).unwrap()))?;
+    info!("added analysis profiles to fns");
+    Ok(())
+}
+
+// Adds a field to the end of every fn record (see `FnRecord`) with `old_num_fields` fields, made from
+// the record's existing fields (each given by its position). Records with any other number of fields
+// (e.g. written by an earlier migration with the current layout) are left alone
+fn append_fn_field(db: &Db, old_num_fields: usize, new_field: impl for<'a> Fn(&'a dyn Fn(usize) -> &'a [u8]) -> Result<Vec<u8>>) -> Result<()> {
+    let fn_tree = db.open_tree(FN_TREE);
+    let fn_ids: Vec<Vec<u8>> = fn_tree.iter().map(|(key, _val)| key).collect();
+    for key in fn_ids {
+        let old = fn_tree.get(&key).unwrap();
+        let end = |i: usize| u32::from_le_bytes(old[i * 4..i * 4 + 4].try_into().unwrap()) as usize;
+        if old.len() < old_num_fields * 4 || end(old_num_fields - 1) != old.len() - old_num_fields * 4 {
+            continue
+        }
+        let fields = &old[old_num_fields * 4..];
+        let field = |i: usize| &fields[if i == 0 { 0 } else { end(i - 1) }..end(i)];
+        let new = new_field(&field)?;
+        let mut record = Vec::with_capacity(old.len() + 4 + new.len());
+        record.extend_from_slice(&old[..old_num_fields * 4]);
+        record.extend_from_slice(&((fields.len() + new.len()) as u32).to_le_bytes());
+        record.extend_from_slice(fields);
+        record.extend_from_slice(&new);
+        fn_tree.insert(&key, record);
+    }
+    Ok(())
+}
+
+// Fns were stored as a bincode `FnDetail` up to version 3
+fn migrate_fn_records(db: &Db) -> Result<()> {
+    let fn_tree = db.open_tree(FN_TREE);
+    let fn_ids: Vec<Vec<u8>> = fn_tree.iter().map(|(key, _val)| key).collect();
+    for key in fn_ids {
+        let fndetail: FnDetail = bincode::deserialize(&fn_tree.get(&key).unwrap())?;
+        fn_tree.insert(&key, encode_fn(&fndetail));
+    }
+    Ok(())
+}
+
+// Fns taking no params were keyed by a sentinel with the param types, and fns returning nothing by
+// `()` with the ret types. Text search indexes loaded before keep them until they're loaded again,
+// which is harmless as candidates that aren't keys match no fns
+fn migrate_sig_markers(db: &Db) -> Result<()> {
+    reindex_fn_sets(db, MARKER_TREE, marker_keys);
+    for &tree_name in [PARAM_TREE, PARAM_NO_REFS_TREE].iter() {
+        db.open_tree(tree_name).remove("<NOARGS>");
+    }
+    for &tree_name in [RET_TREE, RET_NO_REFS_TREE].iter() {
+        db.open_tree(tree_name).remove("()");
+    }
+    Ok(())
+}
+
+fn migrate_fn_flags(db: &Db) -> Result<()> {
+    index_fn_sets(db, FLAG_TREE, flag_keys)
+}
+
+// Fills a new tree of `FN_SET_TREES` from the fns already stored, with sets bincode'd as they were
+// before version 8
+fn index_fn_sets(db: &Db, tree_name: &str, keys_fn: fn(&FnDetail) -> Vec<String>) -> Result<()> {
+    let mut sets: HashMap<String, HashSet<u64>> = HashMap::new();
+    for (key, val) in db.open_tree(FN_TREE).iter() {
+        let fn_id: u64 = bincode::deserialize(&key)?;
+        for set_key in keys_fn(&FnRecord::new(val).to_fndetail()) {
+            sets.entry(set_key).or_insert_with(HashSet::new).insert(fn_id);
+        }
+    }
+    let tree = db.open_tree(tree_name);
+    for (set_key, fn_ids) in sets {
+        tree.insert(set_key.as_bytes(), bincode::serialize(&fn_ids).unwrap());
+    }
+    Ok(())
+}
+
+// Rewrites a tree of `FN_SET_TREES` from the fns stored, for when what it's keyed by changes. Keys
+// that are no longer derived from any fn are removed
+fn reindex_fn_sets(db: &Db, tree_name: &str, keys_fn: fn(&FnDetail) -> Vec<String>) {
+    let passes = normalization(db);
+    let mut sets: HashMap<String, FnIdSet> = HashMap::new();
+    for (key, val) in db.open_tree(FN_TREE).iter() {
+        let fn_id: u64 = bincode::deserialize(&key).unwrap();
+        for set_key in fn_set_keys(&passes, tree_name, keys_fn, &FnRecord::new(val).to_fndetail()) {
+            sets.entry(set_key).or_insert_with(FnIdSet::new).insert(fn_id);
+        }
+    }
+    let tree = db.open_tree(tree_name);
+    let stale: Vec<Vec<u8>> = tree.iter()
+        .map(|(key, _val)| key)
+        .filter(|key| !sets.contains_key(str::from_utf8(key).unwrap()))
+        .collect();
+    for key in stale {
+        tree.remove(&key);
+    }
+    for (set_key, fn_ids) in sets {
+        tree.insert(set_key.as_bytes(), encode_fn_ids(&fn_ids));
+    }
+    info!("reindexed fn id sets in {}", tree_name);
+}
+
+fn migrate_fn_set_encoding(db: &Db) -> Result<()> {
+    for (tree_name, _) in FN_SET_TREES.iter() {
+        let tree = db.open_tree(tree_name);
+        for (key, val) in tree.iter() {
+            let fn_ids: HashSet<u64> = bincode::deserialize(&val)?;
+            tree.insert(&key, encode_fn_ids(&fn_ids.into_iter().collect()));
+        }
+        info!("re-encoded fn id sets in {}", tree_name);
+    }
+    Ok(())
+}
+
+// `CrateMeta` at schema version 5
+#[derive(Deserialize)]
+struct CrateMetaV5 {
+    name: String,
+    version: String,
+    license: Option<String>,
+    repository: Option<String>,
+    docsrs_url: String,
+    indexed_at: u64,
+}
+
+// Crates need analyzing again to know their categories and keywords, until then they have none (and
+// no facets)
+fn migrate_crate_facets(db: &Db) -> Result<()> {
+    let meta_tree = db.open_tree(CRATE_META_TREE);
+    let keys: Vec<Vec<u8>> = meta_tree.iter().map(|(key, _val)| key).collect();
+    for key in keys {
+        let old: CrateMetaV5 = bincode::deserialize(&meta_tree.get(&key).unwrap())?;
+        let meta = CrateMeta {
+            name: old.name,
+            version: old.version,
+            license: old.license,
+            repository: old.repository,
+            docsrs_url: old.docsrs_url,
+            indexed_at: old.indexed_at,
+            categories: vec![],
+            keywords: vec![],
+        };
+        meta_tree.insert(&key, bincode::serialize(&meta).unwrap());
+    }
+    Ok(())
+}
+
+// Checks the DB can be read by this version of reeves, migrating it (every index in it) if it's from
+// an older one. A new DB is stamped with the current version
+pub(crate) fn check_schema(db: &Db) -> Result<DbMeta> {
+    let meta_tree = db.open_tree(DB_META_TREE);
+    let mut meta = match meta_tree.get(DB_META_KEY) {
+        Some(bytes) => bincode::deserialize::<DbMeta>(&bytes)
+            .map_err(|e| anyhow!("unreadable DB metadata, the DB may be corrupt: {}", e))?,
+        None if db.tree_names().iter().all(|name| db.open_tree(name).iter().next().is_none()) => {
+            let meta = current_meta();
+            meta_tree.insert(DB_META_KEY, bincode::serialize(&meta).unwrap());
+            return Ok(meta)
+        },
+        // Created before the metadata was recorded
+        None => DbMeta { schema_version: 0, layout_hash: layout_hash(), tool_version: "unknown".to_owned() },
+    };
+
+    if meta.schema_version > SCHEMA_VERSION {
+        bail!("DB has schema version {} (created by reeves {}), but this reeves only understands up to version {}",
+              meta.schema_version, meta.tool_version, SCHEMA_VERSION)
+    }
+    while meta.schema_version < SCHEMA_VERSION {
+        let migration = MIGRATIONS.iter().find(|m| m.from == meta.schema_version)
+            .ok_or_else(|| anyhow!("no migration from DB schema version {}, the DB needs rebuilding", meta.schema_version))?;
+        info!("migrating DB from schema version {}: {}", migration.from, migration.description);
+        for index_db in indexes::all_indexes(db) {
+            (migration.migrate)(&index_db)?;
+        }
+        meta.schema_version += 1;
+        meta.layout_hash = layout_hash();
+        meta_tree.insert(DB_META_KEY, bincode::serialize(&meta).unwrap());
+    }
+
+    if meta.layout_hash != layout_hash() {
+        bail!("DB records were written with a different layout (created by reeves {}), the DB needs rebuilding", meta.tool_version)
+    }
+    Ok(meta)
+}
+
+fn current_meta() -> DbMeta {
+    DbMeta { schema_version: SCHEMA_VERSION, layout_hash: layout_hash(), tool_version: env!("CARGO_PKG_VERSION").to_owned() }
+}
+
+// A hash of the serialized form of a sample of each record stored with bincode. Adding, removing or
+// changing the type of a field changes the bytes, so a layout change that wasn't given a
+// `SCHEMA_VERSION` bump is still caught (renaming, or swapping fields of the same type, isn't - but
+// doesn't need to be, as bincode doesn't care)
+pub(crate) fn layout_hash() -> u64 {
+    let location = SourceLocation { file: "src/lib.rs".to_owned(), line: 1, character: 2 };
+    let generic = GenericParamDetail { name: "T".to_owned(), bounds: vec!["Clone".to_owned()] };
+    let fndetail = FnDetail {
+        krate: "krate".to_owned(),
+        version: "0.1.0".to_owned(),
+        kind: ItemKind::Fn,
+        visibility: ItemVisibility::Public,
+        path: "krate::f".to_owned(),
+        aliases: vec!["krate::g".to_owned()],
+        import_path: "krate::f".to_owned(),
+        params: vec!["&T".to_owned()],
+        ret: "Vec<T>".to_owned(),
+        param_reprs: vec![TypeRepr::parse("&T")],
+        ret_repr: TypeRepr::parse("Vec<T>"),
+        written_params: Some(vec!["&T".to_owned()]),
+        written_ret: Some("Vec<T>".to_owned()),
+        ret_impls: vec!["Clone".to_owned()],
+        generics: vec![generic],
+        is_const: true,
+        is_async: true,
+        is_unsafe: true,
+        features: vec!["std".to_owned()],
+        profile: AnalysisProfile { target: Some("thumbv7em-none-eabihf".to_owned()), no_default_features: true },
+        doc: Some("doc".to_owned()),
+        location: Some(location),
+        deref_from: Some("[T]".to_owned()),
+        deprecated: Some("use g".to_owned()),
+        unstable: Some("f_feature".to_owned()),
+        s: "pub fn f<T: Clone>(&T) -> Vec<T>".to_owned(),
+    };
+    let meta = CrateMeta {
+        name: "krate".to_owned(),
+        version: "0.1.0".to_owned(),
+        license: Some("MIT".to_owned()),
+        repository: Some("https://example.com".to_owned()),
+        docsrs_url: "https://docs.rs/krate/0.1.0".to_owned(),
+        indexed_at: 1,
+        categories: vec!["parsing".to_owned()],
+        keywords: vec!["json".to_owned()],
+    };
+    let job = JobRecord {
+        job_id: 1,
+        name: "krate".to_owned(),
+        version: "0.1.0".to_owned(),
+        status: JobStatus::Analyzed { num_fns: 1 },
+        history: vec![(1, JobStatus::Failed("err".to_owned()))],
+    };
+    let query = SavedQuery {
+        params: Some(vec!["&str".to_owned()]),
+        ret: Some("u8".to_owned()),
+        ret_impl: Some("Clone".to_owned()),
+        name: Some("f".to_owned()),
+        doc: Some("doc".to_owned()),
+    };
+    let history = HistoryEntry { id: 1, query, count: 1, first_searched: 1, last_searched: 2, last_num_results: 3, pinned: true };
+    let tracked = TrackedCrate {
+        name: "krate".to_owned(),
+        old_versions: OldVersions::Purge,
+        checked_at: Some(1),
+        last_outcome: Some(CheckOutcome::Indexed { version: "0.1.0".to_owned(), num_fns: 1 }),
+    };
+    let journal_entry = JournalEntry {
+        id: 1,
+        op: JournalOp::Purge,
+        krate: "krate".to_owned(),
+        version: "0.1.0".to_owned(),
+        num_fns: 1,
+        timestamp: 1,
+        tool_version: "0.1.0".to_owned(),
+        schema_version: 1,
+    };
+    let fn_ids: FnIdSet = [1, 2, 300].iter().cloned().collect();
+    let samples = [
+        format!("{:?}", encode_fn(&fndetail)),
+        format!("{:?}", encode_fn_ids(&fn_ids)),
+        format!("{:?}", bincode::serialize(&meta).unwrap()),
+        format!("{:?}", bincode::serialize(&job).unwrap()),
+        format!("{:?}", bincode::serialize(&history).unwrap()),
+        format!("{:?}", bincode::serialize(&tracked).unwrap()),
+        format!("{:?}", bincode::serialize(&journal_entry).unwrap()),
+    ];
+    stable_hash(&samples.iter().map(|s| s.as_str()).collect::<Vec<_>>())
+}
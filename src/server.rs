@@ -3,13 +3,14 @@ use actix_web::http::header::{ContentEncoding, ContentType};
 use actix_web::middleware;
 use actix_web::web;
 use filesystem::{FakeFileSystem, FileSystem};
-use log::{info, trace};
 use std::fs;
 use std::io::{self, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
+use tracing::{info, trace};
 
+use reeves::Db;
 use reeves_types::*;
 
 macro_rules! resp {
@@ -53,12 +54,13 @@ macro_rules! respbin {
 //}
 
 struct InnerData {
-    db: sled::Db,
+    db: Db,
+    search_cache: reeves::SearchCache,
 }
 
 impl InnerData {
-    fn new(db: sled::Db) -> Self {
-        Self { db }
+    fn new(db: Db) -> Self {
+        Self { db, search_cache: reeves::SearchCache::new() }
     }
 }
 
@@ -72,16 +74,71 @@ type ServerData = web::Data<MyServerData>;
 // Handlers
 
 async fn srv_post_reeves_search(state: ServerData, body: web::Bytes) -> impl Responder {
-    let proto::SearchRequest { params, ret } = bincode::deserialize(&body).unwrap();
-    let searchreq_str = format!("{:?} {:?}", params, ret);
-    let fndetails = reeves::search(&state.s.db, params, ret);
+    let proto::SearchRequest { query, options } = bincode::deserialize(&body).unwrap();
+    let searchreq_str = format!("{:?} {:?}", query, options);
+    let first_page = options.cursor.is_none() && options.offset == 0;
+    let reeves::SearchResults { fndetails, next_cursor, warnings, crates, explanations, groups, facets, suggestions } = reeves::search(&state.s.db, &state.s.search_cache, &query, &options);
     info!("returning {} results for {}", fndetails.len(), searchreq_str);
+    if first_page {
+        reeves::record_search(&state.s.db, &reeves::SavedQuery::from_query(&query), fndetails.len())
+    }
+    let ret = proto::SearchResult {
+        fndetails,
+        crates,
+        explanations,
+        groups,
+        facets,
+        next_cursor,
+        warnings,
+        suggestions,
+    };
+    respbin!(&ret)
+}
+
+async fn srv_post_reeves_search_applicable(state: ServerData, body: web::Bytes) -> impl Responder {
+    let proto::SearchApplicableRequest { types, filters } = bincode::deserialize(&body).unwrap();
+    let searchreq_str = format!("{:?} {:?}", types, filters);
+    let fndetails = reeves::search_applicable(&state.s.db, types, &filters);
+    info!("returning {} applicable results for {}", fndetails.len(), searchreq_str);
+    let crates = reeves::crate_metas_for(&state.s.db, &fndetails);
     let ret = proto::SearchResult {
         fndetails,
+        crates,
+        explanations: None,
+        groups: None,
+        facets: None,
+        next_cursor: None,
+        warnings: vec![],
+        suggestions: vec![],
     };
     respbin!(&ret)
 }
 
+async fn srv_post_reeves_search_compositions(state: ServerData, body: web::Bytes) -> impl Responder {
+    let proto::SearchCompositionsRequest { from, to, max_steps, filters } = bincode::deserialize(&body).unwrap();
+    let chains = reeves::search_compositions(&state.s.db, &from, &to, max_steps, &filters);
+    info!("returning {} chains from {} to {}", chains.len(), from, to);
+    respbin!(&chains)
+}
+
+async fn srv_post_reeves_type_info(state: ServerData, body: web::Bytes) -> impl Responder {
+    let proto::TypeInfoRequest { ty } = bincode::deserialize(&body).unwrap();
+    let info = reeves::type_info(&state.s.db, &ty);
+    info!("returning info on {}: {} producers, {} consumers", ty, info.num_producers, info.num_consumers);
+    respbin!(&info)
+}
+
+async fn srv_post_reeves_journal(state: ServerData, body: web::Bytes) -> impl Responder {
+    let proto::JournalRequest { krate, after } = bincode::deserialize(&body).unwrap();
+    let entries = reeves::journal(&state.s.db, krate.as_deref(), after);
+    info!("returning {} journal entries", entries.len());
+    respbin!(&entries)
+}
+
+async fn srv_get_metrics() -> impl Responder {
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(reeves::render_metrics())
+}
+
 fn load_static(static_tar: &Path) -> FakeFileSystem {
     let rdr = BufReader::new(fs::File::open(static_tar).unwrap());
     let ar = tar::Archive::new(rdr);
@@ -112,7 +169,8 @@ fn archive_to_fake_filesystem(mut ar: tar::Archive<impl Read>) -> FakeFileSystem
 
 // Main control functions
 
-pub fn serve(db: sled::Db, addr: String, static_tar: PathBuf) {
+pub fn serve(db: Db, addr: String, static_tar: PathBuf) {
+    reeves::init_index_size_metrics(&db);
     let state = MyServerData { s: Arc::new(InnerData::new(db)) };
 
     let fake_fs = load_static(&static_tar);
@@ -123,6 +181,11 @@ pub fn serve(db: sled::Db, addr: String, static_tar: PathBuf) {
         let app = app.wrap(middleware::Logger::default());
         let app = app.wrap(middleware::Compress::new(ContentEncoding::Auto));
         let app = app.route("/reeves/search", web::post().to(srv_post_reeves_search));
+        let app = app.route("/reeves/search-applicable", web::post().to(srv_post_reeves_search_applicable));
+        let app = app.route("/reeves/search-compositions", web::post().to(srv_post_reeves_search_compositions));
+        let app = app.route("/reeves/type-info", web::post().to(srv_post_reeves_type_info));
+        let app = app.route("/reeves/journal", web::post().to(srv_post_reeves_journal));
+        let app = app.route("/metrics", web::get().to(srv_get_metrics));
         let app = app.service(actix_files::Files::new_with_filesystem_and_namedfile_open_and_renderer(
             fake_fs.clone(),
             |fs, path| {
@@ -153,3 +216,17 @@ pub fn serve(db: sled::Db, addr: String, static_tar: PathBuf) {
             .await
     }).unwrap()
 }
+
+// Just the metrics, for servers that don't otherwise speak HTTP
+pub fn serve_metrics(addr: String) {
+    let app_factory = || App::new().route("/metrics", web::get().to(srv_get_metrics));
+
+    info!("Metrics server starting on {}", addr);
+    actix_rt::System::new("metrics server").block_on(async {
+        HttpServer::new(app_factory)
+            .bind(addr)
+            .unwrap()
+            .run()
+            .await
+    }).unwrap()
+}
@@ -0,0 +1,151 @@
+use anyhow::{Result, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+use reeves_types::*;
+
+use crate::{Db, MARKER_TREE, NAME_TREE, PARAM_TREE, RET_TREE, normalization};
+use crate::snapshot::ReadSnapshot;
+
+// Fns sharing the most types with the target are scored, as scoring means reading each one
+const MAX_SIMILAR_CANDIDATES: usize = 5000;
+// How much each part of a signature counts towards the score, adding up to 1
+const PARAMS_WEIGHT: f64 = 0.5;
+const RET_WEIGHT: f64 = 0.35;
+const RECEIVER_WEIGHT: f64 = 0.15;
+
+// What to find fns like
+#[derive(Debug, Clone)]
+pub enum SimilarTarget {
+    Fn(u64),
+    // The first fn at the path, e.g. `regex::Regex::new`
+    Path(String),
+    // In the query syntax, e.g. `&str -> Result<Regex, Error>`
+    Signature(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct SimilarFn {
+    pub fndetail: FnDetail,
+    pub score: f64, // 0 to 1, 1 being the same signature
+    pub shared_params: usize,
+    pub same_ret: bool,
+    pub same_receiver: bool, // the first param
+}
+
+// The signature being compared against, with types normalized as the index's param and ret keys are
+struct TargetSig {
+    params: Vec<String>,
+    ret: Option<String>,
+    receiver: Option<String>,
+    fn_id: Option<u64>,
+    krate: Option<String>,
+}
+
+// Indexed fns ranked by how alike their signatures are to the target's - the params they share
+// (in any order), the same ret, the same receiver - e.g. to find other crates offering the same
+// thing. With `other_crates`, fns of the target's crate are left out (only for fn targets)
+pub fn similar_to(db: &Db, target: &SimilarTarget, other_crates: bool, limit: usize) -> Result<Vec<SimilarFn>> {
+    let mut snapshot = ReadSnapshot::take(db);
+    let passes = normalization(db);
+    let normalize = |ty: &str| normalize_type(&TypeRepr::parse(ty).to_string(), &passes);
+    let target = match target {
+        SimilarTarget::Fn(fn_id) => match snapshot.get_fn(*fn_id) {
+            Some(fndetail) => fn_target(*fn_id, &fndetail, normalize),
+            None => bail!("no fn with id {}", fn_id),
+        },
+        SimilarTarget::Path(path) => {
+            let fn_ids = db.open_tree(NAME_TREE).get_fn_ids(path.trim().as_bytes());
+            let found = fn_ids.iter().flat_map(|fn_ids| fn_ids.iter())
+                .find_map(|fn_id| snapshot.get_fn(fn_id).map(|fndetail| (fn_id, fndetail)));
+            match found {
+                Some((fn_id, fndetail)) => fn_target(fn_id, &fndetail, normalize),
+                None => bail!("no fn at path {}", path),
+            }
+        },
+        SimilarTarget::Signature(sig) => {
+            let query = Query::parse(sig);
+            let params: Vec<String> = query.param_strs().unwrap_or_default().iter().map(|param| normalize(param)).collect();
+            let receiver = query.receiver_str().map(|receiver| normalize(&receiver)).or_else(|| params.first().cloned());
+            TargetSig { params, ret: query.ret_str().map(|ret| normalize(&ret)), receiver, fn_id: None, krate: None }
+        },
+    };
+
+    // Fns with any of the target's types, counting how many each has
+    let mut hits: HashMap<u64, usize> = HashMap::new();
+    let param_tree = db.open_tree(PARAM_TREE);
+    let ret_tree = db.open_tree(RET_TREE);
+    let marker_tree = db.open_tree(MARKER_TREE);
+    let mut sets = vec![];
+    for param in target.params.iter() {
+        sets.extend(param_tree.get_fn_ids(param.as_bytes()))
+    }
+    if target.params.is_empty() {
+        sets.extend(marker_tree.get_fn_ids(engine::SigMarker::NoParams.key().as_bytes()))
+    }
+    match target.ret.as_ref() {
+        Some(ret) if TypeRepr::parse(ret).is_unit() => sets.extend(marker_tree.get_fn_ids(engine::SigMarker::NoRet.key().as_bytes())),
+        Some(ret) => sets.extend(ret_tree.get_fn_ids(ret.as_bytes())),
+        None => (),
+    }
+    for fn_ids in sets {
+        for fn_id in fn_ids.iter() {
+            *hits.entry(fn_id).or_insert(0) += 1
+        }
+    }
+    if let Some(fn_id) = target.fn_id {
+        hits.remove(&fn_id);
+    }
+    let mut candidates: Vec<(u64, usize)> = hits.into_iter().collect();
+    candidates.sort_by(|(id1, n1), (id2, n2)| n2.cmp(n1).then(id1.cmp(id2)));
+    candidates.truncate(MAX_SIMILAR_CANDIDATES);
+
+    let mut similar: Vec<(u64, SimilarFn)> = vec![];
+    for (fn_id, _) in candidates {
+        let record = match snapshot.get_fn_record(fn_id) {
+            Some(record) => record,
+            None => continue,
+        };
+        if other_crates && target.krate.as_deref() == Some(record.krate()) {
+            continue
+        }
+        let params: Vec<String> = record.params().iter().map(|param| normalize(param)).collect();
+        let ret = normalize(record.ret());
+        let shared_params = num_shared(&target.params, &params);
+        let same_ret = target.ret.as_ref() == Some(&ret);
+        let same_receiver = target.receiver.is_some() && target.receiver.as_ref() == params.first();
+        let max_params = target.params.len().max(params.len());
+        let params_score = if max_params == 0 { 1.0 } else { shared_params as f64 / max_params as f64 };
+        let score = PARAMS_WEIGHT * params_score + RET_WEIGHT * same_ret as u8 as f64 + RECEIVER_WEIGHT * same_receiver as u8 as f64;
+        similar.push((fn_id, SimilarFn { fndetail: record.to_fndetail(), score, shared_params, same_ret, same_receiver }))
+    }
+    similar.sort_by(|(id1, s1), (id2, s2)| {
+        s2.score.partial_cmp(&s1.score).unwrap()
+            .then_with(|| engine::result_order(&s1.fndetail.krate, &s1.fndetail.s, &s1.fndetail.version, *id1)
+                .cmp(&engine::result_order(&s2.fndetail.krate, &s2.fndetail.s, &s2.fndetail.version, *id2)))
+    });
+    Ok(similar.into_iter().take(limit).map(|(_, similar_fn)| similar_fn).collect())
+}
+
+fn fn_target(fn_id: u64, fndetail: &FnDetail, normalize: impl Fn(&str) -> String) -> TargetSig {
+    let params: Vec<String> = fndetail.params.iter().map(|param| normalize(param)).collect();
+    TargetSig {
+        receiver: params.first().cloned(),
+        params,
+        ret: Some(normalize(&fndetail.ret)),
+        fn_id: Some(fn_id),
+        krate: Some(fndetail.krate.clone()),
+    }
+}
+
+// Params of one matched with params of the other, each at most once
+fn num_shared(params1: &[String], params2: &[String]) -> usize {
+    let mut unmatched: Vec<&String> = params2.iter().collect();
+    params1.iter()
+        .filter(|param| match unmatched.iter().position(|p| p == param) {
+            Some(i) => { unmatched.remove(i); true },
+            None => false,
+        })
+        .count()
+}
@@ -0,0 +1,281 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use reeves_types::*;
+use reeves_types::engine::{CompactFn, CompactIndex, SigMarker};
+
+use crate::{CRATE_TREE, FN_TREE, MARKER_TREE, PARAM_TREE, RET_TREE, Db, crate_meta, parse_crate_key, stable_hash};
+use crate::fnidset::decode_fn_ids;
+use crate::fnrecord::FnRecord;
+
+// Loaded by the index page, which searches it in the browser
+const SEARCH_BLOB: &str = "search.json";
+// For frontends that run the full query engine in the browser, see `compact_index`
+const COMPACT_INDEX_BLOB: &str = "index.bin";
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60em; margin: 1em auto; padding: 0 1em; }
+code { font-size: 0.95em; }
+li { margin: 0.2em 0; }
+.doc { color: #555; margin-left: 1em; }
+.meta { color: #555; }
+#q { width: 100%; font-size: 1.1em; padding: 0.3em; }
+";
+
+// Matches the CLI query syntax loosely - `<comma-separated params> -> <ret>`, where each part matches
+// any type containing it, or any text in the signature if there's no `->`
+const SEARCH_SCRIPT: &str = r#"
+const MAX_SHOWN = 200;
+const esc = s => s.replace(/[&<>"']/g, c => "&#" + c.charCodeAt(0) + ";");
+fetch("search.json").then(r => r.json()).then(entries => {
+    const input = document.getElementById("q");
+    const results = document.getElementById("results");
+    const run = () => {
+        const q = input.value.trim().toLowerCase();
+        if (!q) { results.innerHTML = ""; return; }
+        let matches;
+        if (q.includes("->")) {
+            const [params, ret] = q.split("->").map(s => s.trim());
+            const wanted = params.split(",").map(s => s.trim()).filter(s => s);
+            matches = e => (!ret || e.r.toLowerCase().includes(ret)) &&
+                wanted.every(w => e.p.some(p => p.toLowerCase().includes(w)));
+        } else {
+            matches = e => e.s.toLowerCase().includes(q);
+        }
+        const found = entries.filter(matches);
+        results.innerHTML = found.slice(0, MAX_SHOWN).map(e =>
+            `<li><a href="crates/${encodeURIComponent(e.c)}/${encodeURIComponent(e.v)}.html">[${esc(e.c)} ${esc(e.v)}]</a> <code>${esc(e.s)}</code></li>`
+        ).join("") + (found.length > MAX_SHOWN ? `<li>... and ${found.length - MAX_SHOWN} more</li>` : "");
+    };
+    input.addEventListener("input", run);
+    run();
+});
+"#;
+
+// An entry in the search blob, with short keys as there's one per fn
+#[derive(Serialize)]
+struct SearchEntry<'a> {
+    s: &'a str,
+    c: &'a str, // crate
+    v: &'a str, // version
+    p: &'a [String],
+    r: &'a str,
+}
+
+// Renders the whole index as a static site that needs no backend (e.g. for GitHub Pages) - an index
+// page that searches a JSON blob of every fn in the browser, a page per crate version listing its fns,
+// and a page per type listing the fns taking and returning it
+pub fn generate_site(db: &Db, out_dir: &Path) -> Result<()> {
+    let crate_tree = db.open_tree(CRATE_TREE);
+    let fn_tree = db.open_tree(FN_TREE);
+
+    let mut krates: Vec<(CrateMeta, Vec<FnDetail>)> = vec![];
+    for (key, val) in crate_tree.iter() {
+        let (name, version) = parse_crate_key(&key);
+        let fn_ids: Vec<u64> = bincode::deserialize(&val).unwrap();
+        let mut fndetails: Vec<FnDetail> = fn_ids.iter()
+            .map(|fn_id| FnRecord::new(fn_tree.get(bincode::serialize(fn_id).unwrap()).unwrap()).to_fndetail())
+            .collect();
+        fndetails.sort_by(|fd1, fd2| fd1.s.cmp(&fd2.s));
+        // Crates analyzed before metadata was recorded still get a page
+        let meta = crate_meta(db, name, version).unwrap_or_else(|| CrateMeta {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            license: None,
+            repository: None,
+            docsrs_url: format!("https://docs.rs/{}/{}", name, version),
+            indexed_at: 0,
+            categories: vec![],
+            keywords: vec![],
+        });
+        krates.push((meta, fndetails))
+    }
+    info!("generating site for {} crate versions in {}", krates.len(), out_dir.display());
+
+    fs::create_dir_all(out_dir.join("crates"))?;
+    fs::create_dir_all(out_dir.join("types"))?;
+
+    // type => (fns taking it, fns returning it)
+    let mut types: BTreeMap<&str, (Vec<&FnDetail>, Vec<&FnDetail>)> = BTreeMap::new();
+    let mut search_entries = vec![];
+    for (meta, fndetails) in krates.iter() {
+        for fndetail in fndetails {
+            for param in fndetail.params.iter() {
+                let taking = &mut types.entry(param.as_str()).or_default().0;
+                // e.g. multiple params of the same type
+                if !taking.iter().any(|fd| std::ptr::eq(*fd, fndetail)) {
+                    taking.push(fndetail)
+                }
+            }
+            types.entry(fndetail.ret.as_str()).or_default().1.push(fndetail);
+            search_entries.push(SearchEntry { s: &fndetail.s, c: &fndetail.krate, v: &fndetail.version, p: &fndetail.params, r: &fndetail.ret });
+        }
+        let crate_dir = out_dir.join("crates").join(&meta.name);
+        fs::create_dir_all(&crate_dir)?;
+        write_page(&crate_dir.join(format!("{}.html", meta.version)), &crate_page(meta, fndetails))?;
+    }
+    for (ty, (taking, returning)) in types.iter() {
+        write_page(&out_dir.join(type_page_path(ty)), &type_page(ty, taking, returning))?;
+    }
+    write_page(&out_dir.join(SEARCH_BLOB), &serde_json::to_string(&search_entries).unwrap())?;
+    fs::write(out_dir.join(COMPACT_INDEX_BLOB), compact_index(db).to_bytes())
+        .with_context(|| format!("failed to write {}", COMPACT_INDEX_BLOB))?;
+    write_page(&out_dir.join("index.html"), &index_page(&krates))?;
+    info!("generated pages for {} crate versions and {} types", krates.len(), types.len());
+    Ok(())
+}
+
+// A snapshot of the committed crates for searching without a server (see `engine::CompactIndex`),
+// with the param and ret trees as they are
+pub fn compact_index(db: &Db) -> CompactIndex {
+    let crate_tree = db.open_tree(CRATE_TREE);
+    let fn_tree = db.open_tree(FN_TREE);
+    let mut crates = vec![];
+    let mut fns = vec![];
+    let mut fn_idxs: HashMap<u64, u32> = HashMap::new(); // fn id => index in fns
+    for (key, val) in crate_tree.iter() {
+        let (name, version) = parse_crate_key(&key);
+        let krate = crates.len() as u32;
+        crates.push((name.to_owned(), version.to_owned()));
+        let fn_ids: Vec<u64> = bincode::deserialize(&val).unwrap();
+        for fn_id in fn_ids {
+            let record = FnRecord::new(fn_tree.get(bincode::serialize(&fn_id).unwrap()).unwrap());
+            fn_idxs.insert(fn_id, fns.len() as u32);
+            fns.push(CompactFn { krate, s: record.s().to_owned(), doc: record.doc() })
+        }
+    }
+    // Fns of crates that aren't committed yet are left out
+    let compact_tree = |tree_name: &str| -> Vec<(String, Vec<u32>)> {
+        db.open_tree(tree_name).iter()
+            .filter_map(|(key, val)| {
+                let fn_ids = decode_fn_ids(&val);
+                let mut idxs: Vec<u32> = fn_ids.iter().filter_map(|fn_id| fn_idxs.get(&fn_id).cloned()).collect();
+                if idxs.is_empty() {
+                    return None
+                }
+                idxs.sort();
+                Some((String::from_utf8(key).unwrap(), idxs))
+            })
+            .collect()
+    };
+    let params = compact_tree(PARAM_TREE);
+    let rets = compact_tree(RET_TREE);
+    let markers = compact_tree(MARKER_TREE).into_iter()
+        .filter_map(|(key, idxs)| SigMarker::ALL.iter().find(|marker| marker.key() == key).map(|&marker| (marker, idxs)))
+        .collect();
+    info!("compacted {} fns of {} crate versions, with {} param and {} ret types", fns.len(), crates.len(), params.len(), rets.len());
+    CompactIndex::new(crates, fns, params, rets, markers)
+}
+
+fn write_page(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
+
+// Types contain all sorts of characters that don't belong in a file name
+fn type_page_path(ty: &str) -> String {
+    format!("types/{:016x}.html", stable_hash(&[ty]))
+}
+
+fn crate_page_path(krate: &str, version: &str) -> String {
+    format!("crates/{}/{}.html", krate, version)
+}
+
+// `root` is the relative path from the page to the root of the site
+fn page(title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{style}</style>\n</head>\n\
+         <body>\n<p><a href=\"{root}index.html\">typesearch</a></p>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title), style = STYLE, root = root, body = body,
+    )
+}
+
+fn index_page(krates: &[(CrateMeta, Vec<FnDetail>)]) -> String {
+    let mut body = String::new();
+    body.push_str("<input id=\"q\" placeholder=\"e.g. &amp;str, usize -> Option&lt;char&gt;\" autofocus>\n<ul id=\"results\"></ul>\n");
+    body.push_str("<h2>Crates</h2>\n<ul>\n");
+    for (meta, fndetails) in krates {
+        body.push_str(&format!("<li><a href=\"{}\">{} {}</a> <span class=\"meta\">({} fns)</span></li>\n",
+                               escape(&crate_page_path(&meta.name, &meta.version)), escape(&meta.name), escape(&meta.version), fndetails.len()));
+    }
+    body.push_str("</ul>\n");
+    body.push_str(&format!("<script>{}</script>\n", SEARCH_SCRIPT));
+    page("typesearch", "", &body)
+}
+
+fn is_web_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+fn crate_page(meta: &CrateMeta, fndetails: &[FnDetail]) -> String {
+    let root = "../../";
+    let mut body = String::new();
+    body.push_str(&format!("<p class=\"meta\"><a href=\"{}\">docs</a>", escape(&meta.docsrs_url)));
+    // From the crate's Cargo.toml, so anything but a web URL (e.g. `javascript:`) isn't linked
+    match &meta.repository {
+        Some(repository) if is_web_url(repository) => body.push_str(&format!(" | <a href=\"{}\">repo</a>", escape(repository))),
+        Some(repository) => body.push_str(&format!(" | repo: {}", escape(repository))),
+        None => (),
+    }
+    if let Some(license) = &meta.license {
+        body.push_str(&format!(" | {}", escape(license)));
+    }
+    body.push_str("</p>\n<ul>\n");
+    for fndetail in fndetails {
+        body.push_str(&format!("<li><code>{}</code>", escape(&fndetail.s)));
+        // Link to the pages of the types involved
+        let mut tys: Vec<&str> = vec![];
+        for ty in fndetail.params.iter().chain(Some(&fndetail.ret)) {
+            if !tys.contains(&ty.as_str()) { tys.push(ty) }
+        }
+        let links: Vec<String> = tys.iter()
+            .map(|ty| format!("<a href=\"{}{}\">{}</a>", root, type_page_path(ty), escape(ty)))
+            .collect();
+        body.push_str(&format!(" <span class=\"meta\">[{}]</span>", links.join(", ")));
+        if let Some(doc) = &fndetail.doc {
+            body.push_str(&format!("<div class=\"doc\">{}</div>", escape(doc)));
+        }
+        body.push_str("</li>\n");
+    }
+    body.push_str("</ul>\n");
+    page(&format!("{} {}", meta.name, meta.version), root, &body)
+}
+
+fn type_page(ty: &str, taking: &[&FnDetail], returning: &[&FnDetail]) -> String {
+    let root = "../";
+    let fn_list = |fndetails: &[&FnDetail]| {
+        let mut list = String::from("<ul>\n");
+        for fndetail in fndetails {
+            list.push_str(&format!("<li><a href=\"{}{}\">[{} {}]</a> <code>{}</code></li>\n",
+                                   root, escape(&crate_page_path(&fndetail.krate, &fndetail.version)),
+                                   escape(&fndetail.krate), escape(&fndetail.version), escape(&fndetail.s)));
+        }
+        list.push_str("</ul>\n");
+        list
+    };
+    let mut body = String::new();
+    body.push_str(&format!("<h2>Taking <code>{}</code></h2>\n", escape(ty)));
+    body.push_str(&fn_list(taking));
+    body.push_str(&format!("<h2>Returning <code>{}</code></h2>\n", escape(ty)));
+    body.push_str(&fn_list(returning));
+    page(ty, root, &body)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
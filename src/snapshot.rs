@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use reeves_types::*;
+
+use crate::{CRATE_GENERATION_TREE, CRATE_TREE, FN_TREE, Db, Transaction, Tree, TxResult, crate_key};
+use crate::fnrecord::FnRecord;
+
+// Key in the generation tree of the latest generation, which can't be a crate key (they always have
+// a name and version)
+const LATEST_GENERATION_KEY: &[u8] = b"";
+
+// What a search can see of the index - the crates committed when it started. Crates are added over
+// several transactions (see `add_crate`) and searches read one key at a time, so otherwise a search
+// running alongside indexing could find fns of a crate that's only partly added, or fns that have
+// been purged since it read their ids
+pub(crate) struct ReadSnapshot {
+    generation: u64,
+    fn_tree: Tree,
+    crate_tree: Tree,
+    generation_tree: Tree,
+    visible: HashMap<(String, String), bool>, // (crate name, version) => visible, populated on demand
+}
+
+impl ReadSnapshot {
+    pub(crate) fn take(db: &Db) -> Self {
+        let generation_tree = db.open_tree(CRATE_GENERATION_TREE);
+        let generation = generation_tree.get(LATEST_GENERATION_KEY)
+            .map_or(0, |bytes| bincode::deserialize(&bytes).unwrap());
+        Self {
+            generation,
+            fn_tree: db.open_tree(FN_TREE),
+            crate_tree: db.open_tree(CRATE_TREE),
+            generation_tree,
+            visible: HashMap::new(),
+        }
+    }
+
+    // The fn, unless it isn't part of the snapshot
+    pub(crate) fn get_fn(&mut self, fn_id: u64) -> Option<FnDetail> {
+        self.get_fn_record(fn_id).map(|record| record.to_fndetail())
+    }
+
+    // The same, for decoding only some of it
+    pub(crate) fn get_fn_record(&mut self, fn_id: u64) -> Option<FnRecord> {
+        let record = FnRecord::new(self.fn_tree.get(bincode::serialize(&fn_id).unwrap())?);
+        if self.is_visible(record.krate(), record.version()) { Some(record) } else { None }
+    }
+
+    pub(crate) fn is_visible(&mut self, name: &str, version: &str) -> bool {
+        let (generation, generation_tree, crate_tree) = (self.generation, &self.generation_tree, &self.crate_tree);
+        *self.visible.entry((name.to_owned(), version.to_owned())).or_insert_with(|| {
+            let key = crate_key(name, version);
+            match generation_tree.get(&key) {
+                Some(bytes) => bincode::deserialize::<u64>(&bytes).unwrap() <= generation,
+                // Committed before generations were recorded, or not committed yet
+                None => crate_tree.contains_key(&key),
+            }
+        })
+    }
+}
+
+// Called in the transaction committing a crate, which becomes visible to searches starting after it
+pub(crate) fn commit_generation(tx: &dyn Transaction, crate_key: &[u8]) -> TxResult<()> {
+    let generation = match tx.get(CRATE_GENERATION_TREE, LATEST_GENERATION_KEY)? {
+        Some(bytes) => bincode::deserialize::<u64>(&bytes).unwrap() + 1,
+        None => 1,
+    };
+    let generation_bytes = bincode::serialize(&generation).unwrap();
+    tx.insert(CRATE_GENERATION_TREE, LATEST_GENERATION_KEY, &generation_bytes)?;
+    tx.insert(CRATE_GENERATION_TREE, crate_key, &generation_bytes)
+}
@@ -0,0 +1,761 @@
+use anyhow::{Context, Result};
+use rusqlite::{OptionalExtension, TransactionBehavior, params};
+use serde::{Serialize, Deserialize};
+use sled::Transactional;
+use sled::transaction::{ConflictableTransactionError, TransactionError, TransactionalTree, UnabortableTransactionError};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{debug_span, info};
+
+use crate::FnIdSet;
+use crate::fnidset::FnSetCache;
+use crate::metrics;
+
+// Storage for the index is a set of named trees (ordered maps from bytes to bytes), as provided by
+// sled. The SQLite store keeps each tree as a table, so the index is a single file that's easy to
+// ship around, and doesn't need exclusive access to a directory. The in-memory store never touches
+// disk after loading, for tests and small deployments
+pub trait Store: Send + Sync {
+    fn get(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>>;
+    fn insert(&self, tree: &str, key: &[u8], val: &[u8]);
+    fn remove(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>>;
+    // In key order
+    fn scan_prefix<'a>(&'a self, tree: &str, prefix: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+    fn tree_names(&self) -> Vec<String>;
+    // Unique across the lifetime of the store
+    fn generate_id(&self) -> u64;
+    // Runs `f` atomically over the named trees (which must be all it touches). It may be run more
+    // than once (e.g. on conflict), so shouldn't have side effects outside the transaction
+    fn transaction(&self, trees: &[&str], f: &mut dyn FnMut(&dyn Transaction) -> TxResult<()>);
+}
+
+pub trait Transaction {
+    fn get(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>>;
+    fn insert(&self, tree: &str, key: &[u8], val: &[u8]) -> TxResult<()>;
+    fn remove(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>>;
+}
+
+// Must be propagated out of transactions with `?`, so the store can retry them on conflict
+#[derive(Debug)]
+pub struct TxError(UnabortableTransactionError);
+
+pub type TxResult<T> = Result<T, TxError>;
+
+// Roughly how much memory a single large write (e.g. adding a crate) may use before it's split up
+const DEFAULT_WRITE_BUDGET: usize = 64 * 1024 * 1024;
+
+// Trees of a named index are stored as `index/<name>/<tree>`, see `indexes::open_index`. Those of
+// the default index keep their plain names, so DBs from before named indexes are its contents
+const INDEX_TREE_PREFIX: &str = "index/";
+
+// A handle to one index in a store - the default one, unless opened with `indexes::open_index`.
+// Everything done through it only sees that index's trees
+#[derive(Clone)]
+pub struct Db {
+    store: Arc<dyn Store>,
+    write_budget: usize, // bytes
+    index: Option<String>, // None for the default index
+    fn_set_cache: Arc<FnSetCache>, // invalidated by every write through the store's handles
+    keys_cache: Arc<KeysCache>, // likewise
+    // Whether the DB's fuzzy searched keys are kept in the text search - not for scratch DBs, whose
+    // text index names would be the same as the real DB's
+    text_search: bool,
+}
+
+impl Db {
+    fn new(store: Arc<dyn Store>) -> Self {
+        Self {
+            store,
+            write_budget: DEFAULT_WRITE_BUDGET,
+            index: None,
+            fn_set_cache: Arc::new(FnSetCache::new()),
+            keys_cache: Arc::new(KeysCache::new()),
+            text_search: true,
+        }
+    }
+
+    pub(crate) fn fn_set_cache(&self) -> &FnSetCache {
+        &self.fn_set_cache
+    }
+
+    pub(crate) fn with_index(&self, index: Option<&str>) -> Self {
+        Self { index: index.map(str::to_owned), ..self.clone() }
+    }
+
+    pub fn index_name(&self) -> Option<&str> {
+        self.index.as_deref()
+    }
+
+    fn tree_name(&self, name: &str) -> String {
+        match &self.index {
+            Some(index) => format!("{}{}/{}", INDEX_TREE_PREFIX, index, name),
+            None => name.to_owned(),
+        }
+    }
+
+    pub(crate) fn has_text_search(&self) -> bool {
+        self.text_search
+    }
+
+    // The text search has a flat namespace of indexes, so a named index's are prefixed with its name
+    pub(crate) fn text_index_name(&self, name: &str) -> String {
+        match &self.index {
+            Some(index) => format!("{}-{}", index, name),
+            None => name.to_owned(),
+        }
+    }
+
+    pub fn with_write_budget(self, write_budget: usize) -> Self {
+        Self { write_budget, ..self }
+    }
+
+    pub fn write_budget(&self) -> usize {
+        self.write_budget
+    }
+
+    pub fn open_tree(&self, name: &str) -> Tree {
+        Tree { store: self.store.clone(), name: self.tree_name(name), fn_set_cache: self.fn_set_cache.clone(), keys_cache: self.keys_cache.clone() }
+    }
+
+    // Of this index only
+    pub fn tree_names(&self) -> Vec<String> {
+        let names = self.store.tree_names().into_iter();
+        match &self.index {
+            Some(_) => {
+                let prefix = self.tree_name("");
+                names.filter_map(|name| name.strip_prefix(&prefix).map(str::to_owned)).collect()
+            },
+            None => names.filter(|name| !name.starts_with(INDEX_TREE_PREFIX)).collect(),
+        }
+    }
+
+    pub fn generate_id(&self) -> u64 {
+        self.store.generate_id()
+    }
+
+    // Empty, see `InMemoryStore`. For tests and benches, so it doesn't use the text search - searches
+    // just scan keys for candidates
+    pub fn in_memory() -> Self {
+        Self { text_search: false, ..Db::new(Arc::new(InMemoryStore::new(Snapshot { trees: vec![], next_id: 0 }))) }
+    }
+
+    pub fn transaction<T>(&self, trees: &[&str], mut f: impl FnMut(&dyn Transaction) -> TxResult<T>) -> T {
+        let _span = debug_span!("transaction", trees = ?trees).entered();
+        let tree_names: Vec<String> = trees.iter().map(|name| self.tree_name(name)).collect();
+        let tree_names: Vec<&str> = tree_names.iter().map(String::as_str).collect();
+        let mut ret = None;
+        let written = RefCell::new(vec![]);
+        self.store.transaction(&tree_names, &mut |tx| {
+            // Whatever an attempt that's retried wrote doesn't matter, as it's only used to invalidate
+            ret = Some(f(&DbTransaction { db: self, tx, written: &written })?);
+            Ok(())
+        });
+        for (tree, key) in written.into_inner() {
+            self.fn_set_cache.invalidate(&tree, &key);
+            self.keys_cache.invalidate(&tree)
+        }
+        ret.unwrap()
+    }
+}
+
+// Maps the tree names a transaction is given to those of the index it's on, and notes what it
+// writes, so cached fn id sets can be invalidated once it's committed
+struct DbTransaction<'a> {
+    db: &'a Db,
+    tx: &'a dyn Transaction,
+    written: &'a RefCell<Vec<(String, Vec<u8>)>>, // (tree, key)
+}
+
+impl Transaction for DbTransaction<'_> {
+    fn get(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+        self.tx.get(&self.db.tree_name(tree), key)
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], val: &[u8]) -> TxResult<()> {
+        let tree = self.db.tree_name(tree);
+        self.tx.insert(&tree, key, val)?;
+        self.written.borrow_mut().push((tree, key.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+        let tree = self.db.tree_name(tree);
+        let ret = self.tx.remove(&tree, key)?;
+        self.written.borrow_mut().push((tree, key.to_vec()));
+        Ok(ret)
+    }
+}
+
+// A handle to one tree in a store
+#[derive(Clone)]
+pub struct Tree {
+    store: Arc<dyn Store>,
+    name: String,
+    fn_set_cache: Arc<FnSetCache>,
+    keys_cache: Arc<KeysCache>,
+}
+
+impl Tree {
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>> {
+        let _timer = metrics::DB_READ_SECONDS.with_label_values(&["get"]).start_timer();
+        self.store.get(&self.name, key.as_ref())
+    }
+
+    pub fn contains_key(&self, key: impl AsRef<[u8]>) -> bool {
+        self.get(key).is_some()
+    }
+
+    // For trees of fn id sets, decoded, and cached if large (see `FnSetCache`)
+    pub(crate) fn get_fn_ids(&self, key: impl AsRef<[u8]>) -> Option<Arc<FnIdSet>> {
+        self.fn_set_cache.get_or_load(&self.name, key.as_ref(), || self.get(key.as_ref()))
+    }
+
+    // Every key as a string, cached until the tree is next written (see `KeysCache`)
+    pub(crate) fn keys(&self) -> Arc<Vec<String>> {
+        self.keys_cache.get_or_load(&self.name, || self.iter().map(|(key, _)| String::from_utf8_lossy(&key).into_owned()).collect())
+    }
+
+    pub fn insert(&self, key: impl AsRef<[u8]>, val: impl AsRef<[u8]>) {
+        self.store.insert(&self.name, key.as_ref(), val.as_ref());
+        self.fn_set_cache.invalidate(&self.name, key.as_ref());
+        self.keys_cache.invalidate(&self.name)
+    }
+
+    pub fn remove(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>> {
+        let ret = self.store.remove(&self.name, key.as_ref());
+        self.fn_set_cache.invalidate(&self.name, key.as_ref());
+        self.keys_cache.invalidate(&self.name);
+        ret
+    }
+
+    // Iterates the whole tree, like sled
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.scan_prefix([])
+    }
+
+    pub fn scan_prefix(&self, prefix: impl AsRef<[u8]>) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.store.scan_prefix(&self.name, prefix.as_ref())
+    }
+}
+
+// All the keys of trees that are compared against as a whole, e.g. every type for `type_suggestions`,
+// so doing it again doesn't re-read the tree. Shared by all handles to a store like `FnSetCache`,
+// but a tree's keys are dropped by any write to it, whether or not that adds or removes a key
+struct KeysCache {
+    inner: Mutex<KeysCacheInner>,
+}
+
+struct KeysCacheInner {
+    keys: HashMap<String, Arc<Vec<String>>>, // tree name => keys in order
+    // Bumped by every invalidation, so keys read before one aren't cached after it
+    generation: u64,
+}
+
+impl KeysCache {
+    fn new() -> Self {
+        Self { inner: Mutex::new(KeysCacheInner { keys: HashMap::new(), generation: 0 }) }
+    }
+
+    fn get_or_load(&self, tree: &str, load: impl FnOnce() -> Vec<String>) -> Arc<Vec<String>> {
+        let generation = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(keys) = inner.keys.get(tree) {
+                return keys.clone()
+            }
+            inner.generation
+        };
+        // Not holding the lock while reading the tree, as for `FnSetCache`
+        let keys = Arc::new(load());
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner.keys.insert(tree.to_owned(), keys.clone());
+        }
+        keys
+    }
+
+    fn invalidate(&self, tree: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.keys.remove(tree);
+    }
+}
+
+// A path ending in `.sqlite` is opened with SQLite, one ending in `.snapshot` is loaded into memory
+// (see `export_snapshot`), and anything else is opened with sled
+pub fn open_store(path: &Path) -> Result<Db> {
+    let ext = path.extension().and_then(|ext| ext.to_str());
+    Ok(match ext {
+        Some("sqlite") => {
+            info!("opening sqlite store at {}", path.display());
+            Db::new(Arc::new(SqliteStore::open(path)))
+        },
+        Some("snapshot") => {
+            info!("loading snapshot {} into memory, changes won't be saved", path.display());
+            let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+            let snapshot: Snapshot = bincode::deserialize(&bytes)
+                .with_context(|| format!("{} isn't a reeves DB snapshot", path.display()))?;
+            Db::new(Arc::new(InMemoryStore::new(snapshot)))
+        },
+        _ => Db::new(Arc::new(SledStore(sled::open(path).unwrap()))),
+    })
+}
+
+// Everything in a store, for loading into an `InMemoryStore`
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    trees: Vec<(String, Vec<(Vec<u8>, Vec<u8>)>)>, // tree name => entries in key order
+    next_id: u64, // so ids generated after loading are still unique
+}
+
+// Trees may be written to while exporting, so the snapshot is only consistent if nothing else is
+// using the DB. Includes every index, whichever the handle is for
+pub fn export_snapshot(db: &Db) -> Vec<u8> {
+    let trees = db.store.tree_names().into_iter()
+        .map(|name| {
+            let entries = db.store.scan_prefix(&name, &[]).collect();
+            (name, entries)
+        })
+        .collect();
+    bincode::serialize(&Snapshot { trees, next_id: db.generate_id() }).unwrap()
+}
+
+// Trees are kept in a map each, ordered for scans. Writes go to memory only
+struct InMemoryStore {
+    inner: Mutex<InMemoryInner>,
+}
+
+struct InMemoryInner {
+    trees: HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+    next_id: u64,
+}
+
+impl InMemoryStore {
+    fn new(snapshot: Snapshot) -> Self {
+        let trees = snapshot.trees.into_iter()
+            .map(|(name, entries)| (name, entries.into_iter().collect()))
+            .collect();
+        Self { inner: Mutex::new(InMemoryInner { trees, next_id: snapshot.next_id }) }
+    }
+}
+
+impl InMemoryInner {
+    fn tree(&mut self, tree: &str) -> &mut BTreeMap<Vec<u8>, Vec<u8>> {
+        self.trees.entry(tree.to_owned()).or_insert_with(BTreeMap::new)
+    }
+}
+
+impl Store for InMemoryStore {
+    fn get(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().tree(tree).get(key).cloned()
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], val: &[u8]) {
+        self.inner.lock().unwrap().tree(tree).insert(key.to_vec(), val.to_vec());
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().tree(tree).remove(key)
+    }
+
+    // Copies the matching entries up front, so the lock isn't held while iterating
+    fn scan_prefix<'a>(&'a self, tree: &str, prefix: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        let mut inner = self.inner.lock().unwrap();
+        let entries: Vec<_> = inner.tree(tree).range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    fn tree_names(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let mut names: Vec<String> = inner.trees.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn generate_id(&self) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        inner.next_id - 1
+    }
+
+    // The store is locked for the whole transaction, so transactions never conflict
+    fn transaction(&self, trees: &[&str], f: &mut dyn FnMut(&dyn Transaction) -> TxResult<()>) {
+        struct InMemoryTransaction<'a> {
+            names: &'a [&'a str],
+            inner: RefCell<&'a mut InMemoryInner>,
+        }
+        impl<'a> InMemoryTransaction<'a> {
+            fn check(&self, tree: &str) {
+                assert!(self.names.contains(&tree), "tree not part of transaction");
+            }
+        }
+        impl<'a> Transaction for InMemoryTransaction<'a> {
+            fn get(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+                self.check(tree);
+                Ok(self.inner.borrow_mut().tree(tree).get(key).cloned())
+            }
+            fn insert(&self, tree: &str, key: &[u8], val: &[u8]) -> TxResult<()> {
+                self.check(tree);
+                self.inner.borrow_mut().tree(tree).insert(key.to_vec(), val.to_vec());
+                Ok(())
+            }
+            fn remove(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+                self.check(tree);
+                Ok(self.inner.borrow_mut().tree(tree).remove(key))
+            }
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        f(&InMemoryTransaction { names: trees, inner: RefCell::new(&mut *inner) }).unwrap()
+    }
+}
+
+struct SledStore(sled::Db);
+
+impl SledStore {
+    fn tree(&self, name: &str) -> sled::Tree {
+        self.0.open_tree(name).unwrap()
+    }
+}
+
+impl Store for SledStore {
+    fn get(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.tree(tree).get(key).unwrap().map(|val| val.to_vec())
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], val: &[u8]) {
+        self.tree(tree).insert(key, val).unwrap();
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.tree(tree).remove(key).unwrap().map(|val| val.to_vec())
+    }
+
+    fn scan_prefix<'a>(&'a self, tree: &str, prefix: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        Box::new(self.tree(tree).scan_prefix(prefix).map(|kv| {
+            let (key, val) = kv.unwrap();
+            (key.to_vec(), val.to_vec())
+        }))
+    }
+
+    fn tree_names(&self) -> Vec<String> {
+        self.0.tree_names().into_iter().map(|name| String::from_utf8(name.to_vec()).unwrap()).collect()
+    }
+
+    fn generate_id(&self) -> u64 {
+        self.0.generate_id().unwrap()
+    }
+
+    fn transaction(&self, trees: &[&str], f: &mut dyn FnMut(&dyn Transaction) -> TxResult<()>) {
+        struct SledTransaction<'a> {
+            names: &'a [&'a str],
+            trees: &'a [TransactionalTree],
+        }
+        impl<'a> SledTransaction<'a> {
+            fn tree(&self, name: &str) -> &TransactionalTree {
+                let i = self.names.iter().position(|&n| n == name).expect("tree not part of transaction");
+                &self.trees[i]
+            }
+        }
+        impl<'a> Transaction for SledTransaction<'a> {
+            fn get(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+                Ok(self.tree(tree).get(key).map_err(TxError)?.map(|val| val.to_vec()))
+            }
+            fn insert(&self, tree: &str, key: &[u8], val: &[u8]) -> TxResult<()> {
+                self.tree(tree).insert(key, val).map_err(TxError)?;
+                Ok(())
+            }
+            fn remove(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+                Ok(self.tree(tree).remove(key).map_err(TxError)?.map(|val| val.to_vec()))
+            }
+        }
+
+        let sled_trees: Vec<sled::Tree> = trees.iter().map(|name| self.tree(name)).collect();
+        // sled wants an Fn, to be able to retry
+        let f = RefCell::new(f);
+        let ret: Result<(), TransactionError<()>> = sled_trees.as_slice().transaction(|tx_trees| {
+            let tx = SledTransaction { names: trees, trees: tx_trees };
+            (f.borrow_mut())(&tx).map_err(|TxError(e)| ConflictableTransactionError::from(e))
+        });
+        ret.unwrap()
+    }
+}
+
+// Trees are stored as tables named `tree_<name>`, created on first use
+struct SqliteStore {
+    inner: Mutex<SqliteInner>,
+}
+
+struct SqliteInner {
+    conn: rusqlite::Connection,
+    tables: HashSet<String>, // trees known to have a table
+}
+
+// How many rows to fetch at a time when iterating
+const SQLITE_SCAN_BATCH: usize = 1000;
+
+impl SqliteStore {
+    fn open(path: &Path) -> Self {
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.execute_batch("
+            PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS generated_id (id INTEGER NOT NULL);
+        ").unwrap();
+        let mut tables = HashSet::new();
+        {
+            let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'tree\\_%' ESCAPE '\\'").unwrap();
+            let names = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+            for name in names {
+                tables.insert(name.unwrap()["tree_".len()..].to_owned());
+            }
+        }
+        Self { inner: Mutex::new(SqliteInner { conn, tables }) }
+    }
+}
+
+impl SqliteInner {
+    fn table(&mut self, tree: &str) -> String {
+        let table = format!("\"tree_{}\"", tree.replace('"', "\"\""));
+        if !self.tables.contains(tree) {
+            self.conn.execute(&format!("CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, val BLOB NOT NULL) WITHOUT ROWID", table), []).unwrap();
+            self.tables.insert(tree.to_owned());
+        }
+        table
+    }
+}
+
+fn sqlite_get(conn: &rusqlite::Connection, table: &str, key: &[u8]) -> Option<Vec<u8>> {
+    conn.query_row(&format!("SELECT val FROM {} WHERE key = ?", table), params![key], |row| row.get(0))
+        .optional().unwrap()
+}
+
+fn sqlite_insert(conn: &rusqlite::Connection, table: &str, key: &[u8], val: &[u8]) {
+    conn.execute(&format!("INSERT OR REPLACE INTO {} (key, val) VALUES (?, ?)", table), params![key, val]).unwrap();
+}
+
+fn sqlite_remove(conn: &rusqlite::Connection, table: &str, key: &[u8]) -> Option<Vec<u8>> {
+    let val = sqlite_get(conn, table, key);
+    if val.is_some() {
+        conn.execute(&format!("DELETE FROM {} WHERE key = ?", table), params![key]).unwrap();
+    }
+    val
+}
+
+impl Store for SqliteStore {
+    fn get(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock().unwrap();
+        let table = inner.table(tree);
+        sqlite_get(&inner.conn, &table, key)
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], val: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let table = inner.table(tree);
+        sqlite_insert(&inner.conn, &table, key, val)
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock().unwrap();
+        let table = inner.table(tree);
+        sqlite_remove(&inner.conn, &table, key)
+    }
+
+    // Rows are fetched in batches, so the connection isn't held for the whole iteration
+    fn scan_prefix<'a>(&'a self, tree: &str, prefix: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        let table = self.inner.lock().unwrap().table(tree);
+        let prefix = prefix.to_vec();
+        let mut last_key: Option<Vec<u8>> = None;
+        let mut buf = VecDeque::new();
+        let mut done = false;
+        Box::new(std::iter::from_fn(move || {
+            if buf.is_empty() && !done {
+                let inner = self.inner.lock().unwrap();
+                // Blobs compare like memcmp, the same order as sled
+                let from = last_key.as_ref().unwrap_or(&prefix);
+                let op = if last_key.is_some() { ">" } else { ">=" };
+                let mut stmt = inner.conn.prepare_cached(&format!("SELECT key, val FROM {} WHERE key {} ? ORDER BY key LIMIT ?", table, op)).unwrap();
+                let rows = stmt.query_map(params![from, SQLITE_SCAN_BATCH as i64], |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))).unwrap();
+                let mut num_rows = 0;
+                for row in rows {
+                    let (key, val) = row.unwrap();
+                    num_rows += 1;
+                    if !key.starts_with(&prefix) {
+                        done = true;
+                        break
+                    }
+                    last_key = Some(key.clone());
+                    buf.push_back((key, val));
+                }
+                if num_rows < SQLITE_SCAN_BATCH {
+                    done = true
+                }
+            }
+            buf.pop_front()
+        }))
+    }
+
+    fn tree_names(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let mut names: Vec<String> = inner.tables.iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn generate_id(&self) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let tx = inner.conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+        let id: Option<i64> = tx.query_row("SELECT id FROM generated_id", [], |row| row.get(0)).optional().unwrap();
+        let id = match id {
+            Some(id) => {
+                tx.execute("UPDATE generated_id SET id = ?", params![id + 1]).unwrap();
+                id
+            },
+            None => {
+                tx.execute("INSERT INTO generated_id (id) VALUES (1)", []).unwrap();
+                0
+            },
+        };
+        tx.commit().unwrap();
+        id as u64
+    }
+
+    // There's only one connection, so transactions never conflict
+    fn transaction(&self, trees: &[&str], f: &mut dyn FnMut(&dyn Transaction) -> TxResult<()>) {
+        struct SqliteTransaction<'a> {
+            tx: &'a rusqlite::Connection,
+            tables: Vec<(String, String)>, // tree name => table
+        }
+        impl<'a> SqliteTransaction<'a> {
+            fn table(&self, tree: &str) -> &str {
+                &self.tables.iter().find(|(name, _)| *name == tree).expect("tree not part of transaction").1
+            }
+        }
+        impl<'a> Transaction for SqliteTransaction<'a> {
+            fn get(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+                Ok(sqlite_get(self.tx, self.table(tree), key))
+            }
+            fn insert(&self, tree: &str, key: &[u8], val: &[u8]) -> TxResult<()> {
+                sqlite_insert(self.tx, self.table(tree), key, val);
+                Ok(())
+            }
+            fn remove(&self, tree: &str, key: &[u8]) -> TxResult<Option<Vec<u8>>> {
+                Ok(sqlite_remove(self.tx, self.table(tree), key))
+            }
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let tables: Vec<_> = trees.iter().map(|&tree| (tree.to_owned(), inner.table(tree))).collect();
+        let tx = inner.conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+        f(&SqliteTransaction { tx: &tx, tables }).unwrap();
+        tx.commit().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh store of each kind, with what keeps its files around
+    fn stores() -> Vec<(&'static str, Db, Option<tempfile::TempDir>)> {
+        let dir = tempfile::tempdir().unwrap();
+        let sqlite = Db::new(Arc::new(SqliteStore::open(&dir.path().join("db.sqlite"))));
+        let sled = Db::new(Arc::new(SledStore(sled::Config::new().temporary(true).open().unwrap())));
+        vec![("in-memory", Db::in_memory(), None), ("sqlite", sqlite, Some(dir)), ("sled", sled, None)]
+    }
+
+    // What each store is asked to do and what it answers, which must be the same for all of them
+    fn exercise(db: &Db) -> Vec<String> {
+        let mut out = vec![];
+        let tree = db.open_tree("t");
+        for key in [&b"b"[..], b"a", b"ab", b"a\xff", b"ac", b"c"].iter() {
+            tree.insert(key, key)
+        }
+        out.push(format!("{:?}", tree.get(b"ab")));
+        out.push(format!("{:?}", tree.get(b"zz")));
+        out.push(format!("{:?}", tree.remove(b"c")));
+        out.push(format!("{:?}", tree.remove(b"c")));
+        out.push(format!("{:?}", tree.iter().map(|(key, _)| key).collect::<Vec<_>>()));
+        out.push(format!("{:?}", tree.scan_prefix(b"a").map(|(key, _)| key).collect::<Vec<_>>()));
+        out.push(format!("{:?}", tree.scan_prefix(b"ab").collect::<Vec<_>>()));
+        out.push(tree.len().to_string());
+
+        let ret = db.transaction(&["t", "u"], |tx| {
+            let prev = tx.get("t", b"a")?;
+            tx.insert("u", b"k", b"v")?;
+            tx.remove("t", b"a")?;
+            Ok(prev)
+        });
+        out.push(format!("{:?}", ret));
+        out.push(format!("{:?} {:?}", tree.get(b"a"), db.open_tree("u").get(b"k")));
+
+        // Named indexes only see their own trees
+        let index = db.with_index(Some("x"));
+        index.open_tree("t").insert(b"a", b"x");
+        out.push(format!("{:?} {:?}", tree.get(b"a"), index.open_tree("t").get(b"a")));
+        let mut names: Vec<String> = db.tree_names().into_iter().filter(|name| name == "t" || name == "u").collect();
+        names.sort();
+        out.push(format!("{:?} {:?}", names, index.tree_names()));
+        out
+    }
+
+    #[test]
+    fn stores_agree() {
+        let results: Vec<(&str, Vec<String>)> = stores().iter().map(|(kind, db, _dir)| (*kind, exercise(db))).collect();
+        let (_, expected) = &results[0];
+        assert_eq!(expected[0], "Some([97, 98])");
+        assert_eq!(expected[4], "[[97], [97, 98], [97, 99], [97, 255], [98]]");
+        assert_eq!(expected[5], "[[97], [97, 98], [97, 99], [97, 255]]");
+        for (kind, result) in &results[1..] {
+            assert_eq!(result, expected, "{} store differs from the in-memory store", kind);
+        }
+    }
+
+    #[test]
+    fn generated_ids_are_unique() {
+        for (kind, db, _dir) in stores() {
+            let ids: Vec<u64> = (0..100).map(|_| db.generate_id()).collect();
+            let unique: HashSet<u64> = ids.iter().cloned().collect();
+            assert_eq!(unique.len(), ids.len(), "{} store repeated an id", kind);
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        for (kind, db, _dir) in stores() {
+            db.open_tree("t").insert(b"k", b"v");
+            db.with_index(Some("x")).open_tree("t").insert(b"k", b"x");
+            let last_id = db.generate_id();
+            let snapshot: Snapshot = bincode::deserialize(&export_snapshot(&db)).unwrap();
+            let loaded = Db::new(Arc::new(InMemoryStore::new(snapshot)));
+            assert_eq!(loaded.open_tree("t").get(b"k"), Some(b"v".to_vec()), "{}", kind);
+            assert_eq!(loaded.with_index(Some("x")).open_tree("t").get(b"k"), Some(b"x".to_vec()), "{}", kind);
+            assert!(loaded.generate_id() > last_id, "{}", kind);
+        }
+    }
+
+    #[test]
+    fn keys_cache_invalidates() {
+        let db = Db::in_memory();
+        let tree = db.open_tree("t");
+        tree.insert(b"b", b"");
+        let keys = tree.keys();
+        assert_eq!(*keys, vec!["b"]);
+        // Cached, by every handle to the store
+        assert!(Arc::ptr_eq(&keys, &db.open_tree("t").keys()));
+        tree.insert(b"a", b"");
+        assert_eq!(*tree.keys(), vec!["a", "b"]);
+        db.transaction(&["t"], |tx| tx.remove("t", b"b").map(drop));
+        assert_eq!(*tree.keys(), vec!["a"]);
+        // Other indexes' trees of the same name are separate
+        assert!(db.with_index(Some("x")).open_tree("t").keys().is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{Result, bail};
+use std::str;
+use tracing::info;
+
+use reeves_types::*;
+
+use crate::Db;
+
+// Types to treat as equivalent when searching, configured by whoever runs the index - e.g. `str` and
+// `String`, or a crate's alias for a common type. Stored both ways round
+const SYNONYM_TREE: &str = "synonym"; // type \0 synonym_type => []
+
+// Each part of a query gets at most this many alternatives, each with one synonym swapped in
+const MAX_SYNONYM_EXPANSIONS: usize = 8;
+
+pub fn add_synonym(db: &Db, ty: &str, synonym: &str) -> Result<()> {
+    let (ty, synonym) = (normalize(ty), normalize(synonym));
+    if ty == synonym {
+        bail!("{} is the same type as {}", ty, synonym)
+    }
+    info!("adding synonym {} = {}", ty, synonym);
+    let tree = db.open_tree(SYNONYM_TREE);
+    tree.insert(synonym_key(&ty, &synonym), []);
+    tree.insert(synonym_key(&synonym, &ty), []);
+    Ok(())
+}
+
+// Returns whether they were synonyms
+pub fn remove_synonym(db: &Db, ty: &str, synonym: &str) -> bool {
+    let (ty, synonym) = (normalize(ty), normalize(synonym));
+    let tree = db.open_tree(SYNONYM_TREE);
+    let removed = tree.remove(synonym_key(&ty, &synonym)).is_some();
+    tree.remove(synonym_key(&synonym, &ty));
+    removed
+}
+
+// Each pair once, in order
+pub fn list_synonyms(db: &Db) -> Vec<(String, String)> {
+    load_synonyms(db).into_iter().filter(|(ty, synonym)| ty < synonym).collect()
+}
+
+// Both ways round
+pub(crate) fn load_synonyms(db: &Db) -> Vec<(String, String)> {
+    db.open_tree(SYNONYM_TREE).iter()
+        .map(|(key, _val)| {
+            let key = str::from_utf8(&key).unwrap();
+            let mut parts = key.splitn(2, '\0');
+            (parts.next().unwrap().to_owned(), parts.next().expect("malformed synonym key").to_owned())
+        })
+        .collect()
+}
+
+// Alternatives to a part of a query, each with one type swapped for a synonym wherever it appears
+// in it, so `str = String` turns `&str` into `&String`. `normalize` is applied to the
+// alternatives, as it is to the query
+pub(crate) fn expand(term: &str, synonyms: &[(String, String)], normalize: impl Fn(&str) -> String) -> Vec<String> {
+    let repr = TypeRepr::parse(term);
+    let mut alternatives = vec![];
+    for (ty, synonym) in synonyms {
+        let replaced = repr.replace(&TypeRepr::parse(ty), &TypeRepr::parse(synonym));
+        if replaced == repr {
+            continue
+        }
+        let alternative = normalize(&replaced.to_string());
+        if alternative != term && !alternatives.contains(&alternative) {
+            alternatives.push(alternative)
+        }
+        if alternatives.len() == MAX_SYNONYM_EXPANSIONS {
+            break
+        }
+    }
+    alternatives
+}
+
+// The form types are indexed in, so synonyms match however they're written
+fn normalize(ty: &str) -> String {
+    TypeRepr::parse(ty).to_string()
+}
+
+fn synonym_key(ty: &str, synonym: &str) -> Vec<u8> {
+    [ty.as_bytes(), &[0], synonym.as_bytes()].concat()
+}
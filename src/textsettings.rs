@@ -0,0 +1,73 @@
+use anyhow::Result;
+use meilisearch_sdk as meili;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use tracing::info;
+
+use crate::{Db, TEXT_INDEXED_TREES};
+
+// Settings of the text search indexes that can be tuned (the rest follow from how the indexes are
+// searched), kept in the DB so `load_text_search` applies them to the indexes it creates. Each index
+// has its own, as it has its own text search indexes
+const TEXT_SEARCH_SETTINGS_TREE: &str = "text-search-settings"; // TEXT_SEARCH_SETTINGS_KEY => bincode::serialize(TextSearchSettings)
+const TEXT_SEARCH_SETTINGS_KEY: &[u8] = b"settings";
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextSearchSettings {
+    // Words of tokenized types (see `tokenize_type`) to match each other, e.g. "vec" => ["vector"]
+    #[serde(default)]
+    pub synonyms: HashMap<String, Vec<String>>,
+    // Words left out of queries, e.g. "std"
+    #[serde(default)]
+    pub stop_words: Vec<String>,
+    // In meilisearch's terms, e.g. ["typo", "words", "proximity", "attribute", "exactness"]. None for
+    // its defaults
+    #[serde(default)]
+    pub ranking_rules: Option<Vec<String>>,
+}
+
+pub fn text_search_settings(db: &Db) -> TextSearchSettings {
+    db.open_tree(TEXT_SEARCH_SETTINGS_TREE).get(TEXT_SEARCH_SETTINGS_KEY)
+        .map_or_else(TextSearchSettings::default, |bytes| bincode::deserialize(&bytes).unwrap())
+}
+
+pub(crate) fn meili_settings(settings: &TextSearchSettings) -> meili::settings::Settings {
+    meili::settings::Settings {
+        synonyms: Some(settings.synonyms.clone()),
+        stop_words: Some(settings.stop_words.clone()),
+        ranking_rules: settings.ranking_rules.clone(),
+        distinct_attribute: None,
+        filterable_attributes: Some(vec![]),
+        searchable_attributes: Some(vec!["ty".into()]),
+        displayed_attributes: Some(vec!["orig_ty".into()]),
+    }
+}
+
+// Saves the settings and applies them to the text search indexes as they are, so they take effect
+// without reloading. Indexes not created yet (by `load_text_search`) get them when they are
+pub fn update_search_settings(db: &Db, settings: &TextSearchSettings) -> Result<()> {
+    db.open_tree(TEXT_SEARCH_SETTINGS_TREE).insert(TEXT_SEARCH_SETTINGS_KEY, bincode::serialize(settings).unwrap());
+    let client = meili::client::Client::new("http://localhost:7700", "no_key");
+    let ret: Result<(), meili::errors::Error> = futures::executor::block_on(async {
+        for &(_, _, text_index, _) in TEXT_INDEXED_TREES {
+            let index_name = db.text_index_name(text_index);
+            let index = match client.get_index(&index_name).await {
+                Ok(index) => index,
+                Err(_) => {
+                    info!("text search index {} doesn't exist yet, not updating its settings", index_name);
+                    continue
+                },
+            };
+            // Leaving ranking rules out of the settings would keep the ones it has
+            if settings.ranking_rules.is_none() {
+                index.reset_ranking_rules().await?.wait_for_pending_update(None, None).await.unwrap().unwrap();
+            }
+            index.set_settings(&meili_settings(settings)).await?.wait_for_pending_update(None, None).await.unwrap().unwrap();
+            info!("updated settings of text search index {}", index_name);
+        }
+        Ok(())
+    });
+    Ok(ret?)
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use either::Either;
+use serde::{Serialize, Deserialize};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use reeves_types::*;
+
+use crate::{Db, crate_versions, has_crate, remove_crate, save_analysis, save_analysis_error};
+
+// Crates to keep indexed at their newest version, see `run_scheduler`
+const TRACKED_TREE: &str = "tracked"; // name.as_bytes() => bincode::serialize(TrackedCrate)
+
+// How often the scheduler looks for tracked crates due a check
+const SCHEDULER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// What to do with the versions already indexed once a new one is
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OldVersions {
+    Retain,
+    Purge,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    // Already indexed, or its analysis errored (which isn't retried, as for any other crate)
+    Current { version: String },
+    Indexed { version: String, num_fns: usize },
+    // The older versions are kept, whatever the policy
+    AnalysisError { version: String, err: String },
+    // e.g. crates.io couldn't be reached, or the crate couldn't be downloaded - tried again next check
+    Failed(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct TrackedCrate {
+    pub name: String,
+    pub old_versions: OldVersions,
+    pub checked_at: Option<u64>, // unix timestamp secs
+    pub last_outcome: Option<CheckOutcome>,
+}
+
+// Returns whether the crate wasn't already tracked. If it was, only the policy changes
+pub fn track_crate(db: &Db, name: &str, old_versions: OldVersions) -> bool {
+    let tree = db.open_tree(TRACKED_TREE);
+    let (tracked, is_new) = match tree.get(name) {
+        Some(bytes) => (TrackedCrate { old_versions, ..bincode::deserialize::<TrackedCrate>(&bytes).unwrap() }, false),
+        None => (TrackedCrate { name: name.to_owned(), old_versions, checked_at: None, last_outcome: None }, true),
+    };
+    tree.insert(name, bincode::serialize(&tracked).unwrap());
+    is_new
+}
+
+// Returns whether it was tracked. Versions already indexed stay indexed
+pub fn untrack_crate(db: &Db, name: &str) -> bool {
+    db.open_tree(TRACKED_TREE).remove(name).is_some()
+}
+
+// In name order
+pub fn tracked_crates(db: &Db) -> Vec<TrackedCrate> {
+    db.open_tree(TRACKED_TREE).iter()
+        .map(|(_, bytes)| bincode::deserialize(&bytes).unwrap())
+        .collect()
+}
+
+// Checks each tracked crate for a new version every `interval` forever, using `latest_version` to find
+// it and `analyze` (as for `run_worker`) to download and analyze it. A new version is saved before
+// old ones are purged, so searches always find one of them
+pub fn run_scheduler(db: &Db, interval: Duration, latest_version: &dyn Fn(&str) -> Result<String>,
+                     analyze: &dyn Fn(&str, &str) -> Result<(CrateMeta, Either<Vec<FnDetail>, String>)>) -> ! {
+    loop {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let due = tracked_crates(db).into_iter()
+            .filter(|tracked| tracked.checked_at.map_or(true, |checked_at| now.saturating_sub(checked_at) >= interval.as_secs()));
+        for tracked in due {
+            let outcome = check_tracked(db, &tracked, latest_version, analyze);
+            info!("checked tracked crate {}: {:?}", tracked.name, outcome);
+            let tree = db.open_tree(TRACKED_TREE);
+            // May have been untracked (or its policy changed) while checking
+            if let Some(bytes) = tree.get(&tracked.name) {
+                let checked_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+                let tracked = TrackedCrate { checked_at: Some(checked_at), last_outcome: Some(outcome), ..bincode::deserialize::<TrackedCrate>(&bytes).unwrap() };
+                tree.insert(&tracked.name, bincode::serialize(&tracked).unwrap());
+            }
+        }
+        thread::sleep(SCHEDULER_POLL_INTERVAL)
+    }
+}
+
+fn check_tracked(db: &Db, tracked: &TrackedCrate, latest_version: &dyn Fn(&str) -> Result<String>,
+                 analyze: &dyn Fn(&str, &str) -> Result<(CrateMeta, Either<Vec<FnDetail>, String>)>) -> CheckOutcome {
+    let name = &tracked.name;
+    let version = match latest_version(name) {
+        Ok(version) => version,
+        Err(e) => {
+            warn!("failed to find the latest version of {}: {:?}", name, e);
+            return CheckOutcome::Failed(format!("{:?}", e))
+        },
+    };
+    if has_crate(db, name, &version) {
+        return CheckOutcome::Current { version }
+    }
+    info!("analyzing new version {} of tracked crate {}", version, name);
+    match analyze(name, &version) {
+        Ok((meta, Either::Left(fndetails))) => {
+            let num_fns = fndetails.len();
+            save_analysis(db, name, &version, &meta, fndetails);
+            if tracked.old_versions == OldVersions::Purge {
+                for old_version in crate_versions(db, name).into_iter().filter(|v| *v != version) {
+                    info!("purging {} {}, replaced by {}", name, old_version, version);
+                    remove_crate(db, name, Some(&old_version));
+                }
+            }
+            CheckOutcome::Indexed { version, num_fns }
+        },
+        Ok((_, Either::Right(err))) => {
+            save_analysis_error(db, name, &version, &err);
+            CheckOutcome::AnalysisError { version, err }
+        },
+        Err(e) => {
+            warn!("failed to analyze {} {}: {:?}", name, version, e);
+            CheckOutcome::Failed(format!("{:?}", e))
+        },
+    }
+}
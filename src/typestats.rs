@@ -0,0 +1,84 @@
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Db, SavedQuery};
+
+// How often each type is searched for, summed over all searches, so whoever runs the index can see
+// which types people want that it's missing (e.g. to pick crates to index next). Unlike the search
+// history, nothing about individual searches is kept. Off unless enabled, see `set_type_stats_enabled`
+const TYPE_STATS_TREE: &str = "type-stats"; // type_str.as_bytes() => bincode::serialize(TypeSearchStats)
+const TYPE_STATS_ENABLED_TREE: &str = "type-stats-enabled"; // TYPE_STATS_ENABLED_KEY => []
+const TYPE_STATS_ENABLED_KEY: &[u8] = b"enabled";
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct TypeSearchStats {
+    pub ty: String, // as searched for, e.g. `&str`
+    pub searches: u64,
+    pub empty_searches: u64, // of those, the searches with no results
+    pub last_searched: u64, // unix timestamp secs
+}
+
+// Counts already recorded are kept when disabling, until enabled again
+pub fn set_type_stats_enabled(db: &Db, enabled: bool) {
+    let tree = db.open_tree(TYPE_STATS_ENABLED_TREE);
+    if enabled {
+        tree.insert(TYPE_STATS_ENABLED_KEY, []);
+    } else {
+        tree.remove(TYPE_STATS_ENABLED_KEY);
+    }
+}
+
+pub fn type_stats_enabled(db: &Db) -> bool {
+    db.open_tree(TYPE_STATS_ENABLED_TREE).contains_key(TYPE_STATS_ENABLED_KEY)
+}
+
+// Counts each type in the query's params, ret and ret impl (not wildcards), if enabled. Called by
+// `record_search`, so only for the first page of a search
+pub(crate) fn record_type_searches(db: &Db, query: &SavedQuery, num_results: usize) {
+    if !type_stats_enabled(db) {
+        return
+    }
+    let mut tys: Vec<&str> = query.params.iter().flatten().map(String::as_str)
+        .chain(query.ret.as_deref())
+        .chain(query.ret_impl.as_deref())
+        .map(str::trim)
+        .filter(|ty| !ty.is_empty() && *ty != "*" && *ty != "_")
+        .collect();
+    tys.sort();
+    tys.dedup();
+    if tys.is_empty() {
+        return
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    db.transaction(&[TYPE_STATS_TREE], |tx| {
+        for ty in &tys {
+            let mut stats = match tx.get(TYPE_STATS_TREE, ty.as_bytes())? {
+                Some(bytes) => bincode::deserialize(&bytes).unwrap(),
+                None => TypeSearchStats { ty: ty.to_string(), searches: 0, empty_searches: 0, last_searched: 0 },
+            };
+            stats.searches += 1;
+            stats.empty_searches += (num_results == 0) as u64;
+            stats.last_searched = now;
+            tx.insert(TYPE_STATS_TREE, ty.as_bytes(), &bincode::serialize(&stats).unwrap())?;
+        }
+        Ok(())
+    })
+}
+
+// The most searched types first, or with `empty_only` just the types that have been in searches with
+// no results, most such searches first - likely candidates for crates (or synonyms) to add
+pub fn type_search_stats(db: &Db, empty_only: bool, limit: usize) -> Vec<TypeSearchStats> {
+    let mut stats: Vec<TypeSearchStats> = db.open_tree(TYPE_STATS_TREE).iter()
+        .map(|(_key, val)| bincode::deserialize(&val).unwrap())
+        .filter(|stats: &TypeSearchStats| !empty_only || stats.empty_searches > 0)
+        .collect();
+    if empty_only {
+        stats.sort_by_key(|stats| (Reverse(stats.empty_searches), Reverse(stats.searches)));
+    } else {
+        stats.sort_by_key(|stats| (Reverse(stats.searches), Reverse(stats.last_searched)));
+    }
+    stats.truncate(limit);
+    stats
+}